@@ -1,11 +1,34 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, TryRecvError};
 use std::sync::Mutex;
 use tauri::State;
+use uuid::Uuid;
 
-use opensilicon_core::cell::CellId;
-use opensilicon_core::commands::{AddGeometryCommand, RemoveGeometryCommand, MoveGeometryCommand};
-use opensilicon_core::geometry::{GeomPrimitive, Point, Rect, Polygon, Path as LayoutPath, Via};
-use opensilicon_core::{Cell, LayoutDatabase};
+use opensilicon_core::cell::{CellId, Properties, Shape, ShapeId};
+use opensilicon_core::commands::{
+    AddGeometryCommand, RemoveGeometryCommand, MoveGeometryCommand, TransformGeometryCommand,
+    DuplicateGeometryCommand, AlignGeometryCommand, Alignment, StretchGeometryCommand,
+    StretchTarget,
+};
+use opensilicon_core::geometry::{
+    BBox, GeomPrimitive, Point, Rect, Polygon, Path as LayoutPath, PathEndStyle, Text,
+    TextPresentation, Via,
+};
+use opensilicon_core::cell::{ArrayParams, CellInstance, Pin, PinDirection};
+use opensilicon_core::design_commands::{
+    AddLayerCommand, CreateCellCommand, DeleteCellCommand, DeleteInstanceCommand,
+    EditLayerCommand, PlaceInstanceCommand, PropertyOwner, RemovePinCommand, RenameCellCommand,
+    SetAllLayersVisibleCommand, SetInstanceArrayCommand, SetPinCommand, SetPropertyCommand,
+    TransformInstanceCommand,
+};
+use opensilicon_core::journal::{Journal, JournalStatus};
+use opensilicon_core::layer::Layer;
+use opensilicon_core::{
+    Cell, ChangeEvent, DbUnits, FlattenOptions, HistoryLimits, LayerId, LayoutDatabase,
+    Orientation, RegionQuery, Transform,
+};
 use opensilicon_renderer::Viewport;
 
 /// Shared application state managed by Tauri.
@@ -14,6 +37,9 @@ pub struct AppState {
     pub viewport: Mutex<Viewport>,
     /// Path of the currently open file (if any), for "Save" re-save flow.
     pub current_file: Mutex<Option<CurrentFile>>,
+    /// Changes to the database not yet picked up by `poll_changes`. Nothing
+    /// is queued until the first poll subscribes.
+    pub changes: Mutex<Option<Receiver<ChangeEvent>>>,
 }
 
 /// Tracks what file is currently open and its format.
@@ -35,6 +61,7 @@ impl Default for AppState {
             database: Mutex::new(LayoutDatabase::new("Untitled Project")),
             viewport: Mutex::new(Viewport::new(1400.0, 900.0)),
             current_file: Mutex::new(None),
+            changes: Mutex::new(None),
         }
     }
 }
@@ -53,6 +80,9 @@ fn get_project_info(state: State<AppState>) -> Result<ProjectInfo, String> {
                 .map(|c| c.name.clone())
                 .unwrap_or_default()
         }),
+        warnings: Vec::new(),
+        recovery_pending: false,
+        recovery_stale: false,
     })
 }
 
@@ -61,21 +91,50 @@ struct ProjectInfo {
     name: String,
     cell_count: usize,
     top_cell: Option<String>,
+    /// Non-fatal problems found while loading the file.
+    warnings: Vec<String>,
+    /// The opened file has unsaved edits from an earlier session. They are
+    /// kept, and not journaled over, until `recover_project` or
+    /// `discard_recovery` is called.
+    recovery_pending: bool,
+    /// The pending edits were made to an earlier version of the file, which
+    /// has changed since; recovering them discards those changes.
+    recovery_stale: bool,
 }
 
 /// Create a new cell.
 #[tauri::command]
 fn create_cell(state: State<AppState>, name: String) -> Result<String, String> {
     let mut db = state.database.lock().map_err(|e| e.to_string())?;
-    let cell = Cell::new(&name);
-    let id = db.add_cell(cell);
+    let cmd = CreateCellCommand::new(&name);
+    let id = cmd.cell_id();
+    db.execute_command(Box::new(cmd)).map_err(|e| e.to_string())?;
     Ok(id.to_string())
 }
 
+/// Rename a cell (via undoable command).
+#[tauri::command]
+fn rename_cell(state: State<AppState>, cell_id: String, name: String) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let cmd = Box::new(RenameCellCommand::new(id, &name));
+    db.execute_command(cmd).map_err(|e| e.to_string())
+}
+
+/// Delete a cell and every instance of it (via undoable command).
+#[tauri::command]
+fn delete_cell(state: State<AppState>, cell_id: String) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    db.execute_command(Box::new(DeleteCellCommand::new(id)))
+        .map_err(|e| e.to_string())
+}
+
 /// List all cells.
 #[tauri::command]
 fn list_cells(state: State<AppState>) -> Result<Vec<CellInfo>, String> {
     let db = state.database.lock().map_err(|e| e.to_string())?;
+    let u = db.units();
     Ok(db
         .all_cells()
         .map(|c| CellInfo {
@@ -83,6 +142,11 @@ fn list_cells(state: State<AppState>) -> Result<Vec<CellInfo>, String> {
             name: c.name.clone(),
             geometry_count: c.geometry_count(),
             instance_count: c.instance_count(),
+            bbox: db.cell_bbox(&c.id).map(|bb| {
+                let (x1, y1) = u.point_to_um(&bb.min);
+                let (x2, y2) = u.point_to_um(&bb.max);
+                [x1, y1, x2, y2]
+            }),
         })
         .collect())
 }
@@ -93,6 +157,244 @@ struct CellInfo {
     name: String,
     geometry_count: usize,
     instance_count: usize,
+    /// Hierarchical extents `[x1, y1, x2, y2]` in µm, including subcells.
+    bbox: Option<[f64; 4]>,
+}
+
+// ── Instance and pin commands ────────────────────────────────────────
+
+/// Place an instance of `child_id` in a cell (via undoable command).
+/// Returns the new instance's ID.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn place_instance(
+    state: State<AppState>,
+    cell_id: String,
+    child_id: String,
+    name: String,
+    x: f64,
+    y: f64,
+    orientation: Option<Orientation>,
+    array: Option<InstanceArray>,
+) -> Result<String, String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let child: CellId = child_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let offset = db.units().point_from_um(x, y);
+    let transform = Transform::new(offset, orientation.unwrap_or_default());
+    let mut instance = CellInstance::new(child, &name, transform);
+    instance.array = array.map(|a| a.to_array(&db.units()));
+    let cmd = PlaceInstanceCommand::new(id, instance);
+    let instance_id = cmd.instance_id();
+    db.execute_command(Box::new(cmd)).map_err(|e| e.to_string())?;
+    Ok(instance_id.to_string())
+}
+
+/// Move an instance by a delta offset (via undoable command).
+#[tauri::command]
+fn move_instance(
+    state: State<AppState>,
+    cell_id: String,
+    instance_id: String,
+    dx: f64,
+    dy: f64,
+) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let instance_id: Uuid = instance_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let delta = db.units().point_from_um(dx, dy);
+    let cmd = TransformInstanceCommand::translate(&db, id, instance_id, delta)
+        .map_err(|e| e.to_string())?;
+    db.execute_command(Box::new(cmd)).map_err(|e| e.to_string())
+}
+
+/// Set an instance's origin and orientation (via undoable command).
+#[tauri::command]
+fn transform_instance(
+    state: State<AppState>,
+    cell_id: String,
+    instance_id: String,
+    x: f64,
+    y: f64,
+    orientation: Orientation,
+) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let instance_id: Uuid = instance_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let transform = Transform::new(db.units().point_from_um(x, y), orientation);
+    let cmd = Box::new(TransformInstanceCommand::new(id, instance_id, transform));
+    db.execute_command(cmd).map_err(|e| e.to_string())
+}
+
+/// Turn an instance into an array, change its array, or with no `array`
+/// make it a single placement again (via undoable command).
+#[tauri::command]
+fn set_instance_array(
+    state: State<AppState>,
+    cell_id: String,
+    instance_id: String,
+    array: Option<InstanceArray>,
+) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let instance_id: Uuid = instance_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let array = array.map(|a| a.to_array(&db.units()));
+    let cmd = Box::new(SetInstanceArrayCommand::new(id, instance_id, array));
+    db.execute_command(cmd).map_err(|e| e.to_string())
+}
+
+/// Remove an instance from a cell (via undoable command).
+#[tauri::command]
+fn delete_instance(
+    state: State<AppState>,
+    cell_id: String,
+    instance_id: String,
+) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let instance_id: Uuid = instance_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let cmd = Box::new(DeleteInstanceCommand::new(id, instance_id));
+    db.execute_command(cmd).map_err(|e| e.to_string())
+}
+
+/// Add a rectangular pin to a cell, or replace the pin called `replace`
+/// (via undoable command).
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn set_pin(
+    state: State<AppState>,
+    cell_id: String,
+    replace: Option<String>,
+    name: String,
+    layer: u32,
+    rect: [f64; 4],
+    direction: PinDirection,
+) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let u = db.units();
+    let [x1, y1, x2, y2] = rect.map(|v| u.to_dbu(v));
+    let pin = Pin {
+        name,
+        layer_id: layer,
+        shape: GeomPrimitive::Rect(Rect::new(layer, x1, y1, x2, y2)),
+        direction,
+    };
+    let cmd = match replace {
+        Some(old) => SetPinCommand::replace(id, &old, pin),
+        None => SetPinCommand::add(id, pin),
+    };
+    db.execute_command(Box::new(cmd)).map_err(|e| e.to_string())
+}
+
+/// Remove a pin by name (via undoable command).
+#[tauri::command]
+fn remove_pin(state: State<AppState>, cell_id: String, name: String) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let cmd = Box::new(RemovePinCommand::new(id, &name));
+    db.execute_command(cmd).map_err(|e| e.to_string())
+}
+
+// ── Property commands ────────────────────────────────────────────────
+
+/// The cell itself, or the shape or instance in it, whose properties are
+/// meant.
+fn property_owner(
+    shape_id: Option<String>,
+    instance_id: Option<String>,
+) -> Result<PropertyOwner, String> {
+    let parse = |id: String| id.parse::<Uuid>().map_err(|e| e.to_string());
+    match (shape_id, instance_id) {
+        (Some(_), Some(_)) => Err("give a shape or an instance, not both".to_string()),
+        (Some(id), None) => Ok(PropertyOwner::Shape(parse(id)?)),
+        (None, Some(id)) => Ok(PropertyOwner::Instance(parse(id)?)),
+        (None, None) => Ok(PropertyOwner::Cell),
+    }
+}
+
+/// Properties of a cell, or of one of its shapes or instances, keyed by
+/// GDS-II attribute number.
+#[tauri::command]
+fn get_properties(
+    state: State<AppState>,
+    cell_id: String,
+    shape_id: Option<String>,
+    instance_id: Option<String>,
+) -> Result<Properties, String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let owner = property_owner(shape_id, instance_id)?;
+    let db = state.database.lock().map_err(|e| e.to_string())?;
+    let cell = db.get_cell(&id).ok_or_else(|| format!("Unknown cell {}", id))?;
+    let properties = match owner {
+        PropertyOwner::Cell => Some(&cell.properties),
+        PropertyOwner::Shape(shape) => cell.shape(&shape).map(|s| &s.properties),
+        PropertyOwner::Instance(instance) => cell.instance(&instance).map(|i| &i.properties),
+    };
+    properties.cloned().ok_or_else(|| format!("{:?} not found in cell {}", owner, id))
+}
+
+/// Set a property, or remove it when `value` is missing (via undoable
+/// command).
+#[tauri::command]
+fn set_property(
+    state: State<AppState>,
+    cell_id: String,
+    shape_id: Option<String>,
+    instance_id: Option<String>,
+    attribute: u16,
+    value: Option<String>,
+) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let owner = property_owner(shape_id, instance_id)?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let cmd = Box::new(SetPropertyCommand::new(id, owner, attribute, value));
+    db.execute_command(cmd).map_err(|e| e.to_string())
+}
+
+// ── Layer commands ───────────────────────────────────────────────────
+
+/// Add a layer to the technology stack (via undoable command).
+#[tauri::command]
+fn add_layer(
+    state: State<AppState>,
+    layer: u32,
+    name: String,
+    gds_layer: u16,
+    gds_datatype: u16,
+    color: Option<[u8; 3]>,
+) -> Result<(), String> {
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let mut new_layer = Layer::new(layer, &name, gds_layer, gds_datatype);
+    if let Some([r, g, b]) = color {
+        new_layer = new_layer.with_color(r, g, b);
+    }
+    db.execute_command(Box::new(AddLayerCommand::new(new_layer)))
+        .map_err(|e| e.to_string())
+}
+
+/// Show or hide a layer (via undoable command).
+#[tauri::command]
+fn set_layer_visibility(state: State<AppState>, layer: u32, visible: bool) -> Result<(), String> {
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    db.execute_command(Box::new(EditLayerCommand::set_visible(layer, visible)))
+        .map_err(|e| e.to_string())
+}
+
+/// Change a layer's color (via undoable command).
+#[tauri::command]
+fn set_layer_color(state: State<AppState>, layer: u32, r: u8, g: u8, b: u8) -> Result<(), String> {
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    db.execute_command(Box::new(EditLayerCommand::set_color(layer, r, g, b)))
+        .map_err(|e| e.to_string())
+}
+
+/// Show or hide all layers (via undoable command).
+#[tauri::command]
+fn set_all_layers_visible(state: State<AppState>, visible: bool) -> Result<(), String> {
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    db.execute_command(Box::new(SetAllLayersVisibleCommand::new(visible)))
+        .map_err(|e| e.to_string())
 }
 
 /// Get viewport state.
@@ -131,6 +433,150 @@ fn redo(state: State<AppState>) -> Result<bool, String> {
     Ok(db.redo())
 }
 
+/// Start grouping subsequent edits into one undo entry.
+#[tauri::command]
+fn begin_transaction(state: State<AppState>, description: String) -> Result<(), String> {
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    db.begin_transaction(&description);
+    Ok(())
+}
+
+/// Finish the open transaction, keeping its edits.
+#[tauri::command]
+fn commit_transaction(state: State<AppState>) -> Result<(), String> {
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    db.commit().map_err(|e| e.to_string())
+}
+
+/// Abandon the open transaction, undoing its edits.
+#[tauri::command]
+fn rollback_transaction(state: State<AppState>) -> Result<(), String> {
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    db.rollback().map_err(|e| e.to_string())
+}
+
+/// Undo and redo entries for the history panel, the next to apply first.
+#[derive(Serialize)]
+struct HistoryInfo {
+    undo: Vec<String>,
+    redo: Vec<String>,
+}
+
+#[tauri::command]
+fn get_history(state: State<AppState>) -> Result<HistoryInfo, String> {
+    let db = state.database.lock().map_err(|e| e.to_string())?;
+    let owned = |descriptions: Vec<&str>| descriptions.into_iter().map(String::from).collect();
+    Ok(HistoryInfo {
+        undo: owned(db.undo_descriptions()),
+        redo: owned(db.redo_descriptions()),
+    })
+}
+
+/// Stop merging edits into the last undo entry, e.g. when a drag ends.
+#[tauri::command]
+fn seal_history(state: State<AppState>) -> Result<(), String> {
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    db.seal_history();
+    Ok(())
+}
+
+/// Limit the undo history by entry count and estimated size; `None`
+/// removes a limit.
+#[tauri::command]
+fn set_history_limits(
+    state: State<AppState>,
+    max_entries: Option<usize>,
+    max_bytes: Option<usize>,
+) -> Result<(), String> {
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    db.set_history_limits(HistoryLimits {
+        max_entries,
+        max_bytes,
+    });
+    Ok(())
+}
+
+// ── Change notification ──────────────────────────────────────────────
+
+/// A database change, with the affected area in µm.
+#[derive(Serialize)]
+struct ChangeInfo {
+    /// `cell_added`, `cell_removed`, `cell_changed`, `shapes_added`,
+    /// `shapes_removed`, `shapes_modified`, `instances_changed`,
+    /// `layers_changed`, or `reset` when the whole database was replaced.
+    kind: &'static str,
+    cell_id: Option<String>,
+    /// The shapes or instances concerned.
+    ids: Vec<String>,
+    layers: Vec<LayerId>,
+    /// `[x1, y1, x2, y2]` in the cell's coordinates.
+    bbox: Option<[f64; 4]>,
+}
+
+/// Changes made since the last call, oldest first, for refreshing only what
+/// changed. The first call returns a single `reset`, since changes are only
+/// recorded from then on.
+#[tauri::command]
+fn poll_changes(state: State<AppState>) -> Result<Vec<ChangeInfo>, String> {
+    let mut changes = state.changes.lock().map_err(|e| e.to_string())?;
+    let mut events = Vec::new();
+    loop {
+        match changes.as_ref().map(Receiver::try_recv) {
+            Some(Ok(event)) => events.push(event),
+            Some(Err(TryRecvError::Empty)) => break,
+            // Not subscribed yet, or the database was replaced, e.g. by
+            // opening a file.
+            None | Some(Err(TryRecvError::Disconnected)) => {
+                let mut db = state.database.lock().map_err(|e| e.to_string())?;
+                *changes = Some(db.subscribe());
+                return Ok(vec![ChangeInfo {
+                    kind: "reset",
+                    cell_id: None,
+                    ids: Vec::new(),
+                    layers: Vec::new(),
+                    bbox: None,
+                }]);
+            }
+        }
+    }
+    drop(changes);
+
+    let db = state.database.lock().map_err(|e| e.to_string())?;
+    let u = db.units();
+    Ok(events
+        .iter()
+        .map(|event| {
+            let (kind, ids, layers) = match event {
+                ChangeEvent::CellAdded(_) => ("cell_added", Vec::new(), Vec::new()),
+                ChangeEvent::CellRemoved(_) => ("cell_removed", Vec::new(), Vec::new()),
+                ChangeEvent::CellChanged(_) => ("cell_changed", Vec::new(), Vec::new()),
+                ChangeEvent::ShapesAdded(c) => ("shapes_added", c.shapes.clone(), c.layers.clone()),
+                ChangeEvent::ShapesRemoved(c) => {
+                    ("shapes_removed", c.shapes.clone(), c.layers.clone())
+                }
+                ChangeEvent::ShapesModified(c) => {
+                    ("shapes_modified", c.shapes.clone(), c.layers.clone())
+                }
+                ChangeEvent::InstancesChanged(c) => {
+                    ("instances_changed", c.instances.clone(), Vec::new())
+                }
+                ChangeEvent::LayersChanged => ("layers_changed", Vec::new(), Vec::new()),
+            };
+            ChangeInfo {
+                kind,
+                cell_id: event.cell().map(|id| id.to_string()),
+                ids: ids.iter().map(|id| id.to_string()).collect(),
+                layers,
+                bbox: event.dirty().map(|bb| {
+                    let (x1, y1) = u.point_to_um(&bb.min);
+                    let (x2, y2) = u.point_to_um(&bb.max);
+                    [x1, y1, x2, y2]
+                }),
+            }
+        })
+        .collect())
+}
+
 // ── File I/O Commands ────────────────────────────────────────────────
 
 /// Open a GDS-II file and load it into the database.
@@ -142,11 +588,17 @@ fn open_gds_file(state: State<AppState>, path: String) -> Result<ProjectInfo, St
 
     let file = File::open(&path).map_err(|e| format!("Failed to open file: {}", e))?;
     let reader = BufReader::new(file);
-    let mut gds_reader = GdsReader::new(reader);
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    // Map the file's layer/datatype pairs onto the current technology layers.
+    let mut gds_reader = GdsReader::new(reader).with_layer_stack(db.layer_stack.clone());
     let new_db = gds_reader.read().map_err(|e| format!("GDS parse error: {}", e))?;
+    let mut warnings: Vec<String> = gds_reader.warnings().iter().map(|w| w.to_string()).collect();
 
-    let mut db = state.database.lock().map_err(|e| e.to_string())?;
     *db = new_db;
+    let status = Journal::status(Path::new(&path));
+    if status == JournalStatus::Clean {
+        warnings.extend(start_journal(&mut db, &path));
+    }
 
     Ok(ProjectInfo {
         name: db.name.clone(),
@@ -156,6 +608,9 @@ fn open_gds_file(state: State<AppState>, path: String) -> Result<ProjectInfo, St
                 .map(|c| c.name.clone())
                 .unwrap_or_default()
         }),
+        warnings,
+        recovery_pending: status != JournalStatus::Clean,
+        recovery_stale: status == JournalStatus::Stale,
     })
 }
 
@@ -166,20 +621,27 @@ fn save_gds_file(state: State<AppState>, path: String) -> Result<(), String> {
     use std::fs::File;
     use std::io::BufWriter;
 
-    let db = state.database.lock().map_err(|e| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
     let file = File::create(&path).map_err(|e| format!("Failed to create file: {}", e))?;
     let writer = BufWriter::new(file);
-    let mut gds_writer = GdsWriter::new(writer);
+    // Keep the instance names the editor shows.
+    let mut gds_writer = GdsWriter::new(writer).with_instance_names(true);
     gds_writer.write(&db).map_err(|e| format!("GDS write error: {}", e))?;
+    if let Some(warning) = start_journal(&mut db, &path) {
+        log::warn!("{}", warning);
+    }
     Ok(())
 }
 
 /// Save/load the database as JSON (native project format).
 #[tauri::command]
 fn save_project_json(state: State<AppState>, path: String) -> Result<(), String> {
-    let db = state.database.lock().map_err(|e| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
     let json = db.to_json().map_err(|e| e.to_string())?;
     std::fs::write(&path, json).map_err(|e| e.to_string())?;
+    if let Some(warning) = start_journal(&mut db, &path) {
+        log::warn!("{}", warning);
+    }
     Ok(())
 }
 
@@ -189,6 +651,74 @@ fn open_project_json(state: State<AppState>, path: String) -> Result<ProjectInfo
     let new_db = LayoutDatabase::from_json(&json).map_err(|e| e.to_string())?;
     let mut db = state.database.lock().map_err(|e| e.to_string())?;
     *db = new_db;
+    let status = Journal::status(Path::new(&path));
+    let warnings = if status == JournalStatus::Clean {
+        start_journal(&mut db, &path).into_iter().collect()
+    } else {
+        Vec::new()
+    };
+    Ok(ProjectInfo {
+        name: db.name.clone(),
+        cell_count: db.cell_count(),
+        top_cell: db.top_cell.map(|id| {
+            db.get_cell(&id)
+                .map(|c| c.name.clone())
+                .unwrap_or_default()
+        }),
+        warnings,
+        recovery_pending: status != JournalStatus::Clean,
+        recovery_stale: status == JournalStatus::Stale,
+    })
+}
+
+// ── Crash recovery ───────────────────────────────────────────────────
+//
+// Edits are journaled next to the open project file (see
+// `opensilicon_core::journal`). Opening a file with unsaved edits from an
+// earlier session leaves its journal alone and reports `recovery_pending`;
+// the frontend then either recovers the edits or discards them, which
+// starts a fresh journal for the file as opened.
+
+/// Journal edits to `db` next to `path` from now on. Returns a warning if
+/// the journal cannot be written; editing works regardless.
+fn start_journal(db: &mut LayoutDatabase, path: &str) -> Option<String> {
+    db.detach_journal();
+    match Journal::create(Path::new(path), db) {
+        Ok(journal) => {
+            db.attach_journal(journal);
+            None
+        }
+        Err(e) => Some(format!("Crash recovery is unavailable: {}", e)),
+    }
+}
+
+/// Whether `path` has edits from an earlier session that were never saved,
+/// and whether the file changed since.
+#[tauri::command]
+fn recovery_status(path: String) -> JournalStatus {
+    Journal::status(Path::new(&path))
+}
+
+/// Restore the unsaved edits of an earlier session, undo history included,
+/// and keep journaling. The project file itself is not modified.
+#[tauri::command]
+fn recover_project(state: State<AppState>, path: String) -> Result<ProjectInfo, String> {
+    let project = Path::new(&path);
+    let recovered = Journal::recover(project).map_err(|e| e.to_string())?;
+    let mut warnings = Vec::new();
+    if recovered.skipped > 0 {
+        warnings.push(format!(
+            "{} journal entries could not be read and were dropped",
+            recovered.skipped
+        ));
+    }
+
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    *db = recovered.database;
+    match Journal::resume(project, &db) {
+        Ok(journal) => db.attach_journal(journal),
+        Err(e) => warnings.push(format!("Crash recovery is unavailable: {}", e)),
+    }
     Ok(ProjectInfo {
         name: db.name.clone(),
         cell_count: db.cell_count(),
@@ -197,12 +727,27 @@ fn open_project_json(state: State<AppState>, path: String) -> Result<ProjectInfo
                 .map(|c| c.name.clone())
                 .unwrap_or_default()
         }),
+        warnings,
+        recovery_pending: false,
+        recovery_stale: false,
     })
 }
 
+/// Throw away the unsaved edits of an earlier session and journal the
+/// project as it is open now, which must be the file at `path`.
+#[tauri::command]
+fn discard_recovery(state: State<AppState>, path: String) -> Result<Vec<String>, String> {
+    Journal::discard(Path::new(&path)).map_err(|e| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    Ok(start_journal(&mut db, &path).into_iter().collect())
+}
+
 // ── Geometry Commands ────────────────────────────────────────────────
+//
+// Coordinates cross the IPC boundary in micrometers and are converted to the
+// database's integer units (see `LayoutDatabase::units`) on the Rust side.
 
-/// Add a rectangle to a cell (via undoable command).
+/// Add a rectangle to a cell (via undoable command). Returns the new shape's ID.
 #[tauri::command]
 fn add_rect(
     state: State<AppState>,
@@ -212,35 +757,43 @@ fn add_rect(
     y1: f64,
     x2: f64,
     y2: f64,
-) -> Result<usize, String> {
+) -> Result<String, String> {
     let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
-    let geom = GeomPrimitive::Rect(Rect::new(layer, x1, y1, x2, y2));
     let mut db = state.database.lock().map_err(|e| e.to_string())?;
-    let cmd = Box::new(AddGeometryCommand::new(id, geom));
-    db.execute_command(cmd);
-    let count = db.get_cell(&id).map(|c| c.geometry_count()).unwrap_or(0);
-    Ok(count)
+    let u = db.units();
+    let geom = GeomPrimitive::Rect(Rect::new(
+        layer,
+        u.to_dbu(x1),
+        u.to_dbu(y1),
+        u.to_dbu(x2),
+        u.to_dbu(y2),
+    ));
+    let cmd = AddGeometryCommand::new(id, geom);
+    let shape_id = cmd.shape_id();
+    db.execute_command(Box::new(cmd)).map_err(|e| e.to_string())?;
+    Ok(shape_id.to_string())
 }
 
-/// Add a polygon to a cell (via undoable command).
+/// Add a polygon to a cell (via undoable command). Returns the new shape's ID.
 #[tauri::command]
 fn add_polygon(
     state: State<AppState>,
     cell_id: String,
     layer: u32,
     vertices: Vec<[f64; 2]>,
-) -> Result<usize, String> {
+) -> Result<String, String> {
     let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
-    let points: Vec<Point> = vertices.iter().map(|v| Point::new(v[0], v[1])).collect();
-    let geom = GeomPrimitive::Polygon(Polygon::new(layer, points));
     let mut db = state.database.lock().map_err(|e| e.to_string())?;
-    let cmd = Box::new(AddGeometryCommand::new(id, geom));
-    db.execute_command(cmd);
-    let count = db.get_cell(&id).map(|c| c.geometry_count()).unwrap_or(0);
-    Ok(count)
+    let u = db.units();
+    let points: Vec<Point> = vertices.iter().map(|v| u.point_from_um(v[0], v[1])).collect();
+    let geom = GeomPrimitive::Polygon(Polygon::new(layer, points));
+    let cmd = AddGeometryCommand::new(id, geom);
+    let shape_id = cmd.shape_id();
+    db.execute_command(Box::new(cmd)).map_err(|e| e.to_string())?;
+    Ok(shape_id.to_string())
 }
 
-/// Add a path/wire to a cell (via undoable command).
+/// Add a path/wire to a cell (via undoable command). Returns the new shape's ID.
 #[tauri::command]
 fn add_path(
     state: State<AppState>,
@@ -248,60 +801,428 @@ fn add_path(
     layer: u32,
     points: Vec<[f64; 2]>,
     width: f64,
-) -> Result<usize, String> {
+    end_style: Option<FlatEndStyle>,
+) -> Result<String, String> {
     let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
-    let pts: Vec<Point> = points.iter().map(|v| Point::new(v[0], v[1])).collect();
-    let geom = GeomPrimitive::Path(LayoutPath::new(layer, pts, width));
     let mut db = state.database.lock().map_err(|e| e.to_string())?;
-    let cmd = Box::new(AddGeometryCommand::new(id, geom));
-    db.execute_command(cmd);
-    let count = db.get_cell(&id).map(|c| c.geometry_count()).unwrap_or(0);
-    Ok(count)
+    let u = db.units();
+    let pts: Vec<Point> = points.iter().map(|v| u.point_from_um(v[0], v[1])).collect();
+    let end_style = end_style.map(|s| s.to_end_style(&u)).unwrap_or_default();
+    let path = LayoutPath::new(layer, pts, u.to_dbu(width)).with_end_style(end_style);
+    let geom = GeomPrimitive::Path(path);
+    let cmd = AddGeometryCommand::new(id, geom);
+    let shape_id = cmd.shape_id();
+    db.execute_command(Box::new(cmd)).map_err(|e| e.to_string())?;
+    Ok(shape_id.to_string())
 }
 
-/// Remove a geometry from a cell by index (via undoable command).
+/// Add a text label to a cell (via undoable command). Returns the new shape's ID.
+#[tauri::command]
+fn add_label(
+    state: State<AppState>,
+    cell_id: String,
+    layer: u32,
+    x: f64,
+    y: f64,
+    label: FlatLabel,
+) -> Result<String, String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let u = db.units();
+    let record = FlatGeometry {
+        id: None,
+        geom_type: "text".into(),
+        layer_id: layer,
+        points: vec![FlatPoint { x, y }],
+        width: None,
+        end_style: None,
+        holes: None,
+        outline: None,
+        label: Some(label),
+        properties: Properties::new(),
+    };
+    let geom = record.to_primitive(&u).expect("text records with a label convert");
+    let cmd = AddGeometryCommand::new(id, geom);
+    let shape_id = cmd.shape_id();
+    db.execute_command(Box::new(cmd)).map_err(|e| e.to_string())?;
+    Ok(shape_id.to_string())
+}
+
+/// Remove a shape from a cell (via undoable command).
 #[tauri::command]
 fn remove_geometry(
     state: State<AppState>,
     cell_id: String,
-    index: usize,
+    shape_id: String,
 ) -> Result<bool, String> {
     let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let shape_id: ShapeId = shape_id.parse().map_err(|e: uuid::Error| e.to_string())?;
     let mut db = state.database.lock().map_err(|e| e.to_string())?;
-    let exists = db.get_cell(&id).map(|c| index < c.geometry_count()).unwrap_or(false);
+    let exists = db.get_cell(&id).is_some_and(|c| c.shape(&shape_id).is_some());
     if exists {
-        let cmd = Box::new(RemoveGeometryCommand::new(id, index));
-        db.execute_command(cmd);
+        let cmd = Box::new(RemoveGeometryCommand::new(id, shape_id));
+        db.execute_command(cmd).map_err(|e| e.to_string())?;
     }
     Ok(exists)
 }
 
-/// Move geometries in a cell by a delta offset (via undoable command).
+/// Move shapes in a cell by a delta offset (via undoable command).
 #[tauri::command]
 fn move_geometries(
     state: State<AppState>,
     cell_id: String,
-    indices: Vec<usize>,
+    shape_ids: Vec<String>,
     dx: f64,
     dy: f64,
 ) -> Result<(), String> {
     let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let shape_ids = parse_shape_ids(&shape_ids)?;
     let mut db = state.database.lock().map_err(|e| e.to_string())?;
-    let cmd = Box::new(MoveGeometryCommand::new(id, indices, Point::new(dx, dy)));
-    db.execute_command(cmd);
+    let delta = db.units().point_from_um(dx, dy);
+    let cmd = Box::new(MoveGeometryCommand::new(id, shape_ids, delta));
+    db.execute_command(cmd).map_err(|e| e.to_string())?;
     Ok(())
 }
 
-/// Get all geometries in a cell as JSON (for canvas rendering).
+fn parse_shape_ids(shape_ids: &[String]) -> Result<Vec<ShapeId>, String> {
+    shape_ids
+        .iter()
+        .map(|s| s.parse::<ShapeId>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())
+}
+
+/// Rotate shapes counter-clockwise about a pivot (via undoable command).
+#[tauri::command]
+fn rotate_geometries(
+    state: State<AppState>,
+    cell_id: String,
+    shape_ids: Vec<String>,
+    pivot_x: f64,
+    pivot_y: f64,
+    degrees: f64,
+) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let shape_ids = parse_shape_ids(&shape_ids)?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let pivot = db.units().point_from_um(pivot_x, pivot_y);
+    let cmd = Box::new(TransformGeometryCommand::rotate(id, shape_ids, pivot, degrees));
+    db.execute_command(cmd).map_err(|e| e.to_string())
+}
+
+/// Mirror shapes about the horizontal (`mirror_x`) or vertical line
+/// through a pivot (via undoable command).
+#[tauri::command]
+fn mirror_geometries(
+    state: State<AppState>,
+    cell_id: String,
+    shape_ids: Vec<String>,
+    pivot_x: f64,
+    pivot_y: f64,
+    mirror_x: bool,
+) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let shape_ids = parse_shape_ids(&shape_ids)?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let pivot = db.units().point_from_um(pivot_x, pivot_y);
+    let cmd = Box::new(TransformGeometryCommand::mirror(id, shape_ids, pivot, mirror_x));
+    db.execute_command(cmd).map_err(|e| e.to_string())
+}
+
+/// Scale shapes about a pivot (via undoable command).
+#[tauri::command]
+fn scale_geometries(
+    state: State<AppState>,
+    cell_id: String,
+    shape_ids: Vec<String>,
+    pivot_x: f64,
+    pivot_y: f64,
+    factor: f64,
+) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let shape_ids = parse_shape_ids(&shape_ids)?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let pivot = db.units().point_from_um(pivot_x, pivot_y);
+    let cmd = Box::new(TransformGeometryCommand::scale(id, shape_ids, pivot, factor));
+    db.execute_command(cmd).map_err(|e| e.to_string())
+}
+
+/// Copy shapes by an offset (via undoable command). Returns the copies' IDs.
+#[tauri::command]
+fn duplicate_geometries(
+    state: State<AppState>,
+    cell_id: String,
+    shape_ids: Vec<String>,
+    dx: f64,
+    dy: f64,
+) -> Result<Vec<String>, String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let shape_ids = parse_shape_ids(&shape_ids)?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let delta = db.units().point_from_um(dx, dy);
+    let cmd = DuplicateGeometryCommand::duplicate(id, shape_ids, delta);
+    let copies = cmd.copy_ids().iter().map(|c| c.to_string()).collect();
+    db.execute_command(Box::new(cmd)).map_err(|e| e.to_string())?;
+    Ok(copies)
+}
+
+/// Copy shapes into a columns × rows array with the given pitch, the
+/// originals being the first element (via undoable command). Returns the
+/// copies' IDs.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn array_copy_geometries(
+    state: State<AppState>,
+    cell_id: String,
+    shape_ids: Vec<String>,
+    columns: usize,
+    rows: usize,
+    pitch_x: f64,
+    pitch_y: f64,
+) -> Result<Vec<String>, String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let shape_ids = parse_shape_ids(&shape_ids)?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let pitch = db.units().point_from_um(pitch_x, pitch_y);
+    let cmd = DuplicateGeometryCommand::array(id, shape_ids, columns, rows, pitch);
+    let copies = cmd.copy_ids().iter().map(|c| c.to_string()).collect();
+    db.execute_command(Box::new(cmd)).map_err(|e| e.to_string())?;
+    Ok(copies)
+}
+
+/// Align shapes to a common edge or center, or distribute them evenly
+/// (via undoable command).
+#[tauri::command]
+fn align_geometries(
+    state: State<AppState>,
+    cell_id: String,
+    shape_ids: Vec<String>,
+    alignment: Alignment,
+) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let shape_ids = parse_shape_ids(&shape_ids)?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let cmd = Box::new(AlignGeometryCommand::new(id, shape_ids, alignment));
+    db.execute_command(cmd).map_err(|e| e.to_string())
+}
+
+/// Move one vertex or edge of a rectangle, polygon or path (via undoable
+/// command).
+#[tauri::command]
+fn stretch_geometry(
+    state: State<AppState>,
+    cell_id: String,
+    shape_id: String,
+    target: StretchTarget,
+    dx: f64,
+    dy: f64,
+) -> Result<(), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let shape_id: ShapeId = shape_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let mut db = state.database.lock().map_err(|e| e.to_string())?;
+    let delta = db.units().point_from_um(dx, dy);
+    let cmd = Box::new(StretchGeometryCommand::new(id, shape_id, target, delta));
+    db.execute_command(cmd).map_err(|e| e.to_string())
+}
+
+/// Get all geometries in a cell (for canvas rendering), in micrometers.
 #[tauri::command]
 fn get_cell_geometries(
     state: State<AppState>,
     cell_id: String,
-) -> Result<serde_json::Value, String> {
+) -> Result<Vec<FlatGeometry>, String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let db = state.database.lock().map_err(|e| e.to_string())?;
+    let u = db.units();
+    let cell = db.get_cell(&id).ok_or("Cell not found")?;
+    Ok(cell
+        .shapes
+        .iter()
+        .map(|s| FlatGeometry::from_primitive(&s.geometry, &u).with_shape(s))
+        .collect())
+}
+
+/// Get the geometries of a cell whose bounding boxes touch a region (for
+/// viewport culling), in drawing order and in micrometers. Subcells are not
+/// included.
+#[tauri::command]
+fn get_cell_geometries_in_region(
+    state: State<AppState>,
+    cell_id: String,
+    x1: f64,
+    y1: f64,
+    x2: f64,
+    y2: f64,
+    layer: Option<u32>,
+) -> Result<Vec<FlatGeometry>, String> {
     let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
     let db = state.database.lock().map_err(|e| e.to_string())?;
+    let u = db.units();
     let cell = db.get_cell(&id).ok_or("Cell not found")?;
-    serde_json::to_value(&cell.geometries).map_err(|e| e.to_string())
+    let region = BBox::from_points(&[u.point_from_um(x1, y1), u.point_from_um(x2, y2)])
+        .expect("two points");
+    let hits: HashSet<ShapeId> = db.query_region(&id, &region, layer).into_iter().collect();
+    Ok(cell
+        .shapes
+        .iter()
+        .filter(|s| hits.contains(&s.id))
+        .map(|s| FlatGeometry::from_primitive(&s.geometry, &u).with_shape(s))
+        .collect())
+}
+
+/// Find the shapes touching a region anywhere below a cell, without
+/// flattening it. Geometries are placed in the cell's coordinates (µm);
+/// `min_size` (µm) skips shapes and instances too small to matter at the
+/// current zoom.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn query_hierarchy_region(
+    state: State<AppState>,
+    cell_id: String,
+    x1: f64,
+    y1: f64,
+    x2: f64,
+    y2: f64,
+    layer: Option<u32>,
+    max_depth: Option<usize>,
+    min_size: Option<f64>,
+) -> Result<Vec<FlatGeometry>, String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let db = state.database.lock().map_err(|e| e.to_string())?;
+    let u = db.units();
+    let region = BBox::from_points(&[u.point_from_um(x1, y1), u.point_from_um(x2, y2)])
+        .expect("two points");
+    let query = RegionQuery {
+        layer,
+        max_depth,
+        min_size: min_size.map(|s| u.to_dbu(s)).unwrap_or(0),
+    };
+    let hits = db.query_hierarchy(&id, &region, &query).map_err(|e| e.to_string())?;
+    Ok(hits
+        .iter()
+        .filter_map(|hit| {
+            let flat = FlatGeometry::from_primitive(&hit.geometry(&db)?, &u);
+            Some(if hit.path.is_empty() { flat.with_id(hit.shape) } else { flat })
+        })
+        .collect())
+}
+
+// ── Hit testing and snapping ─────────────────────────────────────
+//
+// Tolerances are given in screen pixels and converted with the current
+// viewport zoom; results are in micrometers.
+
+/// Parse a cell ID and a query point and convert a pixel tolerance to DBU.
+fn snap_query(
+    state: &State<AppState>,
+    db: &LayoutDatabase,
+    cell_id: &str,
+    x: f64,
+    y: f64,
+    tolerance_px: f64,
+) -> Result<(CellId, Point, i64), String> {
+    let id: CellId = cell_id.parse().map_err(|e: uuid::Error| e.to_string())?;
+    let zoom = state.viewport.lock().map_err(|e| e.to_string())?.zoom;
+    let u = db.units();
+    Ok((id, u.point_from_um(x, y), u.to_dbu(tolerance_px / zoom)))
+}
+
+/// Shapes of a cell under a point (exact, not by bounding box), topmost first.
+#[tauri::command]
+fn hit_test(
+    state: State<AppState>,
+    cell_id: String,
+    x: f64,
+    y: f64,
+    tolerance_px: f64,
+    layer: Option<u32>,
+) -> Result<Vec<String>, String> {
+    let db = state.database.lock().map_err(|e| e.to_string())?;
+    let (id, p, tol) = snap_query(&state, &db, &cell_id, x, y, tolerance_px)?;
+    Ok(db.hit_test(&id, &p, tol, layer).iter().map(|s| s.to_string()).collect())
+}
+
+#[derive(Serialize)]
+struct ShapeHit {
+    shape_id: String,
+    distance: f64,
+}
+
+/// The shape of a cell closest to a point, within the tolerance.
+#[tauri::command]
+fn nearest_shape(
+    state: State<AppState>,
+    cell_id: String,
+    x: f64,
+    y: f64,
+    tolerance_px: f64,
+    layer: Option<u32>,
+) -> Result<Option<ShapeHit>, String> {
+    let db = state.database.lock().map_err(|e| e.to_string())?;
+    let (id, p, tol) = snap_query(&state, &db, &cell_id, x, y, tolerance_px)?;
+    let u = db.units();
+    Ok(db.nearest_shape(&id, &p, tol, layer).map(|(shape, d)| ShapeHit {
+        shape_id: shape.to_string(),
+        distance: d / u.dbu_per_um,
+    }))
+}
+
+#[derive(Serialize)]
+struct FlatEdgeSnap {
+    shape_id: String,
+    start: FlatPoint,
+    end: FlatPoint,
+    point: FlatPoint,
+    distance: f64,
+}
+
+/// The closest point on any shape outline of a cell, within the tolerance.
+#[tauri::command]
+fn snap_to_edge(
+    state: State<AppState>,
+    cell_id: String,
+    x: f64,
+    y: f64,
+    tolerance_px: f64,
+    layer: Option<u32>,
+) -> Result<Option<FlatEdgeSnap>, String> {
+    let db = state.database.lock().map_err(|e| e.to_string())?;
+    let (id, p, tol) = snap_query(&state, &db, &cell_id, x, y, tolerance_px)?;
+    let u = db.units();
+    Ok(db.nearest_edge(&id, &p, tol, layer).map(|snap| FlatEdgeSnap {
+        shape_id: snap.shape.to_string(),
+        start: FlatPoint::from_point(&snap.start, &u),
+        end: FlatPoint::from_point(&snap.end, &u),
+        point: FlatPoint::from_point(&snap.point, &u),
+        distance: snap.distance / u.dbu_per_um,
+    }))
+}
+
+#[derive(Serialize)]
+struct FlatVertexSnap {
+    shape_id: String,
+    point: FlatPoint,
+    distance: f64,
+}
+
+/// The closest shape vertex of a cell, within the tolerance.
+#[tauri::command]
+fn snap_to_vertex(
+    state: State<AppState>,
+    cell_id: String,
+    x: f64,
+    y: f64,
+    tolerance_px: f64,
+    layer: Option<u32>,
+) -> Result<Option<FlatVertexSnap>, String> {
+    let db = state.database.lock().map_err(|e| e.to_string())?;
+    let (id, p, tol) = snap_query(&state, &db, &cell_id, x, y, tolerance_px)?;
+    let u = db.units();
+    Ok(db.nearest_vertex(&id, &p, tol, layer).map(|snap| FlatVertexSnap {
+        shape_id: snap.shape.to_string(),
+        point: FlatPoint::from_point(&snap.point, &u),
+        distance: snap.distance / u.dbu_per_um,
+    }))
 }
 
 // ── Geometry sync commands (Rust DB ↔ Frontend stores) ───────────
@@ -311,18 +1232,48 @@ fn get_cell_geometries(
 #[tauri::command]
 fn export_all_geometries(state: State<AppState>) -> Result<Vec<FlatGeometry>, String> {
     let db = state.database.lock().map_err(|e| e.to_string())?;
+    let u = db.units();
     let mut out = Vec::new();
     for cell in db.all_cells() {
-        for geom in &cell.geometries {
-            out.push(FlatGeometry::from_primitive(geom));
+        for shape in &cell.shapes {
+            out.push(FlatGeometry::from_primitive(&shape.geometry, &u).with_shape(shape));
         }
     }
     Ok(out)
 }
 
+/// Return the flattened geometries of every root cell, with instance and
+/// array placements expanded, for display only. Records that come from
+/// instances have no `id`.
+#[tauri::command]
+fn export_flattened_geometries(state: State<AppState>) -> Result<Vec<FlatGeometry>, String> {
+    let db = state.database.lock().map_err(|e| e.to_string())?;
+    let u = db.units();
+    let mut out = Vec::new();
+    for root in db.root_cells() {
+        let shapes = db
+            .flatten(&root, FlattenOptions::new())
+            .map_err(|e| e.to_string())?;
+        // Only the root's own shapes can be addressed by ID in the root.
+        let cell = db.get_cell(&root);
+        out.extend(shapes.map(|s| {
+            let flat = FlatGeometry::from_primitive(&s.geometry, &u);
+            match cell.and_then(|c| c.shape(&s.shape)).filter(|_| s.path.is_empty()) {
+                Some(shape) => flat.with_shape(shape),
+                None => flat,
+            }
+        }));
+    }
+    Ok(out)
+}
+
 /// A geometry record the TypeScript side can directly map to CanvasGeometry.
+/// All coordinates and widths are in micrometers.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct FlatGeometry {
+    /// Stable shape ID, where the shape can be addressed in the cell.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
     #[serde(rename = "type")]
     geom_type: String,
     #[serde(rename = "layerId")]
@@ -330,6 +1281,78 @@ struct FlatGeometry {
     points: Vec<FlatPoint>,
     #[serde(skip_serializing_if = "Option::is_none")]
     width: Option<f64>,
+    /// Path end style (paths only).
+    #[serde(rename = "endStyle", default, skip_serializing_if = "Option::is_none")]
+    end_style: Option<FlatEndStyle>,
+    /// Holes of a polygon, whose outer ring is `points` (polygons only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    holes: Option<Vec<Vec<FlatPoint>>>,
+    /// Exact outline of a path as drawn, or of a polygon with holes as a
+    /// single keyholed ring, for rendering (export only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    outline: Option<Vec<Vec<FlatPoint>>>,
+    /// String and placement of a text label (labels only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<FlatLabel>,
+    /// Properties of the stored shape, keyed by GDS-II attribute number.
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    properties: Properties,
+}
+
+/// A text label at the IPC boundary. Its anchor is the record's only point.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct FlatLabel {
+    text: String,
+    /// Counter-clockwise rotation in degrees, applied after any mirroring.
+    #[serde(default)]
+    rotation: f64,
+    /// Mirrored about the x axis.
+    #[serde(default)]
+    mirror: bool,
+    #[serde(default = "unit_magnification")]
+    magnification: f64,
+    #[serde(default)]
+    presentation: TextPresentation,
+}
+
+fn unit_magnification() -> f64 {
+    1.0
+}
+
+/// Path end style at the IPC boundary, with extensions in µm.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum FlatEndStyle {
+    Flush,
+    Round,
+    HalfWidth,
+    Custom { begin: f64, end: f64 },
+}
+
+impl FlatEndStyle {
+    fn from_end_style(s: PathEndStyle, u: &DbUnits) -> Self {
+        match s {
+            PathEndStyle::Flush => FlatEndStyle::Flush,
+            PathEndStyle::Round => FlatEndStyle::Round,
+            PathEndStyle::HalfWidth => FlatEndStyle::HalfWidth,
+            PathEndStyle::Custom { begin, end } => FlatEndStyle::Custom {
+                begin: u.to_um(begin),
+                end: u.to_um(end),
+            },
+        }
+    }
+
+    fn to_end_style(self, u: &DbUnits) -> PathEndStyle {
+        match self {
+            FlatEndStyle::Flush => PathEndStyle::Flush,
+            FlatEndStyle::Round => PathEndStyle::Round,
+            FlatEndStyle::HalfWidth => PathEndStyle::HalfWidth,
+            FlatEndStyle::Custom { begin, end } => PathEndStyle::Custom {
+                begin: u.to_dbu(begin),
+                end: u.to_dbu(end),
+            },
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -338,58 +1361,185 @@ struct FlatPoint {
     y: f64,
 }
 
+impl FlatPoint {
+    fn from_point(p: &Point, u: &DbUnits) -> Self {
+        let (x, y) = u.point_to_um(p);
+        FlatPoint { x, y }
+    }
+
+    fn to_point(&self, u: &DbUnits) -> Point {
+        u.point_from_um(self.x, self.y)
+    }
+}
+
+/// Array placement of an instance, with pitches in µm.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct InstanceArray {
+    columns: u32,
+    rows: u32,
+    column_pitch: [f64; 2],
+    row_pitch: [f64; 2],
+}
+
+impl InstanceArray {
+    fn to_array(self, u: &DbUnits) -> ArrayParams {
+        let [cx, cy] = self.column_pitch;
+        let [rx, ry] = self.row_pitch;
+        ArrayParams::new(self.columns, self.rows, u.point_from_um(cx, cy), u.point_from_um(rx, ry))
+    }
+}
+
 impl FlatGeometry {
-    fn from_primitive(p: &GeomPrimitive) -> Self {
+    fn from_primitive(p: &GeomPrimitive, u: &DbUnits) -> Self {
         match p {
             GeomPrimitive::Rect(r) => FlatGeometry {
+                id: None,
                 geom_type: "rect".into(),
                 layer_id: r.layer_id,
                 points: vec![
-                    FlatPoint { x: r.lower_left.x, y: r.lower_left.y },
-                    FlatPoint { x: r.upper_right.x, y: r.upper_right.y },
+                    FlatPoint::from_point(&r.lower_left, u),
+                    FlatPoint::from_point(&r.upper_right, u),
                 ],
                 width: None,
+                end_style: None,
+                holes: None,
+                outline: None,
+                label: None,
+                properties: Properties::new(),
             },
             GeomPrimitive::Polygon(p) => FlatGeometry {
+                id: None,
                 geom_type: "polygon".into(),
                 layer_id: p.layer_id,
-                points: p.vertices.iter().map(|v| FlatPoint { x: v.x, y: v.y }).collect(),
+                points: p.vertices.iter().map(|v| FlatPoint::from_point(v, u)).collect(),
                 width: None,
+                end_style: None,
+                holes: (!p.holes.is_empty()).then(|| {
+                    p.holes
+                        .iter()
+                        .map(|h| h.iter().map(|v| FlatPoint::from_point(v, u)).collect())
+                        .collect()
+                }),
+                // Canvases that draw single rings can draw the keyholed form.
+                outline: (!p.holes.is_empty()).then(|| {
+                    let ring = p.keyholed().vertices;
+                    vec![ring.iter().map(|v| FlatPoint::from_point(v, u)).collect()]
+                }),
+                label: None,
+                properties: Properties::new(),
             },
             GeomPrimitive::Path(p) => FlatGeometry {
+                id: None,
                 geom_type: "path".into(),
                 layer_id: p.layer_id,
-                points: p.points.iter().map(|v| FlatPoint { x: v.x, y: v.y }).collect(),
-                width: Some(p.width),
+                points: p.points.iter().map(|v| FlatPoint::from_point(v, u)).collect(),
+                width: Some(u.to_um(p.width)),
+                end_style: Some(FlatEndStyle::from_end_style(p.end_style, u)),
+                holes: None,
+                outline: Some(
+                    p.to_polygons()
+                        .iter()
+                        .map(|poly| {
+                            let ring = poly.keyholed().vertices;
+                            ring.iter().map(|v| FlatPoint::from_point(v, u)).collect()
+                        })
+                        .collect(),
+                ),
+                label: None,
+                properties: Properties::new(),
             },
             GeomPrimitive::Via(v) => FlatGeometry {
+                id: None,
                 geom_type: "via".into(),
                 layer_id: v.cut_layer,
-                points: vec![FlatPoint { x: v.position.x, y: v.position.y }],
-                width: Some(v.width),
+                points: vec![FlatPoint::from_point(&v.position, u)],
+                width: Some(u.to_um(v.width)),
+                end_style: None,
+                holes: None,
+                outline: None,
+                label: None,
+                properties: Properties::new(),
+            },
+            GeomPrimitive::Text(t) => FlatGeometry {
+                id: None,
+                geom_type: "text".into(),
+                layer_id: t.layer_id,
+                points: vec![FlatPoint::from_point(&t.position(), u)],
+                width: None,
+                end_style: None,
+                holes: None,
+                outline: None,
+                label: Some(FlatLabel {
+                    text: t.text.clone(),
+                    rotation: t.transform.rotation_degrees(),
+                    mirror: t.transform.mirror_x(),
+                    magnification: t.transform.magnification,
+                    presentation: t.presentation,
+                }),
+                properties: Properties::new(),
             },
         }
     }
 
-    fn to_primitive(&self) -> Option<GeomPrimitive> {
+    fn with_id(mut self, id: ShapeId) -> Self {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    /// Address the record to a stored shape, carrying its properties.
+    fn with_shape(mut self, shape: &Shape) -> Self {
+        self.properties = shape.properties.clone();
+        self.with_id(shape.id)
+    }
+
+    /// The stored shape, keeping the record's ID if it has a valid one.
+    fn to_shape(&self, u: &DbUnits) -> Option<Shape> {
+        let geometry = self.to_primitive(u)?;
+        let shape = Shape::new(geometry).with_properties(self.properties.clone());
+        Some(match self.id.as_deref().and_then(|id| id.parse().ok()) {
+            Some(id) => Shape { id, ..shape },
+            None => shape,
+        })
+    }
+
+    fn to_primitive(&self, u: &DbUnits) -> Option<GeomPrimitive> {
         match self.geom_type.as_str() {
             "rect" if self.points.len() >= 2 => {
-                let p1 = &self.points[0];
-                let p2 = &self.points[1];
+                let p1 = self.points[0].to_point(u);
+                let p2 = self.points[1].to_point(u);
                 Some(GeomPrimitive::Rect(Rect::new(self.layer_id, p1.x, p1.y, p2.x, p2.y)))
             }
             "polygon" if self.points.len() >= 3 => {
-                let pts: Vec<Point> = self.points.iter().map(|p| Point::new(p.x, p.y)).collect();
-                Some(GeomPrimitive::Polygon(Polygon::new(self.layer_id, pts)))
+                let pts: Vec<Point> = self.points.iter().map(|p| p.to_point(u)).collect();
+                let holes = self
+                    .holes
+                    .iter()
+                    .flatten()
+                    .map(|h| h.iter().map(|p| p.to_point(u)).collect())
+                    .collect();
+                Some(GeomPrimitive::Polygon(Polygon::with_holes(self.layer_id, pts, holes)))
             }
             "path" if self.points.len() >= 2 => {
-                let pts: Vec<Point> = self.points.iter().map(|p| Point::new(p.x, p.y)).collect();
-                Some(GeomPrimitive::Path(LayoutPath::new(self.layer_id, pts, self.width.unwrap_or(0.1))))
+                let pts: Vec<Point> = self.points.iter().map(|p| p.to_point(u)).collect();
+                let width = u.to_dbu(self.width.unwrap_or(0.1));
+                let end_style = self.end_style.map(|s| s.to_end_style(u)).unwrap_or_default();
+                let path = LayoutPath::new(self.layer_id, pts, width).with_end_style(end_style);
+                Some(GeomPrimitive::Path(path))
             }
             "via" if !self.points.is_empty() => {
-                let p = &self.points[0];
-                let w = self.width.unwrap_or(0.17);
-                Some(GeomPrimitive::Via(Via::new(self.layer_id, self.layer_id, self.layer_id, Point::new(p.x, p.y), w, w)))
+                let p = self.points[0].to_point(u);
+                let w = u.to_dbu(self.width.unwrap_or(0.17));
+                Some(GeomPrimitive::Via(Via::new(self.layer_id, self.layer_id, self.layer_id, p, w, w)))
+            }
+            "text" if !self.points.is_empty() => {
+                let label = self.label.as_ref()?;
+                let p = self.points[0].to_point(u);
+                let transform =
+                    Transform::from_parts(p, label.mirror, label.rotation, label.magnification);
+                let text = Text::new(self.layer_id, label.text.clone(), p)
+                    .with_transform(transform)
+                    .with_presentation(label.presentation);
+                Some(GeomPrimitive::Text(text))
             }
             _ => None,
         }
@@ -415,7 +1565,7 @@ fn import_all_geometries(
     let top_id = if let Some(id) = db.top_cell {
         // Clear existing geometries
         if let Some(cell) = db.get_cell_mut(&id) {
-            cell.geometries.clear();
+            cell.shapes.clear();
         }
         id
     } else {
@@ -423,14 +1573,26 @@ fn import_all_geometries(
         db.add_cell(cell)
     };
 
-    // Add geometries
+    // Add geometries. Records of shapes stored in other cells reach the top
+    // cell through its instances already, so they are left where they are.
+    let u = db.units();
+    let elsewhere: HashSet<ShapeId> = db
+        .all_cells()
+        .filter(|c| c.id != top_id)
+        .flat_map(|c| c.shapes.iter().map(|s| s.id))
+        .collect();
     if let Some(cell) = db.get_cell_mut(&top_id) {
         for fg in &geometries {
-            if let Some(prim) = fg.to_primitive() {
-                cell.geometries.push(prim);
+            if let Some(shape) = fg.to_shape(&u).filter(|s| !elsewhere.contains(&s.id)) {
+                cell.add_shape(shape);
             }
         }
     }
+    // The import bypasses the command history, so the journal has to start
+    // over from here.
+    if let Err(e) = db.checkpoint(false) {
+        log::warn!("Failed to write journal checkpoint: {}", e);
+    }
 
     Ok(ProjectInfo {
         name: db.name.clone(),
@@ -440,6 +1602,9 @@ fn import_all_geometries(
                 .map(|c| c.name.clone())
                 .unwrap_or_default()
         }),
+        warnings: Vec::new(),
+        recovery_pending: false,
+        recovery_stale: false,
     })
 }
 
@@ -473,22 +1638,62 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             get_project_info,
             create_cell,
+            rename_cell,
+            delete_cell,
             list_cells,
+            place_instance,
+            move_instance,
+            transform_instance,
+            set_instance_array,
+            delete_instance,
+            set_pin,
+            remove_pin,
+            get_properties,
+            set_property,
+            add_layer,
+            set_layer_visibility,
+            set_layer_color,
+            set_all_layers_visible,
             get_viewport,
             update_viewport,
             undo,
             redo,
+            begin_transaction,
+            commit_transaction,
+            rollback_transaction,
+            get_history,
+            poll_changes,
+            seal_history,
+            set_history_limits,
             open_gds_file,
             save_gds_file,
             save_project_json,
             open_project_json,
+            recovery_status,
+            recover_project,
+            discard_recovery,
             add_rect,
             add_polygon,
             add_path,
+            add_label,
             remove_geometry,
             move_geometries,
+            rotate_geometries,
+            mirror_geometries,
+            scale_geometries,
+            duplicate_geometries,
+            array_copy_geometries,
+            align_geometries,
+            stretch_geometry,
             get_cell_geometries,
+            get_cell_geometries_in_region,
+            query_hierarchy_region,
+            hit_test,
+            nearest_shape,
+            snap_to_edge,
+            snap_to_vertex,
             export_all_geometries,
+            export_flattened_geometries,
             import_all_geometries,
             get_current_file,
             set_current_file,
@@ -413,25 +413,41 @@ export function drawRect(
 }
 
 /**
- * Draw a polygon with fill, pattern overlay, stroke, and glow.
+ * Trace a polygon's outer ring and holes as closed subpaths.
+ */
+function tracePolygon(
+  ctx: CanvasRenderingContext2D,
+  screenPoints: { x: number; y: number }[],
+  holes: { x: number; y: number }[][],
+) {
+  ctx.beginPath();
+  for (const ring of [screenPoints, ...holes]) {
+    if (ring.length < 3) continue;
+    ctx.moveTo(ring[0].x, ring[0].y);
+    for (let i = 1; i < ring.length; i++) {
+      ctx.lineTo(ring[i].x, ring[i].y);
+    }
+    ctx.closePath();
+  }
+}
+
+/**
+ * Draw a polygon with fill, pattern overlay, stroke, and glow. Holes are
+ * left unfilled by the even-odd rule.
  */
 export function drawPolygon(
   ctx: CanvasRenderingContext2D,
   screenPoints: { x: number; y: number }[],
   style: RenderStyle,
   _zoom: number,
+  holes: { x: number; y: number }[][] = [],
 ) {
   if (screenPoints.length < 3) return;
 
   ctx.save();
 
   // Build path
-  ctx.beginPath();
-  ctx.moveTo(screenPoints[0].x, screenPoints[0].y);
-  for (let i = 1; i < screenPoints.length; i++) {
-    ctx.lineTo(screenPoints[i].x, screenPoints[i].y);
-  }
-  ctx.closePath();
+  tracePolygon(ctx, screenPoints, holes);
 
   // Glow
   if (style.glowColor) {
@@ -441,7 +457,7 @@ export function drawPolygon(
 
   // Fill
   ctx.fillStyle = style.fillColor;
-  ctx.fill();
+  ctx.fill("evenodd");
 
   ctx.shadowColor = "transparent";
   ctx.shadowBlur = 0;
@@ -449,7 +465,7 @@ export function drawPolygon(
   // Pattern overlay
   if (style.pattern) {
     ctx.save();
-    ctx.clip();
+    ctx.clip("evenodd");
     // Fill pattern over bounding area
     let minX = Infinity, maxX = -Infinity, minY = Infinity, maxY = -Infinity;
     for (const p of screenPoints) {
@@ -464,12 +480,7 @@ export function drawPolygon(
   }
 
   // Re-create path for stroke
-  ctx.beginPath();
-  ctx.moveTo(screenPoints[0].x, screenPoints[0].y);
-  for (let i = 1; i < screenPoints.length; i++) {
-    ctx.lineTo(screenPoints[i].x, screenPoints[i].y);
-  }
-  ctx.closePath();
+  tracePolygon(ctx, screenPoints, holes);
   ctx.strokeStyle = style.strokeColor;
   ctx.lineWidth = style.lineWidth;
   ctx.stroke();
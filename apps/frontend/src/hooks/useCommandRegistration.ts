@@ -26,8 +26,66 @@ import {
   importAllGeometries,
   setCurrentFile,
   getCurrentFile,
+  recoveryStatus,
+  recoverProject,
+  discardRecovery,
   type FlatGeometry,
 } from "../ipc/bridge";
+import type { CanvasGeometry } from "../stores/geometryStore";
+
+/** localStorage key of the last layout opened, checked for recovery on startup. */
+const LAST_LAYOUT_KEY = "opensilicon-last-layout";
+
+function askToRecover(path: string, stale: boolean): boolean {
+  const name = path.split(/[\\/]/).pop() ?? path;
+  const changed = stale
+    ? " The file has changed since, and recovering will replace those changes."
+    : "";
+  return confirm(
+    `"${name}" has unsaved edits from a session that did not close cleanly.${changed} Recover them?`,
+  );
+}
+
+/**
+ * Open a GDS-II file or JSON project in Rust and load its geometries into
+ * the frontend store. If the file has unsaved edits from an earlier session,
+ * `recover` says whether to restore them; the user is asked if it is unset.
+ */
+async function openLayout(path: string, recover?: boolean): Promise<void> {
+  const isGds = /\.(gds2?)$/i.test(path);
+  const terminal = useSimStore.getState().appendTerminalLine;
+
+  let info = isGds ? await openGdsFile(path) : await openProjectJson(path);
+  let recovered = false;
+  if (info.recovery_pending) {
+    if (recover ?? askToRecover(path, info.recovery_stale)) {
+      info = await recoverProject(path);
+      recovered = true;
+    } else {
+      info.warnings.push(...(await discardRecovery(path)));
+    }
+  }
+  for (const warning of info.warnings) {
+    terminal(`> Warning: ${warning}`);
+  }
+
+  const geoms = await exportAllGeometries();
+  useGeometryStore.getState().load(
+    geoms as CanvasGeometry[],
+    path.split(/[\\/]/).pop() ?? (isGds ? "GDS Design" : "Project"),
+  );
+  if (recovered) {
+    // The recovered edits are not in the file yet.
+    useGeometryStore.setState({ modified: true });
+  }
+  await setCurrentFile(path, isGds ? "gds" : "json");
+  localStorage.setItem(LAST_LAYOUT_KEY, path);
+  if (recovered) {
+    terminal(`> Recovered unsaved edits: ${path}`);
+  } else {
+    terminal(isGds ? `> Opened GDS: ${path}` : `> Opened project: ${path}`);
+  }
+}
 
 /**
  * Registers all built-in commands on mount.
@@ -137,26 +195,12 @@ export function useCommandRegistration() {
               const terminal = useSimStore.getState().appendTerminalLine;
 
               if (isGds) {
-                // Open in Rust → export flat geometries → load into frontend store
-                await openGdsFile(path);
-                const geoms = await exportAllGeometries();
-                useGeometryStore.getState().load(
-                  geoms as import("../stores/geometryStore").CanvasGeometry[],
-                  path.split(/[\\/]/).pop() ?? "GDS Design",
-                );
-                await setCurrentFile(path, "gds");
-                terminal(`> Opened GDS: ${path}`);
+                // Open in Rust → export geometries → load into frontend store
+                await openLayout(path);
               } else {
                 // JSON project — try Rust first, then fallback for browser-only JSON
                 try {
-                  await openProjectJson(path);
-                  const geoms = await exportAllGeometries();
-                  useGeometryStore.getState().load(
-                    geoms as import("../stores/geometryStore").CanvasGeometry[],
-                    path.split(/[\\/]/).pop() ?? "Project",
-                  );
-                  await setCurrentFile(path, "json");
-                  terminal(`> Opened project: ${path}`);
+                  await openLayout(path);
                 } catch {
                   // Fallback: browser FileReader for dev mode
                   terminal(`> Rust backend unavailable, using browser file reader.`);
@@ -1478,4 +1522,22 @@ export function useCommandRegistration() {
 
     registerCommands(commands);
   }, []); // eslint-disable-line react-hooks/exhaustive-deps
+
+  // Offer to recover the last layout if its session did not close cleanly.
+  useEffect(() => {
+    const path = localStorage.getItem(LAST_LAYOUT_KEY);
+    if (!path) return;
+    (async () => {
+      try {
+        const status = await recoveryStatus(path);
+        if (status !== "clean" && askToRecover(path, status === "stale")) {
+          await openLayout(path, true);
+        }
+      } catch (err) {
+        useSimStore.getState().appendTerminalLine(
+          `> Error recovering ${path}: ${err instanceof Error ? err.message : String(err)}`,
+        );
+      }
+    })();
+  }, []);
 }
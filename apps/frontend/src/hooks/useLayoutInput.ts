@@ -241,7 +241,9 @@ export function useLayoutInput(opts: UseLayoutInputOptions) {
           for (const sel of selectedItems) {
             const geom = updated[sel.geometryIndex];
             if (geom) {
-              geom.points = dragState.originalGeometries[sel.geometryIndex].points.map((p) => ({ x: p.x + dx, y: p.y + dy }));
+              const orig = dragState.originalGeometries[sel.geometryIndex];
+              geom.points = orig.points.map((p) => ({ x: p.x + dx, y: p.y + dy }));
+              geom.holes = orig.holes?.map((h) => h.map((p) => ({ x: p.x + dx, y: p.y + dy })));
             }
           }
           setDragPreview(updated);
@@ -401,7 +403,7 @@ export function useLayoutInput(opts: UseLayoutInputOptions) {
         const entries = selectedItems
           .map((s) => currentGeoms[s.geometryIndex])
           .filter(Boolean)
-          .map((g) => ({ type: g.type, layerId: g.layerId, points: g.points, width: g.width }));
+          .map((g) => ({ type: g.type, layerId: g.layerId, points: g.points, holes: g.holes, width: g.width }));
         useToolStore.getState().copyGeometries(entries);
       }
 
@@ -412,7 +414,7 @@ export function useLayoutInput(opts: UseLayoutInputOptions) {
         const entries = selectedItems
           .map((s) => currentGeoms[s.geometryIndex])
           .filter(Boolean)
-          .map((g) => ({ type: g.type, layerId: g.layerId, points: g.points, width: g.width }));
+          .map((g) => ({ type: g.type, layerId: g.layerId, points: g.points, holes: g.holes, width: g.width }));
         useToolStore.getState().copyGeometries(entries);
         removeGeometries(selectedItems.map((s) => s.geometryIndex));
         clearSelection();
@@ -427,6 +429,7 @@ export function useLayoutInput(opts: UseLayoutInputOptions) {
           const newGeoms: CanvasGeometry[] = pasted.map((entry) => ({
             type: entry.type, layerId: entry.layerId,
             points: entry.points.map((p) => ({ x: p.x + offset, y: p.y + offset })),
+            holes: entry.holes?.map((h) => h.map((p) => ({ x: p.x + offset, y: p.y + offset }))),
             width: entry.width,
           }));
           const baseIndex = geometriesRef.current.length;
@@ -476,6 +479,7 @@ export function useLayoutInput(opts: UseLayoutInputOptions) {
             .map((g) => ({
               ...g, id: undefined as unknown as string,
               points: g.points.map((p) => ({ x: p.x + dupOffset, y: p.y + dupOffset })),
+              holes: g.holes?.map((h) => h.map((p) => ({ x: p.x + dupOffset, y: p.y + dupOffset }))),
             }));
           const baseIndex = currentGeoms.length;
           commitGeometries((prev) => [...prev, ...newGeoms]);
@@ -255,7 +255,8 @@ function renderGeometry(
 
   if (geom.type === "polygon" && geom.points.length >= 3) {
     const screenPoints = geom.points.map((p) => ({ x: toSX(p.x), y: toSY(p.y) }));
-    drawPolygon(ctx, screenPoints, style, vp.zoom);
+    const screenHoles = geom.holes?.map((h) => h.map((p) => ({ x: toSX(p.x), y: toSY(p.y) })));
+    drawPolygon(ctx, screenPoints, style, vp.zoom, screenHoles);
     if (isSelected) drawSelectionHandles(ctx, screenPoints, layer.color);
   }
 
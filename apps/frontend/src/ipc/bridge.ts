@@ -62,6 +62,18 @@ export interface ProjectInfo {
   name: string;
   cell_count: number;
   top_cell: string | null;
+  /** Non-fatal problems found while loading the file. */
+  warnings: string[];
+  /**
+   * The opened file has unsaved edits from an earlier session. Follow up
+   * with `recoverProject` or `discardRecovery`.
+   */
+  recovery_pending: boolean;
+  /**
+   * The pending edits were made to an earlier version of the file, which
+   * has changed since; recovering them discards those changes.
+   */
+  recovery_stale: boolean;
 }
 
 export async function getProjectInfo(): Promise<ProjectInfo> {
@@ -75,6 +87,8 @@ export interface CellInfo {
   name: string;
   geometry_count: number;
   instance_count: number;
+  /** Hierarchical extents [x1, y1, x2, y2] in µm, including subcells. */
+  bbox: [number, number, number, number] | null;
 }
 
 export async function createCell(name: string): Promise<string> {
@@ -85,6 +99,182 @@ export async function listCells(): Promise<CellInfo[]> {
   return invoke<CellInfo[]>("list_cells");
 }
 
+export async function renameCell(cellId: string, name: string): Promise<void> {
+  return invoke("rename_cell", { cell_id: cellId, name });
+}
+
+/** Deletes the cell and every instance of it. */
+export async function deleteCell(cellId: string): Promise<void> {
+  return invoke("delete_cell", { cell_id: cellId });
+}
+
+// ── Instances and pins ──
+
+export type Orientation = "R0" | "R90" | "R180" | "R270" | "MX" | "MY" | "MXR90" | "MYR90";
+
+/** A columns × rows array placement, with pitch vectors [x, y] in µm. */
+export interface InstanceArray {
+  columns: number;
+  rows: number;
+  column_pitch: [number, number];
+  row_pitch: [number, number];
+}
+
+/** Returns the new instance's ID. */
+export async function placeInstance(
+  cellId: string,
+  childId: string,
+  name: string,
+  x: number,
+  y: number,
+  orientation?: Orientation,
+  array?: InstanceArray
+): Promise<string> {
+  return invoke<string>("place_instance", {
+    cell_id: cellId,
+    child_id: childId,
+    name,
+    x,
+    y,
+    orientation,
+    array,
+  });
+}
+
+export async function moveInstance(
+  cellId: string,
+  instanceId: string,
+  dx: number,
+  dy: number
+): Promise<void> {
+  return invoke("move_instance", { cell_id: cellId, instance_id: instanceId, dx, dy });
+}
+
+export async function transformInstance(
+  cellId: string,
+  instanceId: string,
+  x: number,
+  y: number,
+  orientation: Orientation
+): Promise<void> {
+  return invoke("transform_instance", {
+    cell_id: cellId,
+    instance_id: instanceId,
+    x,
+    y,
+    orientation,
+  });
+}
+
+/** Makes the instance an array, or a single placement again with no `array`. */
+export async function setInstanceArray(
+  cellId: string,
+  instanceId: string,
+  array?: InstanceArray
+): Promise<void> {
+  return invoke("set_instance_array", { cell_id: cellId, instance_id: instanceId, array });
+}
+
+export async function deleteInstance(cellId: string, instanceId: string): Promise<void> {
+  return invoke("delete_instance", { cell_id: cellId, instance_id: instanceId });
+}
+
+export type PinDirection = "Input" | "Output" | "InOut" | "Power" | "Ground";
+
+/** Adds a rectangular pin, or replaces the pin named `replace`. */
+export async function setPin(
+  cellId: string,
+  name: string,
+  layer: number,
+  rect: [number, number, number, number],
+  direction: PinDirection,
+  replace?: string
+): Promise<void> {
+  return invoke("set_pin", {
+    cell_id: cellId,
+    replace,
+    name,
+    layer,
+    rect,
+    direction,
+  });
+}
+
+export async function removePin(cellId: string, name: string): Promise<void> {
+  return invoke("remove_pin", { cell_id: cellId, name });
+}
+
+// ── Properties ──
+
+/** Property values keyed by GDS-II attribute number. */
+export type Properties = Record<number, string>;
+
+/**
+ * Whose properties are meant: the cell itself, or one of its shapes or
+ * instances.
+ */
+export interface PropertyOwner {
+  shapeId?: string;
+  instanceId?: string;
+}
+
+export async function getProperties(
+  cellId: string,
+  owner: PropertyOwner = {}
+): Promise<Properties> {
+  return invoke<Properties>("get_properties", {
+    cell_id: cellId,
+    shape_id: owner.shapeId,
+    instance_id: owner.instanceId,
+  });
+}
+
+/** Sets a property, or removes it when `value` is undefined. */
+export async function setProperty(
+  cellId: string,
+  owner: PropertyOwner,
+  attribute: number,
+  value?: string
+): Promise<void> {
+  return invoke("set_property", {
+    cell_id: cellId,
+    shape_id: owner.shapeId,
+    instance_id: owner.instanceId,
+    attribute,
+    value,
+  });
+}
+
+// ── Layers ──
+
+export async function addLayer(
+  layer: number,
+  name: string,
+  gdsLayer: number,
+  gdsDatatype: number,
+  color?: [number, number, number]
+): Promise<void> {
+  return invoke("add_layer", {
+    layer,
+    name,
+    gds_layer: gdsLayer,
+    gds_datatype: gdsDatatype,
+    color,
+  });
+}
+
+export async function setLayerVisibility(layer: number, visible: boolean): Promise<void> {
+  return invoke("set_layer_visibility", { layer, visible });
+}
+
+export async function setLayerColor(layer: number, r: number, g: number, b: number): Promise<void> {
+  return invoke("set_layer_color", { layer, r, g, b });
+}
+
+export async function setAllLayersVisible(visible: boolean): Promise<void> {
+  return invoke("set_all_layers_visible", { visible });
+}
+
 // ── Viewport ──
 
 export interface Viewport {
@@ -121,6 +311,76 @@ export async function redo(): Promise<boolean> {
   return invoke<boolean>("redo");
 }
 
+export async function beginTransaction(description: string): Promise<void> {
+  return invoke<void>("begin_transaction", { description });
+}
+
+export async function commitTransaction(): Promise<void> {
+  return invoke<void>("commit_transaction");
+}
+
+export async function rollbackTransaction(): Promise<void> {
+  return invoke<void>("rollback_transaction");
+}
+
+/** Undo and redo entry descriptions, the next one to apply first. */
+export interface HistoryInfo {
+  undo: string[];
+  redo: string[];
+}
+
+export async function getHistory(): Promise<HistoryInfo> {
+  return invoke<HistoryInfo>("get_history");
+}
+
+/** Start a new undo entry for the next edit, e.g. when a drag ends. */
+export async function sealHistory(): Promise<void> {
+  return invoke<void>("seal_history");
+}
+
+/** Limit the undo history; `null` removes a limit. */
+export async function setHistoryLimits(
+  maxEntries: number | null,
+  maxBytes: number | null
+): Promise<void> {
+  return invoke<void>("set_history_limits", {
+    max_entries: maxEntries,
+    max_bytes: maxBytes,
+  });
+}
+
+// ── Change notification ──
+
+export type ChangeKind =
+  | "cell_added"
+  | "cell_removed"
+  | "cell_changed"
+  | "shapes_added"
+  | "shapes_removed"
+  | "shapes_modified"
+  | "instances_changed"
+  | "layers_changed"
+  /** The whole database was replaced; reload everything. */
+  | "reset";
+
+export interface ChangeInfo {
+  kind: ChangeKind;
+  cell_id: string | null;
+  /** The shapes or instances concerned. */
+  ids: string[];
+  layers: number[];
+  /** Affected area `[x1, y1, x2, y2]` in µm, in the cell's coordinates. */
+  bbox: [number, number, number, number] | null;
+}
+
+/**
+ * Database changes since the last call, oldest first. Changes are recorded
+ * from the first call on, which returns a single `reset`.
+ */
+export async function pollChanges(): Promise<ChangeInfo[]> {
+  return invoke<ChangeInfo[]>("poll_changes");
+}
+
 // ── File I/O ──
 
 export async function openGdsFile(path: string): Promise<ProjectInfo> {
@@ -139,6 +399,36 @@ export async function openProjectJson(path: string): Promise<ProjectInfo> {
   return invoke<ProjectInfo>("open_project_json", { path });
 }
 
+// ── Crash recovery ──
+//
+// Opening a file with unsaved edits from an earlier session reports
+// `recovery_pending` and leaves them on disk; follow up with
+// `recoverProject` or `discardRecovery`.
+
+/** Journaled edits found next to a project file. */
+export type RecoveryStatus = "clean" | "pending" | "stale";
+
+/**
+ * Whether `path` has edits from an earlier session that were never saved
+ * ("pending"), and whether the file changed since they were made ("stale").
+ */
+export async function recoveryStatus(path: string): Promise<RecoveryStatus> {
+  return invoke<RecoveryStatus>("recovery_status", { path });
+}
+
+/** Restore the unsaved edits of an earlier session, undo history included. */
+export async function recoverProject(path: string): Promise<ProjectInfo> {
+  return invoke<ProjectInfo>("recover_project", { path });
+}
+
+/**
+ * Throw away the unsaved edits of an earlier session and journal the project
+ * as currently open, which must be `path`. Returns any journaling warnings.
+ */
+export async function discardRecovery(path: string): Promise<string[]> {
+  return invoke<string[]>("discard_recovery", { path });
+}
+
 // ── Geometry ──
 
 export async function addRect(
@@ -148,8 +438,8 @@ export async function addRect(
   y1: number,
   x2: number,
   y2: number
-): Promise<number> {
-  return invoke<number>("add_rect", {
+): Promise<string> {
+  return invoke<string>("add_rect", {
     cell_id: cellId,
     layer,
     x1,
@@ -163,47 +453,337 @@ export async function addPolygon(
   cellId: string,
   layer: number,
   vertices: [number, number][]
-): Promise<number> {
-  return invoke<number>("add_polygon", {
+): Promise<string> {
+  return invoke<string>("add_polygon", {
     cell_id: cellId,
     layer,
     vertices,
   });
 }
 
+/**
+ * Path end style — matches the Rust FlatEndStyle enum.
+ * Custom extensions are in micrometers.
+ */
+export type PathEndStyle =
+  | { kind: "flush" }
+  | { kind: "round" }
+  | { kind: "half_width" }
+  | { kind: "custom"; begin: number; end: number };
+
 export async function addPath(
   cellId: string,
   layer: number,
   points: [number, number][],
-  width: number
-): Promise<number> {
-  return invoke<number>("add_path", {
+  width: number,
+  endStyle?: PathEndStyle
+): Promise<string> {
+  return invoke<string>("add_path", {
     cell_id: cellId,
     layer,
     points,
     width,
+    end_style: endStyle,
+  });
+}
+
+/** Font (0–3) and justification of a text label — matches the Rust TextPresentation. */
+export interface TextPresentation {
+  font: number;
+  horizontal: "Left" | "Center" | "Right";
+  vertical: "Top" | "Middle" | "Bottom";
+}
+
+/** A text label's string and placement about its anchor point. */
+export interface Label {
+  text: string;
+  /** Counter-clockwise rotation in degrees, applied after any mirroring. */
+  rotation?: number;
+  /** Mirrored about the x axis. */
+  mirror?: boolean;
+  magnification?: number;
+  presentation?: TextPresentation;
+}
+
+export async function addLabel(
+  cellId: string,
+  layer: number,
+  x: number,
+  y: number,
+  label: Label
+): Promise<string> {
+  return invoke<string>("add_label", {
+    cell_id: cellId,
+    layer,
+    x,
+    y,
+    label,
   });
 }
 
 export async function removeGeometry(
   cellId: string,
-  index: number
+  shapeId: string
 ): Promise<boolean> {
   return invoke<boolean>("remove_geometry", {
     cell_id: cellId,
-    index,
+    shape_id: shapeId,
+  });
+}
+
+export async function moveGeometries(
+  cellId: string,
+  shapeIds: string[],
+  dx: number,
+  dy: number
+): Promise<void> {
+  return invoke("move_geometries", {
+    cell_id: cellId,
+    shape_ids: shapeIds,
+    dx,
+    dy,
+  });
+}
+
+export async function rotateGeometries(
+  cellId: string,
+  shapeIds: string[],
+  pivotX: number,
+  pivotY: number,
+  degrees: number
+): Promise<void> {
+  return invoke("rotate_geometries", {
+    cell_id: cellId,
+    shape_ids: shapeIds,
+    pivot_x: pivotX,
+    pivot_y: pivotY,
+    degrees,
+  });
+}
+
+/** Mirror about the horizontal line through the pivot if `mirrorX`, else the vertical one. */
+export async function mirrorGeometries(
+  cellId: string,
+  shapeIds: string[],
+  pivotX: number,
+  pivotY: number,
+  mirrorX: boolean
+): Promise<void> {
+  return invoke("mirror_geometries", {
+    cell_id: cellId,
+    shape_ids: shapeIds,
+    pivot_x: pivotX,
+    pivot_y: pivotY,
+    mirror_x: mirrorX,
+  });
+}
+
+export async function scaleGeometries(
+  cellId: string,
+  shapeIds: string[],
+  pivotX: number,
+  pivotY: number,
+  factor: number
+): Promise<void> {
+  return invoke("scale_geometries", {
+    cell_id: cellId,
+    shape_ids: shapeIds,
+    pivot_x: pivotX,
+    pivot_y: pivotY,
+    factor,
   });
 }
 
-export interface GeometryData {
-  Rect?: { layer_id: number; lower_left: { x: number; y: number }; upper_right: { x: number; y: number } };
-  Polygon?: { layer_id: number; vertices: { x: number; y: number }[] };
-  Path?: { layer_id: number; points: { x: number; y: number }[]; width: number };
-  Via?: { bottom_layer: number; top_layer: number; cut_layer: number; position: { x: number; y: number }; width: number; height: number };
+/** Returns the IDs of the copies. */
+export async function duplicateGeometries(
+  cellId: string,
+  shapeIds: string[],
+  dx: number,
+  dy: number
+): Promise<string[]> {
+  return invoke<string[]>("duplicate_geometries", {
+    cell_id: cellId,
+    shape_ids: shapeIds,
+    dx,
+    dy,
+  });
 }
 
-export async function getCellGeometries(cellId: string): Promise<GeometryData[]> {
-  return invoke<GeometryData[]>("get_cell_geometries", { cell_id: cellId });
+/** Returns the IDs of the copies; the originals are the first array element. */
+export async function arrayCopyGeometries(
+  cellId: string,
+  shapeIds: string[],
+  columns: number,
+  rows: number,
+  pitchX: number,
+  pitchY: number
+): Promise<string[]> {
+  return invoke<string[]>("array_copy_geometries", {
+    cell_id: cellId,
+    shape_ids: shapeIds,
+    columns,
+    rows,
+    pitch_x: pitchX,
+    pitch_y: pitchY,
+  });
+}
+
+export type Alignment =
+  | "left"
+  | "right"
+  | "bottom"
+  | "top"
+  | "center_x"
+  | "center_y"
+  | "distribute_x"
+  | "distribute_y";
+
+export async function alignGeometries(
+  cellId: string,
+  shapeIds: string[],
+  alignment: Alignment
+): Promise<void> {
+  return invoke("align_geometries", {
+    cell_id: cellId,
+    shape_ids: shapeIds,
+    alignment,
+  });
+}
+
+/** Vertex or edge index; edge `i` runs from vertex `i` to the next. */
+export type StretchTarget = { vertex: number } | { edge: number };
+
+export async function stretchGeometry(
+  cellId: string,
+  shapeId: string,
+  target: StretchTarget,
+  dx: number,
+  dy: number
+): Promise<void> {
+  return invoke("stretch_geometry", {
+    cell_id: cellId,
+    shape_id: shapeId,
+    target,
+    dx,
+    dy,
+  });
+}
+
+export async function getCellGeometries(cellId: string): Promise<FlatGeometry[]> {
+  return invoke<FlatGeometry[]>("get_cell_geometries", { cell_id: cellId });
+}
+
+/**
+ * Geometries of a cell whose bounding boxes touch a region (µm), in drawing
+ * order, looked up in the cell's spatial index. Subcells are not included.
+ */
+export async function getCellGeometriesInRegion(
+  cellId: string,
+  x1: number,
+  y1: number,
+  x2: number,
+  y2: number,
+  layer?: number
+): Promise<FlatGeometry[]> {
+  return invoke<FlatGeometry[]>("get_cell_geometries_in_region", {
+    cell_id: cellId,
+    x1,
+    y1,
+    x2,
+    y2,
+    layer,
+  });
+}
+
+/**
+ * Shapes touching a region (µm) anywhere below a cell, placed in the cell's
+ * coordinates, without flattening. `minSize` (µm) skips shapes and
+ * instances too small to draw at the current zoom.
+ */
+export async function queryHierarchyRegion(
+  cellId: string,
+  x1: number,
+  y1: number,
+  x2: number,
+  y2: number,
+  options: { layer?: number; maxDepth?: number; minSize?: number } = {}
+): Promise<FlatGeometry[]> {
+  return invoke<FlatGeometry[]>("query_hierarchy_region", {
+    cell_id: cellId,
+    x1,
+    y1,
+    x2,
+    y2,
+    layer: options.layer,
+    max_depth: options.maxDepth,
+    min_size: options.minSize,
+  });
+}
+
+// ── Hit testing and snapping ──
+// Exact tests against the cell's Rust spatial index. Tolerances are in
+// screen pixels (converted with the synced viewport zoom); results in µm.
+// Not used by the layout canvas yet: it tests its own store until its edits
+// go through the database (see utils/layoutHitTesting.ts).
+
+export interface ShapeHit {
+  shape_id: string;
+  distance: number;
+}
+
+export interface EdgeSnap {
+  shape_id: string;
+  start: { x: number; y: number };
+  end: { x: number; y: number };
+  point: { x: number; y: number };
+  distance: number;
+}
+
+export interface VertexSnap {
+  shape_id: string;
+  point: { x: number; y: number };
+  distance: number;
+}
+
+/** Shapes under a point, topmost first. */
+export async function hitTest(
+  cellId: string,
+  x: number,
+  y: number,
+  tolerancePx: number,
+  layer?: number
+): Promise<string[]> {
+  return invoke<string[]>("hit_test", { cell_id: cellId, x, y, tolerance_px: tolerancePx, layer });
+}
+
+export async function nearestShape(
+  cellId: string,
+  x: number,
+  y: number,
+  tolerancePx: number,
+  layer?: number
+): Promise<ShapeHit | null> {
+  return invoke<ShapeHit | null>("nearest_shape", { cell_id: cellId, x, y, tolerance_px: tolerancePx, layer });
+}
+
+export async function snapToEdge(
+  cellId: string,
+  x: number,
+  y: number,
+  tolerancePx: number,
+  layer?: number
+): Promise<EdgeSnap | null> {
+  return invoke<EdgeSnap | null>("snap_to_edge", { cell_id: cellId, x, y, tolerance_px: tolerancePx, layer });
+}
+
+export async function snapToVertex(
+  cellId: string,
+  x: number,
+  y: number,
+  tolerancePx: number,
+  layer?: number
+): Promise<VertexSnap | null> {
+  return invoke<VertexSnap | null>("snap_to_vertex", { cell_id: cellId, x, y, tolerance_px: tolerancePx, layer });
 }
 
 // ── Geometry sync (Rust DB ↔ Frontend stores) ──
@@ -211,12 +791,29 @@ export async function getCellGeometries(cellId: string): Promise<GeometryData[]>
 /**
  * FlatGeometry — matches the Rust FlatGeometry struct.
  * Directly compatible with CanvasGeometry from geometryStore.
+ * Coordinates and widths are in micrometers; the Rust side stores them as
+ * integer database units and converts at the IPC boundary.
  */
 export interface FlatGeometry {
+  /** Stable shape ID, where the shape can be addressed in its cell. */
+  id?: string;
   type: string;
   layerId: number;
   points: { x: number; y: number }[];
   width?: number;
+  /** Path end style (paths only). */
+  endStyle?: PathEndStyle;
+  /** Holes of a polygon, whose outer ring is `points` (polygons only). */
+  holes?: { x: number; y: number }[][];
+  /**
+   * Exact drawn outline of a path, or of a polygon with holes as a single
+   * keyholed ring, for rendering (export only).
+   */
+  outline?: { x: number; y: number }[][];
+  /** String and placement of a text label (labels only). */
+  label?: Label;
+  /** Properties of the stored shape, keyed by GDS-II attribute number. */
+  properties?: Properties;
 }
 
 /**
@@ -226,6 +823,15 @@ export async function exportAllGeometries(): Promise<FlatGeometry[]> {
   return invoke<FlatGeometry[]>("export_all_geometries");
 }
 
+/**
+ * Export the flattened geometries of every root cell, with instances and
+ * arrays expanded. For display only: records from instances have no `id`,
+ * so do not feed the result back through `importAllGeometries`.
+ */
+export async function exportFlattenedGeometries(): Promise<FlatGeometry[]> {
+  return invoke<FlatGeometry[]>("export_flattened_geometries");
+}
+
 /**
  * Import geometries from the frontend store into the Rust database.
  */
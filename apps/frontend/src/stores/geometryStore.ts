@@ -65,6 +65,8 @@ export interface CanvasGeometry {
   type: "rect" | "polygon" | "path" | "via" | "instance";
   layerId: number;
   points: { x: number; y: number }[];
+  /** Holes of a polygon, whose outer ring is `points` */
+  holes?: { x: number; y: number }[][];
   width?: number;
   /** Optional name for identification */
   name?: string;
@@ -159,6 +161,7 @@ function cloneGeometries(geoms: CanvasGeometry[]): CanvasGeometry[] {
   return geoms.map((g) => ({
     ...g,
     points: g.points.map((p) => ({ ...p })),
+    holes: g.holes?.map((h) => h.map((p) => ({ ...p }))),
     properties: g.properties ? { ...g.properties } : undefined,
   }));
 }
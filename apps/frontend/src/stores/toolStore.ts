@@ -49,6 +49,7 @@ export interface ClipboardEntry {
   type: "rect" | "polygon" | "path" | "via" | "instance";
   layerId: number;
   points: { x: number; y: number }[];
+  holes?: { x: number; y: number }[][];
   width?: number;
 }
 
@@ -202,7 +203,13 @@ export const useToolStore = create<ToolStoreState>((set, get) => ({
   // ── Clipboard ────────────────────────────────────────────────────
 
   copyGeometries: (entries) => {
-    set({ clipboard: entries.map((e) => ({ ...e, points: e.points.map((p) => ({ ...p })) })) });
+    set({
+      clipboard: entries.map((e) => ({
+        ...e,
+        points: e.points.map((p) => ({ ...p })),
+        holes: e.holes?.map((h) => h.map((p) => ({ ...p }))),
+      })),
+    });
   },
 
   paste: () => {
@@ -210,6 +217,7 @@ export const useToolStore = create<ToolStoreState>((set, get) => ({
     return get().clipboard.map((e) => ({
       ...e,
       points: e.points.map((p) => ({ ...p })),
+      holes: e.holes?.map((h) => h.map((p) => ({ ...p }))),
     }));
   },
 }));
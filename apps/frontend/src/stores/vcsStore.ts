@@ -153,7 +153,11 @@ function generateId(): string {
 }
 
 function cloneGeom(g: CanvasGeometry): CanvasGeometry {
-  return { ...g, points: g.points.map((p) => ({ ...p })) };
+  return {
+    ...g,
+    points: g.points.map((p) => ({ ...p })),
+    holes: g.holes?.map((h) => h.map((p) => ({ ...p }))),
+  };
 }
 
 function cloneGeometries(geoms: CanvasGeometry[]): CanvasGeometry[] {
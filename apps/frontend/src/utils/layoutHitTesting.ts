@@ -4,6 +4,11 @@
  *
  * Zero React / store dependencies — these are plain functions that
  * operate on CanvasGeometry / LayerDef arrays.
+ *
+ * The canvas edits its own geometry store and only syncs it to the Rust
+ * database on import/export, so selection still tests the store here
+ * rather than calling the Rust `hitTest` / `snapTo*` commands, which would
+ * see stale shapes. Replace these once edits go through the database.
  */
 
 import type { CanvasGeometry } from "../stores/geometryStore";
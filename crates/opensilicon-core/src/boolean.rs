@@ -0,0 +1,1036 @@
+//! Polygon boolean operations (AND / OR / NOT / XOR) on sets of primitives.
+//!
+//! The engine works directly on integer database units:
+//!
+//! 1. Every primitive is converted to one or more closed rings and every
+//!    non-horizontal ring edge becomes a segment carrying a winding
+//!    contribution for its operand.
+//! 2. Segments are split at all mutual intersections and touch points
+//!    (including the ends of horizontal edges) so that no two segments cross
+//!    in their interiors.
+//! 3. A sweep over horizontal slabs computes the winding number of both
+//!    operands on either side of every segment. Segments whose sides differ
+//!    in the result predicate are boundary edges; horizontal boundary edges
+//!    fall out of comparing consecutive slabs.
+//! 4. Boundary edges are chained into rings (outer rings counter-clockwise,
+//!    holes clockwise) and holes are attached to their enclosing outer ring
+//!    as [`Polygon::holes`].
+//!
+//! Shapes are filled with the non-zero winding rule, after each input ring has
+//! been normalized to counter-clockwise orientation. Intersections between
+//! Manhattan and 45° edges always land on the grid (given the usual even
+//! parity of 45° geometry) and are exact; other intersections are rounded to
+//! the nearest database unit.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::geometry::{Coord, GeomPrimitive, Point, Polygon};
+use crate::layer::LayerId;
+
+/// A boolean operation between operand A and operand B.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BooleanOp {
+    /// Area covered by both A and B.
+    And,
+    /// Area covered by A or B.
+    Or,
+    /// Area covered by A but not B.
+    Not,
+    /// Area covered by exactly one of A and B.
+    Xor,
+}
+
+impl BooleanOp {
+    fn evaluate(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            BooleanOp::And => in_a && in_b,
+            BooleanOp::Or => in_a || in_b,
+            BooleanOp::Not => in_a && !in_b,
+            BooleanOp::Xor => in_a != in_b,
+        }
+    }
+}
+
+/// Combine two sets of primitives and return the merged result on `layer`.
+pub fn boolean<'a, A, B>(a: A, b: B, op: BooleanOp, layer: LayerId) -> Vec<Polygon>
+where
+    A: IntoIterator<Item = &'a GeomPrimitive>,
+    B: IntoIterator<Item = &'a GeomPrimitive>,
+{
+    let mut segments = Vec::new();
+    for prim in a {
+        for region in primitive_regions(prim) {
+            push_region(&mut segments, &region, Operand::A);
+        }
+    }
+    for prim in b {
+        for region in primitive_regions(prim) {
+            push_region(&mut segments, &region, Operand::B);
+        }
+    }
+    to_polygons(group_rings(run(segments, op)), layer)
+}
+
+/// Merge a set of primitives into non-overlapping polygons on `layer`.
+pub fn merge<'a, I>(shapes: I, layer: LayerId) -> Vec<Polygon>
+where
+    I: IntoIterator<Item = &'a GeomPrimitive>,
+{
+    boolean(shapes, std::iter::empty(), BooleanOp::Or, layer)
+}
+
+/// Run a boolean operation between the shapes of `shapes` on `a_layers` and
+/// the shapes on `b_layers`, producing polygons on `out_layer`.
+pub fn layer_boolean(
+    shapes: &[GeomPrimitive],
+    a_layers: &[LayerId],
+    b_layers: &[LayerId],
+    op: BooleanOp,
+    out_layer: LayerId,
+) -> Vec<Polygon> {
+    boolean(
+        shapes.iter().filter(|g| a_layers.contains(&g.layer_id())),
+        shapes.iter().filter(|g| b_layers.contains(&g.layer_id())),
+        op,
+        out_layer,
+    )
+}
+
+/// A filled outer ring (counter-clockwise) with its holes (clockwise).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Region {
+    pub outer: Vec<Point>,
+    pub holes: Vec<Vec<Point>>,
+}
+
+impl Region {
+    /// Iterate over the outer ring and all hole rings.
+    pub fn rings(&self) -> impl Iterator<Item = &Vec<Point>> {
+        std::iter::once(&self.outer).chain(self.holes.iter())
+    }
+}
+
+/// Merge a set of primitives into regions, keeping holes separate.
+pub(crate) fn merged_regions<'a, I>(shapes: I) -> Vec<Region>
+where
+    I: IntoIterator<Item = &'a GeomPrimitive>,
+{
+    let mut segments = Vec::new();
+    for prim in shapes {
+        for region in primitive_regions(prim) {
+            push_region(&mut segments, &region, Operand::A);
+        }
+    }
+    group_rings(run(segments, BooleanOp::Or))
+}
+
+/// Merge possibly overlapping rings (of any orientation) into polygons.
+pub(crate) fn merge_rings(rings: &[Vec<Point>], layer: LayerId) -> Vec<Polygon> {
+    let mut segments = Vec::new();
+    for ring in rings {
+        push_ring(&mut segments, ring, Operand::A, false);
+    }
+    to_polygons(group_rings(run(segments, BooleanOp::Or)), layer)
+}
+
+/// Boolean operation between two sets of regions.
+pub(crate) fn region_boolean(a: &[Region], b: &[Region], op: BooleanOp, layer: LayerId) -> Vec<Polygon> {
+    let mut segments = Vec::new();
+    for (regions, operand) in [(a, Operand::A), (b, Operand::B)] {
+        for region in regions {
+            push_region(&mut segments, region, operand);
+        }
+    }
+    to_polygons(group_rings(run(segments, op)), layer)
+}
+
+// ── Input conversion ─────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    A,
+    B,
+}
+
+/// Regions describing the filled area of a primitive.
+fn primitive_regions(prim: &GeomPrimitive) -> Vec<Region> {
+    let solid = |outer| Region {
+        outer,
+        holes: Vec::new(),
+    };
+    match prim {
+        GeomPrimitive::Rect(r) => vec![solid(box_ring(r.lower_left, r.upper_right))],
+        GeomPrimitive::Polygon(p) if p.holes.is_empty() => vec![solid(p.vertices.clone())],
+        GeomPrimitive::Polygon(p) => resolve_holes(p),
+        GeomPrimitive::Path(p) => p.outline_rings().into_iter().map(solid).collect(),
+        GeomPrimitive::Via(v) => {
+            let bb = v.bbox();
+            vec![solid(box_ring(bb.min, bb.max))]
+        }
+        // Labels cover no area.
+        GeomPrimitive::Text(_) => Vec::new(),
+    }
+}
+
+/// Resolve a polygon with holes into clean regions: the outer ring is
+/// filled with the non-zero rule and everything covered by a hole is removed,
+/// so that misplaced or overlapping holes cannot add area.
+fn resolve_holes(p: &Polygon) -> Vec<Region> {
+    let mut segments = Vec::new();
+    push_ring(&mut segments, &p.vertices, Operand::A, false);
+    for hole in &p.holes {
+        push_ring(&mut segments, hole, Operand::B, false);
+    }
+    group_rings(run(segments, BooleanOp::Not))
+}
+
+fn box_ring(min: Point, max: Point) -> Vec<Point> {
+    vec![
+        min,
+        Point::new(max.x, min.y),
+        max,
+        Point::new(min.x, max.y),
+    ]
+}
+
+/// An edge piece with per-operand winding. Winding edges have
+/// `lo.y < hi.y`; horizontal edges (`lo.y == hi.y`, `lo.x < hi.x`) only take
+/// part in splitting and are dropped afterwards.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    lo: Point,
+    hi: Point,
+    wind_a: i32,
+    wind_b: i32,
+}
+
+impl Segment {
+    fn min_x(&self) -> Coord {
+        self.lo.x.min(self.hi.x)
+    }
+
+    fn max_x(&self) -> Coord {
+        self.lo.x.max(self.hi.x)
+    }
+
+    /// Exact x coordinate where the segment crosses the line `2y = y2`.
+    fn x_at_doubled_y(&self, y2: i128) -> Ratio {
+        let dy = (self.hi.y - self.lo.y) as i128;
+        let dx = (self.hi.x - self.lo.x) as i128;
+        Ratio::new(
+            2 * self.lo.x as i128 * dy + dx * (y2 - 2 * self.lo.y as i128),
+            2 * dy,
+        )
+    }
+
+    fn x_at(&self, y: Coord) -> Ratio {
+        self.x_at_doubled_y(2 * y as i128)
+    }
+
+    fn is_horizontal(&self) -> bool {
+        self.lo.y == self.hi.y
+    }
+}
+
+fn push_region(segments: &mut Vec<Segment>, region: &Region, operand: Operand) {
+    push_ring(segments, &region.outer, operand, false);
+    for hole in &region.holes {
+        push_ring(segments, hole, operand, true);
+    }
+}
+
+/// Add a ring's edges, normalized to counter-clockwise orientation (or
+/// clockwise for a hole, which subtracts from the enclosing ring).
+fn push_ring(segments: &mut Vec<Segment>, ring: &[Point], operand: Operand, hole: bool) {
+    if ring.len() < 3 {
+        return;
+    }
+    let sign = if (signed_area2(ring) < 0) != hole { -1 } else { 1 };
+    for i in 0..ring.len() {
+        let (p, q) = (ring[i], ring[(i + 1) % ring.len()]);
+        if p == q {
+            continue;
+        }
+        if p.y == q.y {
+            // Horizontal edges never cross a horizontal ray and carry no
+            // winding, but they still cut the segments they touch.
+            let (lo, hi) = if p.x < q.x { (p, q) } else { (q, p) };
+            segments.push(Segment { lo, hi, wind_a: 0, wind_b: 0 });
+            continue;
+        }
+        let (lo, hi, dir) = if p.y < q.y { (p, q, 1) } else { (q, p, -1) };
+        let wind = dir * sign;
+        let (wind_a, wind_b) = match operand {
+            Operand::A => (wind, 0),
+            Operand::B => (0, wind),
+        };
+        segments.push(Segment { lo, hi, wind_a, wind_b });
+    }
+}
+
+// ── Exact rational helper ────────────────────────────────────────────
+
+/// A rational number with a positive denominator, compared exactly.
+#[derive(Debug, Clone, Copy)]
+struct Ratio {
+    num: i128,
+    den: i128,
+}
+
+impl Ratio {
+    fn new(num: i128, den: i128) -> Self {
+        if den < 0 {
+            Self { num: -num, den: -den }
+        } else {
+            Self { num, den }
+        }
+    }
+
+    /// Round to the nearest integer (halves away from zero).
+    fn round(&self) -> Coord {
+        round_div(self.num, self.den) as Coord
+    }
+}
+
+impl PartialEq for Ratio {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ratio {}
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ratio {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.num * other.den).cmp(&(other.num * self.den))
+    }
+}
+
+fn round_div(num: i128, den: i128) -> i128 {
+    let (num, den) = if den < 0 { (-num, -den) } else { (num, den) };
+    if num >= 0 {
+        (2 * num + den) / (2 * den)
+    } else {
+        -((-2 * num + den) / (2 * den))
+    }
+}
+
+fn cross(ax: i128, ay: i128, bx: i128, by: i128) -> i128 {
+    ax * by - ay * bx
+}
+
+/// Twice the signed area of a ring (positive for counter-clockwise).
+pub(crate) fn signed_area2(ring: &[Point]) -> i128 {
+    let n = ring.len();
+    (0..n)
+        .map(|i| {
+            let (p, q) = (ring[i], ring[(i + 1) % n]);
+            p.x as i128 * q.y as i128 - q.x as i128 * p.y as i128
+        })
+        .sum()
+}
+
+// ── Segment splitting ────────────────────────────────────────────────
+
+/// Split segments at all mutual intersections. Repeats while rounded
+/// intersection points may have introduced new crossings.
+fn split_segments(mut segments: Vec<Segment>) -> Vec<Segment> {
+    for _ in 0..4 {
+        let (split, inexact) = split_pass(&segments);
+        segments = split;
+        if !inexact {
+            break;
+        }
+    }
+    merge_duplicates(segments)
+}
+
+fn split_pass(segments: &[Segment]) -> (Vec<Segment>, bool) {
+    let mut order: Vec<usize> = (0..segments.len()).collect();
+    order.sort_by_key(|&i| segments[i].min_x());
+
+    let mut cuts: Vec<Vec<Point>> = vec![Vec::new(); segments.len()];
+    let mut inexact = false;
+
+    for (k, &i) in order.iter().enumerate() {
+        let si = &segments[i];
+        for &j in &order[k + 1..] {
+            let sj = &segments[j];
+            if sj.min_x() > si.max_x() {
+                break;
+            }
+            if sj.lo.y > si.hi.y || sj.hi.y < si.lo.y {
+                continue;
+            }
+            inexact |= intersect(si, sj, i, j, &mut cuts);
+        }
+    }
+
+    let mut out = Vec::with_capacity(segments.len());
+    for (seg, mut pts) in segments.iter().zip(cuts) {
+        if pts.is_empty() || seg.is_horizontal() {
+            out.push(*seg);
+            continue;
+        }
+        let (dx, dy) = (seg.hi.x - seg.lo.x, seg.hi.y - seg.lo.y);
+        let along = |p: &Point| (p.x - seg.lo.x) as i128 * dx as i128 + (p.y - seg.lo.y) as i128 * dy as i128;
+        pts.sort_by_key(along);
+        pts.dedup();
+        let mut prev = seg.lo;
+        for p in pts.into_iter().chain(std::iter::once(seg.hi)) {
+            match p.y.cmp(&prev.y) {
+                Ordering::Greater => {
+                    out.push(Segment { lo: prev, hi: p, ..*seg });
+                    prev = p;
+                }
+                // A rounded cut produced a horizontal step; it carries no
+                // winding, so continue from the cut point.
+                Ordering::Equal => prev = p,
+                // Rounding moved the cut below the previous one; skip it.
+                Ordering::Less => {}
+            }
+        }
+    }
+    (out, inexact)
+}
+
+/// Record the cut points of two segments. Returns true if a crossing had to
+/// be rounded onto the grid.
+fn intersect(si: &Segment, sj: &Segment, i: usize, j: usize, cuts: &mut [Vec<Point>]) -> bool {
+    let (ax, ay) = (si.lo.x as i128, si.lo.y as i128);
+    let (dx1, dy1) = ((si.hi.x - si.lo.x) as i128, (si.hi.y - si.lo.y) as i128);
+    let (bx, by) = (sj.lo.x as i128, sj.lo.y as i128);
+    let (dx2, dy2) = ((sj.hi.x - sj.lo.x) as i128, (sj.hi.y - sj.lo.y) as i128);
+
+    let denom = cross(dx1, dy1, dx2, dy2);
+    let (ex, ey) = (bx - ax, by - ay);
+
+    if denom == 0 {
+        if si.is_horizontal() {
+            return false; // horizontals are never cut
+        }
+        if cross(ex, ey, dx1, dy1) != 0 {
+            return false; // parallel, not collinear
+        }
+        // Collinear: cut each segment at the other's endpoints.
+        for p in [sj.lo, sj.hi] {
+            if strictly_inside(si, p) {
+                cuts[i].push(p);
+            }
+        }
+        for p in [si.lo, si.hi] {
+            if strictly_inside(sj, p) {
+                cuts[j].push(p);
+            }
+        }
+        return false;
+    }
+
+    let t_num = cross(ex, ey, dx2, dy2);
+    let u_num = cross(ex, ey, dx1, dy1);
+    let in_range = |n: i128| {
+        if denom > 0 {
+            n >= 0 && n <= denom
+        } else {
+            n <= 0 && n >= denom
+        }
+    };
+    if !in_range(t_num) || !in_range(u_num) {
+        return false;
+    }
+
+    let px = ax * denom + dx1 * t_num;
+    let py = ay * denom + dy1 * t_num;
+    let exact = px % denom == 0 && py % denom == 0;
+    let p = Point::new(round_div(px, denom) as Coord, round_div(py, denom) as Coord);
+
+    let mut rounded = false;
+    for (seg, k) in [(si, i), (sj, j)] {
+        if !seg.is_horizontal() && p != seg.lo && p != seg.hi {
+            cuts[k].push(p);
+            rounded |= !exact;
+        }
+    }
+    rounded
+}
+
+/// Whether collinear point `p` lies strictly between the segment endpoints.
+fn strictly_inside(seg: &Segment, p: Point) -> bool {
+    p.y > seg.lo.y && p.y < seg.hi.y
+}
+
+/// Combine identical segments by summing their windings, dropping
+/// horizontal and cancelled edges.
+fn merge_duplicates(mut segments: Vec<Segment>) -> Vec<Segment> {
+    segments.sort_by_key(|s| (s.lo.y, s.lo.x, s.hi.y, s.hi.x));
+    let mut out: Vec<Segment> = Vec::with_capacity(segments.len());
+    for s in segments {
+        match out.last_mut() {
+            Some(last) if last.lo == s.lo && last.hi == s.hi => {
+                last.wind_a += s.wind_a;
+                last.wind_b += s.wind_b;
+            }
+            _ => out.push(s),
+        }
+    }
+    out.retain(|s| s.wind_a != 0 || s.wind_b != 0);
+    out
+}
+
+// ── Slab sweep ───────────────────────────────────────────────────────
+
+/// A directed boundary edge of the result (interior on its left).
+type Edge = (Point, Point);
+
+fn run(segments: Vec<Segment>, op: BooleanOp) -> Vec<Vec<Point>> {
+    let segments = split_segments(segments);
+    let edges = boundary_edges(&segments, op);
+    chain_rings(&edges)
+}
+
+fn boundary_edges(segments: &[Segment], op: BooleanOp) -> Vec<Edge> {
+    let mut ys: Vec<Coord> = segments.iter().flat_map(|s| [s.lo.y, s.hi.y]).collect();
+    ys.sort_unstable();
+    ys.dedup();
+
+    let mut by_start: Vec<usize> = (0..segments.len()).collect();
+    by_start.sort_by_key(|&i| segments[i].lo.y);
+
+    let mut sides: Vec<Option<(bool, bool)>> = vec![None; segments.len()];
+    let mut edges = Vec::new();
+    let mut active: Vec<usize> = Vec::new();
+    let mut next = 0;
+    // Inside intervals along the top of the previous slab.
+    let mut below: Vec<(Ratio, Ratio)> = Vec::new();
+
+    for (k, &y) in ys.iter().enumerate() {
+        active.retain(|&i| segments[i].hi.y > y);
+        while next < by_start.len() && segments[by_start[next]].lo.y == y {
+            active.push(by_start[next]);
+            next += 1;
+        }
+
+        let mut above: Vec<(Ratio, Ratio)> = Vec::new();
+        let mut top: Vec<(Ratio, Ratio)> = Vec::new();
+
+        if let Some(&y_next) = ys.get(k + 1) {
+            let y2 = y as i128 + y_next as i128;
+            active.sort_by_cached_key(|&i| segments[i].x_at_doubled_y(y2));
+
+            let (mut wa, mut wb) = (0i32, 0i32);
+            let mut inside = false;
+            let mut open: Option<usize> = None;
+            for &i in &active {
+                let s = &segments[i];
+                wa -= s.wind_a;
+                wb -= s.wind_b;
+                let now_inside = op.evaluate(wa != 0, wb != 0);
+                if sides[i].is_none() {
+                    sides[i] = Some((inside, now_inside));
+                }
+                if now_inside && !inside {
+                    open = Some(i);
+                } else if inside && !now_inside {
+                    if let Some(start) = open.take() {
+                        let (l, r) = (&segments[start], s);
+                        push_interval(&mut above, l.x_at(y), r.x_at(y));
+                        push_interval(&mut top, l.x_at(y_next), r.x_at(y_next));
+                    }
+                }
+                inside = now_inside;
+            }
+        }
+
+        horizontal_edges(&above, &below, y, &mut edges);
+        below = top;
+    }
+
+    for (seg, side) in segments.iter().zip(sides) {
+        match side {
+            Some((false, true)) => edges.push((seg.hi, seg.lo)),
+            Some((true, false)) => edges.push((seg.lo, seg.hi)),
+            _ => {}
+        }
+    }
+    edges
+}
+
+/// Append an interval, joining it to the previous one when they touch.
+fn push_interval(list: &mut Vec<(Ratio, Ratio)>, start: Ratio, end: Ratio) {
+    if start >= end {
+        return;
+    }
+    match list.last_mut() {
+        Some(last) if last.1 >= start => last.1 = last.1.max(end),
+        _ => list.push((start, end)),
+    }
+}
+
+/// Emit horizontal boundary edges where the inside state just above `y`
+/// differs from the state just below it.
+fn horizontal_edges(
+    above: &[(Ratio, Ratio)],
+    below: &[(Ratio, Ratio)],
+    y: Coord,
+    edges: &mut Vec<Edge>,
+) {
+    let mut xs: Vec<Ratio> = above
+        .iter()
+        .chain(below)
+        .flat_map(|&(a, b)| [a, b])
+        .collect();
+    xs.sort();
+    xs.dedup();
+
+    let covers = |list: &[(Ratio, Ratio)], idx: &mut usize, x: Ratio| {
+        while *idx < list.len() && list[*idx].1 <= x {
+            *idx += 1;
+        }
+        *idx < list.len() && list[*idx].0 <= x
+    };
+
+    let (mut ia, mut ib) = (0, 0);
+    // Pending run of boundary: (start, inside-above).
+    let mut run: Option<(Ratio, bool)> = None;
+    for w in xs.windows(2) {
+        let in_above = covers(above, &mut ia, w[0]);
+        let in_below = covers(below, &mut ib, w[0]);
+        let state = (in_above != in_below).then_some(in_above);
+        if let Some((start, up)) = run {
+            if state == Some(up) {
+                continue;
+            }
+            emit_horizontal(start, w[0], y, up, edges);
+        }
+        run = state.map(|up| (w[0], up));
+    }
+    if let (Some((start, up)), Some(&end)) = (run, xs.last()) {
+        emit_horizontal(start, end, y, up, edges);
+    }
+}
+
+fn emit_horizontal(start: Ratio, end: Ratio, y: Coord, inside_above: bool, edges: &mut Vec<Edge>) {
+    let (x0, x1) = (start.round(), end.round());
+    if x0 == x1 {
+        return;
+    }
+    let (p, q) = (Point::new(x0, y), Point::new(x1, y));
+    edges.push(if inside_above { (p, q) } else { (q, p) });
+}
+
+// ── Ring assembly ────────────────────────────────────────────────────
+
+/// Chain directed edges into closed rings. At vertices with several
+/// outgoing edges the walk turns as far left as possible, so each ring
+/// encloses a single interior wedge at every vertex.
+fn chain_rings(edges: &[Edge]) -> Vec<Vec<Point>> {
+    let mut outgoing: HashMap<Point, Vec<usize>> = HashMap::new();
+    for (i, e) in edges.iter().enumerate() {
+        outgoing.entry(e.0).or_default().push(i);
+    }
+
+    let mut used = vec![false; edges.len()];
+    let mut rings = Vec::new();
+
+    for first in 0..edges.len() {
+        if used[first] {
+            continue;
+        }
+        used[first] = true;
+        let mut ring = vec![edges[first].0];
+        let mut current = first;
+        loop {
+            let (from, at) = edges[current];
+            let candidates: Vec<usize> = outgoing
+                .get(&at)
+                .map(|v| {
+                    v.iter()
+                        .copied()
+                        .filter(|&i| !used[i] || i == first)
+                        .collect()
+                })
+                .unwrap_or_default();
+            let back = (from.x - at.x, from.y - at.y);
+            let next = candidates.into_iter().min_by(|&i, &j| {
+                let di = (edges[i].1.x - at.x, edges[i].1.y - at.y);
+                let dj = (edges[j].1.x - at.x, edges[j].1.y - at.y);
+                cmp_clockwise_from(back, di, dj)
+            });
+            match next {
+                Some(n) if n != first => {
+                    used[n] = true;
+                    ring.push(at);
+                    current = n;
+                }
+                _ => break,
+            }
+        }
+        let ring = simplify_ring(ring);
+        if ring.len() >= 3 {
+            rings.push(ring);
+        }
+    }
+    rings
+}
+
+/// Order directions by their clockwise angle from `base`, in (0°, 360°].
+fn cmp_clockwise_from(base: (Coord, Coord), a: (Coord, Coord), b: (Coord, Coord)) -> Ordering {
+    let half = |d: (Coord, Coord)| {
+        let c = cross(base.0 as i128, base.1 as i128, d.0 as i128, d.1 as i128);
+        let dot = base.0 as i128 * d.0 as i128 + base.1 as i128 * d.1 as i128;
+        match c.cmp(&0) {
+            Ordering::Less => 0,
+            Ordering::Equal if dot < 0 => 1,
+            Ordering::Greater => 2,
+            Ordering::Equal => 3,
+        }
+    };
+    half(a).cmp(&half(b)).then_with(|| {
+        // Within a half, `a` comes first if `b` lies clockwise of it.
+        0.cmp(&cross(a.0 as i128, a.1 as i128, b.0 as i128, b.1 as i128))
+    })
+}
+
+/// Remove repeated and collinear vertices from a closed ring.
+fn simplify_ring(mut ring: Vec<Point>) -> Vec<Point> {
+    ring.dedup();
+    while ring.len() > 1 && ring.first() == ring.last() {
+        ring.pop();
+    }
+    let mut changed = true;
+    while changed && ring.len() >= 3 {
+        changed = false;
+        let n = ring.len();
+        let mut out = Vec::with_capacity(n);
+        for i in 0..n {
+            let (p, c, q) = (ring[(i + n - 1) % n], ring[i], ring[(i + 1) % n]);
+            let c1 = ((c.x - p.x) as i128, (c.y - p.y) as i128);
+            let c2 = ((q.x - c.x) as i128, (q.y - c.y) as i128);
+            let straight = cross(c1.0, c1.1, c2.0, c2.1) == 0 && c1.0 * c2.0 + c1.1 * c2.1 > 0;
+            if straight {
+                changed = true;
+            } else {
+                out.push(c);
+            }
+        }
+        ring = out;
+    }
+    ring
+}
+
+/// Group rings into outer boundaries and the holes they enclose.
+fn group_rings(rings: Vec<Vec<Point>>) -> Vec<Region> {
+    let mut outers: Vec<(Vec<Point>, i128, Vec<Vec<Point>>)> = Vec::new();
+    let mut holes = Vec::new();
+    for ring in rings {
+        let area = signed_area2(&ring);
+        match area.cmp(&0) {
+            Ordering::Greater => outers.push((ring, area, Vec::new())),
+            Ordering::Less => holes.push(ring),
+            Ordering::Equal => {}
+        }
+    }
+
+    for hole in holes {
+        let probe = hole[0];
+        let owner = outers
+            .iter()
+            .enumerate()
+            .filter(|(_, (outer, _, _))| point_in_ring(probe, outer))
+            .min_by_key(|(_, (_, area, _))| *area)
+            .map(|(i, _)| i);
+        if let Some(i) = owner {
+            outers[i].2.push(hole);
+        }
+    }
+
+    outers
+        .into_iter()
+        .map(|(outer, _, holes)| Region { outer, holes })
+        .collect()
+}
+
+fn to_polygons(regions: Vec<Region>, layer: LayerId) -> Vec<Polygon> {
+    regions
+        .into_iter()
+        .map(|r| Polygon::with_holes(layer, r.outer, r.holes))
+        .collect()
+}
+
+/// Even-odd point-in-ring test with exact arithmetic.
+pub(crate) fn point_in_ring(p: Point, ring: &[Point]) -> bool {
+    let n = ring.len();
+    let mut inside = false;
+    for i in 0..n {
+        let (a, b) = (ring[i], ring[(i + 1) % n]);
+        if (a.y > p.y) != (b.y > p.y) {
+            // x of the edge at p.y compared to p.x, sign-adjusted for direction.
+            let lhs = (p.x - a.x) as i128 * (b.y - a.y) as i128;
+            let rhs = (b.x - a.x) as i128 * (p.y - a.y) as i128;
+            let left_of_edge = if b.y > a.y { lhs < rhs } else { lhs > rhs };
+            if left_of_edge {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+/// Join holes to the outer ring with zero-width horizontal cut lines, so
+/// the result can be stored as a single GDS-compatible vertex ring. The
+/// outer ring must be counter-clockwise and the holes clockwise.
+pub(crate) fn insert_keyholes(mut outer: Vec<Point>, mut holes: Vec<Vec<Point>>) -> Vec<Point> {
+    // Start each hole at its leftmost vertex and process holes left to right,
+    // so a cut line only ever reaches already-joined rings.
+    for hole in holes.iter_mut() {
+        let start = (0..hole.len())
+            .min_by_key(|&i| (hole[i].x, hole[i].y))
+            .unwrap_or(0);
+        hole.rotate_left(start);
+    }
+    holes.sort_by_key(|h| (h[0].x, h[0].y));
+
+    for hole in holes {
+        let p = hole[0];
+        let Some((index, hit)) = cut_target(&outer, p) else {
+            continue;
+        };
+        let mut bridge = Vec::with_capacity(hole.len() + 3);
+        bridge.extend(hole.iter().copied());
+        bridge.push(p);
+        bridge.push(hit);
+        if outer[index] == hit {
+            // Cut lands on a vertex: visit it again after the hole.
+            outer.splice(index + 1..index + 1, bridge);
+        } else {
+            // Cut lands inside the edge starting at `index`.
+            bridge.insert(0, hit);
+            outer.splice(index + 1..index + 1, bridge);
+        }
+    }
+    outer
+}
+
+/// Find where a horizontal ray from `p` towards -x first meets the ring.
+/// Returns the index of the vertex or edge start that was hit, and the hit
+/// point.
+fn cut_target(ring: &[Point], p: Point) -> Option<(usize, Point)> {
+    let n = ring.len();
+    // (x, vertex or edge index, hit point, hit is a vertex)
+    let mut best: Option<(Ratio, usize, Point, bool)> = None;
+    for i in 0..n {
+        let (a, b) = (ring[i], ring[(i + 1) % n]);
+        let (lo, hi) = if a.y <= b.y { (a, b) } else { (b, a) };
+        if p.y < lo.y || p.y > hi.y {
+            continue;
+        }
+        let (x, vertex) = if a.y == b.y {
+            // Horizontal edge on the ray: the cut reaches its right end.
+            let right = if a.x >= b.x { (a, i) } else { (b, (i + 1) % n) };
+            (Ratio::new(right.0.x as i128, 1), Some(right.1))
+        } else {
+            let seg = Segment { lo, hi, wind_a: 0, wind_b: 0 };
+            let x = seg.x_at(p.y);
+            let vertex = if p.y == a.y {
+                Some(i)
+            } else if p.y == b.y {
+                Some((i + 1) % n)
+            } else {
+                None
+            };
+            (x, vertex)
+        };
+        if x > Ratio::new(p.x as i128, 1) {
+            continue;
+        }
+        let candidate = match vertex {
+            Some(v) => (x, v, ring[v], true),
+            None => (x, i, Point::new(x.round(), p.y), false),
+        };
+        let better = match &best {
+            None => true,
+            Some(b) => {
+                x > b.0 || (x == b.0 && !cut_fits(ring, b.1, b.3) && cut_fits(ring, candidate.1, candidate.3))
+            }
+        };
+        if better {
+            best = Some(candidate);
+        }
+    }
+    best.map(|(_, i, hit, _)| (i, hit))
+}
+
+/// Whether a cut from vertex `i` towards +x enters the ring's interior
+/// wedge. Edge hits always fit.
+fn cut_fits(ring: &[Point], i: usize, is_vertex: bool) -> bool {
+    if !is_vertex {
+        return true;
+    }
+    let n = ring.len();
+    let v = ring[i];
+    let prev = ring[(i + n - 1) % n];
+    let next = ring[(i + 1) % n];
+    let to_next = (next.x - v.x, next.y - v.y);
+    let to_prev = (prev.x - v.x, prev.y - v.y);
+    // Interior lies counter-clockwise from `to_next` up to `to_prev`,
+    // i.e. clockwise from `to_prev` up to `to_next`.
+    cmp_clockwise_from(to_prev, (1, 0), to_next) == Ordering::Less
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Rect;
+
+    fn rect(layer: LayerId, x1: Coord, y1: Coord, x2: Coord, y2: Coord) -> GeomPrimitive {
+        GeomPrimitive::Rect(Rect::new(layer, x1, y1, x2, y2))
+    }
+
+    fn total_area(polys: &[Polygon]) -> i128 {
+        polys.iter().map(Polygon::area).sum()
+    }
+
+    #[test]
+    fn test_union_of_overlapping_rects() {
+        let shapes = [rect(1, 0, 0, 10, 10), rect(1, 5, 5, 15, 15)];
+        let result = merge(&shapes, 1);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].vertex_count(), 8);
+        assert_eq!(total_area(&result), 100 + 100 - 25);
+    }
+
+    #[test]
+    fn test_abutting_rects_merge_into_one() {
+        let shapes = [rect(1, 0, 0, 10, 10), rect(1, 10, 0, 20, 10)];
+        let result = merge(&shapes, 1);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].vertex_count(), 4);
+        assert_eq!(result[0].bbox().unwrap().max, Point::new(20, 10));
+    }
+
+    #[test]
+    fn test_corner_touching_rects_stay_separate() {
+        let shapes = [rect(1, 0, 0, 10, 10), rect(1, 10, 10, 20, 20)];
+        let result = merge(&shapes, 1);
+        assert_eq!(result.len(), 2);
+        assert_eq!(total_area(&result), 200);
+    }
+
+    #[test]
+    fn test_and_not_xor() {
+        let a = [rect(1, 0, 0, 10, 10)];
+        let b = [rect(2, 5, 0, 15, 10)];
+        let and = boolean(&a, &b, BooleanOp::And, 3);
+        assert_eq!(and.len(), 1);
+        assert_eq!(and[0].bbox().unwrap().min, Point::new(5, 0));
+        assert_eq!(total_area(&and), 50);
+
+        let not = boolean(&a, &b, BooleanOp::Not, 3);
+        assert_eq!(total_area(&not), 50);
+        assert_eq!(not[0].bbox().unwrap().max, Point::new(5, 10));
+
+        let xor = boolean(&a, &b, BooleanOp::Xor, 3);
+        assert_eq!(xor.len(), 2);
+        assert_eq!(total_area(&xor), 100);
+    }
+
+    #[test]
+    fn test_not_produces_hole() {
+        let a = [rect(1, 0, 0, 100, 100)];
+        let b = [rect(1, 40, 40, 60, 60)];
+        let result = boolean(&a, &b, BooleanOp::Not, 1);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].holes.len(), 1);
+        assert!(signed_area2(&result[0].holes[0]) < 0);
+        assert_eq!(total_area(&result), 100 * 100 - 20 * 20);
+
+        // Keyholing joins the hole by a horizontal cut from its leftmost vertex.
+        let keyholed = result[0].keyholed();
+        assert!(keyholed.holes.is_empty());
+        assert!(keyholed.vertices.contains(&Point::new(0, 40)));
+        assert!(keyholed.vertices.contains(&Point::new(40, 40)));
+        assert_eq!(signed_area2(&keyholed.vertices) / 2, 100 * 100 - 20 * 20);
+    }
+
+    #[test]
+    fn test_keyhole_input_keeps_hole_open() {
+        let a = [rect(1, 0, 0, 100, 100)];
+        let b = [rect(1, 40, 40, 60, 60)];
+        let keyholed: Vec<GeomPrimitive> = boolean(&a, &b, BooleanOp::Not, 1)
+            .iter()
+            .map(|p| GeomPrimitive::Polygon(p.keyholed()))
+            .collect();
+        let merged: Vec<GeomPrimitive> = merge(&keyholed, 1)
+            .into_iter()
+            .map(GeomPrimitive::Polygon)
+            .collect();
+        let probe = [rect(1, 45, 45, 55, 55)];
+        assert!(boolean(&merged, &probe, BooleanOp::And, 1).is_empty());
+        assert_eq!(total_area(&boolean(&a, &merged, BooleanOp::Not, 1)), 400);
+    }
+
+    #[test]
+    fn test_two_holes_side_by_side() {
+        let a = [rect(1, 0, 0, 100, 50)];
+        let b = [rect(1, 10, 10, 30, 40), rect(1, 60, 10, 80, 40)];
+        let result = boolean(&a, &b, BooleanOp::Not, 1);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].holes.len(), 2);
+        assert_eq!(total_area(&result), 5000 - 2 * 600);
+        assert_eq!(signed_area2(&result[0].keyholed().vertices) / 2, 5000 - 2 * 600);
+    }
+
+    #[test]
+    fn test_45_degree_intersection_is_exact() {
+        // Diamond overlapping a square: all crossings lie on the grid.
+        let diamond = GeomPrimitive::Polygon(Polygon::new(
+            1,
+            vec![
+                Point::new(0, -10),
+                Point::new(10, 0),
+                Point::new(0, 10),
+                Point::new(-10, 0),
+            ],
+        ));
+        let square = rect(2, 0, 0, 20, 20);
+        let result = boolean([&diamond], [&square], BooleanOp::And, 3);
+        assert_eq!(result.len(), 1);
+        let mut verts = result[0].vertices.clone();
+        verts.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(
+            verts,
+            vec![Point::new(0, 0), Point::new(0, 10), Point::new(10, 0)]
+        );
+    }
+
+    #[test]
+    fn test_clockwise_input_is_normalized() {
+        let cw = GeomPrimitive::Polygon(Polygon::new(
+            1,
+            vec![
+                Point::new(5, 5),
+                Point::new(5, 15),
+                Point::new(15, 15),
+                Point::new(15, 5),
+            ],
+        ));
+        let shapes = [rect(1, 0, 0, 10, 10), cw];
+        assert_eq!(total_area(&merge(&shapes, 1)), 175);
+    }
+
+    #[test]
+    fn test_layer_boolean_filters_layers() {
+        let shapes = [rect(1, 0, 0, 10, 10), rect(2, 5, 5, 15, 15), rect(3, 0, 0, 100, 100)];
+        let result = layer_boolean(&shapes, &[1], &[2], BooleanOp::And, 9);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].layer_id, 9);
+        assert_eq!(total_area(&result), 25);
+    }
+}
@@ -1,68 +1,211 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
 use uuid::Uuid;
 
-use crate::geometry::{BBox, GeomPrimitive, Point};
+use crate::geometry::{BBox, Coord, GeomPrimitive, Point};
+use crate::validate::{self, InvalidPolygon, PolygonCheck, PolygonIssue};
 use crate::LayerId;
 
+pub use crate::transform::{Orientation, Transform};
+
 /// Unique cell identifier.
 pub type CellId = Uuid;
 
-/// A transformation for placing subcell instances.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub struct Transform {
-    /// Translation offset.
-    pub offset: Point,
-    /// Rotation in degrees (0, 90, 180, 270).
-    pub rotation: f64,
-    /// Mirror about X axis.
-    pub mirror_x: bool,
-    /// Uniform scale factor (typically 1.0).
-    pub scale: f64,
-}
-
-impl Default for Transform {
-    fn default() -> Self {
+/// Unique shape identifier. A shape keeps its ID when it is edited, when
+/// other shapes are removed, across undo/redo and when the project is saved.
+pub type ShapeId = Uuid;
+
+/// Values attached to a cell, shape or instance, such as net names, device
+/// parameters or instance names, keyed by GDS-II property attribute number.
+pub type Properties = BTreeMap<u16, String>;
+
+/// A geometric primitive stored in a cell, with a stable identity.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Shape {
+    pub id: ShapeId,
+    pub geometry: GeomPrimitive,
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
+}
+
+impl Shape {
+    pub fn new(geometry: GeomPrimitive) -> Self {
         Self {
-            offset: Point::new(0.0, 0.0),
-            rotation: 0.0,
-            mirror_x: false,
-            scale: 1.0,
+            id: Uuid::new_v4(),
+            geometry,
+            properties: Properties::new(),
         }
     }
+
+    pub fn with_properties(mut self, properties: Properties) -> Self {
+        self.properties = properties;
+        self
+    }
+}
+
+/// The repetition of an arrayed instance, as in a GDS-II AREF: the cell is
+/// placed `columns × rows` times, element (column, row) shifted by
+/// `column * column_pitch + row * row_pitch` in the parent's coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArrayParams {
+    pub columns: u32,
+    pub rows: u32,
+    pub column_pitch: Point,
+    pub row_pitch: Point,
 }
 
-impl Transform {
-    pub fn translate(x: f64, y: f64) -> Self {
+impl ArrayParams {
+    pub fn new(columns: u32, rows: u32, column_pitch: Point, row_pitch: Point) -> Self {
         Self {
-            offset: Point::new(x, y),
-            ..Default::default()
+            columns,
+            rows,
+            column_pitch,
+            row_pitch,
+        }
+    }
+
+    /// Number of elements.
+    pub fn len(&self) -> usize {
+        self.columns as usize * self.rows as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Shift of element (`column`, `row`) from the first one.
+    pub fn element_offset(&self, column: u32, row: u32) -> Point {
+        let (c, r) = (column as i64, row as i64);
+        Point::new(
+            c * self.column_pitch.x + r * self.row_pitch.x,
+            c * self.column_pitch.y + r * self.row_pitch.y,
+        )
+    }
+
+    /// The same array seen through `transform`: the pitches turned and
+    /// scaled by it.
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        Self {
+            column_pitch: transform.apply_vector(&self.column_pitch),
+            row_pitch: transform.apply_vector(&self.row_pitch),
+            ..*self
+        }
+    }
+
+    /// Columns and rows of the elements whose copy of `first`, the extent
+    /// of element (0, 0), may touch `region`: the region projected onto the
+    /// pitch lattice. Elements at the edge of the block may just miss it.
+    fn block_near(&self, first: &BBox, region: &BBox) -> (Range<u32>, Range<u32>) {
+        // Shifts of `first` that make it touch the region.
+        let x = (region.min.x - first.max.x, region.max.x - first.min.x);
+        let y = (region.min.y - first.max.y, region.max.y - first.min.y);
+        let (p, q) = (self.column_pitch, self.row_pitch);
+        let det = p.x as f64 * q.y as f64 - p.y as f64 * q.x as f64;
+        if det != 0.0 {
+            // Solve column * p + row * q = shift at the corners of the box
+            // of shifts; the solutions bound the block.
+            let (mut columns, mut rows) = (EMPTY, EMPTY);
+            for (sx, sy) in [(x.0, y.0), (x.1, y.0), (x.0, y.1), (x.1, y.1)] {
+                let (sx, sy) = (sx as f64, sy as f64);
+                let column = (sx * q.y as f64 - sy * q.x as f64) / det;
+                let row = (sy * p.x as f64 - sx * p.y as f64) / det;
+                columns = (columns.0.min(column), columns.1.max(column));
+                rows = (rows.0.min(row), rows.1.max(row));
+            }
+            (index_range(columns, self.columns), index_range(rows, self.rows))
+        } else if q == Point::new(0, 0) || self.rows == 1 {
+            (index_range(multiples(p, x, y), self.columns), 0..self.rows)
+        } else if p == Point::new(0, 0) || self.columns == 1 {
+            (0..self.columns, index_range(multiples(q, x, y), self.rows))
+        } else {
+            (0..self.columns, 0..self.rows)
+        }
+    }
+}
+
+/// An empty interval, for taking minima and maxima into.
+const EMPTY: (f64, f64) = (f64::INFINITY, f64::NEG_INFINITY);
+
+/// The real multiples `k` for which `k * pitch` lies in the box `x` × `y`.
+fn multiples(pitch: Point, x: (Coord, Coord), y: (Coord, Coord)) -> (f64, f64) {
+    let mut range = (f64::NEG_INFINITY, f64::INFINITY);
+    for (step, (lo, hi)) in [(pitch.x, x), (pitch.y, y)] {
+        if step == 0 {
+            if lo > 0 || hi < 0 {
+                return EMPTY;
+            }
+            continue;
         }
+        let (a, b) = (lo as f64 / step as f64, hi as f64 / step as f64);
+        range = (range.0.max(a.min(b)), range.1.min(a.max(b)));
     }
+    range
+}
 
-    pub fn apply(&self, point: &Point) -> Point {
-        let mut p = *point;
+/// Indices below `count` in the real interval `range`, with one to spare
+/// on either side against rounding.
+fn index_range(range: (f64, f64), count: u32) -> Range<u32> {
+    if range.0 > range.1 {
+        return 0..0;
+    }
+    let start = (range.0.floor() - 1.0).max(0.0);
+    let end = (range.1.ceil() + 2.0).min(count as f64);
+    if start < end {
+        start as u32..end as u32
+    } else {
+        0..0
+    }
+}
 
-        // Apply scale
-        p.x *= self.scale;
-        p.y *= self.scale;
+/// Placement indices of a block of array elements, row by row; see
+/// [`CellInstance::placement_indices_near`].
+#[derive(Debug, Clone)]
+pub struct PlacementIndices {
+    columns: Range<u32>,
+    rows: Range<u32>,
+    /// Elements per row of the whole array.
+    stride: usize,
+    column: u32,
+}
 
-        // Apply mirror
-        if self.mirror_x {
-            p.y = -p.y;
+impl PlacementIndices {
+    fn new(columns: Range<u32>, rows: Range<u32>, stride: u32) -> Self {
+        Self {
+            column: columns.start,
+            columns,
+            rows,
+            stride: stride as usize,
         }
+    }
+}
 
-        // Apply rotation (simplified for 90-degree increments)
-        let rad = self.rotation.to_radians();
-        let cos_r = rad.cos();
-        let sin_r = rad.sin();
-        let rx = p.x * cos_r - p.y * sin_r;
-        let ry = p.x * sin_r + p.y * cos_r;
+impl Iterator for PlacementIndices {
+    type Item = usize;
 
-        // Apply translation
-        Point::new(rx + self.offset.x, ry + self.offset.y)
+    fn next(&mut self) -> Option<usize> {
+        if self.columns.is_empty() || self.rows.is_empty() {
+            return None;
+        }
+        let index = self.rows.start as usize * self.stride + self.column as usize;
+        self.column += 1;
+        if self.column == self.columns.end {
+            self.column = self.columns.start;
+            self.rows.start += 1;
+        }
+        Some(index)
     }
 }
 
+/// One level of a path down a cell hierarchy: an instance and which of its
+/// placements was taken (always 0 for a plain instance).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PathStep {
+    pub instance: Uuid,
+    /// Index into the instance's placements, as for [`CellInstance::placement`].
+    pub element: usize,
+}
+
 /// A reference to a subcell placed within a parent cell.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CellInstance {
@@ -70,6 +213,11 @@ pub struct CellInstance {
     pub cell_id: CellId,
     pub instance_name: String,
     pub transform: Transform,
+    /// Repetition, for arrayed instances. `transform` places element (0, 0).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub array: Option<ArrayParams>,
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
 }
 
 impl CellInstance {
@@ -79,6 +227,94 @@ impl CellInstance {
             cell_id,
             instance_name: instance_name.to_string(),
             transform,
+            array: None,
+            properties: Properties::new(),
+        }
+    }
+
+    pub fn with_array(mut self, array: ArrayParams) -> Self {
+        self.array = Some(array);
+        self
+    }
+
+    pub fn with_properties(mut self, properties: Properties) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    /// Number of times the cell is placed: 1, or the array's size.
+    pub fn placement_count(&self) -> usize {
+        self.array.map_or(1, |a| a.len())
+    }
+
+    /// Transform of placement `index`, counting array elements along each
+    /// row, then row by row.
+    pub fn placement(&self, index: usize) -> Transform {
+        let Some(array) = self.array.filter(|a| a.columns > 0) else {
+            return self.transform;
+        };
+        let columns = array.columns as usize;
+        let shift = array.element_offset((index % columns) as u32, (index / columns) as u32);
+        let mut transform = self.transform;
+        transform.offset = transform.offset.translate(shift.x, shift.y);
+        transform
+    }
+
+    /// Transforms of every placement.
+    pub fn placements(&self) -> impl Iterator<Item = Transform> + '_ {
+        (0..self.placement_count()).map(|i| self.placement(i))
+    }
+
+    /// Indices of every placement, as for [`placement`](Self::placement).
+    pub fn placement_indices(&self) -> PlacementIndices {
+        match self.array {
+            Some(a) => PlacementIndices::new(0..a.columns, 0..a.rows, a.columns),
+            None => PlacementIndices::new(0..1, 0..1, 1),
+        }
+    }
+
+    /// Indices of the placements at which a cell whose bounding box is
+    /// `bbox` may touch `region`, given in the parent's coordinates. Only
+    /// the block of array elements around the region is visited, however
+    /// large the array; placements at its edge may just miss the region.
+    pub fn placement_indices_near(&self, bbox: &BBox, region: &BBox) -> PlacementIndices {
+        match self.array {
+            Some(a) => {
+                let (columns, rows) = a.block_near(&self.transform.apply_bbox(bbox), region);
+                PlacementIndices::new(columns, rows, a.columns)
+            }
+            None => self.placement_indices(),
+        }
+    }
+
+    /// Extent of every placement of a cell whose bounding box is `bbox`.
+    pub fn placed_bbox(&self, bbox: &BBox) -> BBox {
+        let first = self.transform.apply_bbox(bbox);
+        let Some(array) = self.array.filter(|a| !a.is_empty()) else {
+            return first;
+        };
+        // Elements lie on a parallelogram: its corners bound them all.
+        let (last_column, last_row) = (array.columns - 1, array.rows - 1);
+        [(last_column, 0), (0, last_row), (last_column, last_row)]
+            .iter()
+            .map(|&(c, r)| array.element_offset(c, r))
+            .fold(first, |bb, shift| {
+                bb.union(&BBox::new(
+                    first.min.translate(shift.x, shift.y),
+                    first.max.translate(shift.x, shift.y),
+                ))
+            })
+    }
+
+    /// This instance as placed in a cell that is itself placed by `outer`.
+    pub fn placed_in(&self, outer: &Transform) -> CellInstance {
+        CellInstance {
+            id: Uuid::new_v4(),
+            cell_id: self.cell_id,
+            instance_name: self.instance_name.clone(),
+            transform: outer.compose(&self.transform),
+            array: self.array.map(|a| a.transformed(outer)),
+            properties: self.properties.clone(),
         }
     }
 }
@@ -106,9 +342,11 @@ pub enum PinDirection {
 pub struct Cell {
     pub id: CellId,
     pub name: String,
-    pub geometries: Vec<GeomPrimitive>,
+    pub shapes: Vec<Shape>,
     pub instances: Vec<CellInstance>,
     pub pins: Vec<Pin>,
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
     pub modified: bool,
 }
 
@@ -117,25 +355,73 @@ impl Cell {
         Self {
             id: Uuid::new_v4(),
             name: name.to_string(),
-            geometries: Vec::new(),
+            shapes: Vec::new(),
             instances: Vec::new(),
             pins: Vec::new(),
+            properties: Properties::new(),
             modified: false,
         }
     }
 
-    pub fn add_geometry(&mut self, geom: GeomPrimitive) {
-        self.geometries.push(geom);
+    /// Add a geometry as a new shape and return the shape's ID.
+    pub fn add_geometry(&mut self, geom: GeomPrimitive) -> ShapeId {
+        let shape = Shape::new(geom);
+        let id = shape.id;
+        self.add_shape(shape);
+        id
+    }
+
+    /// Add a shape, keeping its ID.
+    pub fn add_shape(&mut self, shape: Shape) {
+        self.shapes.push(shape);
         self.modified = true;
     }
 
-    pub fn remove_geometry(&mut self, index: usize) -> Option<GeomPrimitive> {
-        if index < self.geometries.len() {
-            self.modified = true;
-            Some(self.geometries.remove(index))
-        } else {
-            None
+    /// Add a geometry, checking polygons according to `check`. Returns the
+    /// issues found; under [`PolygonCheck::Repair`] the repaired polygons are
+    /// stored instead, and under [`PolygonCheck::Reject`] nothing is stored.
+    pub fn add_geometry_checked(
+        &mut self,
+        geom: GeomPrimitive,
+        check: PolygonCheck,
+    ) -> Result<Vec<PolygonIssue>, InvalidPolygon> {
+        let (geoms, issues) = validate::check_primitive(geom, check)?;
+        for geom in geoms {
+            self.add_geometry(geom);
         }
+        Ok(issues)
+    }
+
+    /// Insert a shape at `index` in drawing order (appending if `index` is
+    /// past the end), keeping its ID.
+    pub fn insert_shape(&mut self, index: usize, shape: Shape) {
+        self.shapes.insert(index.min(self.shapes.len()), shape);
+        self.modified = true;
+    }
+
+    /// Remove a shape, returning it with the position it had.
+    pub fn remove_shape(&mut self, id: &ShapeId) -> Option<(usize, Shape)> {
+        let index = self.shape_index(id)?;
+        self.modified = true;
+        Some((index, self.shapes.remove(index)))
+    }
+
+    pub fn shape(&self, id: &ShapeId) -> Option<&Shape> {
+        self.shapes.iter().find(|s| s.id == *id)
+    }
+
+    pub fn shape_mut(&mut self, id: &ShapeId) -> Option<&mut Shape> {
+        self.shapes.iter_mut().find(|s| s.id == *id)
+    }
+
+    /// Current position of a shape in drawing order.
+    pub fn shape_index(&self, id: &ShapeId) -> Option<usize> {
+        self.shapes.iter().position(|s| s.id == *id)
+    }
+
+    /// All geometries in drawing order.
+    pub fn geometries(&self) -> impl Iterator<Item = &GeomPrimitive> {
+        self.shapes.iter().map(|s| &s.geometry)
     }
 
     pub fn add_instance(&mut self, instance: CellInstance) {
@@ -143,16 +429,53 @@ impl Cell {
         self.modified = true;
     }
 
+    /// Insert an instance at `index` (appending if past the end).
+    pub fn insert_instance(&mut self, index: usize, instance: CellInstance) {
+        self.instances.insert(index.min(self.instances.len()), instance);
+        self.modified = true;
+    }
+
+    /// Remove an instance, returning it with the position it had.
+    pub fn remove_instance(&mut self, id: &Uuid) -> Option<(usize, CellInstance)> {
+        let index = self.instances.iter().position(|i| i.id == *id)?;
+        self.modified = true;
+        Some((index, self.instances.remove(index)))
+    }
+
+    pub fn instance(&self, id: &Uuid) -> Option<&CellInstance> {
+        self.instances.iter().find(|i| i.id == *id)
+    }
+
+    pub fn instance_mut(&mut self, id: &Uuid) -> Option<&mut CellInstance> {
+        self.instances.iter_mut().find(|i| i.id == *id)
+    }
+
     pub fn add_pin(&mut self, pin: Pin) {
         self.pins.push(pin);
         self.modified = true;
     }
 
+    /// Insert a pin at `index` (appending if past the end).
+    pub fn insert_pin(&mut self, index: usize, pin: Pin) {
+        self.pins.insert(index.min(self.pins.len()), pin);
+        self.modified = true;
+    }
+
+    /// Remove a pin by name, returning it with the position it had.
+    pub fn remove_pin(&mut self, name: &str) -> Option<(usize, Pin)> {
+        let index = self.pins.iter().position(|p| p.name == name)?;
+        self.modified = true;
+        Some((index, self.pins.remove(index)))
+    }
+
+    pub fn pin(&self, name: &str) -> Option<&Pin> {
+        self.pins.iter().find(|p| p.name == name)
+    }
+
     /// Compute the bounding box of all geometry in this cell (not including subcells).
     pub fn local_bbox(&self) -> Option<BBox> {
         let bboxes: Vec<BBox> = self
-            .geometries
-            .iter()
+            .geometries()
             .filter_map(|g| g.bbox())
             .collect();
 
@@ -169,14 +492,13 @@ impl Cell {
 
     /// Get all geometries on a specific layer.
     pub fn geometries_on_layer(&self, layer_id: LayerId) -> Vec<&GeomPrimitive> {
-        self.geometries
-            .iter()
+        self.geometries()
             .filter(|g| g.layer_id() == layer_id)
             .collect()
     }
 
     pub fn geometry_count(&self) -> usize {
-        self.geometries.len()
+        self.shapes.len()
     }
 
     pub fn instance_count(&self) -> usize {
@@ -187,12 +509,12 @@ impl Cell {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::geometry::Rect;
+    use crate::geometry::{Point, Rect};
 
     #[test]
     fn test_cell_add_geometry() {
         let mut cell = Cell::new("test_cell");
-        let rect = GeomPrimitive::Rect(Rect::new(0, 0.0, 0.0, 100.0, 50.0));
+        let rect = GeomPrimitive::Rect(Rect::new(0, 0, 0, 100, 50));
         cell.add_geometry(rect);
         assert_eq!(cell.geometry_count(), 1);
         assert!(cell.modified);
@@ -201,21 +523,51 @@ mod tests {
     #[test]
     fn test_cell_bbox() {
         let mut cell = Cell::new("test_cell");
-        cell.add_geometry(GeomPrimitive::Rect(Rect::new(0, 0.0, 0.0, 100.0, 50.0)));
-        cell.add_geometry(GeomPrimitive::Rect(Rect::new(1, 50.0, 25.0, 200.0, 75.0)));
+        cell.add_geometry(GeomPrimitive::Rect(Rect::new(0, 0, 0, 100, 50)));
+        cell.add_geometry(GeomPrimitive::Rect(Rect::new(1, 50, 25, 200, 75)));
         let bb = cell.local_bbox().unwrap();
-        assert!((bb.min.x - 0.0).abs() < 1e-10);
-        assert!((bb.min.y - 0.0).abs() < 1e-10);
-        assert!((bb.max.x - 200.0).abs() < 1e-10);
-        assert!((bb.max.y - 75.0).abs() < 1e-10);
+        assert_eq!(bb.min, Point::new(0, 0));
+        assert_eq!(bb.max, Point::new(200, 75));
     }
 
     #[test]
-    fn test_transform_translate() {
-        let t = Transform::translate(10.0, 20.0);
-        let p = Point::new(5.0, 5.0);
-        let result = t.apply(&p);
-        assert!((result.x - 15.0).abs() < 1e-10);
-        assert!((result.y - 25.0).abs() < 1e-10);
+    fn test_shape_ids_survive_removal() {
+        let mut cell = Cell::new("test_cell");
+        let a = cell.add_geometry(GeomPrimitive::Rect(Rect::new(0, 0, 0, 10, 10)));
+        let b = cell.add_geometry(GeomPrimitive::Rect(Rect::new(0, 20, 0, 30, 10)));
+        let (index, removed) = cell.remove_shape(&a).unwrap();
+        assert_eq!((index, removed.id), (0, a));
+        assert_eq!(cell.shape_index(&b), Some(0));
+        assert!(cell.remove_shape(&a).is_none());
+
+        cell.insert_shape(index, removed);
+        assert_eq!(cell.shape_index(&a), Some(0));
+        assert_eq!(cell.shape_index(&b), Some(1));
+    }
+
+    #[test]
+    fn test_placement_indices_near_region() {
+        let bbox = BBox::new(Point::new(0, 0), Point::new(8, 8));
+        let region = BBox::new(Point::new(250, 420), Point::new(290, 445));
+        let arrays = [
+            ArrayParams::new(300, 300, Point::new(10, 0), Point::new(0, 10)),
+            // Skewed, with swapped axes, and a single row.
+            ArrayParams::new(300, 300, Point::new(10, 3), Point::new(-2, 10)),
+            ArrayParams::new(300, 300, Point::new(0, 10), Point::new(10, 0)),
+            ArrayParams::new(300, 1, Point::new(10, 16), Point::new(0, 0)),
+        ];
+        for array in arrays {
+            let inst = CellInstance::new(Uuid::new_v4(), "A", Transform::translate(5, 0))
+                .with_array(array);
+            let near: Vec<usize> = inst.placement_indices_near(&bbox, &region).collect();
+            let touching: Vec<usize> = (0..inst.placement_count())
+                .filter(|&i| inst.placement(i).apply_bbox(&bbox).intersects(&region))
+                .collect();
+            assert!(!touching.is_empty(), "{:?}", array);
+            assert!(touching.iter().all(|i| near.contains(i)), "{:?}", array);
+            assert!(near.len() < 100, "{:?} visits {}", array, near.len());
+        }
+        let plain = CellInstance::new(Uuid::new_v4(), "P", Transform::default());
+        assert_eq!(plain.placement_indices_near(&bbox, &region).collect::<Vec<_>>(), [0]);
     }
 }
@@ -0,0 +1,202 @@
+//! Change notifications from the layout database.
+//!
+//! [`LayoutDatabase::subscribe`](crate::LayoutDatabase::subscribe) hands out
+//! a channel that receives a [`ChangeEvent`] for every mutation made through
+//! the database, including those made by commands and by undo and redo.
+//! Render caches, incremental DRC and frontend stores can then update only
+//! what changed. Bounding boxes are in the coordinates of the changed cell;
+//! cells that place it are affected too, which subscribers can find through
+//! the hierarchy.
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::cell::{CellId, ShapeId};
+use crate::geometry::{BBox, GeomPrimitive};
+use crate::layer::LayerId;
+
+/// One change to the database.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum ChangeEvent {
+    CellAdded(CellId),
+    CellRemoved(CellId),
+    /// The cell was changed through
+    /// [`get_cell_mut`](crate::LayoutDatabase::get_cell_mut), for
+    /// example renamed or given new pins. No detail is known, so treat the
+    /// whole cell as changed.
+    CellChanged(CellId),
+    ShapesAdded(ShapeChange),
+    ShapesRemoved(ShapeChange),
+    ShapesModified(ShapeChange),
+    /// Instances were placed, removed or moved.
+    InstancesChanged(InstanceChange),
+    /// Layers were added, removed, shown, hidden or restyled.
+    LayersChanged,
+}
+
+impl ChangeEvent {
+    /// The cell the change happened in, if it is about one cell.
+    pub fn cell(&self) -> Option<CellId> {
+        match self {
+            ChangeEvent::CellAdded(id)
+            | ChangeEvent::CellRemoved(id)
+            | ChangeEvent::CellChanged(id) => Some(*id),
+            ChangeEvent::ShapesAdded(c)
+            | ChangeEvent::ShapesRemoved(c)
+            | ChangeEvent::ShapesModified(c) => Some(c.cell),
+            ChangeEvent::InstancesChanged(c) => Some(c.cell),
+            ChangeEvent::LayersChanged => None,
+        }
+    }
+
+    /// The region of the cell that needs redrawing or rechecking, if the
+    /// change is limited to one.
+    pub fn dirty(&self) -> Option<BBox> {
+        match self {
+            ChangeEvent::ShapesAdded(c)
+            | ChangeEvent::ShapesRemoved(c)
+            | ChangeEvent::ShapesModified(c) => c.dirty,
+            ChangeEvent::InstancesChanged(c) => c.dirty,
+            _ => None,
+        }
+    }
+}
+
+/// Shapes added to, removed from or edited in one cell.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShapeChange {
+    pub cell: CellId,
+    pub shapes: Vec<ShapeId>,
+    /// Layers the shapes are on, before and after the change.
+    pub layers: Vec<LayerId>,
+    /// Area covered by the shapes, before and after the change.
+    pub dirty: Option<BBox>,
+}
+
+impl ShapeChange {
+    pub fn new(cell: CellId) -> Self {
+        Self {
+            cell,
+            shapes: Vec::new(),
+            layers: Vec::new(),
+            dirty: None,
+        }
+    }
+
+    /// Add the layer and extent of a shape's geometry, once per shape.
+    pub(crate) fn include(&mut self, id: ShapeId, geometry: &GeomPrimitive) {
+        if !self.shapes.contains(&id) {
+            self.shapes.push(id);
+        }
+        if !self.layers.contains(&geometry.layer_id()) {
+            self.layers.push(geometry.layer_id());
+        }
+        self.dirty = union(self.dirty, geometry.bbox());
+    }
+}
+
+/// Instances placed, removed or moved in one cell.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstanceChange {
+    pub cell: CellId,
+    pub instances: Vec<Uuid>,
+    /// Area covered by the instances, before and after the change.
+    pub dirty: Option<BBox>,
+}
+
+impl InstanceChange {
+    pub(crate) fn new(cell: CellId, instance: Uuid, dirty: Option<BBox>) -> Self {
+        Self {
+            cell,
+            instances: vec![instance],
+            dirty,
+        }
+    }
+}
+
+pub(crate) fn union(a: Option<BBox>, b: Option<BBox>) -> Option<BBox> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.union(&b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::{Cell, CellInstance, Transform};
+    use crate::commands::{AddGeometryCommand, MoveGeometryCommand};
+    use crate::design_commands::{DeleteCellCommand, EditLayerCommand};
+    use crate::geometry::{Point, Rect};
+    use crate::layer::Layer;
+    use crate::{LayoutDatabase, Orientation};
+    use std::sync::mpsc::Receiver;
+
+    fn drain(rx: &Receiver<ChangeEvent>) -> Vec<ChangeEvent> {
+        rx.try_iter().collect()
+    }
+
+    #[test]
+    fn test_commands_and_undo_emit_shape_changes() {
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("top"));
+        let rx = db.subscribe();
+
+        let rect = GeomPrimitive::Rect(Rect::new(2, 0, 0, 10, 10));
+        let add = AddGeometryCommand::new(cell_id, rect);
+        let id = add.shape_id();
+        db.execute_command(Box::new(add)).unwrap();
+        let cmd = MoveGeometryCommand::new(cell_id, vec![id], Point::new(20, 0));
+        db.execute_command(Box::new(cmd)).unwrap();
+        db.undo();
+
+        let moved = ShapeChange {
+            cell: cell_id,
+            shapes: vec![id],
+            layers: vec![2],
+            dirty: Some(BBox::new(Point::new(0, 0), Point::new(30, 10))),
+        };
+        let events = drain(&rx);
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[0], ChangeEvent::ShapesAdded(c) if c.layers == [2]));
+        assert_eq!(events[1], ChangeEvent::ShapesModified(moved.clone()));
+        assert_eq!(events[2], ChangeEvent::ShapesModified(moved));
+
+        // Dropped subscribers are forgotten.
+        drop(rx);
+        db.undo();
+        let rx = db.subscribe();
+        db.redo();
+        assert_eq!(drain(&rx).len(), 1);
+    }
+
+    #[test]
+    fn test_structure_edits_emit_events() {
+        let mut db = LayoutDatabase::new("test");
+        db.layer_stack.add_layer(Layer::new(1, "metal1", 68, 20));
+        let top = db.add_cell(Cell::new("top"));
+        let mut leaf = Cell::new("leaf");
+        leaf.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 10)));
+        let leaf = db.add_cell(leaf);
+        let rx = db.subscribe();
+
+        let placement = Transform::new(Point::new(100, 0), Orientation::R0);
+        let instance = CellInstance::new(leaf, "I0", placement);
+        let instance_id = instance.id;
+        db.add_instance(&top, instance).unwrap();
+        let placed = ChangeEvent::InstancesChanged(InstanceChange::new(
+            top,
+            instance_id,
+            Some(BBox::new(Point::new(100, 0), Point::new(110, 10))),
+        ));
+        assert_eq!(drain(&rx), std::slice::from_ref(&placed));
+
+        db.execute_command(Box::new(DeleteCellCommand::new(leaf))).unwrap();
+        assert_eq!(drain(&rx), [placed.clone(), ChangeEvent::CellRemoved(leaf)]);
+        db.undo();
+        assert_eq!(drain(&rx), [ChangeEvent::CellAdded(leaf), placed]);
+
+        db.execute_command(Box::new(EditLayerCommand::set_visible(1, false))).unwrap();
+        assert_eq!(drain(&rx), [ChangeEvent::LayersChanged]);
+    }
+}
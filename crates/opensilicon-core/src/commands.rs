@@ -1,15 +1,84 @@
-use crate::cell::CellId;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::cell::{CellId, Shape, ShapeId};
 use crate::database::LayoutDatabase;
-use crate::geometry::{GeomPrimitive, Point};
+use crate::geometry::{BBox, Coord, GeomPrimitive, Point, Rect};
+use crate::hierarchy::HierarchyError;
+use crate::journal::CommandRecord;
+use crate::layer::LayerId;
+use crate::transform::Transform;
+
+/// Why a command could not be executed.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    #[error("Unknown cell {0}")]
+    UnknownCell(CellId),
+
+    #[error("Shape {shape} not found in cell {cell}")]
+    UnknownShape { cell: CellId, shape: ShapeId },
+
+    #[error("Instance {instance} not found in cell {cell}")]
+    UnknownInstance { cell: CellId, instance: Uuid },
+
+    #[error("Pin '{name}' not found in cell {cell}")]
+    UnknownPin { cell: CellId, name: String },
+
+    #[error("Unknown layer {0}")]
+    UnknownLayer(LayerId),
+
+    #[error("Name '{0}' is already in use")]
+    NameTaken(String),
+
+    #[error("Layer {0} already exists")]
+    DuplicateLayer(LayerId),
+
+    #[error(transparent)]
+    Hierarchy(#[from] HierarchyError),
+
+    #[error("No transaction is open")]
+    NoTransaction,
+
+    #[error("Invalid edit: {0}")]
+    InvalidEdit(String),
+}
 
 /// A reversible command for the undo/redo system.
-pub trait Command: std::fmt::Debug + Send {
-    /// Execute the command (apply changes to the database).
-    fn execute(&mut self, db: &mut LayoutDatabase);
+pub trait Command: std::fmt::Debug + Send + Any {
+    /// Execute the command (apply changes to the database). A command that
+    /// fails must leave the database unchanged.
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError>;
     /// Reverse the command (undo changes).
     fn undo(&mut self, db: &mut LayoutDatabase);
+    /// A serializable copy of the command, including any state recorded by
+    /// [`execute`](Self::execute) for undoing it.
+    fn to_record(&self) -> CommandRecord;
     /// Human-readable description for the undo/redo history.
     fn description(&self) -> &str;
+    /// Fold `next`, which was executed right after this command, into this
+    /// command so both are undone as one step. Returns false if the two
+    /// cannot be combined, leaving this command unchanged.
+    fn merge(&mut self, _next: &dyn Command) -> bool {
+        false
+    }
+
+    /// Rough memory use of the command, for [`HistoryLimits::max_bytes`].
+    /// The default is the length of its serialized record, which holds the
+    /// same geometry the command keeps for undoing; commands that can tell
+    /// more cheaply override it.
+    fn size_hint(&self) -> usize {
+        record_size(&self.to_record())
+    }
+}
+
+/// `command` as a `T`, if it is one.
+pub(crate) fn downcast<T: Command>(command: &dyn Command) -> Option<&T> {
+    (command as &dyn Any).downcast_ref::<T>()
 }
 
 // ══════════════════════════════════════════════════════════════════════
@@ -17,38 +86,43 @@ pub trait Command: std::fmt::Debug + Send {
 // ══════════════════════════════════════════════════════════════════════
 
 /// Add a geometry primitive to a cell.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AddGeometryCommand {
     pub cell_id: CellId,
-    pub geometry: GeomPrimitive,
-    /// Index at which the geometry was inserted (set on execute).
-    inserted_index: Option<usize>,
+    /// The shape to add; its ID is assigned up front so redo re-adds the
+    /// same shape.
+    pub shape: Shape,
 }
 
 impl AddGeometryCommand {
     pub fn new(cell_id: CellId, geometry: GeomPrimitive) -> Self {
         Self {
             cell_id,
-            geometry,
-            inserted_index: None,
+            shape: Shape::new(geometry),
         }
     }
+
+    /// ID of the shape this command adds.
+    pub fn shape_id(&self) -> ShapeId {
+        self.shape.id
+    }
 }
 
 impl Command for AddGeometryCommand {
-    fn execute(&mut self, db: &mut LayoutDatabase) {
-        if let Some(cell) = db.get_cell_mut(&self.cell_id) {
-            cell.add_geometry(self.geometry.clone());
-            self.inserted_index = Some(cell.geometry_count() - 1);
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::AddGeometry(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        if db.add_shape(&self.cell_id, self.shape.clone()) {
+            Ok(())
+        } else {
+            Err(CommandError::UnknownCell(self.cell_id))
         }
     }
 
     fn undo(&mut self, db: &mut LayoutDatabase) {
-        if let Some(idx) = self.inserted_index {
-            if let Some(cell) = db.get_cell_mut(&self.cell_id) {
-                cell.remove_geometry(idx);
-            }
-        }
+        db.remove_shape(&self.cell_id, &self.shape.id);
     }
 
     fn description(&self) -> &str {
@@ -56,44 +130,42 @@ impl Command for AddGeometryCommand {
     }
 }
 
-/// Remove a geometry primitive from a cell by index.
-#[derive(Debug)]
+/// Remove a shape from a cell.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RemoveGeometryCommand {
     pub cell_id: CellId,
-    pub index: usize,
-    /// The removed geometry (saved for undo).
-    removed: Option<GeomPrimitive>,
+    pub shape_id: ShapeId,
+    /// The removed shape and its position (saved for undo).
+    removed: Option<(usize, Shape)>,
 }
 
 impl RemoveGeometryCommand {
-    pub fn new(cell_id: CellId, index: usize) -> Self {
+    pub fn new(cell_id: CellId, shape_id: ShapeId) -> Self {
         Self {
             cell_id,
-            index,
+            shape_id,
             removed: None,
         }
     }
 }
 
 impl Command for RemoveGeometryCommand {
-    fn execute(&mut self, db: &mut LayoutDatabase) {
-        if let Some(cell) = db.get_cell_mut(&self.cell_id) {
-            self.removed = cell.remove_geometry(self.index);
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::RemoveGeometry(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        self.removed = db.remove_shape(&self.cell_id, &self.shape_id);
+        match self.removed {
+            Some(_) => Ok(()),
+            None => Err(missing_shape(db, self.cell_id, self.shape_id)),
         }
     }
 
     fn undo(&mut self, db: &mut LayoutDatabase) {
-        if let Some(geom) = self.removed.take() {
-            if let Some(cell) = db.get_cell_mut(&self.cell_id) {
-                // Re-insert at the original index
-                if self.index <= cell.geometries.len() {
-                    cell.geometries.insert(self.index, geom.clone());
-                } else {
-                    cell.geometries.push(geom.clone());
-                }
-                cell.modified = true;
-                self.removed = Some(geom);
-            }
+        if let Some((index, shape)) = self.removed.take() {
+            // Re-insert at the original position, with the original ID.
+            db.insert_shape(&self.cell_id, index, shape);
         }
     }
 
@@ -102,54 +174,653 @@ impl Command for RemoveGeometryCommand {
     }
 }
 
-/// Move one or more geometry primitives by a delta offset.
-#[derive(Debug)]
+/// Move one or more shapes by a delta offset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MoveGeometryCommand {
     pub cell_id: CellId,
-    pub indices: Vec<usize>,
+    pub shape_ids: Vec<ShapeId>,
     pub delta: Point,
 }
 
 impl MoveGeometryCommand {
-    pub fn new(cell_id: CellId, indices: Vec<usize>, delta: Point) -> Self {
+    pub fn new(cell_id: CellId, shape_ids: Vec<ShapeId>, delta: Point) -> Self {
         Self {
             cell_id,
-            indices,
+            shape_ids,
             delta,
         }
     }
+
+    fn translate(&self, db: &mut LayoutDatabase, dx: Coord, dy: Coord) {
+        db.edit_shapes(&self.cell_id, &self.shape_ids, |g| translate_geometry(g, dx, dy));
+    }
 }
 
 impl Command for MoveGeometryCommand {
-    fn execute(&mut self, db: &mut LayoutDatabase) {
-        if let Some(cell) = db.get_cell_mut(&self.cell_id) {
-            for &idx in &self.indices {
-                if idx < cell.geometries.len() {
-                    translate_geometry(&mut cell.geometries[idx], self.delta.x, self.delta.y);
-                }
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::MoveGeometry(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        check_shapes(db, self.cell_id, &self.shape_ids)?;
+        self.translate(db, self.delta.x, self.delta.y);
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        self.translate(db, -self.delta.x, -self.delta.y);
+    }
+
+    fn description(&self) -> &str {
+        "Move geometry"
+    }
+
+    /// Successive moves of the same selection, as while dragging, add up.
+    fn merge(&mut self, next: &dyn Command) -> bool {
+        match downcast::<Self>(next) {
+            Some(next) if next.cell_id == self.cell_id && next.shape_ids == self.shape_ids => {
+                self.delta = self.delta.translate(next.delta.x, next.delta.y);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>() + std::mem::size_of_val(self.shape_ids.as_slice())
+    }
+}
+
+/// Transform shapes in place: rotate, mirror or scale them about a pivot.
+///
+/// Undo restores the original geometry, so rounding in non-exact transforms
+/// does not accumulate over undo and redo.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransformGeometryCommand {
+    pub cell_id: CellId,
+    pub shape_ids: Vec<ShapeId>,
+    pub transform: Transform,
+    description: String,
+    original: Vec<(ShapeId, GeomPrimitive)>,
+}
+
+impl TransformGeometryCommand {
+    pub fn new(cell_id: CellId, shape_ids: Vec<ShapeId>, transform: Transform) -> Self {
+        Self {
+            cell_id,
+            shape_ids,
+            transform,
+            description: "Transform geometry".to_string(),
+            original: Vec::new(),
+        }
+    }
+
+    /// Rotate counter-clockwise by `degrees` about `pivot`. Multiples of 90°
+    /// are exact.
+    pub fn rotate(cell_id: CellId, shape_ids: Vec<ShapeId>, pivot: Point, degrees: f64) -> Self {
+        let transform = about(pivot, Transform::from_parts(Point::new(0, 0), false, degrees, 1.0));
+        Self {
+            description: "Rotate geometry".to_string(),
+            ..Self::new(cell_id, shape_ids, transform)
+        }
+    }
+
+    /// Mirror about the horizontal line through `pivot` (y → -y) if
+    /// `mirror_x`, otherwise about the vertical line (x → -x).
+    pub fn mirror(cell_id: CellId, shape_ids: Vec<ShapeId>, pivot: Point, mirror_x: bool) -> Self {
+        let degrees = if mirror_x { 0.0 } else { 180.0 };
+        let transform = about(pivot, Transform::from_parts(Point::new(0, 0), true, degrees, 1.0));
+        Self {
+            description: "Mirror geometry".to_string(),
+            ..Self::new(cell_id, shape_ids, transform)
+        }
+    }
+
+    /// Scale by `factor` about `pivot`, rounding to the grid. Path widths and
+    /// via sizes scale too.
+    pub fn scale(cell_id: CellId, shape_ids: Vec<ShapeId>, pivot: Point, factor: f64) -> Self {
+        let transform = about(pivot, Transform::default().with_magnification(factor));
+        Self {
+            description: "Scale geometry".to_string(),
+            ..Self::new(cell_id, shape_ids, transform)
+        }
+    }
+}
+
+/// `transform` applied with `pivot` as its origin.
+fn about(pivot: Point, transform: Transform) -> Transform {
+    Transform::translate(pivot.x, pivot.y)
+        .compose(&transform)
+        .compose(&Transform::translate(-pivot.x, -pivot.y))
+}
+
+impl Command for TransformGeometryCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::TransformGeometry(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let mag = self.transform.magnification;
+        if !(mag.is_finite() && mag > 0.0) {
+            return Err(CommandError::InvalidEdit(format!("scale factor {} is not positive", mag)));
+        }
+        self.original = original_geometry(db, self.cell_id, &self.shape_ids)?;
+        let transform = self.transform;
+        db.edit_shapes(&self.cell_id, &self.shape_ids, |g| *g = transform.apply_primitive(g));
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        restore_geometry(db, self.cell_id, std::mem::take(&mut self.original));
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+/// Copy shapes at one or more offsets.
+///
+/// The copies' IDs are chosen when the command is created, so callers can
+/// refer to them right away and redo brings back the same shapes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateGeometryCommand {
+    pub cell_id: CellId,
+    pub shape_ids: Vec<ShapeId>,
+    pub offsets: Vec<Point>,
+    copy_ids: Vec<ShapeId>,
+    description: String,
+}
+
+impl DuplicateGeometryCommand {
+    /// One copy of every shape per offset.
+    pub fn new(cell_id: CellId, shape_ids: Vec<ShapeId>, offsets: Vec<Point>) -> Self {
+        let copy_ids = (0..shape_ids.len() * offsets.len()).map(|_| Uuid::new_v4()).collect();
+        Self {
+            cell_id,
+            shape_ids,
+            offsets,
+            copy_ids,
+            description: "Duplicate geometry".to_string(),
+        }
+    }
+
+    pub fn duplicate(cell_id: CellId, shape_ids: Vec<ShapeId>, delta: Point) -> Self {
+        Self::new(cell_id, shape_ids, vec![delta])
+    }
+
+    /// Copy into a `columns` × `rows` array with the given pitch. The
+    /// originals stay in place as the first element.
+    pub fn array(
+        cell_id: CellId,
+        shape_ids: Vec<ShapeId>,
+        columns: usize,
+        rows: usize,
+        pitch: Point,
+    ) -> Self {
+        let offsets = (0..rows as Coord)
+            .flat_map(|r| (0..columns as Coord).map(move |c| Point::new(c * pitch.x, r * pitch.y)))
+            .skip(1)
+            .collect();
+        Self {
+            description: "Array copy".to_string(),
+            ..Self::new(cell_id, shape_ids, offsets)
+        }
+    }
+
+    /// IDs of the copies, offset by offset, in the order of `shape_ids`.
+    pub fn copy_ids(&self) -> &[ShapeId] {
+        &self.copy_ids
+    }
+}
+
+impl Command for DuplicateGeometryCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::DuplicateGeometry(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let sources = original_shapes(db, self.cell_id, &self.shape_ids)?;
+        let copies = self.offsets.iter().flat_map(|offset| {
+            sources.iter().map(move |shape| {
+                let mut shape = shape.clone();
+                translate_geometry(&mut shape.geometry, offset.x, offset.y);
+                shape
+            })
+        });
+        for (&id, shape) in self.copy_ids.iter().zip(copies) {
+            db.add_shape(&self.cell_id, Shape { id, ..shape });
+        }
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        for id in self.copy_ids.iter().rev() {
+            db.remove_shape(&self.cell_id, id);
+        }
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn size_hint(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + std::mem::size_of_val(self.shape_ids.as_slice())
+            + std::mem::size_of_val(self.offsets.as_slice())
+            + std::mem::size_of_val(self.copy_ids.as_slice())
+            + self.description.len()
+    }
+}
+
+/// How [`AlignGeometryCommand`] lines shapes up, by their bounding boxes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Alignment {
+    /// Left edges to the leftmost edge of the selection.
+    Left,
+    /// Right edges to the rightmost edge of the selection.
+    Right,
+    /// Bottom edges to the lowest edge of the selection.
+    Bottom,
+    /// Top edges to the highest edge of the selection.
+    Top,
+    /// Horizontal centers to the center of the selection.
+    CenterX,
+    /// Vertical centers to the center of the selection.
+    CenterY,
+    /// Equal horizontal gaps; the leftmost and rightmost shapes stay.
+    DistributeX,
+    /// Equal vertical gaps; the lowest and highest shapes stay.
+    DistributeY,
+}
+
+/// Align shapes to a common edge or center, or distribute them evenly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlignGeometryCommand {
+    pub cell_id: CellId,
+    pub shape_ids: Vec<ShapeId>,
+    pub alignment: Alignment,
+    /// How far each shape moved, recorded on execute.
+    moved: Vec<(ShapeId, Point)>,
+}
+
+impl AlignGeometryCommand {
+    pub fn new(cell_id: CellId, shape_ids: Vec<ShapeId>, alignment: Alignment) -> Self {
+        Self {
+            cell_id,
+            shape_ids,
+            alignment,
+            moved: Vec::new(),
+        }
+    }
+}
+
+/// The offset moving each box into the requested alignment.
+fn alignment_offsets(boxes: &[BBox], alignment: Alignment) -> Vec<Point> {
+    let Some(all) = boxes.iter().copied().reduce(|a, b| a.union(&b)) else {
+        return Vec::new();
+    };
+    let center = all.center();
+    match alignment {
+        Alignment::Left => boxes.iter().map(|b| Point::new(all.min.x - b.min.x, 0)).collect(),
+        Alignment::Right => boxes.iter().map(|b| Point::new(all.max.x - b.max.x, 0)).collect(),
+        Alignment::Bottom => boxes.iter().map(|b| Point::new(0, all.min.y - b.min.y)).collect(),
+        Alignment::Top => boxes.iter().map(|b| Point::new(0, all.max.y - b.max.y)).collect(),
+        Alignment::CenterX => {
+            boxes.iter().map(|b| Point::new(center.x - b.center().x, 0)).collect()
+        }
+        Alignment::CenterY => {
+            boxes.iter().map(|b| Point::new(0, center.y - b.center().y)).collect()
+        }
+        Alignment::DistributeX => distribute(boxes, |b| (b.min.x, b.max.x))
+            .into_iter()
+            .map(|d| Point::new(d, 0))
+            .collect(),
+        Alignment::DistributeY => distribute(boxes, |b| (b.min.y, b.max.y))
+            .into_iter()
+            .map(|d| Point::new(0, d))
+            .collect(),
+    }
+}
+
+/// Offsets along one axis that leave equal gaps between the boxes, keeping
+/// the first and last (by their low edge) in place.
+fn distribute(boxes: &[BBox], span: impl Fn(&BBox) -> (Coord, Coord)) -> Vec<Coord> {
+    let mut offsets = vec![0; boxes.len()];
+    if boxes.len() < 3 {
+        return offsets;
+    }
+    let mut order: Vec<usize> = (0..boxes.len()).collect();
+    order.sort_by_key(|&i| span(&boxes[i]).0);
+    let start = span(&boxes[order[0]]).0;
+    let end = span(&boxes[order[order.len() - 1]]).1;
+    let total: Coord = boxes.iter().map(|b| span(b).1 - span(b).0).sum();
+    let free = end - start - total;
+    let gaps = order.len() as Coord - 1;
+    let mut filled = 0;
+    for (k, &i) in order.iter().enumerate() {
+        let (lo, hi) = span(&boxes[i]);
+        offsets[i] = start + filled + (k as Coord * free).div_euclid(gaps) - lo;
+        filled += hi - lo;
+    }
+    offsets
+}
+
+impl Command for AlignGeometryCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::AlignGeometry(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let shapes: Vec<(ShapeId, BBox)> = original_geometry(db, self.cell_id, &self.shape_ids)?
+            .into_iter()
+            .filter_map(|(id, g)| Some((id, g.bbox()?)))
+            .collect();
+        let boxes: Vec<BBox> = shapes.iter().map(|(_, b)| *b).collect();
+        self.moved = shapes
+            .iter()
+            .zip(alignment_offsets(&boxes, self.alignment))
+            .filter(|(_, d)| d.x != 0 || d.y != 0)
+            .map(|((id, _), d)| (*id, d))
+            .collect();
+        for (id, d) in &self.moved {
+            db.edit_shapes(&self.cell_id, &[*id], |g| translate_geometry(g, d.x, d.y));
+        }
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        for (id, d) in &self.moved {
+            db.edit_shapes(&self.cell_id, &[*id], |g| translate_geometry(g, -d.x, -d.y));
+        }
+    }
+
+    fn description(&self) -> &str {
+        match self.alignment {
+            Alignment::DistributeX | Alignment::DistributeY => "Distribute geometry",
+            _ => "Align geometry",
+        }
+    }
+}
+
+/// The part of a shape that [`StretchGeometryCommand`] moves.
+///
+/// Vertices are numbered along the polygon outline, the path centerline, or
+/// counter-clockwise around a rectangle from its lower-left corner. Edge `i`
+/// runs from vertex `i` to the next one, wrapping around for polygons and
+/// rectangles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StretchTarget {
+    Vertex(usize),
+    Edge(usize),
+}
+
+/// Move one vertex or edge of a rectangle, polygon or path.
+///
+/// Rectangles stay rectangles: a corner drags both adjacent sides, and a
+/// side only moves across its own direction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StretchGeometryCommand {
+    pub cell_id: CellId,
+    pub shape_id: ShapeId,
+    pub target: StretchTarget,
+    pub delta: Point,
+    original: Option<GeomPrimitive>,
+}
+
+impl StretchGeometryCommand {
+    pub fn new(cell_id: CellId, shape_id: ShapeId, target: StretchTarget, delta: Point) -> Self {
+        Self {
+            cell_id,
+            shape_id,
+            target,
+            delta,
+            original: None,
+        }
+    }
+}
+
+/// The geometry with `target` moved by `delta`.
+fn stretch(
+    geom: &GeomPrimitive,
+    target: StretchTarget,
+    delta: Point,
+) -> Result<GeomPrimitive, String> {
+    let move_points = |points: &mut [Point], closed: bool| -> Result<(), String> {
+        let n = points.len();
+        let moved = match target {
+            StretchTarget::Vertex(i) if i < n => vec![i],
+            StretchTarget::Edge(i) if i + 1 < n => vec![i, i + 1],
+            StretchTarget::Edge(i) if closed && i + 1 == n && n > 1 => vec![i, 0],
+            _ => return Err(format!("no {:?} on this shape", target)),
+        };
+        for i in moved {
+            points[i] = points[i].translate(delta.x, delta.y);
+        }
+        Ok(())
+    };
+
+    let mut geom = geom.clone();
+    match &mut geom {
+        GeomPrimitive::Rect(r) => {
+            let (mut x1, mut y1) = (r.lower_left.x, r.lower_left.y);
+            let (mut x2, mut y2) = (r.upper_right.x, r.upper_right.y);
+            let (dx, dy) = (delta.x, delta.y);
+            match target {
+                StretchTarget::Vertex(0) => (x1, y1) = (x1 + dx, y1 + dy),
+                StretchTarget::Vertex(1) => (x2, y1) = (x2 + dx, y1 + dy),
+                StretchTarget::Vertex(2) => (x2, y2) = (x2 + dx, y2 + dy),
+                StretchTarget::Vertex(3) => (x1, y2) = (x1 + dx, y2 + dy),
+                StretchTarget::Edge(0) => y1 += dy,
+                StretchTarget::Edge(1) => x2 += dx,
+                StretchTarget::Edge(2) => y2 += dy,
+                StretchTarget::Edge(3) => x1 += dx,
+                _ => return Err(format!("no {:?} on a rectangle", target)),
             }
-            cell.modified = true;
+            *r = Rect::new(r.layer_id, x1, y1, x2, y2);
         }
+        GeomPrimitive::Polygon(p) => move_points(&mut p.vertices, true)?,
+        GeomPrimitive::Path(p) => move_points(&mut p.points, false)?,
+        GeomPrimitive::Via(_) => return Err("vias cannot be stretched".to_string()),
+        GeomPrimitive::Text(_) => return Err("labels cannot be stretched".to_string()),
+    }
+    Ok(geom)
+}
+
+impl Command for StretchGeometryCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::StretchGeometry(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let (_, original) = original_geometry(db, self.cell_id, &[self.shape_id])?
+            .pop()
+            .expect("one shape checked");
+        let stretched =
+            stretch(&original, self.target, self.delta).map_err(CommandError::InvalidEdit)?;
+        db.edit_shapes(&self.cell_id, &[self.shape_id], |g| *g = stretched.clone());
+        self.original = Some(original);
+        Ok(())
     }
 
     fn undo(&mut self, db: &mut LayoutDatabase) {
-        if let Some(cell) = db.get_cell_mut(&self.cell_id) {
-            for &idx in &self.indices {
-                if idx < cell.geometries.len() {
-                    translate_geometry(&mut cell.geometries[idx], -self.delta.x, -self.delta.y);
+        if let Some(original) = self.original.take() {
+            restore_geometry(db, self.cell_id, vec![(self.shape_id, original)]);
+        }
+    }
+
+    fn description(&self) -> &str {
+        "Stretch geometry"
+    }
+
+    /// Successive stretches of the same vertex or edge add up, unless doing
+    /// them in one step would give a different shape (a rectangle corner
+    /// dragged past the opposite one renumbers the corners).
+    fn merge(&mut self, next: &dyn Command) -> bool {
+        let Some(next) = downcast::<Self>(next) else {
+            return false;
+        };
+        let Some(original) = &self.original else {
+            return false;
+        };
+        if (next.cell_id, next.shape_id, next.target) != (self.cell_id, self.shape_id, self.target)
+        {
+            return false;
+        }
+        let delta = self.delta.translate(next.delta.x, next.delta.y);
+        let stepwise = stretch(original, self.target, self.delta)
+            .and_then(|g| stretch(&g, next.target, next.delta));
+        match (stepwise, stretch(original, self.target, delta)) {
+            (Ok(a), Ok(b)) if a == b => {
+                self.delta = delta;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Grouped sub-commands that execute and undo as one history entry.
+///
+/// Executing runs the sub-commands in order; if one fails, the ones already
+/// run are undone and the error is returned. Undo runs them in reverse.
+#[derive(Debug)]
+pub struct CompoundCommand {
+    description: String,
+    commands: Vec<Box<dyn Command>>,
+}
+
+impl CompoundCommand {
+    pub fn new(description: &str) -> Self {
+        Self {
+            description: description.to_string(),
+            commands: Vec::new(),
+        }
+    }
+
+    /// Append a sub-command (not executed until the compound is).
+    pub fn push(&mut self, command: Box<dyn Command>) {
+        self.commands.push(command);
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Rebuild a compound from recorded sub-commands.
+    pub fn from_records(description: &str, records: Vec<CommandRecord>) -> Self {
+        Self {
+            description: description.to_string(),
+            commands: records.into_iter().map(CommandRecord::into_command).collect(),
+        }
+    }
+}
+
+impl Command for CompoundCommand {
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        for i in 0..self.commands.len() {
+            if let Err(e) = self.commands[i].execute(db) {
+                for done in self.commands[..i].iter_mut().rev() {
+                    done.undo(db);
                 }
+                return Err(e);
             }
-            cell.modified = true;
+        }
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        for command in self.commands.iter_mut().rev() {
+            command.undo(db);
+        }
+    }
+
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::Compound {
+            description: self.description.clone(),
+            commands: self.commands.iter().map(|c| c.to_record()).collect(),
         }
     }
 
     fn description(&self) -> &str {
-        "Move geometry"
+        &self.description
+    }
+
+    fn size_hint(&self) -> usize {
+        self.description.len() + self.commands.iter().map(|c| c.size_hint()).sum::<usize>()
+    }
+}
+
+/// The error for a shape that could not be found.
+fn missing_shape(db: &LayoutDatabase, cell: CellId, shape: ShapeId) -> CommandError {
+    if db.get_cell(&cell).is_none() {
+        CommandError::UnknownCell(cell)
+    } else {
+        CommandError::UnknownShape { cell, shape }
+    }
+}
+
+/// Check that every listed shape exists in the cell.
+fn check_shapes(
+    db: &LayoutDatabase,
+    cell_id: CellId,
+    shape_ids: &[ShapeId],
+) -> Result<(), CommandError> {
+    let cell = db
+        .get_cell(&cell_id)
+        .ok_or(CommandError::UnknownCell(cell_id))?;
+    match shape_ids.iter().find(|id| cell.shape(id).is_none()) {
+        Some(id) => Err(CommandError::UnknownShape {
+            cell: cell_id,
+            shape: *id,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Copies of the listed shapes, properties included, failing if any is
+/// missing.
+fn original_shapes(
+    db: &LayoutDatabase,
+    cell_id: CellId,
+    shape_ids: &[ShapeId],
+) -> Result<Vec<Shape>, CommandError> {
+    check_shapes(db, cell_id, shape_ids)?;
+    let cell = db.get_cell(&cell_id).expect("checked");
+    Ok(shape_ids.iter().map(|id| cell.shape(id).expect("checked").clone()).collect())
+}
+
+/// The current geometry of the listed shapes, failing if any is missing.
+fn original_geometry(
+    db: &LayoutDatabase,
+    cell_id: CellId,
+    shape_ids: &[ShapeId],
+) -> Result<Vec<(ShapeId, GeomPrimitive)>, CommandError> {
+    Ok(original_shapes(db, cell_id, shape_ids)?
+        .into_iter()
+        .map(|shape| (shape.id, shape.geometry))
+        .collect())
+}
+
+/// Put back geometry saved by [`original_geometry`].
+fn restore_geometry(
+    db: &mut LayoutDatabase,
+    cell_id: CellId,
+    original: Vec<(ShapeId, GeomPrimitive)>,
+) {
+    for (id, geometry) in original {
+        db.edit_shapes(&cell_id, &[id], |g| *g = geometry.clone());
     }
 }
 
 /// Helper: translate all points in a geometry by (dx, dy).
-fn translate_geometry(geom: &mut GeomPrimitive, dx: f64, dy: f64) {
+fn translate_geometry(geom: &mut GeomPrimitive, dx: Coord, dy: Coord) {
     match geom {
         GeomPrimitive::Rect(r) => {
             r.lower_left.x += dx;
@@ -158,7 +829,7 @@ fn translate_geometry(geom: &mut GeomPrimitive, dx: f64, dy: f64) {
             r.upper_right.y += dy;
         }
         GeomPrimitive::Polygon(p) => {
-            for pt in &mut p.vertices {
+            for pt in p.vertices.iter_mut().chain(p.holes.iter_mut().flatten()) {
                 pt.x += dx;
                 pt.y += dy;
             }
@@ -173,45 +844,272 @@ fn translate_geometry(geom: &mut GeomPrimitive, dx: f64, dy: f64) {
             v.position.x += dx;
             v.position.y += dy;
         }
+        GeomPrimitive::Text(t) => {
+            t.transform.offset.x += dx;
+            t.transform.offset.y += dy;
+        }
     }
 }
 
+/// Undo entries kept by default.
+pub const DEFAULT_MAX_UNDO_ENTRIES: usize = 1000;
+
+/// Estimated bytes of undo and redo entries kept by default.
+pub const DEFAULT_MAX_UNDO_BYTES: usize = 64 * 1024 * 1024;
+
+/// How much undo history a [`CommandHistory`] keeps. When a limit is
+/// exceeded, the oldest undo entries are dropped; the most recent entry is
+/// always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryLimits {
+    /// Maximum number of undo entries, if limited.
+    pub max_entries: Option<usize>,
+    /// Maximum estimated size of the undo and redo entries, if limited.
+    pub max_bytes: Option<usize>,
+}
+
+impl HistoryLimits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_entries: None,
+            max_bytes: None,
+        }
+    }
+}
+
+impl Default for HistoryLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: Some(DEFAULT_MAX_UNDO_ENTRIES),
+            max_bytes: Some(DEFAULT_MAX_UNDO_BYTES),
+        }
+    }
+}
+
+/// A command on the undo or redo stack with its estimated size, which is
+/// only worked out while the history is limited by size.
+#[derive(Debug)]
+struct Entry {
+    command: Box<dyn Command>,
+    bytes: usize,
+}
+
+impl Entry {
+    fn new(command: Box<dyn Command>, limits: HistoryLimits) -> Self {
+        let mut entry = Self { command, bytes: 0 };
+        entry.measure(limits);
+        entry
+    }
+
+    fn measure(&mut self, limits: HistoryLimits) {
+        self.bytes = if limits.max_bytes.is_some() { self.command.size_hint() } else { 0 };
+    }
+}
+
+/// Length of a command's serialized record.
+fn record_size(record: &CommandRecord) -> usize {
+    struct Counter(usize);
+
+    impl Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    let _ = serde_json::to_writer(&mut counter, record);
+    counter.0
+}
+
 /// Manages the undo/redo history stack.
 #[derive(Debug, Default)]
 pub struct CommandHistory {
-    undo_stack: Vec<Box<dyn Command>>,
-    redo_stack: Vec<Box<dyn Command>>,
+    undo_stack: VecDeque<Entry>,
+    redo_stack: Vec<Entry>,
+    /// Open transactions, innermost last. Commands executed while one is
+    /// open are collected into it instead of the undo stack.
+    transactions: Vec<CompoundCommand>,
+    limits: HistoryLimits,
+    /// Estimated size of both stacks, while limited by size.
+    bytes: usize,
+    /// Whether the next command must start a new undo entry rather than
+    /// merge into the last one.
+    sealed: bool,
 }
 
 impl CommandHistory {
     pub fn new() -> Self {
-        Self {
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+        Self::default()
+    }
+
+    pub fn with_limits(mut self, limits: HistoryLimits) -> Self {
+        self.set_limits(limits);
+        self
+    }
+
+    pub fn limits(&self) -> HistoryLimits {
+        self.limits
+    }
+
+    /// Change the limits, dropping old entries that no longer fit.
+    pub fn set_limits(&mut self, limits: HistoryLimits) {
+        let sizing = limits.max_bytes.is_some() && self.limits.max_bytes.is_none();
+        self.limits = limits;
+        if sizing {
+            for entry in self.undo_stack.iter_mut().chain(&mut self.redo_stack) {
+                entry.measure(limits);
+            }
+            self.recount();
         }
+        self.enforce_limits();
     }
 
-    pub fn execute(&mut self, mut command: Box<dyn Command>, db: &mut LayoutDatabase) {
-        command.execute(db);
-        self.undo_stack.push(command);
+    /// Execute a command and record it. If it fails inside a transaction,
+    /// every open transaction is rolled back.
+    ///
+    /// Outside a transaction, the command is merged into the last undo
+    /// entry when that entry accepts it (see [`Command::merge`]), unless
+    /// the history was [sealed](Self::seal) since.
+    pub fn execute(
+        &mut self,
+        mut command: Box<dyn Command>,
+        db: &mut LayoutDatabase,
+    ) -> Result<(), CommandError> {
+        if let Err(e) = command.execute(db) {
+            while self.rollback(db).is_ok() {}
+            return Err(e);
+        }
+        match self.transactions.last_mut() {
+            Some(transaction) => transaction.push(command),
+            None => self.record(command),
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, command: Box<dyn Command>) {
         // Executing a new command clears the redo stack.
         self.redo_stack.clear();
+        let limits = self.limits;
+        let merged = !self.sealed
+            && self.undo_stack.back_mut().is_some_and(|last| {
+                let merged = last.command.merge(command.as_ref());
+                if merged {
+                    last.measure(limits);
+                }
+                merged
+            });
+        if !merged {
+            self.undo_stack.push_back(Entry::new(command, self.limits));
+        }
+        self.sealed = false;
+        self.recount();
+        self.enforce_limits();
     }
 
+    /// Make the next command start a new undo entry, for example when a
+    /// drag ends, so the next drag of the same selection undoes separately.
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    fn recount(&mut self) {
+        self.bytes = self.undo_stack.iter().chain(&self.redo_stack).map(|e| e.bytes).sum();
+    }
+
+    fn enforce_limits(&mut self) {
+        let HistoryLimits {
+            max_entries,
+            max_bytes,
+        } = self.limits;
+        while self.undo_stack.len() > 1
+            && (max_entries.is_some_and(|max| self.undo_stack.len() > max)
+                || max_bytes.is_some_and(|max| self.bytes > max))
+        {
+            if let Some(oldest) = self.undo_stack.pop_front() {
+                self.bytes -= oldest.bytes;
+            }
+        }
+    }
+
+    /// Open a transaction: commands executed until the matching
+    /// [`commit`](Self::commit) form one undo entry. Transactions nest.
+    pub fn begin_transaction(&mut self, description: &str) {
+        self.transactions.push(CompoundCommand::new(description));
+    }
+
+    /// Close the innermost transaction, recording its commands as one entry
+    /// (in the enclosing transaction, if any). Empty transactions leave no
+    /// entry.
+    pub fn commit(&mut self) -> Result<(), CommandError> {
+        let transaction = self.transactions.pop().ok_or(CommandError::NoTransaction)?;
+        if transaction.is_empty() {
+            return Ok(());
+        }
+        match self.transactions.last_mut() {
+            Some(outer) => outer.push(Box::new(transaction)),
+            None => {
+                self.record(Box::new(transaction));
+                self.sealed = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Close the innermost transaction, undoing its commands.
+    pub fn rollback(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let mut transaction = self.transactions.pop().ok_or(CommandError::NoTransaction)?;
+        transaction.undo(db);
+        Ok(())
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        !self.transactions.is_empty()
+    }
+
+    /// Number of open (nested) transactions.
+    pub fn transaction_depth(&self) -> usize {
+        self.transactions.len()
+    }
+
+    /// Undo the last entry. Not available while a transaction is open.
     pub fn undo(&mut self, db: &mut LayoutDatabase) -> bool {
-        if let Some(mut command) = self.undo_stack.pop() {
-            command.undo(db);
-            self.redo_stack.push(command);
+        if self.in_transaction() {
+            return false;
+        }
+        if let Some(mut entry) = self.undo_stack.pop_back() {
+            entry.command.undo(db);
+            entry.measure(self.limits);
+            self.redo_stack.push(entry);
+            self.sealed = true;
+            self.recount();
             true
         } else {
             false
         }
     }
 
+    /// Redo the last undone entry. Not available while a transaction is
+    /// open; an entry that no longer applies stays on the redo stack.
     pub fn redo(&mut self, db: &mut LayoutDatabase) -> bool {
-        if let Some(mut command) = self.redo_stack.pop() {
-            command.execute(db);
-            self.undo_stack.push(command);
+        if self.in_transaction() {
+            return false;
+        }
+        if let Some(mut entry) = self.redo_stack.pop() {
+            if let Err(e) = entry.command.execute(db) {
+                log::warn!("Cannot redo '{}': {}", entry.command.description(), e);
+                self.redo_stack.push(entry);
+                return false;
+            }
+            entry.measure(self.limits);
+            self.undo_stack.push_back(entry);
+            self.sealed = true;
+            self.recount();
+            self.enforce_limits();
             true
         } else {
             false
@@ -219,23 +1117,425 @@ impl CommandHistory {
     }
 
     pub fn can_undo(&self) -> bool {
-        !self.undo_stack.is_empty()
+        !self.in_transaction() && !self.undo_stack.is_empty()
     }
 
     pub fn can_redo(&self) -> bool {
-        !self.redo_stack.is_empty()
+        !self.in_transaction() && !self.redo_stack.is_empty()
     }
 
     pub fn undo_description(&self) -> Option<&str> {
-        self.undo_stack.last().map(|c| c.description())
+        self.undo_stack.back().map(|e| e.command.description())
     }
 
     pub fn redo_description(&self) -> Option<&str> {
-        self.redo_stack.last().map(|c| c.description())
+        self.redo_stack.last().map(|e| e.command.description())
+    }
+
+    /// Descriptions of every undo entry, the next one to undo first.
+    pub fn undo_descriptions(&self) -> Vec<&str> {
+        self.undo_stack.iter().rev().map(|e| e.command.description()).collect()
+    }
+
+    /// Descriptions of every redo entry, the next one to redo first.
+    pub fn redo_descriptions(&self) -> Vec<&str> {
+        self.redo_stack.iter().rev().map(|e| e.command.description()).collect()
+    }
+
+    /// Estimated memory used by the undo and redo entries, in bytes. Only
+    /// kept up to date while the history is limited by size; otherwise it
+    /// is worked out on each call.
+    pub fn estimated_bytes(&self) -> usize {
+        if self.limits.max_bytes.is_some() {
+            self.bytes
+        } else {
+            self.undo_stack.iter().chain(&self.redo_stack).map(|e| e.command.size_hint()).sum()
+        }
     }
 
     pub fn clear(&mut self) {
         self.undo_stack.clear();
         self.redo_stack.clear();
+        self.transactions.clear();
+        self.bytes = 0;
+    }
+
+    /// Serializable copies of the undo and redo stacks, oldest first.
+    /// Open transactions are not included.
+    pub fn to_records(&self) -> (Vec<CommandRecord>, Vec<CommandRecord>) {
+        let undo = self.undo_stack.iter().map(|e| e.command.to_record()).collect();
+        let redo = self.redo_stack.iter().map(|e| e.command.to_record()).collect();
+        (undo, redo)
+    }
+
+    /// Rebuild a history from [`to_records`](Self::to_records). The result
+    /// is sealed, so the first command recorded starts a new entry.
+    pub fn from_records(undo: Vec<CommandRecord>, redo: Vec<CommandRecord>) -> Self {
+        let limits = HistoryLimits::default();
+        let entries = |records: Vec<CommandRecord>| {
+            records.into_iter().map(move |r| Entry::new(r.into_command(), limits))
+        };
+        let mut history = Self {
+            undo_stack: entries(undo).collect(),
+            redo_stack: entries(redo).collect(),
+            sealed: true,
+            ..Self::default()
+        };
+        history.recount();
+        history
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::{Cell, Properties};
+    use crate::geometry::Rect;
+
+    fn rect(x: Coord) -> GeomPrimitive {
+        GeomPrimitive::Rect(Rect::new(1, x, 0, x + 10, 10))
+    }
+
+    #[test]
+    fn test_commands_track_shapes_by_id() {
+        let mut db = LayoutDatabase::new("test");
+        let mut cell = Cell::new("top");
+        let a = cell.add_geometry(rect(0));
+        let b = cell.add_geometry(rect(100));
+        let cell_id = db.add_cell(cell);
+
+        db.execute_command(Box::new(RemoveGeometryCommand::new(cell_id, a)))
+            .unwrap();
+        // `b` moved to index 0 but is still addressed by its ID.
+        db.execute_command(Box::new(MoveGeometryCommand::new(
+            cell_id,
+            vec![b],
+            Point::new(5, 0),
+        )))
+        .unwrap();
+        let cell = db.get_cell(&cell_id).unwrap();
+        assert_eq!(cell.shape(&b).unwrap().geometry, rect(105));
+
+        assert!(db.undo() && db.undo());
+        let cell = db.get_cell(&cell_id).unwrap();
+        assert_eq!(cell.shape_index(&a), Some(0));
+        assert_eq!(cell.shape(&b).unwrap().geometry, rect(100));
+    }
+
+    #[test]
+    fn test_redo_add_keeps_shape_id() {
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("top"));
+        let cmd = AddGeometryCommand::new(cell_id, rect(0));
+        let id = cmd.shape_id();
+        db.execute_command(Box::new(cmd)).unwrap();
+        assert!(db.undo());
+        assert!(db.get_cell(&cell_id).unwrap().shape(&id).is_none());
+        assert!(db.redo());
+        assert!(db.get_cell(&cell_id).unwrap().shape(&id).is_some());
+    }
+
+    fn add(cell_id: CellId, x: Coord) -> Box<dyn Command> {
+        Box::new(AddGeometryCommand::new(cell_id, rect(x)))
+    }
+
+    #[test]
+    fn test_transaction_is_one_undo_entry() {
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("top"));
+        db.begin_transaction("Add two");
+        db.execute_command(add(cell_id, 0)).unwrap();
+        db.execute_command(add(cell_id, 100)).unwrap();
+        assert!(!db.undo(), "undo is blocked inside a transaction");
+        db.commit().unwrap();
+        assert_eq!(db.undo_description(), Some("Add two"));
+
+        assert!(db.undo());
+        assert_eq!(db.get_cell(&cell_id).unwrap().geometry_count(), 0);
+        assert!(!db.can_undo());
+        assert!(db.redo());
+        assert_eq!(db.get_cell(&cell_id).unwrap().geometry_count(), 2);
+        assert_eq!(db.commit(), Err(CommandError::NoTransaction));
+    }
+
+    #[test]
+    fn test_failed_command_rolls_back_transaction() {
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("top"));
+        let missing = uuid::Uuid::new_v4();
+        let result = db.transaction("Add then move", |db| {
+            db.execute_command(add(cell_id, 0))?;
+            db.begin_transaction("Nested");
+            db.execute_command(add(cell_id, 100))?;
+            db.execute_command(Box::new(MoveGeometryCommand::new(
+                cell_id,
+                vec![missing],
+                Point::new(5, 0),
+            )))
+        });
+        assert_eq!(
+            result,
+            Err(CommandError::UnknownShape {
+                cell: cell_id,
+                shape: missing
+            })
+        );
+        assert!(!db.in_transaction());
+        assert_eq!(db.get_cell(&cell_id).unwrap().geometry_count(), 0);
+        assert!(!db.can_undo());
+    }
+
+    #[test]
+    fn test_explicit_rollback_and_nesting() {
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("top"));
+        db.begin_transaction("Outer");
+        db.execute_command(add(cell_id, 0)).unwrap();
+        db.begin_transaction("Inner");
+        db.execute_command(add(cell_id, 100)).unwrap();
+        db.rollback().unwrap();
+        assert_eq!(db.get_cell(&cell_id).unwrap().geometry_count(), 1);
+        db.begin_transaction("Inner");
+        db.execute_command(add(cell_id, 200)).unwrap();
+        db.commit().unwrap();
+        db.commit().unwrap();
+
+        assert_eq!(db.get_cell(&cell_id).unwrap().geometry_count(), 2);
+        assert!(db.undo());
+        assert_eq!(db.get_cell(&cell_id).unwrap().geometry_count(), 0);
+    }
+
+    #[test]
+    fn test_compound_command_undoes_partial_execution() {
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("top"));
+        let mut compound = CompoundCommand::new("Add and remove");
+        compound.push(add(cell_id, 0));
+        compound.push(Box::new(RemoveGeometryCommand::new(
+            cell_id,
+            uuid::Uuid::new_v4(),
+        )));
+        assert!(db.execute_command(Box::new(compound)).is_err());
+        assert_eq!(db.get_cell(&cell_id).unwrap().geometry_count(), 0);
+        assert!(!db.can_undo());
+    }
+
+    fn geometry(db: &LayoutDatabase, cell_id: CellId, id: ShapeId) -> GeomPrimitive {
+        db.get_cell(&cell_id).unwrap().shape(&id).unwrap().geometry.clone()
+    }
+
+    #[test]
+    fn test_rotate_mirror_scale_about_pivot() {
+        let mut db = LayoutDatabase::new("test");
+        let mut cell = Cell::new("top");
+        let a = cell.add_geometry(rect(0));
+        let cell_id = db.add_cell(cell);
+        let pivot = Point::new(10, 10);
+
+        let cmd = TransformGeometryCommand::rotate(cell_id, vec![a], pivot, 90.0);
+        db.execute_command(Box::new(cmd)).unwrap();
+        assert_eq!(geometry(&db, cell_id, a), GeomPrimitive::Rect(Rect::new(1, 10, 0, 20, 10)));
+        let cmd = TransformGeometryCommand::mirror(cell_id, vec![a], pivot, false);
+        db.execute_command(Box::new(cmd)).unwrap();
+        assert_eq!(geometry(&db, cell_id, a), GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 10)));
+        let cmd = TransformGeometryCommand::scale(cell_id, vec![a], pivot, 0.35);
+        db.execute_command(Box::new(cmd)).unwrap();
+        assert_eq!(geometry(&db, cell_id, a), GeomPrimitive::Rect(Rect::new(1, 6, 6, 10, 10)));
+
+        // Undo restores the exact original despite the rounding.
+        assert!(db.undo());
+        assert_eq!(geometry(&db, cell_id, a), rect(0));
+        let bad = TransformGeometryCommand::scale(cell_id, vec![a], pivot, 0.0);
+        assert!(matches!(db.execute_command(Box::new(bad)), Err(CommandError::InvalidEdit(_))));
+    }
+
+    #[test]
+    fn test_array_copy_keeps_ids_across_redo() {
+        let mut db = LayoutDatabase::new("test");
+        let mut cell = Cell::new("top");
+        let a = cell.add_geometry(rect(0));
+        let cell_id = db.add_cell(cell);
+
+        let cmd = DuplicateGeometryCommand::array(cell_id, vec![a], 3, 2, Point::new(20, 50));
+        let copies = cmd.copy_ids().to_vec();
+        assert_eq!(copies.len(), 5);
+        db.execute_command(Box::new(cmd)).unwrap();
+        assert_eq!(db.get_cell(&cell_id).unwrap().geometry_count(), 6);
+        assert_eq!(
+            geometry(&db, cell_id, copies[4]),
+            GeomPrimitive::Rect(Rect::new(1, 40, 50, 50, 60))
+        );
+
+        assert!(db.undo());
+        assert_eq!(db.get_cell(&cell_id).unwrap().geometry_count(), 1);
+        assert!(db.redo());
+        assert_eq!(geometry(&db, cell_id, copies[0]), rect(20));
+    }
+
+    #[test]
+    fn test_duplicate_keeps_properties() {
+        let mut db = LayoutDatabase::new("test");
+        let mut cell = Cell::new("top");
+        let properties = Properties::from([(1, "VDD".to_string())]);
+        let a = Shape::new(rect(0)).with_properties(properties.clone());
+        let a_id = a.id;
+        cell.add_shape(a);
+        let cell_id = db.add_cell(cell);
+
+        let cmd = DuplicateGeometryCommand::duplicate(cell_id, vec![a_id], Point::new(20, 0));
+        let copy = cmd.copy_ids()[0];
+        db.execute_command(Box::new(cmd)).unwrap();
+        let cell = db.get_cell(&cell_id).unwrap();
+        assert_eq!(cell.shape(&copy).unwrap().properties, properties);
+    }
+
+    #[test]
+    fn test_align_and_distribute() {
+        let mut db = LayoutDatabase::new("test");
+        let mut cell = Cell::new("top");
+        let a = cell.add_geometry(rect(0));
+        let b = cell.add_geometry(GeomPrimitive::Rect(Rect::new(1, 30, 5, 60, 20)));
+        let c = cell.add_geometry(rect(100));
+        let cell_id = db.add_cell(cell);
+        let ids = vec![a, b, c];
+
+        let align = |alignment| Box::new(AlignGeometryCommand::new(cell_id, ids.clone(), alignment));
+        db.execute_command(align(Alignment::DistributeX)).unwrap();
+        // 110 wide, 50 of shapes: gaps of 30.
+        assert_eq!(geometry(&db, cell_id, b), GeomPrimitive::Rect(Rect::new(1, 40, 5, 70, 20)));
+        assert_eq!(geometry(&db, cell_id, c), rect(100));
+
+        db.execute_command(align(Alignment::Top)).unwrap();
+        assert_eq!(geometry(&db, cell_id, a), GeomPrimitive::Rect(Rect::new(1, 0, 10, 10, 20)));
+        assert!(db.undo() && db.undo());
+        assert_eq!(geometry(&db, cell_id, b), GeomPrimitive::Rect(Rect::new(1, 30, 5, 60, 20)));
+    }
+
+    #[test]
+    fn test_stretch_vertices_and_edges() {
+        let mut db = LayoutDatabase::new("test");
+        let mut cell = Cell::new("top");
+        let r = cell.add_geometry(rect(0));
+        let poly = cell.add_geometry(GeomPrimitive::Polygon(crate::geometry::Polygon::new(
+            1,
+            vec![Point::new(0, 0), Point::new(10, 0), Point::new(0, 10)],
+        )));
+        let path = cell.add_geometry(GeomPrimitive::Path(crate::geometry::Path::new(
+            2,
+            vec![Point::new(0, 0), Point::new(50, 0), Point::new(50, 50)],
+            4,
+        )));
+        let cell_id = db.add_cell(cell);
+        let stretch = |id, target, dx, dy| {
+            Box::new(StretchGeometryCommand::new(cell_id, id, target, Point::new(dx, dy)))
+        };
+
+        // A rectangle side only moves across its direction.
+        db.execute_command(stretch(r, StretchTarget::Edge(1), 5, 99)).unwrap();
+        assert_eq!(geometry(&db, cell_id, r), GeomPrimitive::Rect(Rect::new(1, 0, 0, 15, 10)));
+        // The closing edge of a polygon wraps to vertex 0.
+        db.execute_command(stretch(poly, StretchTarget::Edge(2), -5, 0)).unwrap();
+        let GeomPrimitive::Polygon(p) = geometry(&db, cell_id, poly) else { panic!() };
+        assert_eq!(p.vertices, vec![Point::new(-5, 0), Point::new(10, 0), Point::new(-5, 10)]);
+        // Paths do not wrap.
+        assert!(matches!(
+            db.execute_command(stretch(path, StretchTarget::Edge(2), 0, 5)),
+            Err(CommandError::InvalidEdit(_))
+        ));
+        db.execute_command(stretch(path, StretchTarget::Edge(1), 10, 0)).unwrap();
+        let GeomPrimitive::Path(p) = geometry(&db, cell_id, path) else { panic!() };
+        assert_eq!(p.points[1..], [Point::new(60, 0), Point::new(60, 50)]);
+
+        assert!(db.undo() && db.undo() && db.undo());
+        assert_eq!(geometry(&db, cell_id, r), rect(0));
+    }
+
+    #[test]
+    fn test_drag_moves_merge_until_sealed() {
+        let mut db = LayoutDatabase::new("test");
+        let mut cell = Cell::new("top");
+        let a = cell.add_geometry(rect(0));
+        let cell_id = db.add_cell(cell);
+        let step = |dx| Box::new(MoveGeometryCommand::new(cell_id, vec![a], Point::new(dx, 0)));
+
+        for _ in 0..10 {
+            db.execute_command(step(1)).unwrap();
+        }
+        db.seal_history();
+        db.execute_command(step(5)).unwrap();
+        db.execute_command(add(cell_id, 100)).unwrap();
+        db.execute_command(step(5)).unwrap();
+        assert_eq!(
+            db.undo_descriptions(),
+            ["Move geometry", "Add geometry", "Move geometry", "Move geometry"]
+        );
+
+        assert!(db.undo() && db.undo() && db.undo());
+        assert_eq!(geometry(&db, cell_id, a), rect(10));
+        // Undo seals the history too: this move does not join the first.
+        db.execute_command(step(1)).unwrap();
+        assert!(db.undo() && db.undo());
+        assert_eq!(geometry(&db, cell_id, a), rect(0));
+        assert_eq!(db.redo_descriptions(), ["Move geometry", "Move geometry"]);
+    }
+
+    #[test]
+    fn test_history_limits_drop_oldest_entries() {
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("top"));
+        db.set_history_limits(HistoryLimits {
+            max_entries: Some(3),
+            max_bytes: None,
+        });
+        for x in 0..5 {
+            db.execute_command(add(cell_id, x * 100)).unwrap();
+        }
+        assert_eq!(db.undo_descriptions().len(), 3);
+        while db.undo() {}
+        assert_eq!(db.get_cell(&cell_id).unwrap().geometry_count(), 2);
+
+        let mut history = CommandHistory::new();
+        for x in 0..4 {
+            history.execute(add(cell_id, x * 100), &mut db).unwrap();
+        }
+        let per_entry = history.estimated_bytes() / 4;
+        history.set_limits(HistoryLimits {
+            max_entries: None,
+            max_bytes: Some(per_entry * 5 / 2),
+        });
+        assert_eq!(history.undo_descriptions().len(), 2);
+        // The latest entry is kept even if it alone is over the limit.
+        history.set_limits(HistoryLimits {
+            max_entries: None,
+            max_bytes: Some(1),
+        });
+        assert!(history.can_undo());
+        assert_eq!(history.undo_descriptions().len(), 1);
+    }
+
+    #[test]
+    fn test_sizes_are_only_tracked_when_limited() {
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("top"));
+        let mut history = CommandHistory::new().with_limits(HistoryLimits::unlimited());
+        for x in 0..3 {
+            history.execute(add(cell_id, x * 100), &mut db).unwrap();
+        }
+        assert!(history.undo(&mut db));
+        assert_eq!(history.bytes, 0);
+        let total = history.estimated_bytes();
+        assert!(total > 0);
+
+        history.set_limits(HistoryLimits {
+            max_entries: None,
+            max_bytes: Some(usize::MAX),
+        });
+        assert_eq!(history.bytes, total);
+        assert_eq!(history.estimated_bytes(), total);
+
+        // A moved selection is sized without serializing the command.
+        let ids = db.get_cell(&cell_id).unwrap().shapes.iter().map(|s| s.id).collect();
+        let step = MoveGeometryCommand::new(cell_id, ids, Point::new(1, 0));
+        assert!(step.size_hint() < record_size(&step.to_record()));
     }
 }
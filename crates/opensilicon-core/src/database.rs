@@ -1,11 +1,26 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Receiver, Sender};
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::cell::{Cell, CellId};
-use crate::commands::{Command, CommandHistory};
-use crate::layer::LayerStack;
+use crate::cell::{ArrayParams, Cell, CellId, CellInstance, Shape, ShapeId};
+use crate::changes::{self, ChangeEvent, InstanceChange, ShapeChange};
+use crate::commands::{Command, CommandError, CommandHistory, HistoryLimits};
+use crate::geometry::{BBox, GeomPrimitive, Point};
+use crate::hierarchy::{Hierarchy, HierarchyError};
+use crate::journal::{Journal, JournalEntry, JournalError};
+use crate::layer::{LayerId, LayerStack};
+use crate::legacy::LegacyDatabase;
+use crate::spatial::CellSpatialIndex;
+use crate::transform::Transform;
+use crate::units::DbUnits;
+
+/// Version of the JSON project layout written by [`LayoutDatabase::to_json`].
+/// Files without one are from before versioning; those that still store
+/// `dbu_per_nm` predate integer coordinates and are migrated on load.
+pub const FORMAT_VERSION: u32 = 1;
 
 /// The central layout database that holds all cells and the technology layer stack.
 #[derive(Debug, Serialize, Deserialize)]
@@ -23,8 +38,28 @@ pub struct LayoutDatabase {
     /// Command history for undo/redo.
     #[serde(skip)]
     command_history: CommandHistory,
-    /// Database units: nanometers per database unit.
-    pub dbu_per_nm: f64,
+    /// Version of the project file layout, for migrating older files.
+    #[serde(default)]
+    format_version: u32,
+    /// Database units per micrometer (1000.0 means a 1nm database unit).
+    pub dbu_per_um: f64,
+    /// GDS-II UNITS the library was read from, as [database unit in user
+    /// units, database unit in meters], so it is written back unchanged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gds_units: Option<[f64; 2]>,
+    /// Cached hierarchical bounding boxes, invalidated on cell mutation.
+    #[serde(skip)]
+    bbox_cache: RefCell<HashMap<CellId, Option<BBox>>>,
+    /// Per-cell, per-layer shape indices, built on first query. Shape edits
+    /// through the database update them; other cell mutations drop them.
+    #[serde(skip)]
+    spatial: RefCell<HashMap<CellId, CellSpatialIndex>>,
+    /// Crash-recovery journal that history operations are appended to.
+    #[serde(skip)]
+    journal: Option<Journal>,
+    /// Channels that receive a [`ChangeEvent`] for every mutation.
+    #[serde(skip)]
+    subscribers: Vec<Sender<ChangeEvent>>,
 }
 
 impl LayoutDatabase {
@@ -36,18 +71,38 @@ impl LayoutDatabase {
             cells: HashMap::new(),
             top_cell: None,
             command_history: CommandHistory::new(),
-            dbu_per_nm: 1.0,
+            format_version: FORMAT_VERSION,
+            dbu_per_um: DbUnits::default().dbu_per_um,
+            gds_units: None,
+            bbox_cache: RefCell::default(),
+            spatial: RefCell::default(),
+            journal: None,
+            subscribers: Vec::new(),
         }
     }
 
+    /// Unit converter for this database's coordinate grid.
+    pub fn units(&self) -> DbUnits {
+        DbUnits::new(self.dbu_per_um)
+    }
+
     // ── Cell management ──────────────────────────────────────────────
 
     pub fn add_cell(&mut self, cell: Cell) -> CellId {
         let id = cell.id;
-        self.cells.insert(id, cell);
+        self.invalidate_bbox(&id);
+        self.spatial.get_mut().remove(&id);
+        let replaced = self.cells.insert(id, cell).is_some();
         if self.top_cell.is_none() {
             self.top_cell = Some(id);
         }
+        self.emit(|_| {
+            if replaced {
+                ChangeEvent::CellChanged(id)
+            } else {
+                ChangeEvent::CellAdded(id)
+            }
+        });
         id
     }
 
@@ -55,7 +110,17 @@ impl LayoutDatabase {
         self.cells.get(id)
     }
 
+    /// Mutable access to a cell. Invalidates the cached bounding boxes of
+    /// the cell and every cell that places it, and the cell's spatial index.
+    /// Prefer the shape and instance editing methods below, which keep the
+    /// caches and report precise [`ChangeEvent`]s; this one reports
+    /// [`ChangeEvent::CellChanged`].
     pub fn get_cell_mut(&mut self, id: &CellId) -> Option<&mut Cell> {
+        self.invalidate_bbox(id);
+        self.spatial.get_mut().remove(id);
+        if self.cells.contains_key(id) {
+            self.emit(|_| ChangeEvent::CellChanged(*id));
+        }
         self.cells.get_mut(id)
     }
 
@@ -63,7 +128,11 @@ impl LayoutDatabase {
         if self.top_cell == Some(*id) {
             self.top_cell = None;
         }
-        self.cells.remove(id)
+        self.invalidate_bbox(id);
+        self.spatial.get_mut().remove(id);
+        let cell = self.cells.remove(id)?;
+        self.emit(|_| ChangeEvent::CellRemoved(*id));
+        Some(cell)
     }
 
     pub fn find_cell_by_name(&self, name: &str) -> Option<&Cell> {
@@ -82,28 +151,435 @@ impl LayoutDatabase {
         self.cells.values()
     }
 
+    /// Place `instance` in `parent`, refusing instances of unknown cells and
+    /// instances that would make a cell place itself.
+    pub fn add_instance(
+        &mut self,
+        parent: &CellId,
+        instance: CellInstance,
+    ) -> Result<(), HierarchyError> {
+        for id in [parent, &instance.cell_id] {
+            if !self.cells.contains_key(id) {
+                return Err(HierarchyError::UnknownCell(*id));
+            }
+        }
+        let hierarchy = self.hierarchy();
+        let cycle = if instance.cell_id == *parent {
+            Some(vec![*parent, *parent])
+        } else {
+            hierarchy.path(&instance.cell_id, parent).map(|path| {
+                std::iter::once(*parent).chain(path).collect::<Vec<_>>()
+            })
+        };
+        if let Some(cycle) = cycle {
+            return Err(hierarchy.cycle_error(&cycle));
+        }
+
+        self.insert_instance(parent, usize::MAX, instance);
+        Ok(())
+    }
+
+    /// Insert an instance at `index` in a cell (appending if `index` is past
+    /// the end), without checking for cycles. Returns false if the cell does
+    /// not exist.
+    pub fn insert_instance(
+        &mut self,
+        parent: &CellId,
+        index: usize,
+        instance: CellInstance,
+    ) -> bool {
+        self.invalidate_bbox(parent);
+        let Some(cell) = self.cells.get_mut(parent) else {
+            return false;
+        };
+        let id = instance.id;
+        cell.insert_instance(index, instance);
+        self.emit_instance_change(parent, id, None);
+        true
+    }
+
+    /// Remove an instance from a cell, returning it with the position it had.
+    pub fn remove_instance(&mut self, parent: &CellId, id: &Uuid) -> Option<(usize, CellInstance)> {
+        self.invalidate_bbox(parent);
+        let (index, instance) = self.cells.get_mut(parent)?.remove_instance(id)?;
+        self.emit(|db| {
+            let before = db.instance_bbox(&instance);
+            ChangeEvent::InstancesChanged(InstanceChange::new(*parent, *id, before))
+        });
+        Some((index, instance))
+    }
+
+    /// Give an instance a new placement, returning the old one.
+    pub fn set_instance_transform(
+        &mut self,
+        parent: &CellId,
+        id: &Uuid,
+        transform: Transform,
+    ) -> Option<Transform> {
+        self.edit_instance(parent, id, |i| std::mem::replace(&mut i.transform, transform))
+    }
+
+    /// Make an instance an array, change its array or make it a single
+    /// placement again. Returns the old array setting, or `None` if there is
+    /// no such instance.
+    pub fn set_instance_array(
+        &mut self,
+        parent: &CellId,
+        id: &Uuid,
+        array: Option<ArrayParams>,
+    ) -> Option<Option<ArrayParams>> {
+        self.edit_instance(parent, id, |i| std::mem::replace(&mut i.array, array))
+    }
+
+    fn edit_instance<T>(
+        &mut self,
+        parent: &CellId,
+        id: &Uuid,
+        edit: impl FnOnce(&mut CellInstance) -> T,
+    ) -> Option<T> {
+        let before = self.cells.get(parent)?.instance(id).and_then(|i| self.instance_bbox(i));
+        self.invalidate_bbox(parent);
+        let cell = self.cells.get_mut(parent)?;
+        let result = edit(cell.instance_mut(id)?);
+        cell.modified = true;
+        self.emit_instance_change(parent, *id, before);
+        Some(result)
+    }
+
+    /// Report an instance of `parent` as changed, dirtying `before` and the
+    /// area it covers now.
+    fn emit_instance_change(&mut self, parent: &CellId, id: Uuid, before: Option<BBox>) {
+        self.emit(|db| {
+            let after = db.cells[parent].instance(&id).and_then(|i| db.instance_bbox(i));
+            ChangeEvent::InstancesChanged(InstanceChange::new(
+                *parent,
+                id,
+                changes::union(before, after),
+            ))
+        });
+    }
+
+    // ── Shape editing ────────────────────────────────────────────────
+    //
+    // These keep the cell's spatial index up to date instead of dropping it.
+
+    /// Append a shape to a cell. Returns false if the cell does not exist.
+    pub fn add_shape(&mut self, cell_id: &CellId, shape: Shape) -> bool {
+        self.insert_shape(cell_id, usize::MAX, shape)
+    }
+
+    /// Insert a shape at `index` in a cell's drawing order (appending if
+    /// `index` is past the end). Returns false if the cell does not exist.
+    pub fn insert_shape(&mut self, cell_id: &CellId, index: usize, shape: Shape) -> bool {
+        let Some((cell, spatial)) = self.edit_cell(cell_id) else {
+            return false;
+        };
+        if let Some(spatial) = spatial {
+            spatial.insert(&shape);
+        }
+        let mut change = ShapeChange::new(*cell_id);
+        change.include(shape.id, &shape.geometry);
+        cell.insert_shape(index, shape);
+        self.emit(|_| ChangeEvent::ShapesAdded(change));
+        true
+    }
+
+    /// Remove a shape from a cell, returning it with the position it had.
+    pub fn remove_shape(&mut self, cell_id: &CellId, shape_id: &ShapeId) -> Option<(usize, Shape)> {
+        let (cell, spatial) = self.edit_cell(cell_id)?;
+        let (index, shape) = cell.remove_shape(shape_id)?;
+        if let Some(spatial) = spatial {
+            spatial.remove(&shape);
+        }
+        self.emit(|_| {
+            let mut change = ShapeChange::new(*cell_id);
+            change.include(shape.id, &shape.geometry);
+            ChangeEvent::ShapesRemoved(change)
+        });
+        Some((index, shape))
+    }
+
+    /// Apply `edit` to the geometry of each listed shape of a cell. Returns
+    /// the number of shapes edited.
+    pub fn edit_shapes(
+        &mut self,
+        cell_id: &CellId,
+        shape_ids: &[ShapeId],
+        mut edit: impl FnMut(&mut GeomPrimitive),
+    ) -> usize {
+        let observed = !self.subscribers.is_empty();
+        let Some((cell, mut spatial)) = self.edit_cell(cell_id) else {
+            return 0;
+        };
+        let ids: HashSet<&ShapeId> = shape_ids.iter().collect();
+        let mut change = ShapeChange::new(*cell_id);
+        let mut count = 0;
+        for shape in cell.shapes.iter_mut().filter(|s| ids.contains(&s.id)) {
+            if observed {
+                change.include(shape.id, &shape.geometry);
+            }
+            if let Some(spatial) = spatial.as_deref_mut() {
+                spatial.remove(shape);
+                edit(&mut shape.geometry);
+                spatial.insert(shape);
+            } else {
+                edit(&mut shape.geometry);
+            }
+            if observed {
+                change.include(shape.id, &shape.geometry);
+            }
+            count += 1;
+        }
+        if count > 0 {
+            cell.modified = true;
+            self.emit(|_| ChangeEvent::ShapesModified(change));
+        }
+        count
+    }
+
+    /// A cell to edit shapes in, with its spatial index if one is built.
+    fn edit_cell(&mut self, id: &CellId) -> Option<(&mut Cell, Option<&mut CellSpatialIndex>)> {
+        self.invalidate_bbox(id);
+        let cell = self.cells.get_mut(id)?;
+        Some((cell, self.spatial.get_mut().get_mut(id)))
+    }
+
+    // ── Change notification ──────────────────────────────────────────
+
+    /// Receive a [`ChangeEvent`] for every later mutation made through the
+    /// database. Dropping the receiver unsubscribes.
+    pub fn subscribe(&mut self) -> Receiver<ChangeEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Send `event` to subscribers. Code that mutates public fields such as
+    /// [`layer_stack`](Self::layer_stack) directly calls this to report it.
+    pub fn notify(&mut self, event: ChangeEvent) {
+        self.emit(|_| event);
+    }
+
+    /// Send an event to subscribers, building it only if there are any.
+    fn emit(&mut self, event: impl FnOnce(&Self) -> ChangeEvent) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let event = event(self);
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    // ── Spatial queries ──────────────────────────────────────────────
+
+    /// Run `f` on the spatial index of a cell, building it if needed.
+    /// `f` must not query other cells.
+    pub fn with_spatial_index<R>(
+        &self,
+        id: &CellId,
+        f: impl FnOnce(&CellSpatialIndex) -> R,
+    ) -> Option<R> {
+        let cell = self.cells.get(id)?;
+        if !self.spatial.borrow().contains_key(id) {
+            let index = CellSpatialIndex::build(cell);
+            self.spatial.borrow_mut().insert(*id, index);
+        }
+        Some(f(&self.spatial.borrow()[id]))
+    }
+
+    /// Shapes of a cell whose bounding box touches `region`, on `layer` or
+    /// on all layers if `None`. Subcells are not searched.
+    pub fn query_region(&self, id: &CellId, region: &BBox, layer: Option<LayerId>) -> Vec<ShapeId> {
+        self.with_spatial_index(id, |index| index.query_region(region, layer))
+            .unwrap_or_default()
+    }
+
+    /// Shapes of a cell whose bounding box contains `point`, on `layer` or
+    /// on all layers if `None`. Subcells are not searched.
+    pub fn query_point(&self, id: &CellId, point: &Point, layer: Option<LayerId>) -> Vec<ShapeId> {
+        self.with_spatial_index(id, |index| index.query_point(point, layer))
+            .unwrap_or_default()
+    }
+
+    // ── Hierarchy ────────────────────────────────────────────────────
+
+    /// Snapshot of the cell hierarchy graph.
+    pub fn hierarchy(&self) -> Hierarchy<'_> {
+        Hierarchy::new(self)
+    }
+
+    /// Cells that are not placed by any other cell, sorted by name.
+    pub fn root_cells(&self) -> Vec<CellId> {
+        self.hierarchy().roots()
+    }
+
+    /// Number of distinct cells placed below `id`, directly or indirectly.
+    pub fn descendant_count(&self, id: &CellId) -> usize {
+        self.hierarchy().descendants(id).len()
+    }
+
+    // ── Hierarchical extents ─────────────────────────────────────────
+
+    /// Bounding box of a cell including all placed subcells, or `None` if
+    /// the cell and its subcells contain no geometry. Results are cached.
+    pub fn cell_bbox(&self, id: &CellId) -> Option<BBox> {
+        self.cell_bbox_inner(id, &mut HashSet::new())
+    }
+
+    /// Bounding box of a placed instance in its parent's coordinates,
+    /// covering every element of an array.
+    pub fn instance_bbox(&self, instance: &CellInstance) -> Option<BBox> {
+        self.cell_bbox(&instance.cell_id)
+            .map(|bb| instance.placed_bbox(&bb))
+    }
+
+    /// Bounding box of the top cell's full hierarchy.
+    pub fn design_bbox(&self) -> Option<BBox> {
+        self.top_cell.and_then(|id| self.cell_bbox(&id))
+    }
+
+    fn cell_bbox_inner(&self, id: &CellId, visiting: &mut HashSet<CellId>) -> Option<BBox> {
+        if let Some(cached) = self.bbox_cache.borrow().get(id) {
+            return *cached;
+        }
+        let cell = self.cells.get(id)?;
+        // A cyclic reference contributes nothing instead of recursing forever.
+        if !visiting.insert(*id) {
+            return None;
+        }
+
+        let mut bbox = cell.local_bbox();
+        for inst in &cell.instances {
+            let child = self
+                .cell_bbox_inner(&inst.cell_id, visiting)
+                .map(|bb| inst.placed_bbox(&bb));
+            bbox = match (bbox, child) {
+                (Some(a), Some(b)) => Some(a.union(&b)),
+                (a, b) => a.or(b),
+            };
+        }
+
+        visiting.remove(id);
+        self.bbox_cache.borrow_mut().insert(*id, bbox);
+        bbox
+    }
+
+    /// Drop the cached bounding box of `id` and of all its ancestors.
+    fn invalidate_bbox(&self, id: &CellId) {
+        let mut cache = self.bbox_cache.borrow_mut();
+        if cache.is_empty() {
+            return;
+        }
+        let mut pending = vec![*id];
+        let mut seen = HashSet::new();
+        while let Some(child) = pending.pop() {
+            if !seen.insert(child) {
+                continue;
+            }
+            cache.remove(&child);
+            pending.extend(
+                self.cells
+                    .values()
+                    .filter(|c| c.instances.iter().any(|i| i.cell_id == child))
+                    .map(|c| c.id),
+            );
+        }
+    }
+
     // ── Undo / Redo ──────────────────────────────────────────────────
 
     /// Execute a command through the undo/redo system.
     /// Uses temporary swap to satisfy the borrow checker.
-    pub fn execute_command(&mut self, command: Box<dyn Command>) {
-        let mut history = std::mem::take(&mut self.command_history);
-        history.execute(command, self);
-        self.command_history = history;
+    pub fn execute_command(&mut self, command: Box<dyn Command>) -> Result<(), CommandError> {
+        self.write_due_checkpoint();
+        let record = self.journal.is_some().then(|| command.to_record());
+        // A failure inside a transaction rolls it back, which replay has to
+        // reproduce.
+        let in_transaction = self.in_transaction();
+        let result = self.with_history(|history, db| history.execute(command, db));
+        if let Some(record) = record {
+            if result.is_ok() || in_transaction {
+                self.log(JournalEntry::Execute(Box::new(record)));
+            }
+        }
+        result
     }
 
-    pub fn undo(&mut self) -> bool {
+    /// Open a transaction: commands executed until [`commit`](Self::commit)
+    /// undo and redo as one entry with this description. If a command fails
+    /// while a transaction is open, all open transactions are rolled back.
+    pub fn begin_transaction(&mut self, description: &str) {
+        self.write_due_checkpoint();
+        self.command_history.begin_transaction(description);
+        self.log(JournalEntry::Begin(description.to_string()));
+    }
+
+    /// Close the innermost open transaction, keeping its changes.
+    pub fn commit(&mut self) -> Result<(), CommandError> {
+        self.write_due_checkpoint();
+        self.command_history.commit()?;
+        self.log(JournalEntry::Commit);
+        Ok(())
+    }
+
+    /// Close the innermost open transaction, undoing its changes.
+    pub fn rollback(&mut self) -> Result<(), CommandError> {
+        self.write_due_checkpoint();
+        self.with_history(|history, db| history.rollback(db))?;
+        self.log(JournalEntry::Rollback);
+        Ok(())
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.command_history.in_transaction()
+    }
+
+    /// Run `f` in a transaction, committing if it succeeds and rolling back
+    /// if it fails.
+    pub fn transaction<T>(
+        &mut self,
+        description: &str,
+        f: impl FnOnce(&mut Self) -> Result<T, CommandError>,
+    ) -> Result<T, CommandError> {
+        let depth = self.command_history.transaction_depth();
+        self.begin_transaction(description);
+        match f(self) {
+            Ok(value) => {
+                self.commit()?;
+                Ok(value)
+            }
+            Err(e) => {
+                // A failed command has already rolled everything back.
+                if self.command_history.transaction_depth() > depth {
+                    self.rollback()?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn with_history<T>(&mut self, f: impl FnOnce(&mut CommandHistory, &mut Self) -> T) -> T {
         let mut history = std::mem::take(&mut self.command_history);
-        let result = history.undo(self);
+        let result = f(&mut history, self);
         self.command_history = history;
         result
     }
 
+    pub fn undo(&mut self) -> bool {
+        self.write_due_checkpoint();
+        let undone = self.with_history(|history, db| history.undo(db));
+        if undone {
+            self.log(JournalEntry::Undo);
+        }
+        undone
+    }
+
     pub fn redo(&mut self) -> bool {
-        let mut history = std::mem::take(&mut self.command_history);
-        let result = history.redo(self);
-        self.command_history = history;
-        result
+        self.write_due_checkpoint();
+        let redone = self.with_history(|history, db| history.redo(db));
+        if redone {
+            self.log(JournalEntry::Redo);
+        }
+        redone
     }
 
     pub fn can_undo(&self) -> bool {
@@ -114,20 +590,133 @@ impl LayoutDatabase {
         self.command_history.can_redo()
     }
 
+    pub fn undo_description(&self) -> Option<&str> {
+        self.command_history.undo_description()
+    }
+
+    pub fn redo_description(&self) -> Option<&str> {
+        self.command_history.redo_description()
+    }
+
+    /// Descriptions of every undo entry, the next one to undo first.
+    pub fn undo_descriptions(&self) -> Vec<&str> {
+        self.command_history.undo_descriptions()
+    }
+
+    /// Descriptions of every redo entry, the next one to redo first.
+    pub fn redo_descriptions(&self) -> Vec<&str> {
+        self.command_history.redo_descriptions()
+    }
+
+    /// End the current run of mergeable commands, such as at the end of a
+    /// drag: the next command starts a new undo entry.
+    pub fn seal_history(&mut self) {
+        self.write_due_checkpoint();
+        self.command_history.seal();
+        self.log(JournalEntry::Seal);
+    }
+
+    pub fn history_limits(&self) -> HistoryLimits {
+        self.command_history.limits()
+    }
+
+    pub fn set_history_limits(&mut self, limits: HistoryLimits) {
+        self.command_history.set_limits(limits);
+    }
+
+    pub(crate) fn history(&self) -> &CommandHistory {
+        &self.command_history
+    }
+
+    pub(crate) fn set_history(&mut self, history: CommandHistory) {
+        self.command_history = history.with_limits(self.command_history.limits());
+    }
+
+    // ── Journal ──────────────────────────────────────────────────────
+
+    /// Record history operations in `journal` from now on.
+    pub fn attach_journal(&mut self, journal: Journal) {
+        self.command_history.seal();
+        self.journal = Some(journal);
+    }
+
+    pub fn detach_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Compact the journal into a checkpoint of the current state. `saved`
+    /// tells whether the project file now matches the database. Does
+    /// nothing without a journal or while a transaction is open.
+    pub fn checkpoint(&mut self, saved: bool) -> Result<(), JournalError> {
+        if self.in_transaction() {
+            return Ok(());
+        }
+        let Some(mut journal) = self.journal.take() else {
+            return Ok(());
+        };
+        // Replay starts from a sealed history, so the live one must match.
+        self.command_history.seal();
+        let result = journal.checkpoint(self, saved);
+        self.journal = Some(journal);
+        result
+    }
+
+    /// Write the checkpoint a new journal deferred (see [`Journal::create`])
+    /// before the first change it has to be replayed onto.
+    fn write_due_checkpoint(&mut self) {
+        if self.journal.as_ref().is_some_and(Journal::checkpoint_due) {
+            if let Err(e) = self.checkpoint(true) {
+                log::error!("Failed to write journal checkpoint: {}", e);
+            }
+        }
+    }
+
+    /// Append to the journal, compacting it when it has grown long enough.
+    /// Journal failures must not block editing, so they are only logged.
+    fn log(&mut self, entry: JournalEntry) {
+        let Some(journal) = self.journal.as_mut() else {
+            return;
+        };
+        if let Err(e) = journal.append(&entry) {
+            log::error!("Failed to write journal {}: {}", journal.project().display(), e);
+            return;
+        }
+        if journal.needs_checkpoint() {
+            if let Err(e) = self.checkpoint(false) {
+                log::error!("Failed to write journal checkpoint: {}", e);
+            }
+        }
+    }
+
     // ── Serialization ────────────────────────────────────────────────
 
     pub fn to_json(&self) -> Result<String, serde_json::Error> {
         serde_json::to_string_pretty(self)
     }
 
+    /// Load a project saved by [`to_json`](Self::to_json), migrating files
+    /// written by older versions.
     pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
-        serde_json::from_str(json)
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        let mut db: Self = if value.get("dbu_per_nm").is_some() {
+            serde_json::from_value::<LegacyDatabase>(value)?.migrate()
+        } else {
+            serde_json::from_value(value)?
+        };
+        db.format_version = FORMAT_VERSION;
+        Ok(db)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cell::{Orientation, Transform};
+    use crate::geometry::{GeomPrimitive, Point, Rect};
 
     #[test]
     fn test_database_create() {
@@ -147,6 +736,87 @@ mod tests {
         assert_eq!(db.find_cell_by_name("inverter").unwrap().name, "inverter");
     }
 
+    fn hierarchy() -> (LayoutDatabase, CellId, CellId, CellId) {
+        let mut db = LayoutDatabase::new("test");
+        let mut leaf = Cell::new("leaf");
+        leaf.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 20)));
+        let leaf_id = db.add_cell(leaf);
+
+        let mut mid = Cell::new("mid");
+        mid.add_instance(CellInstance::new(
+            leaf_id,
+            "L0",
+            Transform::new(Point::new(100, 0), Orientation::R90),
+        ));
+        let mid_id = db.add_cell(mid);
+
+        let mut top = Cell::new("top");
+        top.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 5, 5)));
+        top.add_instance(CellInstance::new(mid_id, "M0", Transform::translate(0, 1000)));
+        let top_id = db.add_cell(top);
+        (db, leaf_id, mid_id, top_id)
+    }
+
+    #[test]
+    fn test_hierarchical_bbox() {
+        let (db, leaf_id, mid_id, top_id) = hierarchy();
+        let bb = |id| db.cell_bbox(&id).map(|b| (b.min, b.max));
+        assert_eq!(bb(leaf_id), Some((Point::new(0, 0), Point::new(10, 20))));
+        // R90 maps (x, y) to (-y, x).
+        assert_eq!(bb(mid_id), Some((Point::new(80, 0), Point::new(100, 10))));
+        assert_eq!(bb(top_id), Some((Point::new(0, 0), Point::new(100, 1010))));
+    }
+
+    #[test]
+    fn test_bbox_cache_invalidated_by_descendant_edit() {
+        let (mut db, leaf_id, _, top_id) = hierarchy();
+        assert_eq!(db.cell_bbox(&top_id).unwrap().max, Point::new(100, 1010));
+
+        db.get_cell_mut(&leaf_id)
+            .unwrap()
+            .add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 500)));
+        assert_eq!(db.cell_bbox(&top_id).unwrap().min, Point::new(-400, 0));
+    }
+
+    #[test]
+    fn test_bbox_survives_cycles() {
+        let (mut db, leaf_id, _, top_id) = hierarchy();
+        db.get_cell_mut(&leaf_id)
+            .unwrap()
+            .add_instance(CellInstance::new(top_id, "loop", Transform::default()));
+        assert!(db.cell_bbox(&top_id).is_some());
+    }
+
+    #[test]
+    fn test_spatial_index_follows_edits() {
+        use crate::commands::{AddGeometryCommand, MoveGeometryCommand, RemoveGeometryCommand};
+
+        let (mut db, leaf_id, _, _) = hierarchy();
+        let probe = |db: &LayoutDatabase, x, y| db.query_point(&leaf_id, &Point::new(x, y), Some(1));
+        let original = db.get_cell(&leaf_id).unwrap().shapes[0].id;
+        assert_eq!(probe(&db, 5, 5), vec![original]);
+
+        let add = AddGeometryCommand::new(leaf_id, GeomPrimitive::Rect(Rect::new(1, 100, 0, 110, 10)));
+        let added = add.shape_id();
+        db.execute_command(Box::new(add)).unwrap();
+        assert_eq!(probe(&db, 105, 5), vec![added]);
+
+        db.execute_command(Box::new(MoveGeometryCommand::new(leaf_id, vec![added], Point::new(0, 100))))
+            .unwrap();
+        assert!(probe(&db, 105, 5).is_empty());
+        assert_eq!(probe(&db, 105, 105), vec![added]);
+
+        db.execute_command(Box::new(RemoveGeometryCommand::new(leaf_id, original))).unwrap();
+        assert!(probe(&db, 5, 5).is_empty());
+        assert!(db.undo() && db.undo());
+        assert_eq!(probe(&db, 5, 5), vec![original]);
+        assert_eq!(probe(&db, 105, 5), vec![added]);
+
+        // Direct mutation drops the index; it is rebuilt on the next query.
+        db.get_cell_mut(&leaf_id).unwrap().shapes.clear();
+        assert!(probe(&db, 5, 5).is_empty());
+    }
+
     #[test]
     fn test_top_cell_auto_set() {
         let mut db = LayoutDatabase::new("test");
@@ -154,4 +824,60 @@ mod tests {
         let id = db.add_cell(cell);
         assert_eq!(db.top_cell, Some(id));
     }
+
+    #[test]
+    fn test_load_baseline_project() {
+        let top = "6f7d4c1e-3a52-4d8b-9c2e-1b0a5d6e7f80";
+        let leaf = "0b1c2d3e-4f50-4a6b-8c7d-9e0f1a2b3c4d";
+        let json = format!(
+            r#"{{
+              "id": "11111111-2222-4333-8444-555555555555",
+              "name": "old project",
+              "layer_stack": {{ "layers": [] }},
+              "cells": {{
+                "{top}": {{
+                  "id": "{top}", "name": "top", "modified": true, "pins": [],
+                  "geometries": [
+                    {{ "Rect": {{ "layer_id": 1,
+                        "lower_left": {{ "x": 0.0, "y": 0.0 }},
+                        "upper_right": {{ "x": 1.5, "y": 0.25 }} }} }},
+                    {{ "Path": {{ "layer_id": 2, "width": 0.1,
+                        "points": [{{ "x": 0.0, "y": 0.0 }}, {{ "x": 2.0, "y": 0.0 }}] }} }}
+                  ],
+                  "instances": [{{
+                    "id": "99999999-8888-4777-8666-555555555555",
+                    "cell_id": "{leaf}", "instance_name": "L0",
+                    "transform": {{ "offset": {{ "x": 10.0, "y": -2.5 }},
+                                   "rotation": 90.0, "mirror_x": true, "scale": 1.0 }}
+                  }}]
+                }},
+                "{leaf}": {{
+                  "id": "{leaf}", "name": "leaf", "modified": false, "pins": [],
+                  "geometries": [], "instances": []
+                }}
+              }},
+              "top_cell": "{top}",
+              "dbu_per_nm": 1.0
+            }}"#
+        );
+
+        let db = LayoutDatabase::from_json(&json).unwrap();
+        assert_eq!(db.name, "old project");
+        assert_eq!(db.dbu_per_um, 1000.0);
+        let top_id: CellId = top.parse().unwrap();
+        assert_eq!(db.top_cell, Some(top_id));
+        let cell = db.get_cell(&top_id).unwrap();
+        let geoms: Vec<_> = cell.geometries().cloned().collect();
+        assert_eq!(geoms[0], GeomPrimitive::Rect(Rect::new(1, 0, 0, 1500, 250)));
+        let GeomPrimitive::Path(path) = &geoms[1] else { panic!("not a path") };
+        assert_eq!((path.width, path.points[1]), (100, Point::new(2000, 0)));
+        let inst = &cell.instances[0];
+        assert_eq!(inst.cell_id, leaf.parse::<CellId>().unwrap());
+        assert_eq!(inst.transform.offset, Point::new(10_000, -2500));
+        assert_eq!(inst.transform.orientation, Orientation::MXR90);
+
+        // Saved again, it loads as a current project.
+        let reloaded = LayoutDatabase::from_json(&db.to_json().unwrap()).unwrap();
+        assert_eq!(reloaded.get_cell(&top_id).unwrap().shapes, cell.shapes);
+    }
 }
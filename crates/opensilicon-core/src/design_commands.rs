@@ -0,0 +1,1077 @@
+//! Undoable edits to the design structure: cells, instances, pins and the
+//! layer stack. Geometry edits live in [`crate::commands`].
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::cell::{ArrayParams, Cell, CellId, CellInstance, Pin, Properties, Shape, ShapeId};
+use crate::changes::ChangeEvent;
+use crate::commands::{downcast, Command, CommandError};
+use crate::journal::CommandRecord;
+use crate::database::LayoutDatabase;
+use crate::flatten::FlattenOptions;
+use crate::geometry::Point;
+use crate::layer::{Layer, LayerId};
+use crate::transform::Transform;
+
+fn cell_mut(db: &mut LayoutDatabase, id: CellId) -> Result<&mut Cell, CommandError> {
+    db.get_cell_mut(&id).ok_or(CommandError::UnknownCell(id))
+}
+
+fn check_name_free(db: &LayoutDatabase, name: &str) -> Result<(), CommandError> {
+    match db.find_cell_by_name(name) {
+        Some(_) => Err(CommandError::NameTaken(name.to_string())),
+        None => Ok(()),
+    }
+}
+
+// ══════════════════════════════════════════════════════════════════════
+// Cells
+// ══════════════════════════════════════════════════════════════════════
+
+/// Create an empty cell. The first cell becomes the top cell.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateCellCommand {
+    cell: Cell,
+    previous_top: Option<CellId>,
+}
+
+impl CreateCellCommand {
+    pub fn new(name: &str) -> Self {
+        Self {
+            cell: Cell::new(name),
+            previous_top: None,
+        }
+    }
+
+    /// ID of the cell to be created.
+    pub fn cell_id(&self) -> CellId {
+        self.cell.id
+    }
+}
+
+impl Command for CreateCellCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::CreateCell(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        check_name_free(db, &self.cell.name)?;
+        self.previous_top = db.top_cell;
+        db.add_cell(self.cell.clone());
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        db.remove_cell(&self.cell.id);
+        db.top_cell = self.previous_top;
+    }
+
+    fn description(&self) -> &str {
+        "Create cell"
+    }
+}
+
+/// Rename a cell. Names must stay unique.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenameCellCommand {
+    pub cell_id: CellId,
+    pub name: String,
+    old_name: String,
+}
+
+impl RenameCellCommand {
+    pub fn new(cell_id: CellId, name: &str) -> Self {
+        Self {
+            cell_id,
+            name: name.to_string(),
+            old_name: String::new(),
+        }
+    }
+}
+
+impl Command for RenameCellCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::RenameCell(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        if db.find_cell_by_name(&self.name).is_some_and(|c| c.id != self.cell_id) {
+            return Err(CommandError::NameTaken(self.name.clone()));
+        }
+        let cell = cell_mut(db, self.cell_id)?;
+        self.old_name = std::mem::replace(&mut cell.name, self.name.clone());
+        cell.modified = true;
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        if let Some(cell) = db.get_cell_mut(&self.cell_id) {
+            cell.name = self.old_name.clone();
+        }
+    }
+
+    fn description(&self) -> &str {
+        "Rename cell"
+    }
+}
+
+/// Delete a cell together with every instance of it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteCellCommand {
+    pub cell_id: CellId,
+    removed: Option<Cell>,
+    was_top: bool,
+    /// Instances of the cell removed from their parents, in removal order.
+    placements: Vec<(CellId, usize, CellInstance)>,
+}
+
+impl DeleteCellCommand {
+    pub fn new(cell_id: CellId) -> Self {
+        Self {
+            cell_id,
+            removed: None,
+            was_top: false,
+            placements: Vec::new(),
+        }
+    }
+}
+
+impl Command for DeleteCellCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::DeleteCell(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        if db.get_cell(&self.cell_id).is_none() {
+            return Err(CommandError::UnknownCell(self.cell_id));
+        }
+        let parents = db.hierarchy().parents(&self.cell_id).to_vec();
+        self.placements.clear();
+        for parent in parents {
+            let placed: Vec<Uuid> = db.get_cell(&parent).expect("parent exists").instances
+                .iter()
+                .filter(|i| i.cell_id == self.cell_id)
+                .map(|i| i.id)
+                .collect();
+            // Back to front, so recorded indices stay valid when reinserting
+            // in reverse order.
+            for id in placed.iter().rev() {
+                let (index, instance) = db.remove_instance(&parent, id).expect("instance exists");
+                self.placements.push((parent, index, instance));
+            }
+        }
+        self.was_top = db.top_cell == Some(self.cell_id);
+        self.removed = db.remove_cell(&self.cell_id);
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        let Some(cell) = self.removed.take() else {
+            return;
+        };
+        let top = db.top_cell;
+        db.add_cell(cell);
+        db.top_cell = if self.was_top { Some(self.cell_id) } else { top };
+        for (parent, index, instance) in self.placements.drain(..).rev() {
+            db.insert_instance(&parent, index, instance);
+        }
+    }
+
+    fn description(&self) -> &str {
+        "Delete cell"
+    }
+}
+
+/// Flatten a cell's hierarchy into a target cell, as
+/// [`LayoutDatabase::flatten_into`] does.
+///
+/// The IDs given to the flattened shapes are kept, so redo and journal
+/// replay bring back the same shapes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlattenCellCommand {
+    pub source: CellId,
+    pub target: CellId,
+    pub options: FlattenOptions,
+    shape_ids: Vec<ShapeId>,
+    /// The target's contents before flattening (saved for undo).
+    previous: Option<(Vec<Shape>, Vec<CellInstance>)>,
+}
+
+impl FlattenCellCommand {
+    pub fn new(source: CellId, target: CellId, options: FlattenOptions) -> Self {
+        Self {
+            source,
+            target,
+            options,
+            shape_ids: Vec::new(),
+            previous: None,
+        }
+    }
+
+    /// Flatten a cell in place, replacing its instances with their shapes.
+    pub fn in_place(cell_id: CellId, options: FlattenOptions) -> Self {
+        Self::new(cell_id, cell_id, options)
+    }
+
+    /// IDs of the shapes written to the target, once executed.
+    pub fn shape_ids(&self) -> &[ShapeId] {
+        &self.shape_ids
+    }
+}
+
+impl Command for FlattenCellCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::FlattenCell(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let (mut shapes, instances) =
+            db.flattened_contents(&self.source, &self.target, self.options.clone())?;
+        if self.shape_ids.len() == shapes.len() {
+            for (shape, id) in shapes.iter_mut().zip(&self.shape_ids) {
+                shape.id = *id;
+            }
+        } else {
+            self.shape_ids = shapes.iter().map(|s| s.id).collect();
+        }
+        let cell = cell_mut(db, self.target)?;
+        self.previous = Some((cell.shapes.clone(), cell.instances.clone()));
+        if self.source == self.target {
+            cell.shapes.clear();
+            cell.instances.clear();
+        }
+        cell.shapes.extend(shapes);
+        cell.instances.extend(instances);
+        cell.modified = true;
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        if let Some((shapes, instances)) = self.previous.take() {
+            if let Some(cell) = db.get_cell_mut(&self.target) {
+                cell.shapes = shapes;
+                cell.instances = instances;
+            }
+        }
+    }
+
+    fn description(&self) -> &str {
+        "Flatten cell"
+    }
+}
+
+// ══════════════════════════════════════════════════════════════════════
+// Instances
+// ══════════════════════════════════════════════════════════════════════
+
+/// Place an instance of a cell, refusing placements that would create a
+/// cycle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaceInstanceCommand {
+    pub parent: CellId,
+    pub instance: CellInstance,
+}
+
+impl PlaceInstanceCommand {
+    pub fn new(parent: CellId, instance: CellInstance) -> Self {
+        Self { parent, instance }
+    }
+
+    pub fn instance_id(&self) -> Uuid {
+        self.instance.id
+    }
+}
+
+impl Command for PlaceInstanceCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::PlaceInstance(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        db.add_instance(&self.parent, self.instance.clone())?;
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        db.remove_instance(&self.parent, &self.instance.id);
+    }
+
+    fn description(&self) -> &str {
+        "Place instance"
+    }
+}
+
+/// Give an instance a new placement transform.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransformInstanceCommand {
+    pub parent: CellId,
+    pub instance_id: Uuid,
+    pub transform: Transform,
+    description: String,
+    old: Option<Transform>,
+}
+
+impl TransformInstanceCommand {
+    pub fn new(parent: CellId, instance_id: Uuid, transform: Transform) -> Self {
+        Self {
+            parent,
+            instance_id,
+            transform,
+            description: "Transform instance".to_string(),
+            old: None,
+        }
+    }
+
+    /// Shift an instance's placement by `delta`, keeping its orientation.
+    pub fn translate(
+        db: &LayoutDatabase,
+        parent: CellId,
+        instance_id: Uuid,
+        delta: Point,
+    ) -> Result<Self, CommandError> {
+        let instance = db
+            .get_cell(&parent)
+            .ok_or(CommandError::UnknownCell(parent))?
+            .instance(&instance_id)
+            .ok_or(CommandError::UnknownInstance {
+                cell: parent,
+                instance: instance_id,
+            })?;
+        let mut transform = instance.transform;
+        transform.offset = transform.offset.translate(delta.x, delta.y);
+        Ok(Self {
+            description: "Move instance".to_string(),
+            ..Self::new(parent, instance_id, transform)
+        })
+    }
+}
+
+impl Command for TransformInstanceCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::TransformInstance(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let (parent, id) = (self.parent, self.instance_id);
+        if db.get_cell(&parent).is_none() {
+            return Err(CommandError::UnknownCell(parent));
+        }
+        self.old = db.set_instance_transform(&parent, &id, self.transform);
+        match self.old {
+            Some(_) => Ok(()),
+            None => Err(CommandError::UnknownInstance { cell: parent, instance: id }),
+        }
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        if let Some(old) = self.old.take() {
+            db.set_instance_transform(&self.parent, &self.instance_id, old);
+        }
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Successive moves of the same instance, as while dragging it, keep
+    /// the first one's undo state. A move does not merge with a rotation.
+    fn merge(&mut self, next: &dyn Command) -> bool {
+        let same = |next: &Self| {
+            (next.parent, next.instance_id, &next.description)
+                == (self.parent, self.instance_id, &self.description)
+        };
+        match downcast::<Self>(next) {
+            Some(next) if same(next) => {
+                self.transform = next.transform;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Make an instance an array, change its array, or make it a single
+/// placement again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetInstanceArrayCommand {
+    pub parent: CellId,
+    pub instance_id: Uuid,
+    pub array: Option<ArrayParams>,
+    old: Option<ArrayParams>,
+}
+
+impl SetInstanceArrayCommand {
+    pub fn new(parent: CellId, instance_id: Uuid, array: Option<ArrayParams>) -> Self {
+        Self {
+            parent,
+            instance_id,
+            array,
+            old: None,
+        }
+    }
+}
+
+impl Command for SetInstanceArrayCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::SetInstanceArray(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let (parent, id) = (self.parent, self.instance_id);
+        if self.array.is_some_and(|a| a.is_empty()) {
+            return Err(CommandError::InvalidEdit("an array needs at least one element".into()));
+        }
+        if db.get_cell(&parent).is_none() {
+            return Err(CommandError::UnknownCell(parent));
+        }
+        self.old = db
+            .set_instance_array(&parent, &id, self.array)
+            .ok_or(CommandError::UnknownInstance { cell: parent, instance: id })?;
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        db.set_instance_array(&self.parent, &self.instance_id, self.old);
+    }
+
+    fn description(&self) -> &str {
+        "Change instance array"
+    }
+}
+
+/// Remove an instance from its parent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteInstanceCommand {
+    pub parent: CellId,
+    pub instance_id: Uuid,
+    removed: Option<(usize, CellInstance)>,
+}
+
+impl DeleteInstanceCommand {
+    pub fn new(parent: CellId, instance_id: Uuid) -> Self {
+        Self {
+            parent,
+            instance_id,
+            removed: None,
+        }
+    }
+}
+
+impl Command for DeleteInstanceCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::DeleteInstance(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let (parent, id) = (self.parent, self.instance_id);
+        if db.get_cell(&parent).is_none() {
+            return Err(CommandError::UnknownCell(parent));
+        }
+        self.removed = db.remove_instance(&parent, &id);
+        match self.removed {
+            Some(_) => Ok(()),
+            None => Err(CommandError::UnknownInstance { cell: parent, instance: id }),
+        }
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        if let Some((index, instance)) = self.removed.take() {
+            db.insert_instance(&self.parent, index, instance);
+        }
+    }
+
+    fn description(&self) -> &str {
+        "Delete instance"
+    }
+}
+
+// ══════════════════════════════════════════════════════════════════════
+// Pins
+// ══════════════════════════════════════════════════════════════════════
+
+/// Add a pin, or replace the pin called `name` (which may rename it).
+/// Pin names are unique within a cell.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetPinCommand {
+    pub cell_id: CellId,
+    /// The pin to replace, or `None` to add a new one.
+    pub name: Option<String>,
+    pub pin: Pin,
+    old: Option<(usize, Pin)>,
+}
+
+impl SetPinCommand {
+    pub fn add(cell_id: CellId, pin: Pin) -> Self {
+        Self {
+            cell_id,
+            name: None,
+            pin,
+            old: None,
+        }
+    }
+
+    pub fn replace(cell_id: CellId, name: &str, pin: Pin) -> Self {
+        Self {
+            name: Some(name.to_string()),
+            ..Self::add(cell_id, pin)
+        }
+    }
+}
+
+impl Command for SetPinCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::SetPin(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let cell_id = self.cell_id;
+        let cell = cell_mut(db, cell_id)?;
+        let renamed = self.name.as_ref() != Some(&self.pin.name);
+        if renamed && cell.pin(&self.pin.name).is_some() {
+            return Err(CommandError::NameTaken(self.pin.name.clone()));
+        }
+        self.old = match &self.name {
+            Some(name) => Some(cell.remove_pin(name).ok_or_else(|| CommandError::UnknownPin {
+                cell: cell_id,
+                name: name.clone(),
+            })?),
+            None => None,
+        };
+        match &self.old {
+            Some((index, _)) => cell.insert_pin(*index, self.pin.clone()),
+            None => cell.add_pin(self.pin.clone()),
+        }
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        let Some(cell) = db.get_cell_mut(&self.cell_id) else {
+            return;
+        };
+        cell.remove_pin(&self.pin.name);
+        if let Some((index, pin)) = self.old.take() {
+            cell.insert_pin(index, pin);
+        }
+    }
+
+    fn description(&self) -> &str {
+        match self.name {
+            Some(_) => "Edit pin",
+            None => "Add pin",
+        }
+    }
+}
+
+/// Remove a pin by name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemovePinCommand {
+    pub cell_id: CellId,
+    pub name: String,
+    removed: Option<(usize, Pin)>,
+}
+
+impl RemovePinCommand {
+    pub fn new(cell_id: CellId, name: &str) -> Self {
+        Self {
+            cell_id,
+            name: name.to_string(),
+            removed: None,
+        }
+    }
+}
+
+impl Command for RemovePinCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::RemovePin(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let cell_id = self.cell_id;
+        self.removed = cell_mut(db, cell_id)?.remove_pin(&self.name);
+        match self.removed {
+            Some(_) => Ok(()),
+            None => Err(CommandError::UnknownPin { cell: cell_id, name: self.name.clone() }),
+        }
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        if let Some((index, pin)) = self.removed.take() {
+            if let Some(cell) = db.get_cell_mut(&self.cell_id) {
+                cell.insert_pin(index, pin);
+            }
+        }
+    }
+
+    fn description(&self) -> &str {
+        "Remove pin"
+    }
+}
+
+// ══════════════════════════════════════════════════════════════════════
+// Properties
+// ══════════════════════════════════════════════════════════════════════
+
+/// Whose properties a [`SetPropertyCommand`] edits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PropertyOwner {
+    Cell,
+    Shape(ShapeId),
+    Instance(Uuid),
+}
+
+/// Set a property of a cell or of one of its shapes or instances, or with
+/// no `value`, remove it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetPropertyCommand {
+    pub cell_id: CellId,
+    pub owner: PropertyOwner,
+    pub attribute: u16,
+    pub value: Option<String>,
+    old: Option<String>,
+}
+
+impl SetPropertyCommand {
+    pub fn new(
+        cell_id: CellId,
+        owner: PropertyOwner,
+        attribute: u16,
+        value: Option<String>,
+    ) -> Self {
+        Self {
+            cell_id,
+            owner,
+            attribute,
+            value,
+            old: None,
+        }
+    }
+}
+
+fn properties_mut(
+    db: &mut LayoutDatabase,
+    cell_id: CellId,
+    owner: PropertyOwner,
+) -> Result<&mut Properties, CommandError> {
+    let cell = cell_mut(db, cell_id)?;
+    let properties = match owner {
+        PropertyOwner::Cell => &mut cell.properties,
+        PropertyOwner::Shape(shape) => {
+            let found = cell.shapes.iter_mut().find(|s| s.id == shape);
+            &mut found.ok_or(CommandError::UnknownShape { cell: cell_id, shape })?.properties
+        }
+        PropertyOwner::Instance(instance) => {
+            let found = cell.instances.iter_mut().find(|i| i.id == instance);
+            &mut found.ok_or(CommandError::UnknownInstance { cell: cell_id, instance })?.properties
+        }
+    };
+    cell.modified = true;
+    Ok(properties)
+}
+
+impl Command for SetPropertyCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::SetProperty(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let properties = properties_mut(db, self.cell_id, self.owner)?;
+        self.old = match &self.value {
+            Some(value) => properties.insert(self.attribute, value.clone()),
+            None => properties.remove(&self.attribute),
+        };
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        let Ok(properties) = properties_mut(db, self.cell_id, self.owner) else {
+            return;
+        };
+        match self.old.take() {
+            Some(old) => properties.insert(self.attribute, old),
+            None => properties.remove(&self.attribute),
+        };
+    }
+
+    fn description(&self) -> &str {
+        match self.value {
+            Some(_) => "Set property",
+            None => "Remove property",
+        }
+    }
+}
+
+// ══════════════════════════════════════════════════════════════════════
+// Layers
+// ══════════════════════════════════════════════════════════════════════
+
+/// Add a layer to the technology stack.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddLayerCommand {
+    pub layer: Layer,
+}
+
+impl AddLayerCommand {
+    pub fn new(layer: Layer) -> Self {
+        Self { layer }
+    }
+}
+
+impl Command for AddLayerCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::AddLayer(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        if db.layer_stack.get_layer(self.layer.id).is_some() {
+            return Err(CommandError::DuplicateLayer(self.layer.id));
+        }
+        db.layer_stack.add_layer(self.layer.clone());
+        db.notify(ChangeEvent::LayersChanged);
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        db.layer_stack.remove_layer(self.layer.id);
+        db.notify(ChangeEvent::LayersChanged);
+    }
+
+    fn description(&self) -> &str {
+        "Add layer"
+    }
+}
+
+/// Change the properties of a layer: color, visibility, name and so on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditLayerCommand {
+    pub id: LayerId,
+    edit: LayerEdit,
+    old: Option<Layer>,
+}
+
+/// An edit applied by [`EditLayerCommand`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum LayerEdit {
+    Replace(Layer),
+    Visible(bool),
+    Color(u8, u8, u8),
+}
+
+impl EditLayerCommand {
+    /// Replace the layer with the same ID by `layer`.
+    pub fn replace(layer: Layer) -> Self {
+        Self {
+            id: layer.id,
+            edit: LayerEdit::Replace(layer),
+            old: None,
+        }
+    }
+
+    pub fn set_visible(id: LayerId, visible: bool) -> Self {
+        Self {
+            id,
+            edit: LayerEdit::Visible(visible),
+            old: None,
+        }
+    }
+
+    pub fn set_color(id: LayerId, r: u8, g: u8, b: u8) -> Self {
+        Self {
+            id,
+            edit: LayerEdit::Color(r, g, b),
+            old: None,
+        }
+    }
+}
+
+impl Command for EditLayerCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::EditLayer(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        let layer = db
+            .layer_stack
+            .get_layer_mut(self.id)
+            .ok_or(CommandError::UnknownLayer(self.id))?;
+        self.old = Some(layer.clone());
+        match &self.edit {
+            LayerEdit::Replace(new) => *layer = new.clone(),
+            LayerEdit::Visible(visible) => layer.visible = *visible,
+            LayerEdit::Color(r, g, b) => *layer = layer.clone().with_color(*r, *g, *b),
+        }
+        db.notify(ChangeEvent::LayersChanged);
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        if let (Some(old), Some(layer)) = (self.old.take(), db.layer_stack.get_layer_mut(self.id)) {
+            *layer = old;
+            db.notify(ChangeEvent::LayersChanged);
+        }
+    }
+
+    fn description(&self) -> &str {
+        match self.edit {
+            LayerEdit::Visible(_) => "Change layer visibility",
+            LayerEdit::Color(..) => "Change layer color",
+            LayerEdit::Replace(_) => "Edit layer",
+        }
+    }
+
+    /// Successive color changes of one layer, as from a color picker,
+    /// become one entry.
+    fn merge(&mut self, next: &dyn Command) -> bool {
+        let colors = |next: &Self| {
+            next.id == self.id
+                && matches!((&self.edit, &next.edit), (LayerEdit::Color(..), LayerEdit::Color(..)))
+        };
+        match downcast::<Self>(next) {
+            Some(next) if colors(next) => {
+                self.edit = next.edit.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Show or hide every layer at once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetAllLayersVisibleCommand {
+    pub visible: bool,
+    old: Vec<(LayerId, bool)>,
+}
+
+impl SetAllLayersVisibleCommand {
+    pub fn new(visible: bool) -> Self {
+        Self {
+            visible,
+            old: Vec::new(),
+        }
+    }
+}
+
+impl Command for SetAllLayersVisibleCommand {
+    fn to_record(&self) -> CommandRecord {
+        CommandRecord::SetAllLayersVisible(self.clone())
+    }
+
+    fn execute(&mut self, db: &mut LayoutDatabase) -> Result<(), CommandError> {
+        self.old = db.layer_stack.all_layers().iter().map(|l| (l.id, l.visible)).collect();
+        db.layer_stack.set_all_visible(self.visible);
+        db.notify(ChangeEvent::LayersChanged);
+        Ok(())
+    }
+
+    fn undo(&mut self, db: &mut LayoutDatabase) {
+        for (id, visible) in self.old.drain(..) {
+            if let Some(layer) = db.layer_stack.get_layer_mut(id) {
+                layer.visible = visible;
+            }
+        }
+        db.notify(ChangeEvent::LayersChanged);
+    }
+
+    fn description(&self) -> &str {
+        if self.visible {
+            "Show all layers"
+        } else {
+            "Hide all layers"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::PinDirection;
+    use crate::geometry::{GeomPrimitive, Rect};
+
+    fn placed(db: &LayoutDatabase, parent: CellId) -> Vec<Uuid> {
+        db.get_cell(&parent).unwrap().instances.iter().map(|i| i.id).collect()
+    }
+
+    #[test]
+    fn test_cell_create_rename_delete_undo() {
+        let mut db = LayoutDatabase::new("test");
+        let create = CreateCellCommand::new("top");
+        let top = create.cell_id();
+        db.execute_command(Box::new(create)).unwrap();
+        assert_eq!(db.top_cell, Some(top));
+        let create = CreateCellCommand::new("leaf");
+        let leaf = create.cell_id();
+        db.execute_command(Box::new(create)).unwrap();
+        assert_eq!(
+            db.execute_command(Box::new(CreateCellCommand::new("leaf"))),
+            Err(CommandError::NameTaken("leaf".to_string()))
+        );
+
+        let a = CellInstance::new(leaf, "A", Transform::default());
+        let b = CellInstance::new(leaf, "B", Transform::translate(100, 0));
+        let ids = vec![a.id, b.id];
+        db.execute_command(Box::new(PlaceInstanceCommand::new(top, a))).unwrap();
+        db.execute_command(Box::new(PlaceInstanceCommand::new(top, b))).unwrap();
+
+        db.execute_command(Box::new(RenameCellCommand::new(leaf, "inv"))).unwrap();
+        assert!(db.find_cell_by_name("inv").is_some());
+        db.execute_command(Box::new(DeleteCellCommand::new(leaf))).unwrap();
+        assert!(db.get_cell(&leaf).is_none());
+        assert!(placed(&db, top).is_empty());
+
+        assert!(db.undo());
+        assert_eq!(placed(&db, top), ids);
+        assert!(db.undo());
+        assert!(db.find_cell_by_name("leaf").is_some());
+
+        while db.undo() {}
+        assert_eq!(db.cell_count(), 0);
+        assert_eq!(db.top_cell, None);
+    }
+
+    #[test]
+    fn test_flatten_cell_undo() {
+        let mut db = LayoutDatabase::new("test");
+        let mut leaf = Cell::new("leaf");
+        leaf.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 10)));
+        let leaf = db.add_cell(leaf);
+        let mut top = Cell::new("top");
+        let own = top.add_geometry(GeomPrimitive::Rect(Rect::new(2, 0, 0, 5, 5)));
+        top.add_instance(CellInstance::new(leaf, "A", Transform::translate(100, 0)));
+        let top = db.add_cell(top);
+        let before = db.get_cell(&top).unwrap().shapes.clone();
+        let instances = placed(&db, top);
+
+        let mut flatten = FlattenCellCommand::in_place(top, FlattenOptions::new());
+        flatten.execute(&mut db).unwrap();
+        let ids = flatten.shape_ids().to_vec();
+        let cell = db.get_cell(&top).unwrap();
+        assert!(cell.instances.is_empty());
+        assert_eq!(cell.shapes.len(), 2);
+        assert_eq!(cell.shapes[0].id, own);
+        assert_eq!(cell.shapes[1].geometry.bbox().unwrap().min, Point::new(100, 0));
+
+        // Undo restores the instance; replaying the record brings back the
+        // same shape IDs.
+        flatten.undo(&mut db);
+        assert_eq!(db.get_cell(&top).unwrap().shapes, before);
+        assert_eq!(placed(&db, top), instances);
+        let replayed = flatten.to_record().into_command();
+        db.execute_command(replayed).unwrap();
+        let flat: Vec<ShapeId> = db.get_cell(&top).unwrap().shapes.iter().map(|s| s.id).collect();
+        assert_eq!(flat, ids);
+        assert!(db.undo());
+        assert_eq!(placed(&db, top), instances);
+    }
+
+    #[test]
+    fn test_instance_place_move_delete_undo() {
+        let mut db = LayoutDatabase::new("test");
+        let top = db.add_cell(Cell::new("top"));
+        let leaf = db.add_cell(Cell::new("leaf"));
+        let inst = CellInstance::new(leaf, "A", Transform::default());
+        let id = inst.id;
+        db.execute_command(Box::new(PlaceInstanceCommand::new(top, inst))).unwrap();
+
+        // Placing `top` inside `leaf` would make a cycle.
+        let back = CellInstance::new(top, "T", Transform::default());
+        assert!(matches!(
+            db.execute_command(Box::new(PlaceInstanceCommand::new(leaf, back))),
+            Err(CommandError::Hierarchy(_))
+        ));
+
+        let cmd = TransformInstanceCommand::translate(&db, top, id, Point::new(5, 7)).unwrap();
+        db.execute_command(Box::new(cmd)).unwrap();
+        let rotated = Transform::new(Point::new(5, 7), crate::Orientation::R90);
+        db.execute_command(Box::new(TransformInstanceCommand::new(top, id, rotated))).unwrap();
+        let instance = |db: &LayoutDatabase| db.get_cell(&top).unwrap().instance(&id).cloned();
+        assert_eq!(instance(&db).unwrap().transform, rotated);
+
+        db.execute_command(Box::new(DeleteInstanceCommand::new(top, id))).unwrap();
+        assert!(instance(&db).is_none());
+        assert!(db.undo() && db.undo());
+        assert_eq!(instance(&db).unwrap().transform.offset, Point::new(5, 7));
+        assert!(db.undo() && db.undo());
+        assert!(instance(&db).is_none());
+    }
+
+    #[test]
+    fn test_pin_edits_undo() {
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("inv"));
+        let pin = |name: &str| Pin {
+            name: name.to_string(),
+            layer_id: 1,
+            shape: GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 10)),
+            direction: PinDirection::Input,
+        };
+        db.execute_command(Box::new(SetPinCommand::add(cell_id, pin("A")))).unwrap();
+        db.execute_command(Box::new(SetPinCommand::add(cell_id, pin("Y")))).unwrap();
+        assert_eq!(
+            db.execute_command(Box::new(SetPinCommand::replace(cell_id, "A", pin("Y")))),
+            Err(CommandError::NameTaken("Y".to_string()))
+        );
+        db.execute_command(Box::new(SetPinCommand::replace(cell_id, "A", pin("IN")))).unwrap();
+        db.execute_command(Box::new(RemovePinCommand::new(cell_id, "Y"))).unwrap();
+        let names = |db: &LayoutDatabase| -> Vec<String> {
+            db.get_cell(&cell_id).unwrap().pins.iter().map(|p| p.name.clone()).collect()
+        };
+        assert_eq!(names(&db), ["IN"]);
+        assert!(db.undo() && db.undo());
+        assert_eq!(names(&db), ["A", "Y"]);
+    }
+
+    #[test]
+    fn test_layer_edits_undo() {
+        let mut db = LayoutDatabase::new("test");
+        db.layer_stack.add_layer(Layer::new(1, "metal1", 68, 20));
+        let add = |layer| Box::new(AddLayerCommand::new(layer));
+        db.execute_command(add(Layer::new(2, "metal2", 69, 20))).unwrap();
+        assert_eq!(
+            db.execute_command(add(Layer::new(2, "dup", 1, 0))),
+            Err(CommandError::DuplicateLayer(2))
+        );
+        db.execute_command(Box::new(EditLayerCommand::set_visible(1, false))).unwrap();
+        db.execute_command(Box::new(EditLayerCommand::set_color(2, 10, 20, 30))).unwrap();
+        db.execute_command(Box::new(SetAllLayersVisibleCommand::new(false))).unwrap();
+        assert_eq!(db.layer_stack.visible_layers().count(), 0);
+
+        assert!(db.undo());
+        assert_eq!(db.layer_stack.visible_layers().count(), 1);
+        assert!(db.undo());
+        assert_ne!(db.layer_stack.get_layer(2).unwrap().color.r, 10);
+        assert!(db.undo() && db.undo());
+        assert_eq!(db.layer_stack.layer_count(), 1);
+        assert!(db.layer_stack.get_layer(1).unwrap().visible);
+    }
+
+    #[test]
+    fn test_property_edits_undo() {
+        let mut db = LayoutDatabase::new("test");
+        let mut cell = Cell::new("top");
+        let shape = cell.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 10)));
+        let cell_id = db.add_cell(cell);
+        let set = |owner, value: Option<&str>| {
+            Box::new(SetPropertyCommand::new(cell_id, owner, 1, value.map(String::from)))
+        };
+        db.execute_command(set(PropertyOwner::Shape(shape), Some("VDD"))).unwrap();
+        db.execute_command(set(PropertyOwner::Shape(shape), Some("VSS"))).unwrap();
+        db.execute_command(set(PropertyOwner::Cell, Some("inverter"))).unwrap();
+        db.execute_command(set(PropertyOwner::Cell, None)).unwrap();
+        let missing = Uuid::new_v4();
+        assert_eq!(
+            db.execute_command(set(PropertyOwner::Instance(missing), Some("I0"))),
+            Err(CommandError::UnknownInstance { cell: cell_id, instance: missing })
+        );
+
+        let cell = db.get_cell(&cell_id).unwrap();
+        assert!(cell.properties.is_empty());
+        assert_eq!(cell.shape(&shape).unwrap().properties[&1], "VSS");
+        assert!(db.undo());
+        assert_eq!(db.get_cell(&cell_id).unwrap().properties[&1], "inverter");
+        assert!(db.undo() && db.undo());
+        let cell = db.get_cell(&cell_id).unwrap();
+        assert!(cell.properties.is_empty());
+        assert_eq!(cell.shape(&shape).unwrap().properties[&1], "VDD");
+    }
+}
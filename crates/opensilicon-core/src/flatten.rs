@@ -0,0 +1,469 @@
+//! Hierarchy flattening.
+//!
+//! [`LayoutDatabase::flatten`] walks a cell and its subcells depth-first and
+//! yields every shape transformed into the cell's coordinates, together with
+//! the chain of instances it was reached through. [`LayoutDatabase::flatten_into`]
+//! writes the same shapes into a target cell.
+//!
+//! With a depth limit, instances below the limit are not expanded; the
+//! iterator collects them (see [`FlattenIter::unexpanded`]) and
+//! `flatten_into` copies them into the target, so the result still draws the
+//! same layout.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cell::{
+    Cell, CellId, CellInstance, PathStep, PlacementIndices, Properties, Shape, ShapeId,
+    Transform,
+};
+use crate::database::LayoutDatabase;
+use crate::geometry::{BBox, GeomPrimitive};
+use crate::hierarchy::HierarchyError;
+use crate::layer::LayerId;
+
+/// What to flatten.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FlattenOptions {
+    /// Number of instance levels to expand; `None` expands everything and
+    /// `Some(0)` yields only the cell's own shapes.
+    pub max_depth: Option<usize>,
+    /// Only yield shapes on these layers (all layers if `None`).
+    pub layers: Option<HashSet<LayerId>>,
+    /// Only yield shapes whose bounding box touches this region, given in
+    /// the flattened cell's coordinates.
+    pub region: Option<BBox>,
+}
+
+impl FlattenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    pub fn with_layers(mut self, layers: impl IntoIterator<Item = LayerId>) -> Self {
+        self.layers = Some(layers.into_iter().collect());
+        self
+    }
+
+    pub fn with_region(mut self, region: BBox) -> Self {
+        self.region = Some(region);
+        self
+    }
+}
+
+/// A shape of a flattened hierarchy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlatShape {
+    /// The shape in the flattened cell's coordinates.
+    pub geometry: GeomPrimitive,
+    /// The cell that defines the shape.
+    pub cell: CellId,
+    /// The shape in that cell.
+    pub shape: ShapeId,
+    /// The shape's GDS-II properties.
+    pub properties: Properties,
+    /// Placements leading from the flattened cell down to `cell`, empty for
+    /// the flattened cell's own shapes.
+    pub path: Vec<PathStep>,
+}
+
+/// Depth-first iterator over the shapes of a cell hierarchy.
+pub struct FlattenIter<'a> {
+    db: &'a LayoutDatabase,
+    options: FlattenOptions,
+    stack: Vec<Frame<'a>>,
+    unexpanded: Vec<CellInstance>,
+}
+
+/// A cell being walked, with its placement relative to the flattened cell.
+struct Frame<'a> {
+    cell: &'a Cell,
+    transform: Transform,
+    path: Vec<PathStep>,
+    next_shape: usize,
+    next_instance: usize,
+    /// Placements still to visit of the instance before `next_instance`.
+    elements: Option<PlacementIndices>,
+}
+
+impl<'a> FlattenIter<'a> {
+    fn new(db: &'a LayoutDatabase, cell: &'a Cell, options: FlattenOptions) -> Self {
+        Self {
+            db,
+            options,
+            stack: vec![Frame {
+                cell,
+                transform: Transform::default(),
+                path: Vec::new(),
+                next_shape: 0,
+                next_instance: 0,
+                elements: None,
+            }],
+            unexpanded: Vec::new(),
+        }
+    }
+
+    /// Instances met at the depth limit so far, placed relative to the
+    /// flattened cell. Complete once the iterator is exhausted.
+    pub fn unexpanded(&self) -> &[CellInstance] {
+        &self.unexpanded
+    }
+
+    fn touches_region(&self, bbox: Option<BBox>) -> bool {
+        match (&self.options.region, bbox) {
+            (None, _) => true,
+            (Some(region), Some(bbox)) => region.intersects(&bbox),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Start on an instance of the top frame: record it if it is at the
+    /// depth limit, otherwise pick the placements to expand.
+    fn visit_instance(&mut self, inst: &'a CellInstance) {
+        let db = self.db;
+        let Some(child) = db.get_cell(&inst.cell_id) else {
+            return;
+        };
+        let parent = self.stack.last().expect("frame exists");
+        // A cell already being walked would recurse forever.
+        if self.stack.iter().any(|f| f.cell.id == child.id) {
+            log::warn!("Skipping recursive reference to cell '{}'", child.name);
+            return;
+        }
+
+        let depth = self.stack.len() - 1;
+        if self.options.max_depth.is_some_and(|max| depth >= max) {
+            // Arrays stay arrays.
+            let placed = inst.placed_in(&parent.transform);
+            let bbox = db.cell_bbox(&child.id).map(|bb| placed.placed_bbox(&bb));
+            if self.touches_region(bbox) {
+                self.unexpanded.push(placed);
+            }
+            return;
+        }
+
+        let elements = match (&self.options.region, db.cell_bbox(&child.id)) {
+            (None, _) => inst.placement_indices(),
+            (Some(region), Some(bbox)) => {
+                let mut local = parent.transform.inverse().apply_bbox(region);
+                if !parent.transform.is_exact() {
+                    // Allow for rounding in the inverse mapping.
+                    local = BBox::new(local.min.translate(-1, -1), local.max.translate(1, 1));
+                }
+                inst.placement_indices_near(&bbox, &local)
+            }
+            (Some(_), None) => return,
+        };
+        self.stack.last_mut().expect("frame exists").elements = Some(elements);
+    }
+
+    /// Expand placement `element` of the instance the top frame is on, if
+    /// it touches the region.
+    fn visit_element(&mut self, inst: &'a CellInstance, element: usize) {
+        let db = self.db;
+        let child = db.get_cell(&inst.cell_id).expect("checked by visit_instance");
+        let parent = self.stack.last().expect("frame exists");
+        let transform = parent.transform.compose(&inst.placement(element));
+        let bbox = db.cell_bbox(&child.id).map(|bb| transform.apply_bbox(&bb));
+        if !self.touches_region(bbox) {
+            return;
+        }
+        let mut path = parent.path.clone();
+        path.push(PathStep { instance: inst.id, element });
+        self.stack.push(Frame {
+            cell: child,
+            transform,
+            path,
+            next_shape: 0,
+            next_instance: 0,
+            elements: None,
+        });
+    }
+}
+
+impl Iterator for FlattenIter<'_> {
+    type Item = FlatShape;
+
+    fn next(&mut self) -> Option<FlatShape> {
+        loop {
+            let frame = self.stack.last_mut()?;
+            let cell = frame.cell;
+
+            if frame.next_shape < cell.shapes.len() {
+                let shape = &cell.shapes[frame.next_shape];
+                frame.next_shape += 1;
+                if let Some(layers) = &self.options.layers {
+                    if !layers.contains(&shape.geometry.layer_id()) {
+                        continue;
+                    }
+                }
+                let geometry = frame.transform.apply_primitive(&shape.geometry);
+                let path = frame.path.clone();
+                if !self.touches_region(geometry.bbox()) {
+                    continue;
+                }
+                return Some(FlatShape {
+                    geometry,
+                    cell: cell.id,
+                    shape: shape.id,
+                    properties: shape.properties.clone(),
+                    path,
+                });
+            }
+
+            if let Some(element) = frame.elements.as_mut().and_then(Iterator::next) {
+                let inst = &cell.instances[frame.next_instance - 1];
+                self.visit_element(inst, element);
+                continue;
+            }
+
+            if frame.next_instance < cell.instances.len() {
+                let inst = &cell.instances[frame.next_instance];
+                frame.next_instance += 1;
+                frame.elements = None;
+                self.visit_instance(inst);
+                continue;
+            }
+
+            self.stack.pop();
+        }
+    }
+}
+
+impl LayoutDatabase {
+    /// Iterate over the shapes of `id` and its subcells in `id`'s
+    /// coordinates.
+    pub fn flatten(
+        &self,
+        id: &CellId,
+        options: FlattenOptions,
+    ) -> Result<FlattenIter<'_>, HierarchyError> {
+        let cell = self.get_cell(id).ok_or(HierarchyError::UnknownCell(*id))?;
+        Ok(FlattenIter::new(self, cell, options))
+    }
+
+    /// Flatten `source` into `target` and return the number of shapes
+    /// written. Shapes and unexpanded instances are appended to `target`'s
+    /// contents; flattening a cell into itself replaces its contents.
+    ///
+    /// This edit bypasses the undo history; use
+    /// [`FlattenCellCommand`](crate::design_commands::FlattenCellCommand) for an undoable one.
+    pub fn flatten_into(
+        &mut self,
+        source: &CellId,
+        target: &CellId,
+        options: FlattenOptions,
+    ) -> Result<usize, HierarchyError> {
+        let (shapes, instances) = self.flattened_contents(source, target, options)?;
+        let count = shapes.len();
+        let cell = self.get_cell_mut(target).expect("target checked");
+        if source == target {
+            cell.shapes.clear();
+            cell.instances.clear();
+        }
+        cell.shapes.extend(shapes);
+        cell.instances.extend(instances);
+        Ok(count)
+    }
+
+    /// The shapes and unexpanded instances [`flatten_into`](Self::flatten_into)
+    /// writes to `target`. When flattening a cell into itself, its own shapes
+    /// keep their IDs; every other shape gets a new one.
+    pub(crate) fn flattened_contents(
+        &self,
+        source: &CellId,
+        target: &CellId,
+        options: FlattenOptions,
+    ) -> Result<(Vec<Shape>, Vec<CellInstance>), HierarchyError> {
+        if self.get_cell(target).is_none() {
+            return Err(HierarchyError::UnknownCell(*target));
+        }
+        let in_place = source == target;
+        let mut iter = self.flatten(source, options)?;
+        let shapes: Vec<Shape> = iter
+            .by_ref()
+            .map(|s| {
+                let shape = Shape::new(s.geometry).with_properties(s.properties);
+                if in_place && s.path.is_empty() {
+                    Shape { id: s.shape, ..shape }
+                } else {
+                    shape
+                }
+            })
+            .collect();
+        let instances = std::mem::take(&mut iter.unexpanded);
+
+        // Copied instances must not make `target` place itself.
+        let hierarchy = self.hierarchy();
+        for inst in &instances {
+            let cycle = if inst.cell_id == *target {
+                Some(vec![*target, *target])
+            } else {
+                hierarchy
+                    .path(&inst.cell_id, target)
+                    .map(|path| std::iter::once(*target).chain(path).collect::<Vec<_>>())
+            };
+            if let Some(cycle) = cycle {
+                return Err(hierarchy.cycle_error(&cycle));
+            }
+        }
+        Ok((shapes, instances))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::{ArrayParams, Orientation};
+    use crate::geometry::{Point, Rect};
+
+    fn rect(layer: LayerId, x1: i64, y1: i64, x2: i64, y2: i64) -> GeomPrimitive {
+        GeomPrimitive::Rect(Rect::new(layer, x1, y1, x2, y2))
+    }
+
+    /// `top` places `mid` at (1000, 0) and (0, 1000); `mid` places `leaf`
+    /// rotated by 90° at (100, 0).
+    fn hierarchy() -> (LayoutDatabase, CellId, CellId, CellId) {
+        let mut db = LayoutDatabase::new("test");
+        let mut leaf = Cell::new("leaf");
+        leaf.add_geometry(rect(1, 0, 0, 10, 20));
+        leaf.add_geometry(rect(2, 0, 0, 5, 5));
+        let leaf_id = db.add_cell(leaf);
+
+        let mut mid = Cell::new("mid");
+        mid.add_geometry(rect(1, 0, 0, 50, 50));
+        mid.add_instance(CellInstance::new(
+            leaf_id,
+            "L0",
+            Transform::new(Point::new(100, 0), Orientation::R90),
+        ));
+        let mid_id = db.add_cell(mid);
+
+        let mut top = Cell::new("top");
+        top.add_instance(CellInstance::new(mid_id, "M0", Transform::translate(1000, 0)));
+        top.add_instance(CellInstance::new(mid_id, "M1", Transform::translate(0, 1000)));
+        let top_id = db.add_cell(top);
+        (db, leaf_id, mid_id, top_id)
+    }
+
+    #[test]
+    fn test_flatten_transforms_and_traces_shapes() {
+        let (db, leaf_id, _, top_id) = hierarchy();
+        let shapes: Vec<FlatShape> = db.flatten(&top_id, FlattenOptions::new()).unwrap().collect();
+        assert_eq!(shapes.len(), 6);
+
+        let leaf_rect = db.get_cell(&leaf_id).unwrap().shapes[0].id;
+        let first_leaf = shapes.iter().find(|s| s.shape == leaf_rect).unwrap();
+        assert_eq!(first_leaf.geometry, rect(1, 1080, 0, 1100, 10));
+        let top = db.get_cell(&top_id).unwrap();
+        let mid = db.get_cell(&top.instances[0].cell_id).unwrap();
+        let step = |inst: &CellInstance| PathStep { instance: inst.id, element: 0 };
+        assert_eq!(first_leaf.path, vec![step(&top.instances[0]), step(&mid.instances[0])]);
+    }
+
+    #[test]
+    fn test_flatten_filters() {
+        let (db, _, _, top_id) = hierarchy();
+        let count = |options| db.flatten(&top_id, options).unwrap().count();
+        assert_eq!(count(FlattenOptions::new().with_layers([2])), 2);
+        let region = BBox::new(Point::new(900, -10), Point::new(1200, 100));
+        assert_eq!(count(FlattenOptions::new().with_region(region)), 3);
+        assert_eq!(count(FlattenOptions::new().with_region(region).with_layers([1])), 2);
+    }
+
+    #[test]
+    fn test_flatten_depth_limit() {
+        let (db, leaf_id, _, top_id) = hierarchy();
+        let mut iter = db.flatten(&top_id, FlattenOptions::new().with_max_depth(1)).unwrap();
+        let shapes: Vec<FlatShape> = iter.by_ref().collect();
+        assert_eq!(shapes.len(), 2);
+        assert_eq!(iter.unexpanded().len(), 2);
+        assert!(iter.unexpanded().iter().all(|i| i.cell_id == leaf_id));
+        assert_eq!(iter.unexpanded()[0].transform.offset, Point::new(1100, 0));
+
+        assert_eq!(db.flatten(&top_id, FlattenOptions::new().with_max_depth(0)).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_flatten_into_cell() {
+        let (mut db, leaf_id, mid_id, top_id) = hierarchy();
+        let flat_id = db.add_cell(Cell::new("flat"));
+        let written = db
+            .flatten_into(&top_id, &flat_id, FlattenOptions::new().with_max_depth(1))
+            .unwrap();
+        assert_eq!(written, 2);
+        let flat = db.get_cell(&flat_id).unwrap();
+        assert_eq!((flat.geometry_count(), flat.instance_count()), (2, 2));
+        assert_eq!(db.cell_bbox(&flat_id), db.cell_bbox(&top_id));
+
+        // Flattening in place replaces the contents.
+        db.flatten_into(&top_id, &top_id, FlattenOptions::new()).unwrap();
+        let top = db.get_cell(&top_id).unwrap();
+        assert_eq!((top.geometry_count(), top.instance_count()), (6, 0));
+
+        // Copying `leaf` instances into `leaf` itself would be recursive.
+        assert!(matches!(
+            db.flatten_into(&mid_id, &leaf_id, FlattenOptions::new().with_max_depth(0)),
+            Err(HierarchyError::Cycle(_))
+        ));
+    }
+
+    #[test]
+    fn test_flatten_keeps_properties() {
+        let (mut db, leaf_id, _, top_id) = hierarchy();
+        let properties = Properties::from([(1, "VSS".to_string())]);
+        let cell = db.get_cell_mut(&leaf_id).unwrap();
+        cell.shapes[0].properties = properties.clone();
+        let bar = cell.shapes[0].id;
+
+        let shapes: Vec<FlatShape> = db.flatten(&top_id, FlattenOptions::new()).unwrap().collect();
+        for s in &shapes {
+            assert_eq!(s.properties.is_empty(), s.shape != bar);
+        }
+
+        let flat_id = db.add_cell(Cell::new("flat"));
+        db.flatten_into(&top_id, &flat_id, FlattenOptions::new()).unwrap();
+        let flat = db.get_cell(&flat_id).unwrap();
+        let tagged: Vec<&Shape> = flat.shapes.iter().filter(|s| !s.properties.is_empty()).collect();
+        assert_eq!(tagged.len(), 2);
+        assert!(tagged.iter().all(|s| s.properties == properties));
+    }
+
+    #[test]
+    fn test_flatten_arrays() {
+        let (mut db, leaf_id, _, _) = hierarchy();
+        let array = ArrayParams::new(3, 2, Point::new(20, 0), Point::new(0, 30));
+        let mut row = Cell::new("row");
+        row.add_instance(CellInstance::new(leaf_id, "A", Transform::default()).with_array(array));
+        let row_id = db.add_cell(row);
+        let mut top = Cell::new("array_top");
+        let placement = Transform::new(Point::new(1000, 0), Orientation::R90);
+        top.add_instance(CellInstance::new(row_id, "R", placement));
+        let top_id = db.add_cell(top);
+
+        let bbox = BBox::new(Point::new(950, 0), Point::new(1000, 50));
+        assert_eq!(db.cell_bbox(&top_id), Some(bbox));
+        assert_eq!(db.flatten(&top_id, FlattenOptions::new()).unwrap().count(), 12);
+        // Only the last element touches this corner.
+        let region = BBox::new(Point::new(950, 40), Point::new(970, 50));
+        let options = FlattenOptions::new().with_region(region);
+        let shapes: Vec<FlatShape> = db.flatten(&top_id, options).unwrap().collect();
+        assert_eq!(shapes.len(), 2);
+        let row_inst = db.get_cell(&row_id).unwrap().instances[0].id;
+        assert!(shapes.iter().all(|s| s.path[1] == PathStep { instance: row_inst, element: 5 }));
+
+        // Left unexpanded, the array is kept whole with turned pitches.
+        let mut iter = db.flatten(&top_id, FlattenOptions::new().with_max_depth(1)).unwrap();
+        assert_eq!(iter.by_ref().count(), 0);
+        let [inst] = iter.unexpanded() else { panic!() };
+        let turned = ArrayParams::new(3, 2, Point::new(0, 20), Point::new(-30, 0));
+        assert_eq!(inst.array, Some(turned));
+        assert_eq!(db.instance_bbox(inst), Some(bbox));
+    }
+}
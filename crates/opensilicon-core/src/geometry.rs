@@ -1,22 +1,33 @@
 use serde::{Deserialize, Serialize};
 
-/// A 2D point in layout coordinates (nanometers).
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+use crate::transform::Transform;
+
+/// A layout coordinate in integer database units (DBU).
+///
+/// The physical size of one DBU is set per database by
+/// [`LayoutDatabase::dbu_per_um`](crate::LayoutDatabase); conversion to and
+/// from micrometers happens only at the UI/IPC boundary (see [`crate::units`]).
+pub type Coord = i64;
+
+/// A 2D point in layout coordinates (database units).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Point {
-    pub x: f64,
-    pub y: f64,
+    pub x: Coord,
+    pub y: Coord,
 }
 
 impl Point {
-    pub fn new(x: f64, y: f64) -> Self {
+    pub fn new(x: Coord, y: Coord) -> Self {
         Self { x, y }
     }
 
     pub fn distance_to(&self, other: &Point) -> f64 {
-        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2)).sqrt()
+        let dx = (self.x - other.x) as f64;
+        let dy = (self.y - other.y) as f64;
+        (dx * dx + dy * dy).sqrt()
     }
 
-    pub fn translate(&self, dx: f64, dy: f64) -> Self {
+    pub fn translate(&self, dx: Coord, dy: Coord) -> Self {
         Self {
             x: self.x + dx,
             y: self.y + dy,
@@ -25,7 +36,7 @@ impl Point {
 }
 
 /// An axis-aligned bounding box.
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BBox {
     pub min: Point,
     pub max: Point,
@@ -40,10 +51,10 @@ impl BBox {
         if points.is_empty() {
             return None;
         }
-        let mut min_x = f64::MAX;
-        let mut min_y = f64::MAX;
-        let mut max_x = f64::MIN;
-        let mut max_y = f64::MIN;
+        let mut min_x = Coord::MAX;
+        let mut min_y = Coord::MAX;
+        let mut max_x = Coord::MIN;
+        let mut max_y = Coord::MIN;
         for p in points {
             min_x = min_x.min(p.x);
             min_y = min_y.min(p.y);
@@ -56,18 +67,19 @@ impl BBox {
         })
     }
 
-    pub fn width(&self) -> f64 {
+    pub fn width(&self) -> Coord {
         self.max.x - self.min.x
     }
 
-    pub fn height(&self) -> f64 {
+    pub fn height(&self) -> Coord {
         self.max.y - self.min.y
     }
 
+    /// Center of the box, rounded towards negative infinity on odd extents.
     pub fn center(&self) -> Point {
         Point::new(
-            (self.min.x + self.max.x) / 2.0,
-            (self.min.y + self.max.y) / 2.0,
+            (self.min.x + self.max.x).div_euclid(2),
+            (self.min.y + self.max.y).div_euclid(2),
         )
     }
 
@@ -99,7 +111,7 @@ pub struct Rect {
 }
 
 impl Rect {
-    pub fn new(layer_id: crate::LayerId, x1: f64, y1: f64, x2: f64, y2: f64) -> Self {
+    pub fn new(layer_id: crate::LayerId, x1: Coord, y1: Coord, x2: Coord, y2: Coord) -> Self {
         Self {
             layer_id,
             lower_left: Point::new(x1.min(x2), y1.min(y2)),
@@ -111,16 +123,17 @@ impl Rect {
         BBox::new(self.lower_left, self.upper_right)
     }
 
-    pub fn width(&self) -> f64 {
+    pub fn width(&self) -> Coord {
         self.upper_right.x - self.lower_left.x
     }
 
-    pub fn height(&self) -> f64 {
+    pub fn height(&self) -> Coord {
         self.upper_right.y - self.lower_left.y
     }
 
-    pub fn area(&self) -> f64 {
-        self.width() * self.height()
+    /// Exact area in square database units.
+    pub fn area(&self) -> i128 {
+        self.width() as i128 * self.height() as i128
     }
 
     pub fn contains_point(&self, p: &Point) -> bool {
@@ -128,57 +141,150 @@ impl Rect {
     }
 }
 
-/// A polygon defined by a list of vertices.
+/// A polygon defined by an outer vertex ring and optional holes.
+///
+/// Normalized polygons have a counter-clockwise outer ring and clockwise
+/// holes (see [`Polygon::normalized`]). GDS-II cannot store holes directly,
+/// so they are joined to the outer ring with cut lines on export
+/// (see [`Polygon::keyholed`]).
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Polygon {
     pub layer_id: crate::LayerId,
     pub vertices: Vec<Point>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holes: Vec<Vec<Point>>,
 }
 
 impl Polygon {
     pub fn new(layer_id: crate::LayerId, vertices: Vec<Point>) -> Self {
-        Self { layer_id, vertices }
+        Self {
+            layer_id,
+            vertices,
+            holes: Vec::new(),
+        }
+    }
+
+    pub fn with_holes(layer_id: crate::LayerId, vertices: Vec<Point>, holes: Vec<Vec<Point>>) -> Self {
+        Self {
+            layer_id,
+            vertices,
+            holes,
+        }
     }
 
     pub fn bbox(&self) -> Option<BBox> {
         BBox::from_points(&self.vertices)
     }
 
+    /// Number of vertices in the outer ring and all holes.
     pub fn vertex_count(&self) -> usize {
-        self.vertices.len()
+        self.vertices.len() + self.holes.iter().map(Vec::len).sum::<usize>()
+    }
+
+    /// Exact area in square database units (outer ring minus holes).
+    pub fn area(&self) -> i128 {
+        let outer = crate::boolean::signed_area2(&self.vertices).abs();
+        let holes: i128 = self
+            .holes
+            .iter()
+            .map(|h| crate::boolean::signed_area2(h).abs())
+            .sum();
+        (outer - holes) / 2
+    }
+
+    /// The outer ring followed by all holes.
+    pub fn rings(&self) -> impl Iterator<Item = &Vec<Point>> {
+        std::iter::once(&self.vertices).chain(self.holes.iter())
+    }
+
+    /// A single-ring equivalent with every hole joined to the outer ring by
+    /// a zero-width cut line, as required by GDS-II.
+    pub fn keyholed(&self) -> Polygon {
+        if self.holes.is_empty() {
+            return self.clone();
+        }
+        let oriented = |ring: &Vec<Point>, ccw: bool| {
+            let mut ring = ring.clone();
+            if (crate::boolean::signed_area2(&ring) > 0) != ccw {
+                ring.reverse();
+            }
+            ring
+        };
+        let outer = oriented(&self.vertices, true);
+        let holes = self
+            .holes
+            .iter()
+            .filter(|h| h.len() >= 3)
+            .map(|h| oriented(h, false))
+            .collect();
+        Polygon::new(self.layer_id, crate::boolean::insert_keyholes(outer, holes))
     }
 }
 
+/// How a path's ends extend beyond its first and last centerline points.
+///
+/// The variants correspond to GDS-II PATHTYPE 0, 1, 2 and 4.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PathEndStyle {
+    /// Ends are cut square at the end points (PATHTYPE 0).
+    #[default]
+    Flush,
+    /// Ends are half circles centered on the end points (PATHTYPE 1).
+    Round,
+    /// Ends are square, extended by half the width (PATHTYPE 2). For odd
+    /// widths the extension is rounded up to the next whole database unit.
+    HalfWidth,
+    /// Ends are square, extended by explicit amounts (PATHTYPE 4 with
+    /// BGNEXTN / ENDEXTN). Negative extensions pull the ends inwards.
+    Custom { begin: Coord, end: Coord },
+}
+
+/// Number of vertices used to approximate a full circle for round path ends.
+pub const ROUND_END_SEGMENTS: usize = 32;
+
 /// A path (wire) defined by a centerline and width.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Path {
     pub layer_id: crate::LayerId,
     pub points: Vec<Point>,
-    pub width: f64,
+    pub width: Coord,
+    #[serde(default)]
+    pub end_style: PathEndStyle,
 }
 
 impl Path {
-    pub fn new(layer_id: crate::LayerId, points: Vec<Point>, width: f64) -> Self {
+    pub fn new(layer_id: crate::LayerId, points: Vec<Point>, width: Coord) -> Self {
         Self {
             layer_id,
             points,
             width,
+            end_style: PathEndStyle::Flush,
+        }
+    }
+
+    pub fn with_end_style(mut self, end_style: PathEndStyle) -> Self {
+        self.end_style = end_style;
+        self
+    }
+
+    /// How far the square ends extend beyond the first and last points.
+    /// Round ends have no square extension (their caps are separate), and
+    /// half-width ends round odd widths up so the outline never falls short
+    /// of the GDS-II PATHTYPE 2 geometry.
+    pub fn end_extensions(&self) -> (Coord, Coord) {
+        match self.end_style {
+            PathEndStyle::Flush | PathEndStyle::Round => (0, 0),
+            PathEndStyle::HalfWidth => {
+                let half = (self.width + 1).div_euclid(2);
+                (half, half)
+            }
+            PathEndStyle::Custom { begin, end } => (begin, end),
         }
     }
 
     pub fn bbox(&self) -> Option<BBox> {
-        let half_w = self.width / 2.0;
-        let expanded: Vec<Point> = self
-            .points
-            .iter()
-            .flat_map(|p| {
-                vec![
-                    Point::new(p.x - half_w, p.y - half_w),
-                    Point::new(p.x + half_w, p.y + half_w),
-                ]
-            })
-            .collect();
-        BBox::from_points(&expanded)
+        let points: Vec<Point> = self.outline_rings().into_iter().flatten().collect();
+        BBox::from_points(&points).or_else(|| BBox::from_points(&self.points))
     }
 
     pub fn length(&self) -> f64 {
@@ -187,6 +293,112 @@ impl Path {
             .map(|w| w[0].distance_to(&w[1]))
             .sum()
     }
+
+    /// The exact area covered by the path as merged polygons (normally one;
+    /// a path looping back on itself encloses holes as keyholes).
+    pub fn to_polygons(&self) -> Vec<Polygon> {
+        crate::boolean::merge_rings(&self.outline_rings(), self.layer_id)
+    }
+
+    /// Overlapping convex pieces whose union is the area of the path: one
+    /// quadrilateral per segment, a miter (or bevel, for turns sharper than
+    /// 90°) wedge on the outside of every bend, and round caps if requested.
+    pub(crate) fn outline_rings(&self) -> Vec<Vec<Point>> {
+        if self.width <= 0 {
+            return Vec::new();
+        }
+        let mut points = self.points.clone();
+        points.dedup();
+        let hw = self.width as f64 / 2.0;
+
+        let mut rings = Vec::new();
+        let (begin_ext, end_ext) = self.end_extensions();
+        if points.len() == 1 {
+            // A single point only has area through its end caps.
+            let p = points[0];
+            if self.end_style == PathEndStyle::Round {
+                rings.push(circle(p, hw));
+            } else if begin_ext + end_ext > 0 {
+                rings.push(offset_quad(p, p, (1.0, 0.0), begin_ext as f64, end_ext as f64, hw));
+            }
+            return rings;
+        }
+
+        let dirs: Vec<(f64, f64)> = points.windows(2).map(|w| unit(w[0], w[1])).collect();
+        let last = dirs.len() - 1;
+        for (i, w) in points.windows(2).enumerate() {
+            let ext_start = if i == 0 { begin_ext as f64 } else { 0.0 };
+            let ext_end = if i == last { end_ext as f64 } else { 0.0 };
+            rings.push(offset_quad(w[0], w[1], dirs[i], ext_start, ext_end, hw));
+        }
+
+        for i in 1..points.len() - 1 {
+            let (a, p, b) = (points[i - 1], points[i], points[i + 1]);
+            let turn = (p.x - a.x) as i128 * (b.y - p.y) as i128
+                - (p.y - a.y) as i128 * (b.x - p.x) as i128;
+            if turn == 0 {
+                // Straight on, or a full reversal which has no outer side.
+                continue;
+            }
+            let dot = (p.x - a.x) as i128 * (b.x - p.x) as i128
+                + (p.y - a.y) as i128 * (b.y - p.y) as i128;
+            // The outside of a left turn is on the right of the path.
+            let side = if turn > 0 { -hw } else { hw };
+            let (u1, u2) = (dirs[i - 1], dirs[i]);
+            let (n1, n2) = ((-u1.1, u1.0), (-u2.1, u2.0));
+            let first = offset(p, n1.0 * side, n1.1 * side);
+            let second = offset(p, n2.0 * side, n2.1 * side);
+            if dot >= 0 {
+                let k = side / (1.0 + n1.0 * n2.0 + n1.1 * n2.1);
+                let miter = offset(p, (n1.0 + n2.0) * k, (n1.1 + n2.1) * k);
+                rings.push(vec![p, first, miter, second]);
+            } else {
+                rings.push(vec![p, first, second]);
+            }
+        }
+
+        if self.end_style == PathEndStyle::Round {
+            rings.push(circle(points[0], hw));
+            rings.push(circle(points[points.len() - 1], hw));
+        }
+        rings
+    }
+}
+
+fn unit(a: Point, b: Point) -> (f64, f64) {
+    let (dx, dy) = ((b.x - a.x) as f64, (b.y - a.y) as f64);
+    let len = (dx * dx + dy * dy).sqrt();
+    (dx / len, dy / len)
+}
+
+fn offset(p: Point, dx: f64, dy: f64) -> Point {
+    Point::new(
+        (p.x as f64 + dx).round() as Coord,
+        (p.y as f64 + dy).round() as Coord,
+    )
+}
+
+/// The rectangle around the segment `a`→`b` (direction `u`), extended along
+/// the segment by `ext_start` / `ext_end` and across it by `hw`.
+fn offset_quad(a: Point, b: Point, u: (f64, f64), ext_start: f64, ext_end: f64, hw: f64) -> Vec<Point> {
+    let at = |p: Point, along: f64, across: f64| {
+        offset(p, u.0 * along - u.1 * across, u.1 * along + u.0 * across)
+    };
+    vec![
+        at(a, -ext_start, -hw),
+        at(b, ext_end, -hw),
+        at(b, ext_end, hw),
+        at(a, -ext_start, hw),
+    ]
+}
+
+fn circle(center: Point, radius: f64) -> Vec<Point> {
+    (0..ROUND_END_SEGMENTS)
+        .map(|k| {
+            let angle = std::f64::consts::TAU * k as f64 / ROUND_END_SEGMENTS as f64;
+            offset(center, radius * angle.cos(), radius * angle.sin())
+        })
+        .collect()
 }
 
 /// A via connecting two layers.
@@ -196,8 +408,8 @@ pub struct Via {
     pub top_layer: crate::LayerId,
     pub cut_layer: crate::LayerId,
     pub position: Point,
-    pub width: f64,
-    pub height: f64,
+    pub width: Coord,
+    pub height: Coord,
 }
 
 impl Via {
@@ -206,8 +418,8 @@ impl Via {
         top_layer: crate::LayerId,
         cut_layer: crate::LayerId,
         position: Point,
-        width: f64,
-        height: f64,
+        width: Coord,
+        height: Coord,
     ) -> Self {
         Self {
             bottom_layer,
@@ -219,13 +431,132 @@ impl Via {
         }
     }
 
+    /// The via whose [`bbox`](Self::bbox) is exactly `bbox`.
+    pub fn from_bbox(
+        bottom_layer: crate::LayerId,
+        top_layer: crate::LayerId,
+        cut_layer: crate::LayerId,
+        bbox: BBox,
+    ) -> Self {
+        let (width, height) = (bbox.width(), bbox.height());
+        let position = bbox.min.translate(width / 2, height / 2);
+        Self::new(bottom_layer, top_layer, cut_layer, position, width, height)
+    }
+
+    pub fn bbox(&self) -> BBox {
+        let min = Point::new(
+            self.position.x - self.width / 2,
+            self.position.y - self.height / 2,
+        );
+        BBox::new(min, min.translate(self.width, self.height))
+    }
+}
+
+/// Horizontal justification of a text label about its position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HorizontalAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Vertical justification of a text label about its position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum VerticalAlign {
+    #[default]
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// How a text label is drawn: one of four fonts and its justification.
+/// The default matches a GDS-II TEXT without a PRESENTATION record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TextPresentation {
+    /// Font number, 0 to 3.
+    pub font: u8,
+    pub horizontal: HorizontalAlign,
+    pub vertical: VerticalAlign,
+}
+
+impl TextPresentation {
+    /// Decode a GDS-II PRESENTATION bit array.
+    pub fn from_bits(bits: u16) -> Self {
+        let horizontal = match bits & 0x3 {
+            1 => HorizontalAlign::Center,
+            2 => HorizontalAlign::Right,
+            _ => HorizontalAlign::Left,
+        };
+        let vertical = match (bits >> 2) & 0x3 {
+            1 => VerticalAlign::Middle,
+            2 => VerticalAlign::Bottom,
+            _ => VerticalAlign::Top,
+        };
+        Self {
+            font: ((bits >> 4) & 0x3) as u8,
+            horizontal,
+            vertical,
+        }
+    }
+
+    /// Encode as a GDS-II PRESENTATION bit array.
+    pub fn bits(&self) -> u16 {
+        let horizontal = match self.horizontal {
+            HorizontalAlign::Left => 0,
+            HorizontalAlign::Center => 1,
+            HorizontalAlign::Right => 2,
+        };
+        let vertical = match self.vertical {
+            VerticalAlign::Top => 0,
+            VerticalAlign::Middle => 1,
+            VerticalAlign::Bottom => 2,
+        };
+        ((self.font as u16 & 0x3) << 4) | (vertical << 2) | horizontal
+    }
+}
+
+/// A text label, such as a net or pin name.
+///
+/// Labels have no area: their extent is the anchor point alone, since the
+/// drawn size depends on the viewer's font.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Text {
+    /// Layer of the label; in GDS-II its datatype is the TEXTTYPE.
+    pub layer_id: crate::LayerId,
+    pub text: String,
+    /// Anchor position, orientation and magnification of the label.
+    pub transform: Transform,
+    pub presentation: TextPresentation,
+}
+
+impl Text {
+    pub fn new(layer_id: crate::LayerId, text: impl Into<String>, position: Point) -> Self {
+        Self {
+            layer_id,
+            text: text.into(),
+            transform: Transform::new(position, Default::default()),
+            presentation: TextPresentation::default(),
+        }
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_presentation(mut self, presentation: TextPresentation) -> Self {
+        self.presentation = presentation;
+        self
+    }
+
+    /// Where the label is anchored.
+    pub fn position(&self) -> Point {
+        self.transform.offset
+    }
+
     pub fn bbox(&self) -> BBox {
-        let half_w = self.width / 2.0;
-        let half_h = self.height / 2.0;
-        BBox::new(
-            Point::new(self.position.x - half_w, self.position.y - half_h),
-            Point::new(self.position.x + half_w, self.position.y + half_h),
-        )
+        BBox::new(self.position(), self.position())
     }
 }
 
@@ -236,6 +567,7 @@ pub enum GeomPrimitive {
     Polygon(Polygon),
     Path(Path),
     Via(Via),
+    Text(Text),
 }
 
 impl GeomPrimitive {
@@ -245,6 +577,7 @@ impl GeomPrimitive {
             GeomPrimitive::Polygon(p) => p.bbox(),
             GeomPrimitive::Path(p) => p.bbox(),
             GeomPrimitive::Via(v) => Some(v.bbox()),
+            GeomPrimitive::Text(t) => Some(t.bbox()),
         }
     }
 
@@ -254,6 +587,7 @@ impl GeomPrimitive {
             GeomPrimitive::Polygon(p) => p.layer_id,
             GeomPrimitive::Path(p) => p.layer_id,
             GeomPrimitive::Via(v) => v.cut_layer,
+            GeomPrimitive::Text(t) => t.layer_id,
         }
     }
 }
@@ -264,23 +598,115 @@ mod tests {
 
     #[test]
     fn test_point_distance() {
-        let a = Point::new(0.0, 0.0);
-        let b = Point::new(3.0, 4.0);
+        let a = Point::new(0, 0);
+        let b = Point::new(3, 4);
         assert!((a.distance_to(&b) - 5.0).abs() < 1e-10);
     }
 
     #[test]
     fn test_rect_area() {
-        let r = Rect::new(0, 0.0, 0.0, 10.0, 5.0);
-        assert!((r.area() - 50.0).abs() < 1e-10);
+        let r = Rect::new(0, 0, 0, 10, 5);
+        assert_eq!(r.area(), 50);
     }
 
     #[test]
     fn test_bbox_intersection() {
-        let a = BBox::new(Point::new(0.0, 0.0), Point::new(10.0, 10.0));
-        let b = BBox::new(Point::new(5.0, 5.0), Point::new(15.0, 15.0));
-        let c = BBox::new(Point::new(20.0, 20.0), Point::new(30.0, 30.0));
+        let a = BBox::new(Point::new(0, 0), Point::new(10, 10));
+        let b = BBox::new(Point::new(5, 5), Point::new(15, 15));
+        let c = BBox::new(Point::new(20, 20), Point::new(30, 30));
         assert!(a.intersects(&b));
         assert!(!a.intersects(&c));
     }
+
+    #[test]
+    fn test_path_end_styles_bbox() {
+        let path = Path::new(1, vec![Point::new(0, 0), Point::new(100, 0)], 20);
+        let bb = path.bbox().unwrap();
+        assert_eq!((bb.min, bb.max), (Point::new(0, -10), Point::new(100, 10)));
+
+        let bb = path.clone().with_end_style(PathEndStyle::HalfWidth).bbox().unwrap();
+        assert_eq!((bb.min, bb.max), (Point::new(-10, -10), Point::new(110, 10)));
+
+        let odd = Path::new(1, vec![Point::new(0, 0), Point::new(100, 0)], 5)
+            .with_end_style(PathEndStyle::HalfWidth);
+        assert_eq!(odd.end_extensions(), (3, 3));
+        let bb = odd.bbox().unwrap();
+        assert_eq!((bb.min.x, bb.max.x), (-3, 103));
+
+        let custom = PathEndStyle::Custom { begin: 5, end: -3 };
+        let bb = path.clone().with_end_style(custom).bbox().unwrap();
+        assert_eq!((bb.min, bb.max), (Point::new(-5, -10), Point::new(97, 10)));
+
+        let bb = path.with_end_style(PathEndStyle::Round).bbox().unwrap();
+        assert_eq!((bb.min, bb.max), (Point::new(-10, -10), Point::new(110, 10)));
+    }
+
+    #[test]
+    fn test_path_manhattan_bend_is_exact() {
+        let path = Path::new(
+            1,
+            vec![Point::new(0, 0), Point::new(100, 0), Point::new(100, 100)],
+            20,
+        );
+        let polys = path.to_polygons();
+        assert_eq!(polys.len(), 1);
+        let mut vertices = polys[0].vertices.clone();
+        vertices.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(
+            vertices,
+            vec![
+                Point::new(0, -10),
+                Point::new(0, 10),
+                Point::new(90, 10),
+                Point::new(90, 100),
+                Point::new(110, -10),
+                Point::new(110, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_path_acute_corner_is_beveled() {
+        // A hairpin turn: a miter would reach far beyond the bend.
+        let path = Path::new(
+            1,
+            vec![Point::new(0, 0), Point::new(1000, 0), Point::new(0, 100)],
+            20,
+        );
+        let bb = path.bbox().unwrap();
+        assert!(bb.max.x <= 1010, "bevel must not overshoot: {:?}", bb);
+        assert_eq!(path.to_polygons().len(), 1);
+    }
+
+    #[test]
+    fn test_path_round_ends() {
+        let path = Path::new(1, vec![Point::new(0, 0), Point::new(100, 0)], 20)
+            .with_end_style(PathEndStyle::Round);
+        let polys = path.to_polygons();
+        assert_eq!(polys.len(), 1);
+        let bb = polys[0].bbox().unwrap();
+        assert_eq!((bb.min, bb.max), (Point::new(-10, -10), Point::new(110, 10)));
+        // The caps are curved, not square.
+        assert!(!polys[0].vertices.contains(&Point::new(-10, -10)));
+    }
+
+    #[test]
+    fn test_via_bbox_odd_size() {
+        let v = Via::new(1, 2, 3, Point::new(0, 0), 5, 4);
+        let bb = v.bbox();
+        assert_eq!(bb.width(), 5);
+        assert_eq!(bb.height(), 4);
+    }
+
+    #[test]
+    fn test_text_presentation_bits() {
+        let p = TextPresentation {
+            font: 2,
+            horizontal: HorizontalAlign::Center,
+            vertical: VerticalAlign::Bottom,
+        };
+        assert_eq!(p.bits(), 0b10_10_01);
+        assert_eq!(TextPresentation::from_bits(p.bits()), p);
+        assert_eq!(TextPresentation::from_bits(0), TextPresentation::default());
+    }
 }
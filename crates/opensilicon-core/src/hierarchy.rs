@@ -0,0 +1,396 @@
+//! Cell hierarchy graph.
+//!
+//! Cells form a directed graph through their instances: an edge runs from
+//! each cell to every cell it places. [`Hierarchy`] is a snapshot of that
+//! graph taken from a [`LayoutDatabase`], answering parent/child, root,
+//! ordering and depth queries. References to cells that are not in the
+//! database are ignored.
+//!
+//! A valid hierarchy is acyclic. [`LayoutDatabase::add_instance`] refuses
+//! placements that would close a cycle; cycles can still come in through
+//! direct cell edits or imported files, so queries that need an order
+//! report them as [`HierarchyError::Cycle`].
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use thiserror::Error;
+
+use crate::cell::CellId;
+use crate::database::LayoutDatabase;
+
+/// Errors from hierarchy queries and checked hierarchy edits.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum HierarchyError {
+    #[error("Unknown cell {0}")]
+    UnknownCell(CellId),
+
+    /// Cell names along a recursive reference, starting and ending with the
+    /// same cell.
+    #[error("Recursive cell reference: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+}
+
+/// A snapshot of the cell graph of a database.
+pub struct Hierarchy<'a> {
+    db: &'a LayoutDatabase,
+    /// Cells sorted by name (then ID), which fixes the order of all results.
+    cells: Vec<CellId>,
+    /// Distinct placed cells of each cell with their placement counts
+    /// (every element of an array counting once), in order of first
+    /// placement.
+    children: HashMap<CellId, Vec<(CellId, usize)>>,
+    /// Distinct cells placing each cell, sorted by name.
+    parents: HashMap<CellId, Vec<CellId>>,
+}
+
+impl<'a> Hierarchy<'a> {
+    pub fn new(db: &'a LayoutDatabase) -> Self {
+        let mut cells: Vec<CellId> = db.all_cells().map(|c| c.id).collect();
+        cells.sort_by(|a, b| {
+            let name = |id| db.get_cell(id).map(|c| c.name.as_str());
+            name(a).cmp(&name(b)).then(a.cmp(b))
+        });
+
+        let mut children: HashMap<CellId, Vec<(CellId, usize)>> = HashMap::new();
+        let mut parents: HashMap<CellId, Vec<CellId>> = HashMap::new();
+        for id in &cells {
+            let placed = children.entry(*id).or_default();
+            for inst in &db.get_cell(id).expect("listed cell exists").instances {
+                if db.get_cell(&inst.cell_id).is_none() {
+                    continue;
+                }
+                let n = inst.placement_count();
+                match placed.iter_mut().find(|(child, _)| *child == inst.cell_id) {
+                    Some((_, count)) => *count += n,
+                    None => {
+                        placed.push((inst.cell_id, n));
+                        parents.entry(inst.cell_id).or_default().push(*id);
+                    }
+                }
+            }
+        }
+
+        Self {
+            db,
+            cells,
+            children,
+            parents,
+        }
+    }
+
+    /// Distinct cells placed directly by `id`, in order of first placement.
+    pub fn children(&self, id: &CellId) -> Vec<CellId> {
+        self.placements(id).iter().map(|(child, _)| *child).collect()
+    }
+
+    /// Distinct cells placed directly by `id` with how often each is placed,
+    /// counting every element of an array.
+    pub fn placements(&self, id: &CellId) -> &[(CellId, usize)] {
+        self.children.get(id).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Distinct cells that place `id` directly, sorted by name.
+    pub fn parents(&self, id: &CellId) -> &[CellId] {
+        self.parents.get(id).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Cells that are not placed by any other cell, sorted by name.
+    pub fn roots(&self) -> Vec<CellId> {
+        self.cells
+            .iter()
+            .filter(|id| self.parents(id).is_empty())
+            .copied()
+            .collect()
+    }
+
+    /// Distinct cells placed below `id`, directly or indirectly. A cell on
+    /// a cycle through `id` is not its own descendant.
+    pub fn descendants(&self, id: &CellId) -> HashSet<CellId> {
+        let mut seen = HashSet::new();
+        let mut pending = vec![*id];
+        while let Some(current) = pending.pop() {
+            for child in self.children(&current) {
+                if seen.insert(child) {
+                    pending.push(child);
+                }
+            }
+        }
+        seen.remove(id);
+        seen
+    }
+
+    /// Whether `descendant` is placed below `ancestor`.
+    pub fn is_ancestor(&self, ancestor: &CellId, descendant: &CellId) -> bool {
+        self.path(ancestor, descendant).is_some()
+    }
+
+    /// A shortest chain of placements from `from` down to `to`, both
+    /// included, or `None` if `to` is not below `from`.
+    pub fn path(&self, from: &CellId, to: &CellId) -> Option<Vec<CellId>> {
+        let mut placed_by: HashMap<CellId, CellId> = HashMap::new();
+        let mut pending = VecDeque::from([*from]);
+        while let Some(current) = pending.pop_front() {
+            for child in self.children(&current) {
+                if placed_by.contains_key(&child) {
+                    continue;
+                }
+                placed_by.insert(child, current);
+                if child == *to {
+                    let mut path = vec![child];
+                    let mut cell = current;
+                    while cell != *from {
+                        path.push(cell);
+                        cell = placed_by[&cell];
+                    }
+                    path.push(*from);
+                    path.reverse();
+                    return Some(path);
+                }
+                pending.push_back(child);
+            }
+        }
+        None
+    }
+
+    /// Number of direct placements of each cell across the database, with
+    /// arrays counted element by element.
+    pub fn instance_counts(&self) -> HashMap<CellId, usize> {
+        let mut counts: HashMap<CellId, usize> = self.cells.iter().map(|id| (*id, 0)).collect();
+        for placed in self.children.values() {
+            for (child, n) in placed {
+                *counts.entry(*child).or_default() += n;
+            }
+        }
+        counts
+    }
+
+    /// Number of times each cell appears when `root` is fully flattened
+    /// (`root` itself counts once). Cells not below `root` are omitted.
+    pub fn flat_instance_counts(
+        &self,
+        root: &CellId,
+    ) -> Result<HashMap<CellId, usize>, HierarchyError> {
+        if self.db.get_cell(root).is_none() {
+            return Err(HierarchyError::UnknownCell(*root));
+        }
+        let mut counts = HashMap::from([(*root, 1usize)]);
+        for id in self.topological_order()? {
+            let Some(&n) = counts.get(&id) else { continue };
+            for (child, placed) in self.placements(&id) {
+                *counts.entry(*child).or_default() += n * placed;
+            }
+        }
+        Ok(counts)
+    }
+
+    /// All cells ordered so that every cell comes before the cells it
+    /// places. Roots come in name order, other cells in placement order.
+    pub fn topological_order(&self) -> Result<Vec<CellId>, HierarchyError> {
+        let mut in_degree: HashMap<CellId, usize> = self
+            .cells
+            .iter()
+            .map(|id| (*id, self.parents(id).len()))
+            .collect();
+        let mut ready: VecDeque<CellId> = self.roots().into();
+        let mut order = Vec::with_capacity(self.cells.len());
+        while let Some(id) = ready.pop_front() {
+            order.push(id);
+            for child in self.children(&id) {
+                let degree = in_degree.get_mut(&child).expect("child is a listed cell");
+                *degree -= 1;
+                if *degree == 0 {
+                    ready.push_back(child);
+                }
+            }
+        }
+
+        if order.len() < self.cells.len() {
+            let cycle = self.find_cycle().expect("unordered cells lie on a cycle");
+            return Err(self.cycle_error(&cycle));
+        }
+        Ok(order)
+    }
+
+    /// Levels of hierarchy below `id`: 0 for a cell without instances.
+    pub fn depth(&self, id: &CellId) -> Result<usize, HierarchyError> {
+        if self.db.get_cell(id).is_none() {
+            return Err(HierarchyError::UnknownCell(*id));
+        }
+        Ok(self.depths()?[id])
+    }
+
+    /// The deepest hierarchy level in the database (0 if it is flat).
+    pub fn max_depth(&self) -> Result<usize, HierarchyError> {
+        Ok(self.depths()?.into_values().max().unwrap_or(0))
+    }
+
+    fn depths(&self) -> Result<HashMap<CellId, usize>, HierarchyError> {
+        let mut depths = HashMap::new();
+        for id in self.topological_order()?.into_iter().rev() {
+            let depth = self
+                .children(&id)
+                .iter()
+                .map(|child| depths[child] + 1)
+                .max()
+                .unwrap_or(0);
+            depths.insert(id, depth);
+        }
+        Ok(depths)
+    }
+
+    /// A recursive reference, as the cells along it with the first cell
+    /// repeated at the end, or `None` if the hierarchy is acyclic.
+    pub fn find_cycle(&self) -> Option<Vec<CellId>> {
+        // Depth-first search; a child already on the stack closes a cycle.
+        let mut done: HashSet<CellId> = HashSet::new();
+        for start in &self.cells {
+            if done.contains(start) {
+                continue;
+            }
+            let mut stack: Vec<(CellId, usize)> = vec![(*start, 0)];
+            let mut on_stack: HashSet<CellId> = HashSet::from([*start]);
+            while let Some((id, next)) = stack.last_mut() {
+                let (id, children) = (*id, self.placements(id));
+                let Some((child, _)) = children.get(*next) else {
+                    stack.pop();
+                    on_stack.remove(&id);
+                    done.insert(id);
+                    continue;
+                };
+                *next += 1;
+                if on_stack.contains(child) {
+                    let from = stack.iter().position(|(c, _)| c == child).expect("on stack");
+                    let mut cycle: Vec<CellId> = stack[from..].iter().map(|(c, _)| *c).collect();
+                    cycle.push(*child);
+                    return Some(cycle);
+                }
+                if !done.contains(child) {
+                    on_stack.insert(*child);
+                    stack.push((*child, 0));
+                }
+            }
+        }
+        None
+    }
+
+    pub(crate) fn cycle_error(&self, cycle: &[CellId]) -> HierarchyError {
+        HierarchyError::Cycle(
+            cycle
+                .iter()
+                .map(|id| self.db.get_cell(id).map(|c| c.name.clone()).unwrap_or_default())
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::{ArrayParams, Cell, CellInstance, Transform};
+    use crate::geometry::Point;
+
+    fn place(db: &mut LayoutDatabase, parent: CellId, child: CellId) {
+        db.get_cell_mut(&parent)
+            .unwrap()
+            .add_instance(CellInstance::new(child, "I", Transform::default()));
+    }
+
+    /// `top` places `a` and `b`; both place `leaf`, `a` twice.
+    fn diamond() -> (LayoutDatabase, [CellId; 4]) {
+        let mut db = LayoutDatabase::new("test");
+        let ids = ["top", "a", "b", "leaf"].map(|name| db.add_cell(Cell::new(name)));
+        let [top, a, b, leaf] = ids;
+        place(&mut db, top, b);
+        place(&mut db, top, a);
+        place(&mut db, a, leaf);
+        place(&mut db, a, leaf);
+        place(&mut db, b, leaf);
+        (db, ids)
+    }
+
+    #[test]
+    fn test_parents_children_roots() {
+        let (mut db, [top, a, b, leaf]) = diamond();
+        let spare = db.add_cell(Cell::new("spare"));
+        let h = db.hierarchy();
+        assert_eq!(h.children(&top), vec![b, a]);
+        assert_eq!(h.children(&a), vec![leaf]);
+        assert_eq!(h.placements(&a), &[(leaf, 2)]);
+        assert_eq!(h.parents(&leaf), &[a, b]);
+        assert!(h.parents(&top).is_empty());
+        assert_eq!(h.roots(), vec![spare, top]);
+        assert_eq!(h.descendants(&top).len(), 3);
+        assert_eq!(h.path(&top, &leaf), Some(vec![top, b, leaf]));
+        assert!(h.is_ancestor(&top, &leaf));
+        assert!(!h.is_ancestor(&leaf, &top));
+    }
+
+    #[test]
+    fn test_order_depth_and_counts() {
+        let (db, [top, a, b, leaf]) = diamond();
+        let h = db.hierarchy();
+        assert_eq!(h.topological_order().unwrap(), vec![top, b, a, leaf]);
+        assert_eq!(h.max_depth().unwrap(), 2);
+        assert_eq!(h.depth(&a).unwrap(), 1);
+        assert_eq!(h.depth(&leaf).unwrap(), 0);
+
+        let direct = h.instance_counts();
+        assert_eq!((direct[&top], direct[&a], direct[&leaf]), (0, 1, 3));
+        let flat = h.flat_instance_counts(&top).unwrap();
+        assert_eq!((flat[&top], flat[&b], flat[&leaf]), (1, 1, 3));
+    }
+
+    #[test]
+    fn test_counts_include_array_elements() {
+        let (mut db, [top, a, b, leaf]) = diamond();
+        // An AREF of `b` placing it 2 × 3 times more.
+        let array = ArrayParams::new(2, 3, Point::new(100, 0), Point::new(0, 100));
+        db.get_cell_mut(&top)
+            .unwrap()
+            .add_instance(CellInstance::new(b, "ARR", Transform::default()).with_array(array));
+        let h = db.hierarchy();
+        assert_eq!(h.placements(&top), &[(b, 7), (a, 1)]);
+        assert_eq!(h.instance_counts()[&b], 7);
+        let flat = h.flat_instance_counts(&top).unwrap();
+        assert_eq!((flat[&b], flat[&leaf]), (7, 9));
+    }
+
+    #[test]
+    fn test_cycle_detected() {
+        let (mut db, [top, a, _, leaf]) = diamond();
+        assert!(db.hierarchy().find_cycle().is_none());
+
+        place(&mut db, leaf, a);
+        let h = db.hierarchy();
+        let cycle = h.find_cycle().unwrap();
+        assert_eq!(cycle.first(), cycle.last());
+        assert_eq!(cycle.len(), 3);
+        assert_eq!(
+            h.topological_order().unwrap_err().to_string(),
+            "Recursive cell reference: a -> leaf -> a"
+        );
+        assert!(h.max_depth().is_err());
+        assert_eq!(h.roots(), vec![top]);
+    }
+
+    #[test]
+    fn test_add_instance_rejects_cycles() {
+        let (mut db, [top, a, _, leaf]) = diamond();
+        let inst = |id| CellInstance::new(id, "I", Transform::default());
+
+        assert!(matches!(
+            db.add_instance(&leaf, inst(top)),
+            Err(HierarchyError::Cycle(names)) if names == ["leaf", "top", "b", "leaf"]
+        ));
+        assert!(db.add_instance(&a, inst(a)).is_err());
+        let missing = CellId::new_v4();
+        assert_eq!(
+            db.add_instance(&top, inst(missing)),
+            Err(HierarchyError::UnknownCell(missing))
+        );
+
+        let extra = db.add_cell(Cell::new("extra"));
+        db.add_instance(&leaf, inst(extra)).unwrap();
+        assert_eq!(db.hierarchy().max_depth().unwrap(), 3);
+    }
+}
@@ -0,0 +1,563 @@
+//! Crash recovery through an append-only journal of edits.
+//!
+//! While a [`Journal`] is attached to the database, every executed command,
+//! undo, redo and transaction boundary is appended to `<project>.journal`,
+//! one JSON entry per line. `<project>.checkpoint` holds the database and its
+//! undo history as of the last compaction, or the first edit after the last
+//! save; replaying the journal on top of it rebuilds the session. Entries
+//! are flushed as they are written, so the journal survives the application
+//! crashing, though not necessarily the machine losing power.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::commands::{
+    AddGeometryCommand, AlignGeometryCommand, Command, CommandHistory, CompoundCommand,
+    DuplicateGeometryCommand, MoveGeometryCommand, RemoveGeometryCommand, StretchGeometryCommand,
+    TransformGeometryCommand,
+};
+use crate::database::LayoutDatabase;
+use crate::design_commands::{
+    AddLayerCommand, CreateCellCommand, DeleteCellCommand, DeleteInstanceCommand,
+    EditLayerCommand, FlattenCellCommand, PlaceInstanceCommand, RemovePinCommand, RenameCellCommand,
+    SetAllLayersVisibleCommand, SetInstanceArrayCommand, SetPinCommand, SetPropertyCommand,
+    TransformInstanceCommand,
+};
+
+/// Journal entries written between compactions by default.
+pub const DEFAULT_CHECKPOINT_INTERVAL: usize = 500;
+
+/// A serializable form of every [`Command`], as returned by
+/// [`Command::to_record`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CommandRecord {
+    AddGeometry(AddGeometryCommand),
+    RemoveGeometry(RemoveGeometryCommand),
+    MoveGeometry(MoveGeometryCommand),
+    TransformGeometry(TransformGeometryCommand),
+    DuplicateGeometry(DuplicateGeometryCommand),
+    AlignGeometry(AlignGeometryCommand),
+    StretchGeometry(StretchGeometryCommand),
+    CreateCell(CreateCellCommand),
+    RenameCell(RenameCellCommand),
+    DeleteCell(DeleteCellCommand),
+    FlattenCell(FlattenCellCommand),
+    PlaceInstance(PlaceInstanceCommand),
+    TransformInstance(TransformInstanceCommand),
+    SetInstanceArray(SetInstanceArrayCommand),
+    DeleteInstance(DeleteInstanceCommand),
+    SetPin(SetPinCommand),
+    RemovePin(RemovePinCommand),
+    SetProperty(SetPropertyCommand),
+    AddLayer(AddLayerCommand),
+    EditLayer(EditLayerCommand),
+    SetAllLayersVisible(SetAllLayersVisibleCommand),
+    Compound {
+        description: String,
+        commands: Vec<CommandRecord>,
+    },
+}
+
+impl CommandRecord {
+    pub fn into_command(self) -> Box<dyn Command> {
+        match self {
+            CommandRecord::AddGeometry(c) => Box::new(c),
+            CommandRecord::RemoveGeometry(c) => Box::new(c),
+            CommandRecord::MoveGeometry(c) => Box::new(c),
+            CommandRecord::TransformGeometry(c) => Box::new(c),
+            CommandRecord::DuplicateGeometry(c) => Box::new(c),
+            CommandRecord::AlignGeometry(c) => Box::new(c),
+            CommandRecord::StretchGeometry(c) => Box::new(c),
+            CommandRecord::CreateCell(c) => Box::new(c),
+            CommandRecord::RenameCell(c) => Box::new(c),
+            CommandRecord::DeleteCell(c) => Box::new(c),
+            CommandRecord::FlattenCell(c) => Box::new(c),
+            CommandRecord::PlaceInstance(c) => Box::new(c),
+            CommandRecord::TransformInstance(c) => Box::new(c),
+            CommandRecord::SetInstanceArray(c) => Box::new(c),
+            CommandRecord::DeleteInstance(c) => Box::new(c),
+            CommandRecord::SetPin(c) => Box::new(c),
+            CommandRecord::RemovePin(c) => Box::new(c),
+            CommandRecord::SetProperty(c) => Box::new(c),
+            CommandRecord::AddLayer(c) => Box::new(c),
+            CommandRecord::EditLayer(c) => Box::new(c),
+            CommandRecord::SetAllLayersVisible(c) => Box::new(c),
+            CommandRecord::Compound {
+                description,
+                commands,
+            } => Box::new(CompoundCommand::from_records(&description, commands)),
+        }
+    }
+}
+
+/// One line of the journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum JournalEntry {
+    /// A command, recorded as it was before executing.
+    Execute(Box<CommandRecord>),
+    Undo,
+    Redo,
+    Begin(String),
+    Commit,
+    Rollback,
+    /// [`LayoutDatabase::seal_history`].
+    Seal,
+}
+
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid journal data: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("No checkpoint at {0}")]
+    MissingCheckpoint(PathBuf),
+}
+
+/// The database and its history at the start of the journal.
+#[derive(Serialize, Deserialize)]
+struct Checkpoint<D> {
+    /// Whether this state is what the project file holds.
+    saved: bool,
+    /// The project file the session started from.
+    #[serde(default)]
+    project: Option<FileStamp>,
+    database: D,
+    undo: Vec<CommandRecord>,
+    redo: Vec<CommandRecord>,
+}
+
+/// Only the part of a checkpoint needed by [`Journal::status`].
+#[derive(Deserialize)]
+struct CheckpointHeader {
+    saved: bool,
+    #[serde(default)]
+    project: Option<FileStamp>,
+}
+
+/// Size and modification time of a project file, to tell whether it was
+/// changed behind the journal's back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// What [`Journal::status`] found next to a project file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalStatus {
+    /// Nothing beyond what the project file holds.
+    Clean,
+    /// Edits from an earlier session that were never saved.
+    Pending,
+    /// Unsaved edits to an earlier version of the project file: it has been
+    /// changed since they were made, and recovering them discards those
+    /// changes.
+    Stale,
+}
+
+/// The result of [`Journal::recover`].
+#[derive(Debug)]
+pub struct Recovered {
+    pub database: LayoutDatabase,
+    /// Journal entries replayed onto the checkpoint.
+    pub replayed: usize,
+    /// Trailing entries that could not be read, usually one cut short by
+    /// the crash.
+    pub skipped: usize,
+}
+
+/// An open journal for one project file. See the module documentation.
+#[derive(Debug)]
+pub struct Journal {
+    project: PathBuf,
+    /// The project file as it was when journaling started.
+    stamp: Option<FileStamp>,
+    file: File,
+    /// Whether the checkpoint of the saved state is still to be written.
+    checkpoint_due: bool,
+    entries: usize,
+    checkpoint_interval: usize,
+}
+
+impl Journal {
+    /// Start journaling a project whose file holds `db`, replacing any
+    /// previous journal. Call after opening or saving the project.
+    ///
+    /// The checkpoint is not written until the database is about to be
+    /// edited (see [`checkpoint_due`](Self::checkpoint_due)), so opening or
+    /// saving a project costs nothing more until then.
+    pub fn create(project: &Path, db: &LayoutDatabase) -> Result<Self, JournalError> {
+        Self::start(project, db, true)
+    }
+
+    /// Start journaling a project whose file is older than `db`, such as a
+    /// freshly recovered one, replacing any previous journal.
+    pub fn resume(project: &Path, db: &LayoutDatabase) -> Result<Self, JournalError> {
+        Self::start(project, db, false)
+    }
+
+    fn start(project: &Path, db: &LayoutDatabase, saved: bool) -> Result<Self, JournalError> {
+        let stamp = FileStamp::of(project);
+        if !saved {
+            write_checkpoint(project, stamp, db, saved)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::journal_path(project))?;
+        file.set_len(0)?;
+        if saved {
+            // Without a checkpoint there is nothing to recover.
+            match fs::remove_file(Self::checkpoint_path(project)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(Self {
+            project: project.to_path_buf(),
+            stamp,
+            file,
+            checkpoint_due: saved,
+            entries: 0,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+        })
+    }
+
+    /// Compact after this many entries.
+    pub fn with_checkpoint_interval(mut self, entries: usize) -> Self {
+        self.checkpoint_interval = entries.max(1);
+        self
+    }
+
+    pub fn journal_path(project: &Path) -> PathBuf {
+        with_suffix(project, ".journal")
+    }
+
+    pub fn checkpoint_path(project: &Path) -> PathBuf {
+        with_suffix(project, ".checkpoint")
+    }
+
+    /// The project file this journal belongs to.
+    pub fn project(&self) -> &Path {
+        &self.project
+    }
+
+    /// Entries written since the last checkpoint.
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    pub fn append(&mut self, entry: &JournalEntry) -> Result<(), JournalError> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.flush()?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    pub fn needs_checkpoint(&self) -> bool {
+        self.entries >= self.checkpoint_interval
+    }
+
+    /// Whether the checkpoint of the saved state deferred by
+    /// [`create`](Self::create) must be written before the next entry.
+    pub fn checkpoint_due(&self) -> bool {
+        self.checkpoint_due
+    }
+
+    /// Write `db` and its history as the new checkpoint and empty the
+    /// journal. `saved` tells whether `db` matches the project file.
+    pub fn checkpoint(&mut self, db: &LayoutDatabase, saved: bool) -> Result<(), JournalError> {
+        write_checkpoint(&self.project, self.stamp, db, saved)?;
+        self.file.set_len(0)?;
+        self.checkpoint_due = false;
+        self.entries = 0;
+        Ok(())
+    }
+
+    /// Whether the project has journaled edits that are not in its file.
+    pub fn pending(project: &Path) -> bool {
+        Self::status(project) != JournalStatus::Clean
+    }
+
+    /// Whether the project has journaled edits that are not in its file,
+    /// and whether the file changed since they were made.
+    pub fn status(project: &Path) -> JournalStatus {
+        let has_entries = fs::metadata(Self::journal_path(project)).is_ok_and(|m| m.len() > 0);
+        let header: Option<CheckpointHeader> = File::open(Self::checkpoint_path(project))
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok());
+        if !has_entries && header.as_ref().is_none_or(|h| h.saved) {
+            return JournalStatus::Clean;
+        }
+        match header.and_then(|h| h.project) {
+            Some(stamp) if FileStamp::of(project) != Some(stamp) => JournalStatus::Stale,
+            _ => JournalStatus::Pending,
+        }
+    }
+
+    /// Rebuild the last session: load the checkpoint with its undo history
+    /// and replay the journal onto it. A transaction left open by the crash
+    /// is rolled back.
+    pub fn recover(project: &Path) -> Result<Recovered, JournalError> {
+        let checkpoint_path = Self::checkpoint_path(project);
+        let file = match File::open(&checkpoint_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(JournalError::MissingCheckpoint(checkpoint_path))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let checkpoint: Checkpoint<LayoutDatabase> = serde_json::from_reader(BufReader::new(file))?;
+        let mut db = checkpoint.database;
+        db.set_history(CommandHistory::from_records(checkpoint.undo, checkpoint.redo));
+
+        let lines: Vec<String> = match File::open(Self::journal_path(project)) {
+            Ok(file) => BufReader::new(file).lines().collect::<Result<_, _>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut replayed = 0;
+        for line in lines.iter().filter(|l| !l.trim().is_empty()) {
+            // Stop at the first unreadable entry: later ones depend on it.
+            let Ok(entry) = serde_json::from_str::<JournalEntry>(line) else {
+                break;
+            };
+            db.replay(entry);
+            replayed += 1;
+        }
+        while db.rollback().is_ok() {}
+
+        let total = lines.iter().filter(|l| !l.trim().is_empty()).count();
+        Ok(Recovered {
+            database: db,
+            replayed,
+            skipped: total - replayed,
+        })
+    }
+
+    /// Delete the journal and checkpoint of a project.
+    pub fn discard(project: &Path) -> Result<(), JournalError> {
+        for path in [Self::journal_path(project), Self::checkpoint_path(project)] {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Write the checkpoint to a temporary file first, so a crash while writing
+/// leaves the previous one intact.
+fn write_checkpoint(
+    project: &Path,
+    stamp: Option<FileStamp>,
+    db: &LayoutDatabase,
+    saved: bool,
+) -> Result<(), JournalError> {
+    let (undo, redo) = db.history().to_records();
+    let checkpoint = Checkpoint {
+        saved,
+        project: stamp,
+        database: db,
+        undo,
+        redo,
+    };
+    let path = Journal::checkpoint_path(project);
+    let tmp = with_suffix(&path, ".tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer(&mut writer, &checkpoint)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    drop(writer);
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+impl LayoutDatabase {
+    /// Apply one journal entry. Failures are expected to repeat exactly as
+    /// they happened when the entry was written, so they are ignored.
+    fn replay(&mut self, entry: JournalEntry) {
+        match entry {
+            JournalEntry::Execute(record) => {
+                let _ = self.execute_command(record.into_command());
+            }
+            JournalEntry::Undo => {
+                self.undo();
+            }
+            JournalEntry::Redo => {
+                self.redo();
+            }
+            JournalEntry::Begin(description) => self.begin_transaction(&description),
+            JournalEntry::Commit => {
+                let _ = self.commit();
+            }
+            JournalEntry::Rollback => {
+                let _ = self.rollback();
+            }
+            JournalEntry::Seal => self.seal_history(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::{Cell, CellId};
+    use crate::geometry::{GeomPrimitive, Point, Rect};
+
+    fn project_path() -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("opensilicon-journal-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("design.json")
+    }
+
+    fn add(cell_id: CellId, x: i64) -> Box<dyn Command> {
+        let rect = GeomPrimitive::Rect(Rect::new(1, x, 0, x + 10, 10));
+        Box::new(AddGeometryCommand::new(cell_id, rect))
+    }
+
+    fn geometries(db: &LayoutDatabase, cell_id: CellId) -> Vec<GeomPrimitive> {
+        db.get_cell(&cell_id).unwrap().geometries().cloned().collect()
+    }
+
+    #[test]
+    fn test_recover_replays_session_with_history() {
+        let project = project_path();
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("top"));
+        db.attach_journal(Journal::create(&project, &db).unwrap());
+        assert!(!Journal::pending(&project));
+
+        db.execute_command(add(cell_id, 0)).unwrap();
+        let ids: Vec<_> = db.get_cell(&cell_id).unwrap().shapes.iter().map(|s| s.id).collect();
+        db.execute_command(Box::new(MoveGeometryCommand::new(cell_id, ids, Point::new(5, 0))))
+            .unwrap();
+        db.undo();
+        db.transaction("Add two", |db| {
+            db.execute_command(add(cell_id, 100))?;
+            db.execute_command(add(cell_id, 200))
+        })
+        .unwrap();
+        // A failed command outside a transaction changes nothing.
+        assert!(db.execute_command(add(uuid::Uuid::new_v4(), 0)).is_err());
+        assert!(Journal::pending(&project));
+
+        let recovered = Journal::recover(&project).unwrap();
+        assert_eq!((recovered.replayed, recovered.skipped), (7, 0));
+        let mut copy = recovered.database;
+        assert_eq!(geometries(&copy, cell_id), geometries(&db, cell_id));
+        assert_eq!(copy.undo_description(), Some("Add two"));
+        assert!(copy.undo() && copy.undo());
+        assert!(copy.get_cell(&cell_id).unwrap().shapes.is_empty());
+
+        Journal::discard(&project).unwrap();
+        assert!(!Journal::pending(&project));
+        fs::remove_dir_all(project.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_compaction_keeps_history() {
+        let project = project_path();
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("top"));
+        let journal = Journal::create(&project, &db).unwrap().with_checkpoint_interval(2);
+        db.attach_journal(journal);
+        for x in 0..5 {
+            db.execute_command(add(cell_id, x * 100)).unwrap();
+        }
+        db.undo();
+        assert_eq!(db.journal().unwrap().len(), 0);
+
+        // Compacted but never saved: still worth recovering.
+        assert!(Journal::pending(&project));
+        let mut copy = Journal::recover(&project).unwrap().database;
+        assert_eq!(geometries(&copy, cell_id), geometries(&db, cell_id));
+        assert!(copy.redo());
+        assert_eq!(copy.get_cell(&cell_id).unwrap().geometry_count(), 5);
+
+        db.checkpoint(true).unwrap();
+        assert!(!Journal::pending(&project));
+        fs::remove_dir_all(project.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_recover_stops_at_torn_entry() {
+        let project = project_path();
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("top"));
+        db.attach_journal(Journal::create(&project, &db).unwrap());
+        db.execute_command(add(cell_id, 0)).unwrap();
+        // The crash hit in the middle of a transaction...
+        db.begin_transaction("Unfinished");
+        db.execute_command(add(cell_id, 100)).unwrap();
+        drop(db);
+        // ...and while writing an entry.
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(Journal::journal_path(&project))
+            .unwrap();
+        file.write_all(b"{\"Execute\":{\"AddGeo").unwrap();
+
+        let recovered = Journal::recover(&project).unwrap();
+        assert_eq!((recovered.replayed, recovered.skipped), (3, 1));
+        let db = recovered.database;
+        assert!(!db.in_transaction());
+        assert_eq!(db.get_cell(&cell_id).unwrap().geometry_count(), 1);
+        fs::remove_dir_all(project.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_status_reports_changed_project_file() {
+        let project = project_path();
+        fs::write(&project, "{}").unwrap();
+        let mut db = LayoutDatabase::new("test");
+        let cell_id = db.add_cell(Cell::new("top"));
+        db.attach_journal(Journal::create(&project, &db).unwrap());
+        assert_eq!(Journal::status(&project), JournalStatus::Clean);
+        // Nothing is written for the saved state until it is edited.
+        assert!(!Journal::checkpoint_path(&project).exists());
+        db.execute_command(add(cell_id, 0)).unwrap();
+        assert_eq!(Journal::status(&project), JournalStatus::Pending);
+
+        // Saved by something else while the edits were unsaved.
+        fs::write(&project, "{\"changed\": true}").unwrap();
+        assert_eq!(Journal::status(&project), JournalStatus::Stale);
+        assert!(Journal::pending(&project));
+        fs::remove_dir_all(project.parent().unwrap()).unwrap();
+    }
+}
@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
 
-/// A unique layer identifier (typically GDS layer number).
+/// A unique layer identifier.
+///
+/// Layers read from GDS-II that no technology layer maps get the id
+/// [`Layer::gds_id`] packs from their layer/datatype pair, which is the
+/// bare GDS layer number for datatype 0.
 pub type LayerId = u32;
 
 /// Represents a technology layer in the layout.
@@ -34,6 +38,12 @@ impl Layer {
         }
     }
 
+    /// The id of an unmapped GDS-II layer/datatype pair: the datatype in the
+    /// upper 16 bits and the layer number in the lower.
+    pub fn gds_id(gds_layer: u16, gds_datatype: u16) -> LayerId {
+        ((gds_datatype as LayerId) << 16) | gds_layer as LayerId
+    }
+
     pub fn with_color(mut self, r: u8, g: u8, b: u8) -> Self {
         self.color = LayerColor { r, g, b };
         self
@@ -105,6 +115,12 @@ impl LayerStack {
         self.layers.push(layer);
     }
 
+    /// Remove a layer, returning it.
+    pub fn remove_layer(&mut self, id: LayerId) -> Option<Layer> {
+        let index = self.layers.iter().position(|l| l.id == id)?;
+        Some(self.layers.remove(index))
+    }
+
     pub fn get_layer(&self, id: LayerId) -> Option<&Layer> {
         self.layers.iter().find(|l| l.id == id)
     }
@@ -119,6 +135,30 @@ impl LayerStack {
             .find(|l| l.gds_layer == gds_layer && l.gds_datatype == gds_datatype)
     }
 
+    /// The layer for a GDS-II layer/datatype pair, adding one named
+    /// `layer/datatype` if no layer maps it yet.
+    pub fn layer_for_gds(&mut self, gds_layer: u16, gds_datatype: u16) -> LayerId {
+        if let Some(layer) = self.get_layer_by_gds(gds_layer, gds_datatype) {
+            return layer.id;
+        }
+        let mut id = Layer::gds_id(gds_layer, gds_datatype);
+        while self.get_layer(id).is_some() {
+            id = id.wrapping_add(1);
+        }
+        let name = format!("{}/{}", gds_layer, gds_datatype);
+        self.add_layer(Layer::new(id, &name, gds_layer, gds_datatype));
+        id
+    }
+
+    /// The GDS-II layer/datatype pair a layer is written as. Ids not in the
+    /// stack are unpacked as [`Layer::gds_id`] would have packed them.
+    pub fn gds_pair(&self, id: LayerId) -> (u16, u16) {
+        match self.get_layer(id) {
+            Some(layer) => (layer.gds_layer, layer.gds_datatype),
+            None => (id as u16, (id >> 16) as u16),
+        }
+    }
+
     pub fn visible_layers(&self) -> impl Iterator<Item = &Layer> {
         self.layers.iter().filter(|l| l.visible)
     }
@@ -149,3 +189,27 @@ impl Default for LayerStack {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gds_pairs_map_to_distinct_layers() {
+        let mut stack = LayerStack::new();
+        stack.add_layer(Layer::new(1, "metal1", 68, 20));
+        // An unrelated layer already holds the packed id of 68/5.
+        stack.add_layer(Layer::new(Layer::gds_id(68, 5), "other", 1, 0));
+
+        assert_eq!(stack.layer_for_gds(68, 20), 1);
+        assert_eq!(stack.layer_for_gds(68, 0), 68);
+        let label = stack.layer_for_gds(68, 5);
+        assert_eq!(label, Layer::gds_id(68, 5) + 1);
+        assert_eq!(stack.layer_for_gds(68, 5), label);
+        assert_eq!(stack.get_layer(label).unwrap().name, "68/5");
+
+        assert_eq!(stack.gds_pair(1), (68, 20));
+        assert_eq!(stack.gds_pair(label), (68, 5));
+        assert_eq!(stack.gds_pair(Layer::gds_id(7, 3)), (7, 3));
+    }
+}
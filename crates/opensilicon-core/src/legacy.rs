@@ -0,0 +1,201 @@
+//! Reading projects saved before the database used integer coordinates.
+//!
+//! Those projects store `dbu_per_nm`, floating point coordinates in
+//! micrometers, bare geometries in `Cell::geometries` and instance
+//! transforms as rotation, mirror and scale. [`LegacyDatabase::migrate`]
+//! converts them to the current layout onto a grid of the same resolution.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::cell::{Cell, CellId, CellInstance, Pin, PinDirection, Shape};
+use crate::database::LayoutDatabase;
+use crate::geometry::{GeomPrimitive, Path, Point, Polygon, Rect, Via};
+use crate::layer::{LayerId, LayerStack};
+use crate::transform::Transform;
+use crate::units::DbUnits;
+
+#[derive(Deserialize)]
+pub(crate) struct LegacyDatabase {
+    id: Uuid,
+    name: String,
+    layer_stack: LayerStack,
+    cells: HashMap<CellId, LegacyCell>,
+    top_cell: Option<CellId>,
+    /// Nanometers per database unit.
+    dbu_per_nm: f64,
+}
+
+#[derive(Deserialize)]
+struct LegacyCell {
+    id: CellId,
+    name: String,
+    geometries: Vec<LegacyGeometry>,
+    instances: Vec<LegacyInstance>,
+    pins: Vec<LegacyPin>,
+    modified: bool,
+}
+
+#[derive(Deserialize)]
+struct LegacyInstance {
+    id: Uuid,
+    cell_id: CellId,
+    instance_name: String,
+    transform: LegacyTransform,
+}
+
+#[derive(Deserialize)]
+struct LegacyTransform {
+    offset: LegacyPoint,
+    rotation: f64,
+    mirror_x: bool,
+    scale: f64,
+}
+
+#[derive(Deserialize)]
+struct LegacyPin {
+    name: String,
+    layer_id: LayerId,
+    shape: LegacyGeometry,
+    direction: PinDirection,
+}
+
+/// A point in micrometers.
+#[derive(Deserialize, Clone, Copy)]
+struct LegacyPoint {
+    x: f64,
+    y: f64,
+}
+
+#[derive(Deserialize)]
+enum LegacyGeometry {
+    Rect {
+        layer_id: LayerId,
+        lower_left: LegacyPoint,
+        upper_right: LegacyPoint,
+    },
+    Polygon {
+        layer_id: LayerId,
+        vertices: Vec<LegacyPoint>,
+    },
+    Path {
+        layer_id: LayerId,
+        points: Vec<LegacyPoint>,
+        width: f64,
+    },
+    Via {
+        bottom_layer: LayerId,
+        top_layer: LayerId,
+        cut_layer: LayerId,
+        position: LegacyPoint,
+        width: f64,
+        height: f64,
+    },
+}
+
+impl LegacyDatabase {
+    /// The project as a current database, with every coordinate rounded to
+    /// the nearest database unit.
+    pub(crate) fn migrate(self) -> LayoutDatabase {
+        let dbu_per_um = if self.dbu_per_nm > 0.0 {
+            1000.0 / self.dbu_per_nm
+        } else {
+            DbUnits::default().dbu_per_um
+        };
+        let u = DbUnits::new(dbu_per_um);
+
+        let mut db = LayoutDatabase::new(&self.name);
+        db.id = self.id;
+        db.layer_stack = self.layer_stack;
+        db.dbu_per_um = dbu_per_um;
+        for cell in self.cells.into_values() {
+            db.add_cell(cell.migrate(&u));
+        }
+        db.top_cell = self.top_cell;
+        db
+    }
+}
+
+impl LegacyCell {
+    fn migrate(self, u: &DbUnits) -> Cell {
+        let mut cell = Cell::new(&self.name);
+        cell.id = self.id;
+        cell.shapes = self
+            .geometries
+            .into_iter()
+            .map(|g| Shape::new(g.migrate(u)))
+            .collect();
+        cell.instances = self
+            .instances
+            .into_iter()
+            .map(|inst| {
+                let t = inst.transform;
+                let transform =
+                    Transform::from_parts(t.offset.migrate(u), t.mirror_x, t.rotation, t.scale);
+                CellInstance {
+                    id: inst.id,
+                    ..CellInstance::new(inst.cell_id, &inst.instance_name, transform)
+                }
+            })
+            .collect();
+        cell.pins = self
+            .pins
+            .into_iter()
+            .map(|pin| Pin {
+                name: pin.name,
+                layer_id: pin.layer_id,
+                shape: pin.shape.migrate(u),
+                direction: pin.direction,
+            })
+            .collect();
+        cell.modified = self.modified;
+        cell
+    }
+}
+
+impl LegacyPoint {
+    fn migrate(self, u: &DbUnits) -> Point {
+        u.point_from_um(self.x, self.y)
+    }
+}
+
+impl LegacyGeometry {
+    fn migrate(self, u: &DbUnits) -> GeomPrimitive {
+        let points = |points: Vec<LegacyPoint>| points.into_iter().map(|p| p.migrate(u)).collect();
+        match self {
+            LegacyGeometry::Rect {
+                layer_id,
+                lower_left,
+                upper_right,
+            } => {
+                let (a, b) = (lower_left.migrate(u), upper_right.migrate(u));
+                GeomPrimitive::Rect(Rect::new(layer_id, a.x, a.y, b.x, b.y))
+            }
+            LegacyGeometry::Polygon { layer_id, vertices } => {
+                GeomPrimitive::Polygon(Polygon::new(layer_id, points(vertices)))
+            }
+            LegacyGeometry::Path {
+                layer_id,
+                points: centerline,
+                width,
+            } => GeomPrimitive::Path(Path::new(layer_id, points(centerline), u.to_dbu(width))),
+            LegacyGeometry::Via {
+                bottom_layer,
+                top_layer,
+                cut_layer,
+                position,
+                width,
+                height,
+            } => GeomPrimitive::Via(Via::new(
+                bottom_layer,
+                top_layer,
+                cut_layer,
+                position.migrate(u),
+                u.to_dbu(width),
+                u.to_dbu(height),
+            )),
+        }
+    }
+}
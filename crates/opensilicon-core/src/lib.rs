@@ -6,13 +6,35 @@
 //! This crate is the heart of the OpenSilicon EDA kernel.
 
 pub mod geometry;
+pub mod boolean;
+pub mod sizing;
 pub mod cell;
+pub mod transform;
+pub mod hierarchy;
 pub mod database;
+pub mod changes;
+pub mod flatten;
+pub mod query;
 pub mod layer;
+mod legacy;
+pub mod journal;
 pub mod commands;
+pub mod design_commands;
 pub mod spatial;
+pub mod snap;
+pub mod units;
+pub mod validate;
 
 pub use database::LayoutDatabase;
-pub use cell::Cell;
+pub use changes::ChangeEvent;
+pub use cell::{ArrayParams, Cell, PathStep};
+pub use commands::{CommandError, CompoundCommand, HistoryLimits};
+pub use transform::{Orientation, Transform};
+pub use hierarchy::{Hierarchy, HierarchyError};
+pub use flatten::{FlatShape, FlattenOptions};
+pub use query::{RegionHit, RegionQuery};
+pub use snap::{EdgeSnap, VertexSnap};
 pub use layer::{Layer, LayerId};
-pub use geometry::{Rect, Polygon, Path, Via, Point, GeomPrimitive};
+pub use geometry::{Rect, Polygon, Path, PathEndStyle, Via, Point, GeomPrimitive, Coord};
+pub use units::DbUnits;
+pub use validate::{PolygonCheck, PolygonIssue};
@@ -0,0 +1,250 @@
+//! Hierarchical region queries.
+//!
+//! [`LayoutDatabase::query_hierarchy`] finds the shapes touching a region
+//! anywhere below a cell without flattening it. It descends through the
+//! instances whose placed extents touch the region, and searches each
+//! cell's spatial index with the region mapped into that cell's own
+//! coordinates.
+
+use crate::cell::{CellId, PathStep, ShapeId, Transform};
+use crate::database::LayoutDatabase;
+use crate::geometry::{BBox, Coord, GeomPrimitive, Point};
+use crate::hierarchy::HierarchyError;
+use crate::layer::LayerId;
+
+/// Limits for [`LayoutDatabase::query_hierarchy`].
+#[derive(Debug, Clone, Default)]
+pub struct RegionQuery {
+    /// Only report shapes on this layer (all layers if `None`).
+    pub layer: Option<LayerId>,
+    /// Number of instance levels to descend; `None` descends all the way
+    /// and `Some(0)` searches only the queried cell.
+    pub max_depth: Option<usize>,
+    /// Level-of-detail cutoff: shapes and instances whose placed extent is
+    /// smaller than this in both directions are skipped.
+    pub min_size: Coord,
+}
+
+impl RegionQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_layer(mut self, layer: LayerId) -> Self {
+        self.layer = Some(layer);
+        self
+    }
+
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    pub fn with_min_size(mut self, size: Coord) -> Self {
+        self.min_size = size;
+        self
+    }
+
+    fn large_enough(&self, bbox: &BBox) -> bool {
+        bbox.width() >= self.min_size || bbox.height() >= self.min_size
+    }
+}
+
+/// A shape found by a hierarchical region query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionHit {
+    /// The cell that defines the shape.
+    pub cell: CellId,
+    /// The shape in that cell.
+    pub shape: ShapeId,
+    /// The instance placements walked through to reach `cell`; empty for a
+    /// shape of the queried cell itself.
+    pub path: Vec<PathStep>,
+    /// Placement of `cell` in the queried cell's coordinates.
+    pub transform: Transform,
+}
+
+impl RegionHit {
+    /// The shape transformed into the queried cell's coordinates.
+    pub fn geometry(&self, db: &LayoutDatabase) -> Option<GeomPrimitive> {
+        let shape = db.get_cell(&self.cell)?.shape(&self.shape)?;
+        Some(self.transform.apply_primitive(&shape.geometry))
+    }
+}
+
+impl LayoutDatabase {
+    /// Shapes below `id` whose placed bounding box touches `region` (given
+    /// in `id`'s coordinates), descending through instances.
+    pub fn query_hierarchy(
+        &self,
+        id: &CellId,
+        region: &BBox,
+        query: &RegionQuery,
+    ) -> Result<Vec<RegionHit>, HierarchyError> {
+        if self.get_cell(id).is_none() {
+            return Err(HierarchyError::UnknownCell(*id));
+        }
+        let mut hits = Vec::new();
+        let mut stack = vec![(*id, Transform::default(), Vec::new(), vec![*id])];
+        while let Some((cell_id, transform, path, cells)) = stack.pop() {
+            let cell = self.get_cell(&cell_id).expect("only existing cells are pushed");
+
+            let mut local = transform.inverse().apply_bbox(region);
+            if !transform.is_exact() {
+                // Allow for rounding in the inverse mapping.
+                local = BBox::new(local.min.translate(-1, -1), local.max.translate(1, 1));
+            }
+            for shape_id in self.query_region(&cell_id, &local, query.layer) {
+                let shape = cell.shape(&shape_id).expect("indexed shape exists");
+                let Some(placed) = shape.geometry.bbox().map(|bb| transform.apply_bbox(&bb)) else {
+                    continue;
+                };
+                if placed.intersects(region) && query.large_enough(&placed) {
+                    hits.push(RegionHit {
+                        cell: cell_id,
+                        shape: shape_id,
+                        path: path.clone(),
+                        transform,
+                    });
+                }
+            }
+
+            if query.max_depth.is_some_and(|max| path.len() >= max) {
+                continue;
+            }
+            // Push in reverse so instances are visited in placement order.
+            for inst in cell.instances.iter().rev() {
+                if cells.contains(&inst.cell_id) {
+                    continue;
+                }
+                let Some(bbox) = self.cell_bbox(&inst.cell_id) else {
+                    continue;
+                };
+                let whole = transform.apply_bbox(&inst.placed_bbox(&bbox));
+                if !whole.intersects(region) {
+                    continue;
+                }
+                let near: Vec<usize> = inst.placement_indices_near(&bbox, &local).collect();
+                for &index in near.iter().rev() {
+                    let child = transform.compose(&inst.placement(index));
+                    let placed = child.apply_bbox(&bbox);
+                    if !placed.intersects(region) || !query.large_enough(&placed) {
+                        continue;
+                    }
+                    let mut child_path = path.clone();
+                    child_path.push(PathStep { instance: inst.id, element: index });
+                    let mut child_cells = cells.clone();
+                    child_cells.push(inst.cell_id);
+                    stack.push((inst.cell_id, child, child_path, child_cells));
+                }
+            }
+        }
+        Ok(hits)
+    }
+
+    /// Shapes below `id` whose placed bounding box contains `point`.
+    pub fn query_hierarchy_point(
+        &self,
+        id: &CellId,
+        point: &Point,
+        query: &RegionQuery,
+    ) -> Result<Vec<RegionHit>, HierarchyError> {
+        self.query_hierarchy(id, &BBox::new(*point, *point), query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::{ArrayParams, Cell, CellInstance, Orientation};
+    use crate::geometry::Rect;
+
+    fn rect(layer: LayerId, x1: Coord, y1: Coord, x2: Coord, y2: Coord) -> GeomPrimitive {
+        GeomPrimitive::Rect(Rect::new(layer, x1, y1, x2, y2))
+    }
+
+    /// `top` places `mid` at (0, 0) and (1000, 0); `mid` places `leaf`
+    /// rotated by 90° at (100, 0). `leaf` has a metal1 bar and a tiny
+    /// metal2 square.
+    fn hierarchy() -> (LayoutDatabase, CellId, CellId) {
+        let mut db = LayoutDatabase::new("test");
+        let mut leaf = Cell::new("leaf");
+        leaf.add_geometry(rect(1, 0, 0, 10, 40));
+        leaf.add_geometry(rect(2, 0, 0, 2, 2));
+        let leaf_id = db.add_cell(leaf);
+
+        let mut mid = Cell::new("mid");
+        mid.add_instance(CellInstance::new(
+            leaf_id,
+            "L0",
+            Transform::new(Point::new(100, 0), Orientation::R90),
+        ));
+        let mid_id = db.add_cell(mid);
+
+        let mut top = Cell::new("top");
+        top.add_geometry(rect(1, 0, 500, 2000, 510));
+        top.add_instance(CellInstance::new(mid_id, "M0", Transform::default()));
+        top.add_instance(CellInstance::new(mid_id, "M1", Transform::translate(1000, 0)));
+        let top_id = db.add_cell(top);
+        (db, leaf_id, top_id)
+    }
+
+    #[test]
+    fn test_query_descends_instances() {
+        let (db, leaf_id, top_id) = hierarchy();
+        // Around the second placement of the leaf: (1060..1100, 0..10).
+        let region = BBox::new(Point::new(1050, 0), Point::new(1070, 5));
+        let hits = db
+            .query_hierarchy(&top_id, &region, &RegionQuery::new().with_layer(1))
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+        let hit = &hits[0];
+        assert_eq!(hit.cell, leaf_id);
+        assert_eq!(hit.path.len(), 2);
+        assert_eq!(hit.transform.offset, Point::new(1100, 0));
+        assert_eq!(hit.geometry(&db), Some(rect(1, 1060, 0, 1100, 10)));
+    }
+
+    #[test]
+    fn test_query_paths_name_array_elements() {
+        let (mut db, leaf_id, _) = hierarchy();
+        let array = ArrayParams::new(3, 2, Point::new(100, 0), Point::new(0, 100));
+        let mut top = Cell::new("array_top");
+        top.add_instance(CellInstance::new(leaf_id, "A", Transform::default()).with_array(array));
+        let inst = top.instances[0].id;
+        let top_id = db.add_cell(top);
+
+        // Inside the bar of the last element only.
+        let region = BBox::new(Point::new(205, 105), Point::new(206, 106));
+        let hits = db.query_hierarchy(&top_id, &region, &RegionQuery::new()).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].path, vec![PathStep { instance: inst, element: 5 }]);
+    }
+
+    #[test]
+    fn test_query_depth_and_size_limits() {
+        let (db, _, top_id) = hierarchy();
+        let all = BBox::new(Point::new(-5000, -5000), Point::new(5000, 5000));
+        let count = |query: RegionQuery| db.query_hierarchy(&top_id, &all, &query).unwrap().len();
+        assert_eq!(count(RegionQuery::new()), 5);
+        assert_eq!(count(RegionQuery::new().with_max_depth(1)), 1);
+        assert_eq!(count(RegionQuery::new().with_layer(2)), 2);
+        // The 2x2 squares drop out; the 40x10 bars stay.
+        assert_eq!(count(RegionQuery::new().with_min_size(5)), 3);
+        // Whole instances smaller than the cutoff are not entered.
+        assert_eq!(count(RegionQuery::new().with_min_size(100)), 1);
+    }
+
+    #[test]
+    fn test_query_point() {
+        let (db, _, top_id) = hierarchy();
+        let hits = db
+            .query_hierarchy_point(&top_id, &Point::new(70, 5), &RegionQuery::new())
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(db
+            .query_hierarchy_point(&top_id, &Point::new(70, 100), &RegionQuery::new())
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,238 @@
+//! Polygon sizing (grow / shrink / bias).
+//!
+//! Sizing is a Minkowski sum (grow) or difference (shrink) of the merged
+//! input with a small convex kernel: a square for Manhattan corners or an
+//! on-grid octagon for chamfered corners. Shapes are merged first, so sizing
+//! never opens gaps between abutting shapes or leaves overlaps between
+//! shapes that grow into each other.
+//!
+//! Every boundary edge is swept along the kernel (the convex hull of the
+//! kernel placed at both edge endpoints). Growing unions these sweeps with
+//! the merged area; shrinking subtracts them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::boolean::{self, BooleanOp, Region};
+use crate::geometry::{Coord, GeomPrimitive, Point, Polygon};
+use crate::layer::LayerId;
+
+/// How corners are formed when sizing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CornerMode {
+    /// Square kernel: Manhattan corners stay square.
+    #[default]
+    Square,
+    /// Octagonal kernel: convex corners are chamfered with 45° edges.
+    Octagonal,
+}
+
+/// Size a set of primitives by `bias` database units and return the merged
+/// result on `layer`. A positive bias grows, a negative bias shrinks.
+pub fn size<'a, I>(shapes: I, bias: Coord, corners: CornerMode, layer: LayerId) -> Vec<Polygon>
+where
+    I: IntoIterator<Item = &'a GeomPrimitive>,
+{
+    let merged = boolean::merged_regions(shapes);
+    if bias == 0 {
+        return boolean::region_boolean(&merged, &[], BooleanOp::Or, layer);
+    }
+
+    let kernel = kernel(bias.abs(), corners);
+    let sweeps: Vec<Region> = merged
+        .iter()
+        .flat_map(Region::rings)
+        .flat_map(|ring| {
+            (0..ring.len()).map(|i| {
+                let (p, q) = (ring[i], ring[(i + 1) % ring.len()]);
+                Region {
+                    outer: edge_sweep(p, q, &kernel),
+                    holes: Vec::new(),
+                }
+            })
+        })
+        .collect();
+
+    let op = if bias > 0 { BooleanOp::Or } else { BooleanOp::Not };
+    boolean::region_boolean(&merged, &sweeps, op, layer)
+}
+
+/// Size the shapes of `shapes` on `layer` by `bias`, leaving the result on
+/// the same layer.
+pub fn size_layer(
+    shapes: &[GeomPrimitive],
+    layer: LayerId,
+    bias: Coord,
+    corners: CornerMode,
+) -> Vec<Polygon> {
+    size(shapes.iter().filter(|g| g.layer_id() == layer), bias, corners, layer)
+}
+
+/// Grow a set of primitives outwards by `amount` (which must be non-negative).
+pub fn grow<'a, I>(shapes: I, amount: Coord, corners: CornerMode, layer: LayerId) -> Vec<Polygon>
+where
+    I: IntoIterator<Item = &'a GeomPrimitive>,
+{
+    size(shapes, amount.max(0), corners, layer)
+}
+
+/// Shrink a set of primitives inwards by `amount` (which must be non-negative).
+pub fn shrink<'a, I>(shapes: I, amount: Coord, corners: CornerMode, layer: LayerId) -> Vec<Polygon>
+where
+    I: IntoIterator<Item = &'a GeomPrimitive>,
+{
+    size(shapes, -amount.max(0), corners, layer)
+}
+
+/// Vertices of the sizing kernel with half-extent `d`, counter-clockwise.
+fn kernel(d: Coord, corners: CornerMode) -> Vec<Point> {
+    match corners {
+        CornerMode::Square => vec![
+            Point::new(d, -d),
+            Point::new(d, d),
+            Point::new(-d, d),
+            Point::new(-d, -d),
+        ],
+        CornerMode::Octagonal => {
+            // Half-length of the axis-parallel faces of a regular octagon,
+            // rounded to the grid: d * tan(22.5°).
+            let a = (d as f64 * (std::f64::consts::SQRT_2 - 1.0)).round() as Coord;
+            vec![
+                Point::new(d, -a),
+                Point::new(d, a),
+                Point::new(a, d),
+                Point::new(-a, d),
+                Point::new(-d, a),
+                Point::new(-d, -a),
+                Point::new(-a, -d),
+                Point::new(a, -d),
+            ]
+        }
+    }
+}
+
+/// The area swept by the kernel moving along the edge `p`→`q`.
+fn edge_sweep(p: Point, q: Point, kernel: &[Point]) -> Vec<Point> {
+    let points: Vec<Point> = kernel
+        .iter()
+        .flat_map(|k| [p.translate(k.x, k.y), q.translate(k.x, k.y)])
+        .collect();
+    convex_hull(points)
+}
+
+/// Convex hull (counter-clockwise, no collinear vertices) by the monotone
+/// chain algorithm with exact integer arithmetic.
+fn convex_hull(mut points: Vec<Point>) -> Vec<Point> {
+    points.sort_unstable_by_key(|p| (p.x, p.y));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+
+    let turn = |o: Point, a: Point, b: Point| {
+        (a.x - o.x) as i128 * (b.y - o.y) as i128 - (a.y - o.y) as i128 * (b.x - o.x) as i128
+    };
+    let mut hull: Vec<Point> = Vec::with_capacity(points.len() + 1);
+    let lower = points.iter();
+    let upper = points.iter().rev();
+    for chain in [lower.collect::<Vec<_>>(), upper.collect()] {
+        let start = hull.len();
+        for &p in chain {
+            while hull.len() >= start + 2 && turn(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0 {
+                hull.pop();
+            }
+            hull.push(p);
+        }
+        // The last point of each chain starts the other one.
+        hull.pop();
+    }
+    hull
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Rect;
+
+    fn rect(layer: LayerId, x1: Coord, y1: Coord, x2: Coord, y2: Coord) -> GeomPrimitive {
+        GeomPrimitive::Rect(Rect::new(layer, x1, y1, x2, y2))
+    }
+
+    fn total_area(polys: &[Polygon]) -> i128 {
+        polys.iter().map(Polygon::area).sum()
+    }
+
+    #[test]
+    fn test_grow_rect_square() {
+        let result = grow(&[rect(1, 0, 0, 100, 50)], 10, CornerMode::Square, 1);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].vertex_count(), 4);
+        let bb = result[0].bbox().unwrap();
+        assert_eq!((bb.min, bb.max), (Point::new(-10, -10), Point::new(110, 60)));
+    }
+
+    #[test]
+    fn test_grow_rect_octagonal() {
+        let result = grow(&[rect(1, 0, 0, 100, 100)], 10, CornerMode::Octagonal, 1);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].vertex_count(), 8);
+        assert_eq!(result[0].bbox().unwrap().min, Point::new(-10, -10));
+        assert!(result[0].vertices.contains(&Point::new(-4, -10)));
+        assert!(result[0].vertices.contains(&Point::new(-10, -4)));
+    }
+
+    #[test]
+    fn test_shrink_rect() {
+        let result = size(&[rect(1, 0, 0, 100, 50)], -10, CornerMode::Square, 1);
+        assert_eq!(result.len(), 1);
+        assert_eq!(total_area(&result), 80 * 30);
+        assert!(shrink(&[rect(1, 0, 0, 100, 20)], 10, CornerMode::Square, 1).is_empty());
+    }
+
+    #[test]
+    fn test_abutting_shapes_shrink_as_one() {
+        let shapes = [rect(1, 0, 0, 50, 40), rect(1, 50, 0, 100, 40)];
+        let result = shrink(&shapes, 5, CornerMode::Square, 1);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].vertex_count(), 4);
+        assert_eq!(total_area(&result), 90 * 30);
+    }
+
+    #[test]
+    fn test_grown_neighbours_merge() {
+        let shapes = [rect(1, 0, 0, 10, 10), rect(1, 14, 0, 24, 10)];
+        let result = grow(&shapes, 2, CornerMode::Square, 1);
+        assert_eq!(result.len(), 1);
+        assert_eq!(total_area(&result), 28 * 14);
+    }
+
+    #[test]
+    fn test_grow_keeps_concave_corner_square() {
+        // L shape: the inner corner moves diagonally and stays a right angle.
+        let shapes = [rect(1, 0, 0, 30, 10), rect(1, 0, 0, 10, 30)];
+        let result = grow(&shapes, 2, CornerMode::Octagonal, 1);
+        assert_eq!(result.len(), 1);
+        assert!(result[0].vertices.contains(&Point::new(12, 12)));
+    }
+
+    #[test]
+    fn test_shrink_widens_hole() {
+        let ring = [
+            rect(1, 0, 0, 40, 10),
+            rect(1, 0, 30, 40, 40),
+            rect(1, 0, 0, 10, 40),
+            rect(1, 30, 0, 40, 40),
+        ];
+        let result = shrink(&ring, 2, CornerMode::Square, 1);
+        assert_eq!(result.len(), 1);
+        assert_eq!(total_area(&result), 36 * 36 - 24 * 24);
+    }
+
+    #[test]
+    fn test_size_layer_filters() {
+        let shapes = [rect(1, 0, 0, 10, 10), rect(2, 0, 0, 10, 10)];
+        let result = size_layer(&shapes, 2, 5, CornerMode::Square);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].layer_id, 2);
+        assert_eq!(total_area(&result), 20 * 20);
+    }
+}
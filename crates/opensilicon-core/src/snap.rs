@@ -0,0 +1,335 @@
+//! Exact hit testing and snapping for interactive editing.
+//!
+//! The spatial index narrows a query down to the shapes whose bounding box
+//! is within the tolerance; the exact tests below then decide on the real
+//! shape: point-in-polygon (with holes), point-on-path using the path's
+//! drawn outline, and distances to edges and vertices.
+//!
+//! Among shapes at the same distance the topmost one (latest in drawing
+//! order) wins, as it is the one drawn on top.
+
+use std::collections::HashSet;
+
+use crate::boolean::point_in_ring;
+use crate::cell::{Cell, CellId, Shape, ShapeId};
+use crate::database::LayoutDatabase;
+use crate::geometry::{Coord, GeomPrimitive, Point};
+use crate::layer::LayerId;
+
+/// The closest point on a shape's outline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EdgeSnap {
+    pub shape: ShapeId,
+    /// The edge, as drawn.
+    pub start: Point,
+    pub end: Point,
+    /// The closest point on the edge, rounded to the grid.
+    pub point: Point,
+    pub distance: f64,
+}
+
+/// The closest vertex of a shape.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VertexSnap {
+    pub shape: ShapeId,
+    pub point: Point,
+    pub distance: f64,
+}
+
+impl GeomPrimitive {
+    /// Closed outline rings as drawn: the rectangle, the polygon's outer
+    /// ring and holes, a via's cut, or a path's merged outline. A label's
+    /// outline is its anchor point alone.
+    pub fn outline(&self) -> Vec<Vec<Point>> {
+        match self {
+            GeomPrimitive::Rect(_) | GeomPrimitive::Via(_) => {
+                let bb = self.bbox().expect("rects and vias have a bbox");
+                vec![vec![
+                    bb.min,
+                    Point::new(bb.max.x, bb.min.y),
+                    bb.max,
+                    Point::new(bb.min.x, bb.max.y),
+                ]]
+            }
+            GeomPrimitive::Polygon(p) => p.rings().cloned().collect(),
+            GeomPrimitive::Path(p) => p
+                .to_polygons()
+                .iter()
+                .flat_map(|poly| poly.rings().cloned().collect::<Vec<_>>())
+                .collect(),
+            GeomPrimitive::Text(t) => vec![vec![t.position()]],
+        }
+    }
+
+    /// Points to snap to: corners of rectangles, via cuts and polygons,
+    /// the centerline points of paths, and the centers of vias.
+    pub fn snap_vertices(&self) -> Vec<Point> {
+        match self {
+            GeomPrimitive::Path(p) => p.points.clone(),
+            GeomPrimitive::Via(v) => {
+                let mut points = self.outline().remove(0);
+                points.push(v.position);
+                points
+            }
+            _ => self.outline().into_iter().flatten().collect(),
+        }
+    }
+
+    /// Whether `p` lies inside the shape or on its outline.
+    pub fn contains_point(&self, p: &Point) -> bool {
+        match self {
+            GeomPrimitive::Rect(_) | GeomPrimitive::Via(_) => {
+                self.bbox().is_some_and(|bb| bb.contains_point(p))
+            }
+            GeomPrimitive::Polygon(poly) => {
+                let inside = point_in_ring(*p, &poly.vertices)
+                    && !poly.holes.iter().any(|h| point_in_ring(*p, h));
+                inside || on_outline(p, &self.outline())
+            }
+            GeomPrimitive::Path(path) => {
+                let pieces = path.outline_rings();
+                pieces.iter().any(|ring| point_in_ring(*p, ring)) || on_outline(p, &pieces)
+            }
+            GeomPrimitive::Text(t) => *p == t.position(),
+        }
+    }
+
+    /// Distance from `p` to the shape: zero inside, otherwise the distance
+    /// to the nearest outline edge.
+    pub fn distance_to_point(&self, p: &Point) -> f64 {
+        if self.contains_point(p) {
+            return 0.0;
+        }
+        self.nearest_edge(p).map(|(_, _, _, d)| d).unwrap_or(f64::INFINITY)
+    }
+
+    /// The outline edge closest to `p`, with the closest point on it and
+    /// its distance.
+    fn nearest_edge(&self, p: &Point) -> Option<(Point, Point, Point, f64)> {
+        let mut best: Option<(Point, Point, Point, f64)> = None;
+        for ring in self.outline() {
+            for i in 0..ring.len() {
+                let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+                let (closest, d) = closest_on_segment(p, a, b);
+                if best.is_none_or(|(_, _, _, bd)| d < bd) {
+                    best = Some((a, b, closest, d));
+                }
+            }
+        }
+        best
+    }
+}
+
+fn on_outline(p: &Point, rings: &[Vec<Point>]) -> bool {
+    rings.iter().any(|ring| {
+        (0..ring.len()).any(|i| closest_on_segment(p, ring[i], ring[(i + 1) % ring.len()]).1 == 0.0)
+    })
+}
+
+/// The point of segment `a`–`b` closest to `p`, rounded to the grid, and
+/// the exact distance to it.
+fn closest_on_segment(p: &Point, a: Point, b: Point) -> (Point, f64) {
+    let (dx, dy) = ((b.x - a.x) as f64, (b.y - a.y) as f64);
+    let len2 = dx * dx + dy * dy;
+    let t = if len2 == 0.0 {
+        0.0
+    } else {
+        (((p.x - a.x) as f64 * dx + (p.y - a.y) as f64 * dy) / len2).clamp(0.0, 1.0)
+    };
+    let (cx, cy) = (a.x as f64 + t * dx, a.y as f64 + t * dy);
+    let distance = ((p.x as f64 - cx).powi(2) + (p.y as f64 - cy).powi(2)).sqrt();
+    (Point::new(cx.round() as Coord, cy.round() as Coord), distance)
+}
+
+impl LayoutDatabase {
+    /// Shapes of a cell within `tolerance` of `point` (exactly, not by
+    /// bounding box), topmost first. Subcells are not searched.
+    pub fn hit_test(
+        &self,
+        id: &CellId,
+        point: &Point,
+        tolerance: Coord,
+        layer: Option<LayerId>,
+    ) -> Vec<ShapeId> {
+        self.candidates(id, point, tolerance, layer)
+            .filter(|s| s.geometry.distance_to_point(point) <= tolerance as f64)
+            .map(|s| s.id)
+            .collect()
+    }
+
+    /// The shape of a cell closest to `point`, within `max_distance`, and
+    /// its distance (zero if `point` is inside it).
+    pub fn nearest_shape(
+        &self,
+        id: &CellId,
+        point: &Point,
+        max_distance: Coord,
+        layer: Option<LayerId>,
+    ) -> Option<(ShapeId, f64)> {
+        self.candidates(id, point, max_distance, layer)
+            .map(|s| (s.id, s.geometry.distance_to_point(point)))
+            .filter(|(_, d)| *d <= max_distance as f64)
+            .fold(None, |best, hit| closer(best, hit, |h| h.1))
+    }
+
+    /// The closest point on any shape outline of a cell, within
+    /// `max_distance`.
+    pub fn nearest_edge(
+        &self,
+        id: &CellId,
+        point: &Point,
+        max_distance: Coord,
+        layer: Option<LayerId>,
+    ) -> Option<EdgeSnap> {
+        self.candidates(id, point, max_distance, layer)
+            .filter_map(|s| {
+                let (start, end, closest, distance) = s.geometry.nearest_edge(point)?;
+                Some(EdgeSnap {
+                    shape: s.id,
+                    start,
+                    end,
+                    point: closest,
+                    distance,
+                })
+            })
+            .filter(|snap| snap.distance <= max_distance as f64)
+            .fold(None, |best, hit| closer(best, hit, |h| h.distance))
+    }
+
+    /// The closest snap vertex of any shape of a cell, within
+    /// `max_distance`.
+    pub fn nearest_vertex(
+        &self,
+        id: &CellId,
+        point: &Point,
+        max_distance: Coord,
+        layer: Option<LayerId>,
+    ) -> Option<VertexSnap> {
+        self.candidates(id, point, max_distance, layer)
+            .flat_map(|s| {
+                s.geometry.snap_vertices().into_iter().map(|v| VertexSnap {
+                    shape: s.id,
+                    point: v,
+                    distance: v.distance_to(point),
+                })
+            })
+            .filter(|snap| snap.distance <= max_distance as f64)
+            .fold(None, |best, hit| closer(best, hit, |h| h.distance))
+    }
+
+    /// Shapes whose bounding box is within `distance` of `point`, topmost
+    /// first.
+    fn candidates<'a>(
+        &'a self,
+        id: &CellId,
+        point: &Point,
+        distance: Coord,
+        layer: Option<LayerId>,
+    ) -> impl Iterator<Item = &'a Shape> {
+        let ids: HashSet<ShapeId> = self
+            .with_spatial_index(id, |index| index.query_within(point, distance, layer))
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        let shapes = match self.get_cell(id) {
+            Some(Cell { shapes, .. }) if !ids.is_empty() => shapes.as_slice(),
+            _ => &[],
+        };
+        shapes.iter().rev().filter(move |s| ids.contains(&s.id))
+    }
+}
+
+/// Keep the earlier (topmost) of two equally distant hits.
+fn closer<T>(best: Option<T>, hit: T, distance: impl Fn(&T) -> f64) -> Option<T> {
+    match best {
+        Some(b) if distance(&b) <= distance(&hit) => Some(b),
+        _ => Some(hit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{Path, Polygon, Rect};
+
+    fn cell_with(shapes: Vec<GeomPrimitive>) -> (LayoutDatabase, CellId, Vec<ShapeId>) {
+        let mut db = LayoutDatabase::new("test");
+        let mut cell = Cell::new("top");
+        let ids = shapes.into_iter().map(|g| cell.add_geometry(g)).collect();
+        let id = db.add_cell(cell);
+        (db, id, ids)
+    }
+
+    #[test]
+    fn test_polygon_hit_is_exact() {
+        // An L shape: its bbox covers (15, 15) but the polygon does not.
+        let l_shape = Polygon::new(
+            1,
+            vec![
+                Point::new(0, 0),
+                Point::new(20, 0),
+                Point::new(20, 10),
+                Point::new(10, 10),
+                Point::new(10, 20),
+                Point::new(0, 20),
+            ],
+        );
+        let (db, cell, ids) = cell_with(vec![GeomPrimitive::Polygon(l_shape)]);
+        assert_eq!(db.hit_test(&cell, &Point::new(5, 15), 0, None), ids);
+        assert!(db.hit_test(&cell, &Point::new(15, 15), 0, None).is_empty());
+        assert_eq!(db.hit_test(&cell, &Point::new(15, 15), 5, None), ids);
+        // On the boundary counts as a hit.
+        assert_eq!(db.hit_test(&cell, &Point::new(20, 5), 0, None), ids);
+    }
+
+    #[test]
+    fn test_polygon_hole_is_not_hit() {
+        let ring = Polygon::with_holes(
+            1,
+            vec![Point::new(0, 0), Point::new(30, 0), Point::new(30, 30), Point::new(0, 30)],
+            vec![vec![Point::new(10, 10), Point::new(10, 20), Point::new(20, 20), Point::new(20, 10)]],
+        );
+        let (db, cell, _) = cell_with(vec![GeomPrimitive::Polygon(ring)]);
+        assert!(db.hit_test(&cell, &Point::new(15, 15), 0, None).is_empty());
+        assert_eq!(db.nearest_shape(&cell, &Point::new(15, 15), 10, None).unwrap().1, 5.0);
+    }
+
+    #[test]
+    fn test_path_hit_uses_outline() {
+        let path = Path::new(1, vec![Point::new(0, 0), Point::new(100, 100)], 10);
+        let (db, cell, ids) = cell_with(vec![GeomPrimitive::Path(path)]);
+        assert_eq!(db.hit_test(&cell, &Point::new(50, 52), 0, None), ids);
+        // Inside the bbox, far from the diagonal.
+        assert!(db.hit_test(&cell, &Point::new(90, 10), 0, None).is_empty());
+    }
+
+    #[test]
+    fn test_topmost_shape_wins() {
+        let (db, cell, ids) = cell_with(vec![
+            GeomPrimitive::Rect(Rect::new(1, 0, 0, 100, 100)),
+            GeomPrimitive::Rect(Rect::new(2, 40, 40, 60, 60)),
+        ]);
+        assert_eq!(db.hit_test(&cell, &Point::new(50, 50), 0, None), vec![ids[1], ids[0]]);
+        assert_eq!(db.nearest_shape(&cell, &Point::new(50, 50), 0, None), Some((ids[1], 0.0)));
+        assert_eq!(db.nearest_shape(&cell, &Point::new(50, 50), 0, Some(1)), Some((ids[0], 0.0)));
+        assert!(db.nearest_shape(&cell, &Point::new(200, 200), 50, None).is_none());
+    }
+
+    #[test]
+    fn test_snap_to_edge_and_vertex() {
+        let (db, cell, ids) = cell_with(vec![
+            GeomPrimitive::Rect(Rect::new(1, 0, 0, 100, 50)),
+            GeomPrimitive::Path(Path::new(1, vec![Point::new(200, 0), Point::new(200, 100)], 20)),
+        ]);
+        let edge = db.nearest_edge(&cell, &Point::new(40, 53), 5, None).unwrap();
+        assert_eq!(edge.shape, ids[0]);
+        assert_eq!((edge.point, edge.distance), (Point::new(40, 50), 3.0));
+        assert!(db.nearest_edge(&cell, &Point::new(40, 60), 5, None).is_none());
+
+        let vertex = db.nearest_vertex(&cell, &Point::new(97, 46), 10, None).unwrap();
+        assert_eq!((vertex.shape, vertex.point, vertex.distance), (ids[0], Point::new(100, 50), 5.0));
+        // Path vertices are its centerline points.
+        let vertex = db.nearest_vertex(&cell, &Point::new(203, 96), 10, None).unwrap();
+        assert_eq!((vertex.shape, vertex.point), (ids[1], Point::new(200, 100)));
+    }
+}
@@ -1,18 +1,22 @@
-use rstar::{RTree, RTreeObject, AABB};
+use std::collections::HashMap;
 
-use crate::geometry::{BBox, Point};
+use rstar::{Envelope, PointDistance, RTree, RTreeObject, AABB};
 
-/// An entry in the R-tree spatial index, referencing a geometry by its index.
-#[derive(Debug, Clone)]
+use crate::cell::{Cell, Shape, ShapeId};
+use crate::geometry::{BBox, Coord, Point};
+use crate::layer::LayerId;
+
+/// An entry in the R-tree spatial index, referencing a shape by its ID.
+#[derive(Debug, Clone, PartialEq)]
 pub struct SpatialEntry {
-    /// Index into the cell's geometry vector.
-    pub geometry_index: usize,
+    /// The indexed shape.
+    pub shape_id: ShapeId,
     /// Bounding box of the geometry.
     pub bbox: BBox,
 }
 
 impl RTreeObject for SpatialEntry {
-    type Envelope = AABB<[f64; 2]>;
+    type Envelope = AABB<[Coord; 2]>;
 
     fn envelope(&self) -> Self::Envelope {
         AABB::from_corners(
@@ -22,7 +26,19 @@ impl RTreeObject for SpatialEntry {
     }
 }
 
+impl PointDistance for SpatialEntry {
+    /// Squared distance from the point to the entry's bounding box.
+    fn distance_2(&self, point: &[Coord; 2]) -> Coord {
+        self.envelope().distance_2(point)
+    }
+
+    fn contains_point(&self, point: &[Coord; 2]) -> bool {
+        self.envelope().contains_point(point)
+    }
+}
+
 /// Spatial index for fast point-query and viewport culling.
+#[derive(Debug)]
 pub struct SpatialIndex {
     tree: RTree<SpatialEntry>,
 }
@@ -46,6 +62,11 @@ impl SpatialIndex {
         self.tree.insert(entry);
     }
 
+    /// Remove an entry, returning whether it was present.
+    pub fn remove(&mut self, entry: &SpatialEntry) -> bool {
+        self.tree.remove(entry).is_some()
+    }
+
     /// Find all entries whose bounding box contains the given point.
     pub fn query_point(&self, point: &Point) -> Vec<&SpatialEntry> {
         self.tree
@@ -64,6 +85,15 @@ impl SpatialIndex {
             .collect()
     }
 
+    /// Find all entries whose bounding box is within `max_distance` of the
+    /// given point.
+    pub fn query_within(&self, point: &Point, max_distance: Coord) -> Vec<&SpatialEntry> {
+        let max = max_distance.max(0);
+        self.tree
+            .locate_within_distance([point.x, point.y], max.saturating_mul(max))
+            .collect()
+    }
+
     /// Number of entries in the index.
     pub fn len(&self) -> usize {
         self.tree.size()
@@ -80,37 +110,179 @@ impl Default for SpatialIndex {
     }
 }
 
+/// Spatial indices of one cell's shapes, one R-tree per layer.
+///
+/// Vias are indexed on their cut layer. Shapes without a bounding box
+/// (empty polygons or paths) are not indexed.
+#[derive(Debug, Default)]
+pub struct CellSpatialIndex {
+    layers: HashMap<LayerId, SpatialIndex>,
+}
+
+impl CellSpatialIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bulk-load the indices for all shapes of a cell.
+    pub fn build(cell: &Cell) -> Self {
+        let mut entries: HashMap<LayerId, Vec<SpatialEntry>> = HashMap::new();
+        for shape in &cell.shapes {
+            if let Some(entry) = Self::entry(shape) {
+                entries.entry(shape.geometry.layer_id()).or_default().push(entry);
+            }
+        }
+        Self {
+            layers: entries
+                .into_iter()
+                .map(|(layer, entries)| (layer, SpatialIndex::build(entries)))
+                .collect(),
+        }
+    }
+
+    fn entry(shape: &Shape) -> Option<SpatialEntry> {
+        shape.geometry.bbox().map(|bbox| SpatialEntry {
+            shape_id: shape.id,
+            bbox,
+        })
+    }
+
+    /// Index a shape that was added to the cell.
+    pub fn insert(&mut self, shape: &Shape) {
+        if let Some(entry) = Self::entry(shape) {
+            self.layers
+                .entry(shape.geometry.layer_id())
+                .or_default()
+                .insert(entry);
+        }
+    }
+
+    /// Drop a shape that was removed from the cell (or is about to change).
+    /// `shape` must be as it was when it was indexed.
+    pub fn remove(&mut self, shape: &Shape) -> bool {
+        let layer = shape.geometry.layer_id();
+        let (Some(entry), Some(index)) = (Self::entry(shape), self.layers.get_mut(&layer)) else {
+            return false;
+        };
+        let removed = index.remove(&entry);
+        if index.is_empty() {
+            self.layers.remove(&layer);
+        }
+        removed
+    }
+
+    /// The index of one layer, if it has any shapes.
+    pub fn layer(&self, layer: LayerId) -> Option<&SpatialIndex> {
+        self.layers.get(&layer)
+    }
+
+    /// Layers with indexed shapes.
+    pub fn layers(&self) -> impl Iterator<Item = LayerId> + '_ {
+        self.layers.keys().copied()
+    }
+
+    /// Shapes whose bounding box touches `region`, on `layer` or on all
+    /// layers if `None`.
+    pub fn query_region(&self, region: &BBox, layer: Option<LayerId>) -> Vec<ShapeId> {
+        self.indices(layer)
+            .flat_map(|index| index.query_viewport(region))
+            .map(|e| e.shape_id)
+            .collect()
+    }
+
+    /// Shapes whose bounding box contains `point`, on `layer` or on all
+    /// layers if `None`.
+    pub fn query_point(&self, point: &Point, layer: Option<LayerId>) -> Vec<ShapeId> {
+        self.indices(layer)
+            .flat_map(|index| index.query_point(point))
+            .map(|e| e.shape_id)
+            .collect()
+    }
+
+    /// Shapes whose bounding box is within `max_distance` of `point`, on
+    /// `layer` or on all layers if `None`.
+    pub fn query_within(
+        &self,
+        point: &Point,
+        max_distance: Coord,
+        layer: Option<LayerId>,
+    ) -> Vec<ShapeId> {
+        self.indices(layer)
+            .flat_map(|index| index.query_within(point, max_distance))
+            .map(|e| e.shape_id)
+            .collect()
+    }
+
+    fn indices(&self, layer: Option<LayerId>) -> impl Iterator<Item = &SpatialIndex> {
+        let all = layer.is_none();
+        self.layers
+            .iter()
+            .filter(move |(l, _)| all || Some(**l) == layer)
+            .map(|(_, index)| index)
+    }
+
+    /// Total number of indexed shapes.
+    pub fn len(&self) -> usize {
+        self.layers.values().map(SpatialIndex::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use uuid::Uuid;
 
     #[test]
     fn test_spatial_query() {
+        let ids = [Uuid::new_v4(), Uuid::new_v4()];
         let entries = vec![
             SpatialEntry {
-                geometry_index: 0,
-                bbox: BBox::new(Point::new(0.0, 0.0), Point::new(10.0, 10.0)),
+                shape_id: ids[0],
+                bbox: BBox::new(Point::new(0, 0), Point::new(10, 10)),
             },
             SpatialEntry {
-                geometry_index: 1,
-                bbox: BBox::new(Point::new(20.0, 20.0), Point::new(30.0, 30.0)),
+                shape_id: ids[1],
+                bbox: BBox::new(Point::new(20, 20), Point::new(30, 30)),
             },
         ];
         let index = SpatialIndex::build(entries);
 
         // Point inside first entry
-        let results = index.query_point(&Point::new(5.0, 5.0));
+        let results = index.query_point(&Point::new(5, 5));
         assert_eq!(results.len(), 1);
-        assert_eq!(results[0].geometry_index, 0);
+        assert_eq!(results[0].shape_id, ids[0]);
 
         // Point inside second entry
-        let results = index.query_point(&Point::new(25.0, 25.0));
+        let results = index.query_point(&Point::new(25, 25));
         assert_eq!(results.len(), 1);
-        assert_eq!(results[0].geometry_index, 1);
+        assert_eq!(results[0].shape_id, ids[1]);
 
         // Viewport query
-        let viewport = BBox::new(Point::new(-5.0, -5.0), Point::new(15.0, 15.0));
+        let viewport = BBox::new(Point::new(-5, -5), Point::new(15, 15));
         let results = index.query_viewport(&viewport);
         assert_eq!(results.len(), 1);
     }
+
+    #[test]
+    fn test_cell_index_per_layer() {
+        use crate::geometry::{GeomPrimitive, Rect};
+
+        let mut cell = Cell::new("top");
+        let a = cell.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 10)));
+        let b = cell.add_geometry(GeomPrimitive::Rect(Rect::new(2, 0, 0, 10, 10)));
+        let mut index = CellSpatialIndex::build(&cell);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.query_point(&Point::new(5, 5), Some(1)), vec![a]);
+        assert_eq!(index.query_point(&Point::new(5, 5), None).len(), 2);
+
+        let (_, removed) = cell.remove_shape(&b).unwrap();
+        assert!(index.remove(&removed));
+        assert!(index.layer(2).is_none());
+        let region = BBox::new(Point::new(-5, -5), Point::new(0, 0));
+        assert_eq!(index.query_region(&region, None), vec![a]);
+    }
 }
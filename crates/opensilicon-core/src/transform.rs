@@ -0,0 +1,508 @@
+//! Placement transforms: exact Manhattan orientations plus magnification.
+//!
+//! A [`Transform`] maps a point `p` to `R(angle) · O · (mag · p) + offset`,
+//! where `O` is one of the eight [`Orientation`]s (mirror about the x axis
+//! first, then a multiple of 90°). Orthogonal transforms at unit
+//! magnification are applied with integer arithmetic and are exact; the
+//! residual `angle` only exists to represent arbitrary GDS-II ANGLE values.
+
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::{
+    BBox, Coord, GeomPrimitive, Path, PathEndStyle, Point, Polygon, Rect, Text, Via,
+};
+
+/// One of the eight orientations that map the integer grid onto itself.
+///
+/// Names follow the LEF/DEF convention: `MX` mirrors about the x axis
+/// (y → -y), `MY` about the y axis (x → -x), and `MXR90` / `MYR90` mirror
+/// first and then rotate counter-clockwise by 90°.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Orientation {
+    #[default]
+    R0,
+    R90,
+    R180,
+    R270,
+    MX,
+    MY,
+    MXR90,
+    MYR90,
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 8] = [
+        Orientation::R0,
+        Orientation::R90,
+        Orientation::R180,
+        Orientation::R270,
+        Orientation::MX,
+        Orientation::MY,
+        Orientation::MXR90,
+        Orientation::MYR90,
+    ];
+
+    /// Build from "mirror about x, then rotate by `quarter_turns` × 90°".
+    pub fn from_parts(mirror_x: bool, quarter_turns: i64) -> Self {
+        match (mirror_x, quarter_turns.rem_euclid(4)) {
+            (false, 0) => Orientation::R0,
+            (false, 1) => Orientation::R90,
+            (false, 2) => Orientation::R180,
+            (false, _) => Orientation::R270,
+            (true, 0) => Orientation::MX,
+            (true, 1) => Orientation::MXR90,
+            (true, 2) => Orientation::MY,
+            (true, _) => Orientation::MYR90,
+        }
+    }
+
+    /// Whether the orientation mirrors (reverses the winding of rings).
+    pub fn mirror_x(self) -> bool {
+        matches!(
+            self,
+            Orientation::MX | Orientation::MY | Orientation::MXR90 | Orientation::MYR90
+        )
+    }
+
+    /// Counter-clockwise quarter turns applied after the optional mirror.
+    pub fn quarter_turns(self) -> i64 {
+        match self {
+            Orientation::R0 | Orientation::MX => 0,
+            Orientation::R90 | Orientation::MXR90 => 1,
+            Orientation::R180 | Orientation::MY => 2,
+            Orientation::R270 | Orientation::MYR90 => 3,
+        }
+    }
+
+    /// Whether x and y extents are exchanged.
+    pub fn swaps_axes(self) -> bool {
+        self.quarter_turns() % 2 == 1
+    }
+
+    pub fn apply(self, x: Coord, y: Coord) -> (Coord, Coord) {
+        let y = if self.mirror_x() { -y } else { y };
+        match self.quarter_turns() {
+            0 => (x, y),
+            1 => (-y, x),
+            2 => (-x, -y),
+            _ => (y, -x),
+        }
+    }
+
+    /// The orientation equivalent to applying `inner` first, then `self`.
+    pub fn compose(self, inner: Orientation) -> Orientation {
+        // Mirroring reverses the direction of the inner rotation.
+        let inner_turns = if self.mirror_x() {
+            -inner.quarter_turns()
+        } else {
+            inner.quarter_turns()
+        };
+        Orientation::from_parts(
+            self.mirror_x() != inner.mirror_x(),
+            self.quarter_turns() + inner_turns,
+        )
+    }
+
+    pub fn inverse(self) -> Orientation {
+        if self.mirror_x() {
+            // Mirrored orientations are their own inverse.
+            self
+        } else {
+            Orientation::from_parts(false, -self.quarter_turns())
+        }
+    }
+}
+
+/// A transformation for placing subcell instances.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    /// Translation offset (database units).
+    pub offset: Point,
+    /// Exact Manhattan orientation.
+    pub orientation: Orientation,
+    /// Uniform magnification (1.0 unless a GDS MAG record says otherwise).
+    pub magnification: f64,
+    /// Additional counter-clockwise rotation in degrees, applied after the
+    /// orientation. Always in [0, 90); zero for Manhattan placements.
+    pub angle: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            offset: Point::new(0, 0),
+            orientation: Orientation::R0,
+            magnification: 1.0,
+            angle: 0.0,
+        }
+    }
+}
+
+impl Transform {
+    pub fn new(offset: Point, orientation: Orientation) -> Self {
+        Self {
+            offset,
+            orientation,
+            ..Default::default()
+        }
+    }
+
+    pub fn translate(x: Coord, y: Coord) -> Self {
+        Self::new(Point::new(x, y), Orientation::R0)
+    }
+
+    /// Build from GDS-II style parts: mirror about x, then rotate by
+    /// `degrees` counter-clockwise, with magnification. Multiples of 90° are
+    /// stored exactly as an [`Orientation`].
+    pub fn from_parts(offset: Point, mirror_x: bool, degrees: f64, magnification: f64) -> Self {
+        let degrees = degrees.rem_euclid(360.0);
+        let mut quarter_turns = (degrees / 90.0).floor();
+        let mut angle = degrees - quarter_turns * 90.0;
+        // Snap values that only miss a quarter turn by rounding noise.
+        if angle < 1e-9 {
+            angle = 0.0;
+        } else if 90.0 - angle < 1e-9 {
+            angle = 0.0;
+            quarter_turns += 1.0;
+        }
+        Self {
+            offset,
+            orientation: Orientation::from_parts(mirror_x, quarter_turns as i64),
+            magnification,
+            angle,
+        }
+    }
+
+    pub fn with_magnification(mut self, magnification: f64) -> Self {
+        self.magnification = magnification;
+        self
+    }
+
+    pub fn mirror_x(&self) -> bool {
+        self.orientation.mirror_x()
+    }
+
+    /// Total counter-clockwise rotation in degrees, in [0, 360).
+    pub fn rotation_degrees(&self) -> f64 {
+        self.orientation.quarter_turns() as f64 * 90.0 + self.angle
+    }
+
+    /// Whether the transform maps axis-aligned boxes to axis-aligned boxes.
+    pub fn is_orthogonal(&self) -> bool {
+        self.angle == 0.0
+    }
+
+    /// Whether the transform is applied with exact integer arithmetic.
+    pub fn is_exact(&self) -> bool {
+        self.is_orthogonal() && self.magnification == 1.0
+    }
+
+    /// Transform a point: magnify, orient, rotate by the residual angle,
+    /// then translate.
+    pub fn apply(&self, point: &Point) -> Point {
+        if self.is_exact() {
+            let (x, y) = self.orientation.apply(point.x, point.y);
+            return Point::new(x + self.offset.x, y + self.offset.y);
+        }
+        let (x, y) = self.apply_linear(point.x as f64, point.y as f64);
+        Point::new(
+            x.round() as Coord + self.offset.x,
+            y.round() as Coord + self.offset.y,
+        )
+    }
+
+    /// Transform a displacement: like [`apply`](Self::apply) without the
+    /// offset.
+    pub fn apply_vector(&self, vector: &Point) -> Point {
+        Transform {
+            offset: Point::new(0, 0),
+            ..*self
+        }
+        .apply(vector)
+    }
+
+    /// The linear part (everything but the offset) in floating point.
+    fn apply_linear(&self, x: f64, y: f64) -> (f64, f64) {
+        let (x, y) = (x * self.magnification, y * self.magnification);
+        let y = if self.mirror_x() { -y } else { y };
+        // Quarter turns by exchanging axes, so they add no rounding noise.
+        let (x, y) = match self.orientation.quarter_turns() {
+            0 => (x, y),
+            1 => (-y, x),
+            2 => (-x, -y),
+            _ => (y, -x),
+        };
+        if self.angle == 0.0 {
+            return (x, y);
+        }
+        let (sin_r, cos_r) = self.angle.to_radians().sin_cos();
+        (x * cos_r - y * sin_r, x * sin_r + y * cos_r)
+    }
+
+    /// The transform equivalent to applying `inner` first, then `self`.
+    pub fn compose(&self, inner: &Transform) -> Transform {
+        let offset = self.apply(&inner.offset);
+        if self.is_orthogonal() && inner.is_orthogonal() {
+            return Transform {
+                offset,
+                orientation: self.orientation.compose(inner.orientation),
+                magnification: self.magnification * inner.magnification,
+                angle: 0.0,
+            };
+        }
+        let inner_degrees = if self.mirror_x() {
+            -inner.rotation_degrees()
+        } else {
+            inner.rotation_degrees()
+        };
+        Transform::from_parts(
+            offset,
+            self.mirror_x() != inner.mirror_x(),
+            self.rotation_degrees() + inner_degrees,
+            self.magnification * inner.magnification,
+        )
+    }
+
+    /// The transform that undoes `self`. Exact for exact transforms.
+    pub fn inverse(&self) -> Transform {
+        let magnification = 1.0 / self.magnification;
+        let mut inverse = if self.is_orthogonal() {
+            Transform {
+                offset: Point::new(0, 0),
+                orientation: self.orientation.inverse(),
+                magnification,
+                angle: 0.0,
+            }
+        } else {
+            // A mirrored rotation is its own inverse rotation-wise.
+            let degrees = if self.mirror_x() {
+                self.rotation_degrees()
+            } else {
+                -self.rotation_degrees()
+            };
+            Transform::from_parts(Point::new(0, 0), self.mirror_x(), degrees, magnification)
+        };
+        let back = inverse.apply(&self.offset);
+        inverse.offset = Point::new(-back.x, -back.y);
+        inverse
+    }
+
+    /// The bounding box of a transformed box.
+    pub fn apply_bbox(&self, bbox: &BBox) -> BBox {
+        let corners = [
+            bbox.min,
+            Point::new(bbox.max.x, bbox.min.y),
+            bbox.max,
+            Point::new(bbox.min.x, bbox.max.y),
+        ];
+        let moved: Vec<Point> = corners.iter().map(|p| self.apply(p)).collect();
+        BBox::from_points(&moved).expect("four corners")
+    }
+
+    /// Scale a length by the magnification, rounding to the grid.
+    fn scale_length(&self, length: Coord) -> Coord {
+        if self.magnification == 1.0 {
+            length
+        } else {
+            (length as f64 * self.magnification).round() as Coord
+        }
+    }
+
+    /// Transform a ring, keeping its winding direction when mirroring.
+    fn apply_ring(&self, ring: &[Point]) -> Vec<Point> {
+        let mut out: Vec<Point> = ring.iter().map(|p| self.apply(p)).collect();
+        if self.mirror_x() {
+            out.reverse();
+        }
+        out
+    }
+
+    /// Transform a primitive. Rectangles stay rectangles under orthogonal
+    /// transforms and become polygons otherwise; vias stay axis-aligned.
+    pub fn apply_primitive(&self, geom: &GeomPrimitive) -> GeomPrimitive {
+        match geom {
+            GeomPrimitive::Rect(r) => {
+                if self.is_orthogonal() {
+                    let a = self.apply(&r.lower_left);
+                    let b = self.apply(&r.upper_right);
+                    GeomPrimitive::Rect(Rect::new(r.layer_id, a.x, a.y, b.x, b.y))
+                } else {
+                    let bb = r.bbox();
+                    let ring = [
+                        bb.min,
+                        Point::new(bb.max.x, bb.min.y),
+                        bb.max,
+                        Point::new(bb.min.x, bb.max.y),
+                    ];
+                    GeomPrimitive::Polygon(Polygon::new(r.layer_id, self.apply_ring(&ring)))
+                }
+            }
+            GeomPrimitive::Polygon(p) => GeomPrimitive::Polygon(Polygon::with_holes(
+                p.layer_id,
+                self.apply_ring(&p.vertices),
+                p.holes.iter().map(|h| self.apply_ring(h)).collect(),
+            )),
+            GeomPrimitive::Path(p) => {
+                let end_style = match p.end_style {
+                    PathEndStyle::Custom { begin, end } => PathEndStyle::Custom {
+                        begin: self.scale_length(begin),
+                        end: self.scale_length(end),
+                    },
+                    other => other,
+                };
+                GeomPrimitive::Path(
+                    Path::new(
+                        p.layer_id,
+                        p.points.iter().map(|pt| self.apply(pt)).collect(),
+                        self.scale_length(p.width),
+                    )
+                    .with_end_style(end_style),
+                )
+            }
+            // Through the box rather than the center, which is off the grid
+            // by half a unit for an odd-sized via.
+            GeomPrimitive::Via(v) => GeomPrimitive::Via(Via::from_bbox(
+                v.bottom_layer,
+                v.top_layer,
+                v.cut_layer,
+                self.apply_bbox(&v.bbox()),
+            )),
+            GeomPrimitive::Text(t) => GeomPrimitive::Text(Text {
+                transform: self.compose(&t.transform),
+                ..t.clone()
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orientation_matches_definitions() {
+        let p = (3, 7);
+        let apply = |o: Orientation| o.apply(p.0, p.1);
+        assert_eq!(apply(Orientation::R0), (3, 7));
+        assert_eq!(apply(Orientation::R90), (-7, 3));
+        assert_eq!(apply(Orientation::R180), (-3, -7));
+        assert_eq!(apply(Orientation::R270), (7, -3));
+        assert_eq!(apply(Orientation::MX), (3, -7));
+        assert_eq!(apply(Orientation::MY), (-3, 7));
+        assert_eq!(apply(Orientation::MXR90), (7, 3));
+        assert_eq!(apply(Orientation::MYR90), (-7, -3));
+    }
+
+    #[test]
+    fn test_orientation_group() {
+        for a in Orientation::ALL {
+            assert_eq!(a.compose(a.inverse()), Orientation::R0);
+            for b in Orientation::ALL {
+                let ab = a.compose(b);
+                let (x, y) = b.apply(3, 7);
+                assert_eq!(ab.apply(3, 7), a.apply(x, y), "{:?} ∘ {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_transform_translate() {
+        let t = Transform::translate(10, 20);
+        assert_eq!(t.apply(&Point::new(5, 5)), Point::new(15, 25));
+    }
+
+    #[test]
+    fn test_transform_rotation_is_exact() {
+        let t = Transform::from_parts(Point::new(100, 0), true, 90.0, 1.0);
+        assert_eq!(t.orientation, Orientation::MXR90);
+        // Mirror (3, 7) -> (3, -7), rotate 90° -> (7, 3), translate.
+        assert_eq!(t.apply(&Point::new(3, 7)), Point::new(107, 3));
+
+        let t = Transform::from_parts(Point::new(0, 0), false, -90.0, 1.0);
+        assert_eq!(t.orientation, Orientation::R270);
+        assert_eq!(t.apply(&Point::new(123_456_789, 1)), Point::new(1, -123_456_789));
+    }
+
+    #[test]
+    fn test_compose_and_inverse() {
+        let outer = Transform::new(Point::new(1000, -50), Orientation::MYR90);
+        let inner = Transform::new(Point::new(7, 300), Orientation::R90);
+        let both = outer.compose(&inner);
+        let p = Point::new(-13, 42);
+        assert_eq!(both.apply(&p), outer.apply(&inner.apply(&p)));
+        assert!(both.is_exact());
+
+        for t in [outer, inner, both] {
+            let inv = t.inverse();
+            assert_eq!(inv.apply(&t.apply(&p)), p);
+            assert_eq!(t.compose(&inv), Transform::default());
+        }
+    }
+
+    #[test]
+    fn test_arbitrary_angle_and_magnification() {
+        let t = Transform::from_parts(Point::new(0, 0), false, 135.0, 2.0);
+        assert_eq!(t.orientation, Orientation::R90);
+        assert_eq!(t.angle, 45.0);
+        assert_eq!(t.apply(&Point::new(100, 0)), Point::new(-141, 141));
+
+        let round_trip = t.inverse().apply(&t.apply(&Point::new(1000, 500)));
+        assert!((round_trip.x - 1000).abs() <= 1 && (round_trip.y - 500).abs() <= 1);
+
+        let composed = t.compose(&Transform::from_parts(Point::new(0, 0), false, 45.0, 0.5));
+        assert_eq!(composed.orientation, Orientation::R180);
+        assert!(composed.is_exact());
+    }
+
+    #[test]
+    fn test_apply_bbox_and_primitives() {
+        let t = Transform::new(Point::new(10, 0), Orientation::R90);
+        let bb = t.apply_bbox(&BBox::new(Point::new(0, 0), Point::new(4, 2)));
+        assert_eq!((bb.min, bb.max), (Point::new(8, 0), Point::new(10, 4)));
+
+        let via = GeomPrimitive::Via(Via::new(1, 2, 3, Point::new(0, 0), 6, 2));
+        match t.apply_primitive(&via) {
+            GeomPrimitive::Via(v) => assert_eq!((v.position, v.width, v.height), (Point::new(10, 0), 2, 6)),
+            other => panic!("expected via, got {:?}", other),
+        }
+
+        // An odd-sized via keeps its exact footprint under every orientation.
+        let odd = Via::new(1, 2, 3, Point::new(10, 20), 5, 3);
+        for o in Orientation::ALL {
+            let t = Transform::new(Point::new(7, -4), o);
+            match t.apply_primitive(&GeomPrimitive::Via(odd.clone())) {
+                GeomPrimitive::Via(v) => assert_eq!(v.bbox(), t.apply_bbox(&odd.bbox()), "{:?}", o),
+                other => panic!("expected via, got {:?}", other),
+            }
+        }
+        let r180 = Transform::new(Point::new(0, 0), Orientation::R180);
+        match r180.apply_primitive(&GeomPrimitive::Via(odd.clone())) {
+            GeomPrimitive::Via(v) => {
+                let bb = v.bbox();
+                assert_eq!((bb.min, bb.max), (Point::new(-13, -22), Point::new(-8, -19)));
+            }
+            other => panic!("expected via, got {:?}", other),
+        }
+
+        let mirror = Transform::new(Point::new(0, 0), Orientation::MX);
+        let tri = GeomPrimitive::Polygon(Polygon::new(
+            1,
+            vec![Point::new(0, 0), Point::new(10, 0), Point::new(0, 10)],
+        ));
+        match mirror.apply_primitive(&tri) {
+            GeomPrimitive::Polygon(p) => assert!(crate::boolean::signed_area2(&p.vertices) > 0),
+            other => panic!("expected polygon, got {:?}", other),
+        }
+
+        let label = GeomPrimitive::Text(Text::new(1, "VDD", Point::new(2, 0)).with_transform(
+            Transform::new(Point::new(2, 0), Orientation::R90),
+        ));
+        match t.apply_primitive(&label) {
+            GeomPrimitive::Text(text) => {
+                assert_eq!(text.position(), Point::new(10, 2));
+                assert_eq!(text.transform.orientation, Orientation::R180);
+            }
+            other => panic!("expected text, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::{Coord, Point};
+
+/// Conversion between integer database units and physical micrometers.
+///
+/// The database stores every coordinate as an integer [`Coord`]. Floating
+/// point values only appear at the UI/IPC boundary, where they are converted
+/// with this type and rounded to the nearest database unit.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DbUnits {
+    /// Database units per micrometer (1000.0 for a 1nm grid).
+    pub dbu_per_um: f64,
+}
+
+impl Default for DbUnits {
+    fn default() -> Self {
+        Self { dbu_per_um: 1000.0 }
+    }
+}
+
+impl DbUnits {
+    pub fn new(dbu_per_um: f64) -> Self {
+        Self { dbu_per_um }
+    }
+
+    /// Size of one database unit in meters (the second GDS-II UNITS value).
+    pub fn dbu_in_meters(&self) -> f64 {
+        1e-6 / self.dbu_per_um
+    }
+
+    /// Convert micrometers to database units, rounding to the nearest unit.
+    pub fn to_dbu(&self, um: f64) -> Coord {
+        (um * self.dbu_per_um).round() as Coord
+    }
+
+    /// Convert database units to micrometers.
+    pub fn to_um(&self, dbu: Coord) -> f64 {
+        dbu as f64 / self.dbu_per_um
+    }
+
+    pub fn point_from_um(&self, x: f64, y: f64) -> Point {
+        Point::new(self.to_dbu(x), self.to_dbu(y))
+    }
+
+    pub fn point_to_um(&self, p: &Point) -> (f64, f64) {
+        (self.to_um(p.x), self.to_um(p.y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_um_roundtrip_is_exact() {
+        let units = DbUnits::default();
+        for dbu in [-1_000_001, -5, 0, 1, 130, 999_999_999] {
+            assert_eq!(units.to_dbu(units.to_um(dbu)), dbu);
+        }
+    }
+
+    #[test]
+    fn test_to_dbu_rounds() {
+        let units = DbUnits::new(1000.0);
+        assert_eq!(units.to_dbu(0.0004), 0);
+        assert_eq!(units.to_dbu(0.0006), 1);
+        assert_eq!(units.to_dbu(-0.17), -170);
+        assert_eq!(units.point_from_um(1.2346, 0.1), Point::new(1235, 100));
+    }
+}
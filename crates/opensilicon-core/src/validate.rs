@@ -0,0 +1,433 @@
+//! Polygon validity checking and repair.
+//!
+//! [`Polygon::validate`] reports degenerate vertices, wrong ring orientation,
+//! self-intersections and misplaced holes. [`Polygon::normalized`] repairs
+//! all of them by running the polygon through the boolean engine, which
+//! splits self-intersecting rings, turns keyhole cut lines into real holes
+//! and orients rings consistently.
+
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::boolean::{self, signed_area2};
+use crate::geometry::{GeomPrimitive, Point, Polygon};
+
+/// A problem found by [`Polygon::validate`].
+///
+/// Rings are numbered with the outer ring as 0 and hole `i` as `i + 1`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PolygonIssue {
+    #[error("ring {ring} has fewer than 3 distinct vertices")]
+    TooFewVertices { ring: usize },
+
+    #[error("vertex {index} of ring {ring} repeats the previous vertex")]
+    DuplicateVertex { ring: usize, index: usize },
+
+    #[error("vertex {index} of ring {ring} is collinear with its neighbours")]
+    CollinearVertex { ring: usize, index: usize },
+
+    #[error("vertex {index} of ring {ring} is a zero-area spike")]
+    Spike { ring: usize, index: usize },
+
+    #[error("ring {ring} has zero area")]
+    ZeroArea { ring: usize },
+
+    #[error("ring {ring} winds the wrong way")]
+    WrongOrientation { ring: usize },
+
+    #[error("edges intersect at ({}, {})", at.x, at.y)]
+    SelfIntersection { at: Point },
+
+    #[error("hole ring {ring} lies outside the outer ring")]
+    HoleOutside { ring: usize },
+}
+
+/// What to do with invalid polygons when adding geometry or importing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PolygonCheck {
+    /// Store polygons as given without checking them.
+    #[default]
+    Keep,
+    /// Check polygons and report issues, but store them unchanged.
+    Report,
+    /// Check polygons and replace invalid ones by their normalized form.
+    Repair,
+    /// Check polygons and refuse invalid ones.
+    Reject,
+}
+
+/// An invalid polygon refused under [`PolygonCheck::Reject`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid polygon on layer {layer}: {}", issues[0])]
+pub struct InvalidPolygon {
+    pub layer: crate::LayerId,
+    pub issues: Vec<PolygonIssue>,
+}
+
+impl Polygon {
+    /// Check the polygon and return every issue found (empty if valid).
+    ///
+    /// Keyhole cut lines (an edge retraced exactly in the opposite
+    /// direction) are accepted, since that is how GDS-II stores holes.
+    pub fn validate(&self) -> Vec<PolygonIssue> {
+        let mut issues = Vec::new();
+        let mut rings = Vec::new();
+        for (r, ring) in self.rings().enumerate() {
+            check_vertices(r, ring, &mut issues);
+            let stripped = strip_degenerate(ring);
+            let area = signed_area2(&stripped);
+            if stripped.len() < 3 {
+                issues.push(PolygonIssue::TooFewVertices { ring: r });
+                continue;
+            }
+            match (area.cmp(&0), r == 0) {
+                (Ordering::Equal, _) => issues.push(PolygonIssue::ZeroArea { ring: r }),
+                (Ordering::Less, true) | (Ordering::Greater, false) => {
+                    issues.push(PolygonIssue::WrongOrientation { ring: r })
+                }
+                _ => {}
+            }
+            rings.push((r, stripped));
+        }
+
+        let crossings = intersections(&rings);
+        let crossed = !crossings.is_empty();
+        issues.extend(crossings.into_iter().map(|at| PolygonIssue::SelfIntersection { at }));
+
+        if !crossed {
+            if let Some((_, outer)) = rings.iter().find(|(r, _)| *r == 0) {
+                for (r, hole) in rings.iter().filter(|(r, _)| *r > 0) {
+                    if !hole.iter().any(|p| boolean::point_in_ring(*p, outer)) {
+                        issues.push(PolygonIssue::HoleOutside { ring: *r });
+                    }
+                }
+            }
+        }
+        issues
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_empty()
+    }
+
+    /// Repair the polygon into valid polygons: a counter-clockwise outer
+    /// ring without repeated, collinear or spike vertices, and clockwise
+    /// holes strictly inside it. Self-intersecting rings are split (filled
+    /// with the non-zero winding rule) and keyhole cuts become holes.
+    pub fn normalized(&self) -> Vec<Polygon> {
+        boolean::merge([&GeomPrimitive::Polygon(self.clone())], self.layer_id)
+    }
+}
+
+/// Apply `check` to a single primitive. Returns the primitives to store and
+/// the issues found, or an error if the primitive is rejected.
+pub fn check_primitive(
+    geom: GeomPrimitive,
+    check: PolygonCheck,
+) -> Result<(Vec<GeomPrimitive>, Vec<PolygonIssue>), InvalidPolygon> {
+    let GeomPrimitive::Polygon(poly) = &geom else {
+        return Ok((vec![geom], Vec::new()));
+    };
+    if check == PolygonCheck::Keep {
+        return Ok((vec![geom], Vec::new()));
+    }
+    let issues = poly.validate();
+    if issues.is_empty() {
+        return Ok((vec![geom], issues));
+    }
+    match check {
+        PolygonCheck::Keep | PolygonCheck::Report => Ok((vec![geom], issues)),
+        PolygonCheck::Repair => {
+            let repaired = poly.normalized().into_iter().map(GeomPrimitive::Polygon).collect();
+            Ok((repaired, issues))
+        }
+        PolygonCheck::Reject => Err(InvalidPolygon {
+            layer: poly.layer_id,
+            issues,
+        }),
+    }
+}
+
+/// Report repeated, collinear and spike vertices of a raw ring.
+fn check_vertices(r: usize, ring: &[Point], issues: &mut Vec<PolygonIssue>) {
+    let n = ring.len();
+    for i in 0..n {
+        if ring[i] == ring[(i + n - 1) % n] && n > 1 {
+            issues.push(PolygonIssue::DuplicateVertex { ring: r, index: i });
+        }
+    }
+    let mut distinct: Vec<(usize, Point)> = ring.iter().copied().enumerate().collect();
+    distinct.dedup_by_key(|(_, p)| *p);
+    while distinct.len() > 1 && distinct.first().map(|v| v.1) == distinct.last().map(|v| v.1) {
+        distinct.pop();
+    }
+    let m = distinct.len();
+    if m < 3 {
+        return;
+    }
+    for k in 0..m {
+        let (p, (index, c), q) = (distinct[(k + m - 1) % m].1, distinct[k], distinct[(k + 1) % m].1);
+        if is_junction(ring, c) {
+            continue;
+        }
+        match turn_kind(p, c, q) {
+            Turn::Straight => issues.push(PolygonIssue::CollinearVertex { ring: r, index }),
+            Turn::Reversal => issues.push(PolygonIssue::Spike { ring: r, index }),
+            Turn::Corner => {}
+        }
+    }
+}
+
+enum Turn {
+    Corner,
+    Straight,
+    Reversal,
+}
+
+fn turn_kind(p: Point, c: Point, q: Point) -> Turn {
+    let (ax, ay) = ((c.x - p.x) as i128, (c.y - p.y) as i128);
+    let (bx, by) = ((q.x - c.x) as i128, (q.y - c.y) as i128);
+    if ax * by - ay * bx != 0 {
+        Turn::Corner
+    } else if ax * bx + ay * by > 0 {
+        Turn::Straight
+    } else {
+        Turn::Reversal
+    }
+}
+
+/// Whether a vertex is visited more than once, as where a keyhole cut
+/// meets its hole or the outer ring. Such vertices may be collinear.
+fn is_junction(ring: &[Point], p: Point) -> bool {
+    let n = ring.len();
+    (0..n).filter(|&i| ring[i] == p && ring[(i + n - 1) % n] != p).count() > 1
+}
+
+/// Remove repeated, collinear and spike vertices until none are left.
+/// Junction vertices are kept.
+fn strip_degenerate(ring: &[Point]) -> Vec<Point> {
+    let mut ring = ring.to_vec();
+    loop {
+        ring.dedup();
+        while ring.len() > 1 && ring.first() == ring.last() {
+            ring.pop();
+        }
+        let n = ring.len();
+        if n < 3 {
+            return ring;
+        }
+        let Some(i) = (0..n).find(|&i| {
+            !is_junction(&ring, ring[i])
+                && !matches!(turn_kind(ring[(i + n - 1) % n], ring[i], ring[(i + 1) % n]), Turn::Corner)
+        }) else {
+            return ring;
+        };
+        ring.remove(i);
+    }
+}
+
+/// A ring edge for intersection testing.
+struct RingEdge {
+    ring: usize,
+    index: usize,
+    len: usize,
+    a: Point,
+    b: Point,
+}
+
+impl RingEdge {
+    fn adjacent(&self, other: &RingEdge) -> bool {
+        self.ring == other.ring
+            && ((self.index + 1) % self.len == other.index || (other.index + 1) % other.len == self.index)
+    }
+}
+
+/// Points where non-adjacent edges cross, touch in an edge interior, or
+/// partially overlap. Exactly retraced edges (keyhole cuts) are ignored.
+fn intersections(rings: &[(usize, Vec<Point>)]) -> Vec<Point> {
+    let mut edges: Vec<RingEdge> = rings
+        .iter()
+        .flat_map(|(r, ring)| {
+            (0..ring.len()).map(move |i| RingEdge {
+                ring: *r,
+                index: i,
+                len: ring.len(),
+                a: ring[i],
+                b: ring[(i + 1) % ring.len()],
+            })
+        })
+        .collect();
+    edges.sort_by_key(|e| e.a.x.min(e.b.x));
+
+    let mut found = Vec::new();
+    for i in 0..edges.len() {
+        let ei = &edges[i];
+        let max_x = ei.a.x.max(ei.b.x);
+        for ej in edges[i + 1..].iter().take_while(|e| e.a.x.min(e.b.x) <= max_x) {
+            if ei.adjacent(ej) {
+                continue;
+            }
+            if let Some(p) = edge_intersection(ei.a, ei.b, ej.a, ej.b) {
+                found.push(p);
+            }
+        }
+    }
+    found.sort_by_key(|p| (p.x, p.y));
+    found.dedup();
+    found
+}
+
+fn orient(a: Point, b: Point, c: Point) -> i128 {
+    let v = (b.x - a.x) as i128 * (c.y - a.y) as i128 - (b.y - a.y) as i128 * (c.x - a.x) as i128;
+    v.signum()
+}
+
+/// Whether `p` lies on segment `a`-`b` strictly between its end points,
+/// given that it is collinear with it.
+fn strictly_between(a: Point, b: Point, p: Point) -> bool {
+    p != a && p != b && p.x >= a.x.min(b.x) && p.x <= a.x.max(b.x) && p.y >= a.y.min(b.y) && p.y <= a.y.max(b.y)
+}
+
+/// The first point where two segments illegally meet, if any. Segments may
+/// share end points (rings touching at a vertex) or retrace each other.
+fn edge_intersection(a: Point, b: Point, c: Point, d: Point) -> Option<Point> {
+    let (o1, o2) = (orient(a, b, c), orient(a, b, d));
+    let (o3, o4) = (orient(c, d, a), orient(c, d, b));
+
+    if o1 * o2 < 0 && o3 * o4 < 0 {
+        // Proper crossing; report the crossing rounded to the grid.
+        let (dx1, dy1) = ((b.x - a.x) as f64, (b.y - a.y) as f64);
+        let (dx2, dy2) = ((d.x - c.x) as f64, (d.y - c.y) as f64);
+        let t = ((c.x - a.x) as f64 * dy2 - (c.y - a.y) as f64 * dx2) / (dx1 * dy2 - dy1 * dx2);
+        return Some(Point::new(
+            (a.x as f64 + t * dx1).round() as crate::Coord,
+            (a.y as f64 + t * dy1).round() as crate::Coord,
+        ));
+    }
+
+    if o1 == 0 && o2 == 0 {
+        // Collinear: exact retraces are keyhole cuts, any other overlap is not.
+        if (a, b) == (d, c) {
+            return None;
+        }
+        if (a, b) == (c, d) {
+            return Some(a);
+        }
+        return [c, d, a, b]
+            .into_iter()
+            .zip([(a, b), (a, b), (c, d), (c, d)])
+            .find(|(p, (s, e))| strictly_between(*s, *e, *p))
+            .map(|(p, _)| p);
+    }
+
+    // An end point touching the interior of the other edge.
+    [(c, o1, a, b), (d, o2, a, b), (a, o3, c, d), (b, o4, c, d)]
+        .into_iter()
+        .find(|(p, o, s, e)| *o == 0 && strictly_between(*s, *e, *p))
+        .map(|(p, ..)| p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poly(points: &[(i64, i64)]) -> Polygon {
+        Polygon::new(1, points.iter().map(|&(x, y)| Point::new(x, y)).collect())
+    }
+
+    #[test]
+    fn test_valid_polygon() {
+        let square = poly(&[(0, 0), (10, 0), (10, 10), (0, 10)]);
+        assert!(square.is_valid());
+        let with_hole = Polygon::with_holes(
+            1,
+            square.vertices.clone(),
+            vec![vec![Point::new(2, 2), Point::new(2, 8), Point::new(8, 8), Point::new(8, 2)]],
+        );
+        assert!(with_hole.is_valid());
+    }
+
+    #[test]
+    fn test_degenerate_vertices() {
+        let p = poly(&[(0, 0), (5, 0), (10, 0), (10, 0), (10, 10), (0, 10), (0, 12), (0, 10)]);
+        let issues = p.validate();
+        assert!(issues.contains(&PolygonIssue::CollinearVertex { ring: 0, index: 1 }));
+        assert!(issues.contains(&PolygonIssue::DuplicateVertex { ring: 0, index: 3 }));
+        assert!(issues.contains(&PolygonIssue::Spike { ring: 0, index: 6 }));
+        assert!(!issues.iter().any(|i| matches!(i, PolygonIssue::SelfIntersection { .. })));
+    }
+
+    #[test]
+    fn test_orientation() {
+        let cw = poly(&[(0, 0), (0, 10), (10, 10), (10, 0)]);
+        assert_eq!(cw.validate(), vec![PolygonIssue::WrongOrientation { ring: 0 }]);
+        let fixed = cw.normalized();
+        assert_eq!(fixed.len(), 1);
+        assert!(fixed[0].is_valid());
+        assert_eq!(fixed[0].area(), 100);
+    }
+
+    #[test]
+    fn test_bow_tie_is_split() {
+        let bow_tie = poly(&[(0, 0), (10, 10), (10, 0), (0, 10)]);
+        assert!(bow_tie
+            .validate()
+            .contains(&PolygonIssue::SelfIntersection { at: Point::new(5, 5) }));
+        let fixed = bow_tie.normalized();
+        assert_eq!(fixed.len(), 2);
+        assert!(fixed.iter().all(Polygon::is_valid));
+        assert_eq!(fixed.iter().map(Polygon::area).sum::<i128>(), 50);
+    }
+
+    #[test]
+    fn test_keyhole_is_valid_and_normalizes_to_hole() {
+        let keyholed = poly(&[
+            (0, 0),
+            (10, 0),
+            (10, 10),
+            (0, 10),
+            (0, 2),
+            (2, 2),
+            (2, 8),
+            (8, 8),
+            (8, 2),
+            (2, 2),
+            (0, 2),
+        ]);
+        assert!(keyholed.is_valid(), "{:?}", keyholed.validate());
+        let fixed = keyholed.normalized();
+        assert_eq!(fixed.len(), 1);
+        assert_eq!(fixed[0].holes.len(), 1);
+        assert_eq!(fixed[0].area(), 100 - 36);
+        assert!(fixed[0].is_valid());
+    }
+
+    #[test]
+    fn test_hole_outside() {
+        let p = Polygon::with_holes(
+            1,
+            poly(&[(0, 0), (10, 0), (10, 10), (0, 10)]).vertices,
+            vec![vec![Point::new(20, 20), Point::new(20, 30), Point::new(30, 30)]],
+        );
+        assert_eq!(p.validate(), vec![PolygonIssue::HoleOutside { ring: 1 }]);
+        let fixed = p.normalized();
+        assert_eq!(fixed.len(), 1);
+        assert_eq!(fixed[0].area(), 100);
+    }
+
+    #[test]
+    fn test_check_primitive_modes() {
+        let bow_tie = GeomPrimitive::Polygon(poly(&[(0, 0), (10, 10), (10, 0), (0, 10)]));
+        let (kept, issues) = check_primitive(bow_tie.clone(), PolygonCheck::Report).unwrap();
+        assert_eq!(kept, vec![bow_tie.clone()]);
+        assert!(issues.contains(&PolygonIssue::SelfIntersection { at: Point::new(5, 5) }));
+
+        let (repaired, _) = check_primitive(bow_tie.clone(), PolygonCheck::Repair).unwrap();
+        assert_eq!(repaired.len(), 2);
+
+        let err = check_primitive(bow_tie, PolygonCheck::Reject).unwrap_err();
+        assert_eq!(err.layer, 1);
+    }
+}
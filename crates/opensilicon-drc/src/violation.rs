@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use opensilicon_core::cell::ShapeId;
+
 /// Type of DRC violation.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ViolationType {
@@ -31,6 +33,6 @@ pub struct DrcViolation {
     pub layer_id: u32,
     /// Bounding box of the violation region: [min_x, min_y, max_x, max_y]
     pub bbox: [f64; 4],
-    /// Indices of the geometries involved.
-    pub geometry_indices: Vec<usize>,
+    /// IDs of the shapes involved.
+    pub shape_ids: Vec<ShapeId>,
 }
@@ -1,23 +1,31 @@
-///! GDS-II binary format parser.
-///!
-///! GDS-II (Graphic Data System II) is the industry-standard binary format
-///! used by semiconductor fabs. This module reads GDS-II streams and converts
-///! them into OpenSilicon's internal layout database representation.
-///!
-///! ## GDS-II Record Structure
-///! Each record: [2-byte length][2-byte record type][payload]
-///! Record types define the hierarchy: BGNLIB → BGNSTR → BOUNDARY/PATH/SREF → ENDSTR → ENDLIB
-
-use std::io::{self, Read, Seek, SeekFrom};
+//! GDS-II binary format parser.
+//!
+//! GDS-II (Graphic Data System II) is the industry-standard binary format
+//! used by semiconductor fabs. This module reads GDS-II streams and converts
+//! them into OpenSilicon's internal layout database representation.
+//!
+//! ## GDS-II Record Structure
+//! Each record: [2-byte length][2-byte record type][payload]
+//! Record types define the hierarchy: BGNLIB → BGNSTR → BOUNDARY/PATH/TEXT/SREF → ENDSTR → ENDLIB
+
+use std::collections::HashMap;
+use std::io::{self, Read, Seek};
+use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
-use opensilicon_core::geometry::{Point, Polygon, Rect, Path as LayoutPath, Via, GeomPrimitive};
-use opensilicon_core::cell::{Cell, CellInstance, Transform};
+use opensilicon_core::geometry::{
+    Coord, GeomPrimitive, Path as LayoutPath, PathEndStyle, Point, Polygon, Rect, Text,
+    TextPresentation, Via,
+};
+use opensilicon_core::cell::{
+    ArrayParams, Cell, CellId, CellInstance, Properties, Shape, Transform,
+};
 use opensilicon_core::database::LayoutDatabase;
+use opensilicon_core::layer::{LayerId, LayerStack};
+use opensilicon_core::validate::{InvalidPolygon, PolygonCheck, PolygonIssue};
 
 // ── GDS-II Record Types ──────────────────────────────────────────────
 
-#[allow(dead_code)]
 mod record_type {
     pub const HEADER: u16     = 0x0002;
     pub const BGNLIB: u16     = 0x0102;
@@ -51,20 +59,14 @@ mod record_type {
     pub const BOXTYPE: u16    = 0x2E02;
     pub const PROPATTR: u16   = 0x2B02;
     pub const PROPVALUE: u16  = 0x2C06;
+    pub const BGNEXTN: u16    = 0x3003;
+    pub const ENDEXTN: u16    = 0x3103;
 }
 
-// ── Data Type Tags ────────────────────────────────────────────────────
-
-#[allow(dead_code)]
-mod data_type {
-    pub const NO_DATA: u8    = 0x00;
-    pub const BIT_ARRAY: u8  = 0x01;
-    pub const INT16: u8      = 0x02;
-    pub const INT32: u8      = 0x03;
-    pub const REAL4: u8      = 0x04;
-    pub const REAL8: u8      = 0x05;
-    pub const ASCII: u8      = 0x06;
-}
+/// PROPATTR under which instance names are stored on SREF and AREF
+/// elements. GDS-II has no instance names; this attribute is a convention of
+/// this crate, and references without it are named after their structure.
+pub const INSTANCE_NAME_ATTRIBUTE: u16 = 112;
 
 // ── Errors ────────────────────────────────────────────────────────────
 
@@ -85,8 +87,42 @@ pub enum GdsError {
     #[error("Invalid coordinate data")]
     InvalidCoordinates,
 
+    #[error("Array of {columns}x{rows} elements does not fit in a GDS-II AREF")]
+    ArrayTooLarge { columns: u32, rows: u32 },
+
     #[error("Cell '{0}' referenced but not defined")]
     UndefinedCell(String),
+
+    #[error("Coordinate {0} does not fit in a 32-bit GDS-II integer")]
+    CoordinateOverflow(Coord),
+
+    #[error("Record of {0} bytes exceeds the GDS-II record size limit")]
+    RecordTooLong(usize),
+
+    #[error("In cell '{cell}': {source}")]
+    InvalidPolygon {
+        cell: String,
+        #[source]
+        source: InvalidPolygon,
+    },
+}
+
+/// A non-fatal problem found while reading a GDS-II stream.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum GdsWarning {
+    /// A polygon failed validation (and was kept or repaired, depending on
+    /// the reader's [`PolygonCheck`]).
+    #[error("In cell '{cell}': {issue}")]
+    InvalidPolygon { cell: String, issue: PolygonIssue },
+
+    /// An SREF names a structure that is not in the library. The reference
+    /// is dropped.
+    #[error("Cell '{cell}' references undefined cell '{name}'")]
+    UnresolvedReference { cell: String, name: String },
+
+    /// Two structures share a name; references resolve to the first one.
+    #[error("Cell '{0}' is defined more than once")]
+    DuplicateCell(String),
 }
 
 // ── GDS-II Record ─────────────────────────────────────────────────────
@@ -98,16 +134,6 @@ struct GdsRecord {
 }
 
 impl GdsRecord {
-    /// Get the record type (upper byte) without the data type tag.
-    fn kind(&self) -> u8 {
-        (self.record_type >> 8) as u8
-    }
-
-    /// Get the data type tag (lower byte).
-    fn data_type_tag(&self) -> u8 {
-        (self.record_type & 0xFF) as u8
-    }
-
     /// Parse payload as 16-bit integers.
     fn as_i16_vec(&self) -> Vec<i16> {
         self.data
@@ -167,7 +193,7 @@ fn f64_to_gds_real8(value: f64) -> [u8; 8] {
     let mut val = value.abs();
 
     // Find exponent such that 1/16 <= mantissa < 1
-    let mut exponent: i32 = 1;
+    let mut exponent: i32 = 0;
     while val >= 1.0 && exponent < 127 {
         val /= 16.0;
         exponent += 1;
@@ -196,23 +222,70 @@ fn f64_to_gds_real8(value: f64) -> [u8; 8] {
 
 pub struct GdsReader<R: Read + Seek> {
     reader: R,
-    db_unit_in_um: f64,
+    polygon_check: PolygonCheck,
+    strict_references: bool,
+    layers: LayerStack,
+    /// Properties of the element being read.
+    properties: Properties,
+    attribute: Option<u16>,
+    /// Cells in the order their structures appear in the stream.
+    structures: Vec<CellId>,
+    /// Structure names of the references read, by instance ID, until linked.
+    references: HashMap<uuid::Uuid, String>,
+    warnings: Vec<GdsWarning>,
 }
 
 impl<R: Read + Seek> GdsReader<R> {
     pub fn new(reader: R) -> Self {
         Self {
             reader,
-            db_unit_in_um: 0.001, // Default: 1nm database unit
+            polygon_check: PolygonCheck::Keep,
+            strict_references: false,
+            layers: LayerStack::new(),
+            properties: Properties::new(),
+            attribute: None,
+            structures: Vec::new(),
+            references: HashMap::new(),
+            warnings: Vec::new(),
         }
     }
 
+    /// Fail with [`GdsError::UndefinedCell`] on references to undefined
+    /// structures instead of dropping them with a warning.
+    pub fn with_strict_references(mut self, strict: bool) -> Self {
+        self.strict_references = strict;
+        self
+    }
+
+    /// Set how invalid BOUNDARY polygons are handled (default: keep as-is).
+    pub fn with_polygon_check(mut self, check: PolygonCheck) -> Self {
+        self.polygon_check = check;
+        self
+    }
+
+    /// Map GDS-II layer/datatype pairs onto these technology layers. Pairs
+    /// no layer maps are added as new layers of the read database.
+    pub fn with_layer_stack(mut self, layers: LayerStack) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// Non-fatal problems found by the last [`read`](Self::read).
+    pub fn warnings(&self) -> &[GdsWarning] {
+        &self.warnings
+    }
+
     /// Read the entire GDS-II stream into a LayoutDatabase.
     pub fn read(&mut self) -> Result<LayoutDatabase, GdsError> {
         let mut db = LayoutDatabase::new("imported");
+        self.structures.clear();
+        self.references.clear();
+        self.warnings.clear();
 
         self.read_header()?;
         self.read_lib(&mut db)?;
+        self.link(&mut db)?;
+        db.layer_stack = self.layers.clone();
 
         Ok(db)
     }
@@ -227,7 +300,7 @@ impl<R: Read + Seek> GdsReader<R> {
 
         let total_len = u16::from_be_bytes(len_buf) as usize;
         if total_len < 4 {
-            let offset = self.reader.seek(SeekFrom::Current(0)).unwrap_or(0);
+            let offset = self.reader.stream_position().unwrap_or(0);
             return Err(GdsError::InvalidRecord {
                 offset,
                 message: format!("Record length {} is too small", total_len),
@@ -269,11 +342,7 @@ impl<R: Read + Seek> GdsReader<R> {
     }
 
     fn read_lib(&mut self, db: &mut LayoutDatabase) -> Result<(), GdsError> {
-        loop {
-            let rec = match self.read_record()? {
-                Some(r) => r,
-                None => break,
-            };
+        while let Some(rec) = self.read_record()? {
 
             match rec.record_type {
                 record_type::BGNLIB => {
@@ -284,13 +353,25 @@ impl<R: Read + Seek> GdsReader<R> {
                     log::info!("Library name: {}", db.name);
                 }
                 record_type::UNITS => {
+                    // [db unit in user units, db unit in meters]
                     let units = rec.as_f64_vec();
-                    if units.len() >= 2 {
-                        self.db_unit_in_um = units[0] * 1e6; // Convert meters to μm
+                    if units.len() >= 2 && units[1] > 0.0 {
+                        // 1e-6 / 1e-9 is 999.9999999999999: snap to the
+                        // integer grid the file almost certainly meant.
+                        let dbu_per_um = 1e-6 / units[1];
+                        db.dbu_per_um = if (dbu_per_um - dbu_per_um.round()).abs()
+                            <= dbu_per_um * 1e-9
+                        {
+                            dbu_per_um.round()
+                        } else {
+                            dbu_per_um
+                        };
+                        db.gds_units = Some([units[0], units[1]]);
                         log::info!(
-                            "Database unit: {} μm, user unit: {} m",
-                            self.db_unit_in_um,
-                            units[1]
+                            "Database unit: {} m ({} DBU/μm), user unit: {}",
+                            units[1],
+                            db.dbu_per_um,
+                            units[0]
                         );
                     }
                 }
@@ -310,41 +391,138 @@ impl<R: Read + Seek> GdsReader<R> {
         Ok(())
     }
 
+    /// Resolve SREF structure names to cell IDs once every structure is
+    /// known (so forward references work), then pick the top cell.
+    fn link(&mut self, db: &mut LayoutDatabase) -> Result<(), GdsError> {
+        let mut ids: HashMap<String, CellId> = HashMap::new();
+        // Link in stream order, so the first of several same-named
+        // structures is the one references resolve to.
+        let cells: Vec<(String, CellId)> = self
+            .structures
+            .iter()
+            .map(|id| (db.get_cell(id).expect("read cell exists").name.clone(), *id))
+            .collect();
+        for (name, id) in &cells {
+            if ids.contains_key(name) {
+                self.warnings.push(GdsWarning::DuplicateCell(name.clone()));
+            } else {
+                ids.insert(name.clone(), *id);
+            }
+        }
+
+        let mut references = std::mem::take(&mut self.references);
+        for (name, id) in &cells {
+            let cell = db.get_cell_mut(id).expect("listed cell exists");
+            let mut unresolved = Vec::new();
+            cell.instances.retain_mut(|inst| {
+                let Some(target) = references.remove(&inst.id) else {
+                    return true;
+                };
+                match ids.get(&target) {
+                    Some(id) => {
+                        inst.cell_id = *id;
+                        true
+                    }
+                    None => {
+                        unresolved.push(target);
+                        false
+                    }
+                }
+            });
+            for target in unresolved {
+                if self.strict_references {
+                    return Err(GdsError::UndefinedCell(target));
+                }
+                log::warn!("Cell '{}' references undefined cell '{}'", name, target);
+                self.warnings.push(GdsWarning::UnresolvedReference {
+                    cell: name.clone(),
+                    name: target,
+                });
+            }
+        }
+
+        // Of several roots, the one placing the most cells is the top cell.
+        let roots = db.root_cells();
+        if let Some(top) = roots
+            .iter()
+            .min_by_key(|id| std::cmp::Reverse(db.descendant_count(id)))
+        {
+            db.top_cell = Some(*top);
+        }
+        Ok(())
+    }
+
     fn read_structure(&mut self, db: &mut LayoutDatabase) -> Result<(), GdsError> {
         let mut cell = Cell::new("unnamed");
+        let mut attribute = None;
 
-        loop {
-            let rec = match self.read_record()? {
-                Some(r) => r,
-                None => break,
-            };
+        while let Some(rec) = self.read_record()? {
 
             match rec.record_type {
                 record_type::STRNAME => {
                     cell.name = rec.as_string();
                     log::info!("Reading cell: {}", cell.name);
                 }
+                // Structures have no properties in the GDS-II standard; these
+                // follow STRNAME, where the writer puts them.
+                record_type::PROPATTR => {
+                    attribute = rec.as_i16_vec().first().map(|&a| a as u16);
+                }
+                record_type::PROPVALUE => {
+                    if let Some(attribute) = attribute.take() {
+                        cell.properties.insert(attribute, rec.as_string());
+                    }
+                }
                 record_type::BOUNDARY => {
-                    if let Some(geom) = self.read_boundary()? {
-                        cell.add_geometry(geom);
+                    let geom = self.read_boundary()?;
+                    let properties = self.take_properties();
+                    if let Some(geom) = geom {
+                        let first = cell.shapes.len();
+                        let issues = cell
+                            .add_geometry_checked(geom, self.polygon_check)
+                            .map_err(|source| GdsError::InvalidPolygon {
+                                cell: cell.name.clone(),
+                                source,
+                            })?;
+                        self.warnings.extend(issues.into_iter().map(|issue| {
+                            GdsWarning::InvalidPolygon {
+                                cell: cell.name.clone(),
+                                issue,
+                            }
+                        }));
+                        // A repaired polygon may have become several shapes.
+                        for shape in &mut cell.shapes[first..] {
+                            shape.properties = properties.clone();
+                        }
                     }
                 }
                 record_type::PATH => {
-                    if let Some(geom) = self.read_path()? {
-                        cell.add_geometry(geom);
-                    }
+                    let geom = self.read_path()?;
+                    self.add_shape(&mut cell, geom);
                 }
-                record_type::SREF => {
-                    if let Some(inst) = self.read_sref()? {
-                        cell.add_instance(inst);
+                record_type::SREF | record_type::AREF => {
+                    let array = rec.record_type == record_type::AREF;
+                    let inst = self.read_reference(array)?;
+                    let mut properties = self.take_properties();
+                    if let Some(mut inst) = inst {
+                        let name = properties.remove(&INSTANCE_NAME_ATTRIBUTE);
+                        let target = match name {
+                            Some(name) => std::mem::replace(&mut inst.instance_name, name),
+                            None => inst.instance_name.clone(),
+                        };
+                        self.references.insert(inst.id, target);
+                        cell.add_instance(inst.with_properties(properties));
                     }
                 }
                 record_type::BOX => {
-                    if let Some(geom) = self.read_box()? {
-                        cell.add_geometry(geom);
-                    }
+                    let geom = self.read_box()?;
+                    self.add_shape(&mut cell, geom);
+                }
+                record_type::TEXT => {
+                    let geom = self.read_text()?;
+                    self.add_shape(&mut cell, geom);
                 }
-                record_type::TEXT | record_type::NODE | record_type::AREF => {
+                record_type::NODE => {
                     // Skip to ENDEL
                     self.skip_to_endel()?;
                 }
@@ -355,41 +533,69 @@ impl<R: Read + Seek> GdsReader<R> {
             }
         }
 
-        db.add_cell(cell);
+        let id = db.add_cell(cell);
+        self.structures.push(id);
         Ok(())
     }
 
+    /// Add an element read into `cell` with the properties it had.
+    fn add_shape(&mut self, cell: &mut Cell, geom: Option<GeomPrimitive>) {
+        let properties = self.take_properties();
+        if let Some(geom) = geom {
+            cell.add_shape(Shape::new(geom).with_properties(properties));
+        }
+    }
+
+    /// The properties of the element just read. A PROPATTR left without its
+    /// PROPVALUE is dropped so it cannot attach to the next element.
+    fn take_properties(&mut self) -> Properties {
+        self.attribute = None;
+        std::mem::take(&mut self.properties)
+    }
+
+    /// Read the next record of an element, collecting its PROPATTR and
+    /// PROPVALUE pairs into `self.properties` on the way.
+    fn read_element_record(&mut self) -> Result<Option<GdsRecord>, GdsError> {
+        while let Some(rec) = self.read_record()? {
+            match rec.record_type {
+                record_type::PROPATTR => {
+                    self.attribute = rec.as_i16_vec().first().map(|&a| a as u16);
+                }
+                record_type::PROPVALUE => {
+                    if let Some(attribute) = self.attribute.take() {
+                        self.properties.insert(attribute, rec.as_string());
+                    }
+                }
+                _ => return Ok(Some(rec)),
+            }
+        }
+        Ok(None)
+    }
+
     fn read_boundary(&mut self) -> Result<Option<GeomPrimitive>, GdsError> {
-        let mut layer: u32 = 0;
-        let mut _datatype: u16 = 0;
+        let mut gds_layer: u16 = 0;
+        let mut datatype: u16 = 0;
         let mut points: Vec<Point> = Vec::new();
 
-        loop {
-            let rec = match self.read_record()? {
-                Some(r) => r,
-                None => break,
-            };
+        while let Some(rec) = self.read_element_record()? {
 
             match rec.record_type {
                 record_type::LAYER => {
                     let vals = rec.as_i16_vec();
                     if !vals.is_empty() {
-                        layer = vals[0] as u32;
+                        gds_layer = vals[0] as u16;
                     }
                 }
                 record_type::DATATYPE => {
                     let vals = rec.as_i16_vec();
                     if !vals.is_empty() {
-                        _datatype = vals[0] as u16;
+                        datatype = vals[0] as u16;
                     }
                 }
                 record_type::XY => {
                     let coords = rec.as_i32_vec();
                     for pair in coords.chunks_exact(2) {
-                        points.push(Point::new(
-                            pair[0] as f64 * self.db_unit_in_um,
-                            pair[1] as f64 * self.db_unit_in_um,
-                        ));
+                        points.push(Point::new(pair[0] as Coord, pair[1] as Coord));
                     }
                 }
                 record_type::ENDEL => break,
@@ -406,6 +612,7 @@ impl<R: Read + Seek> GdsReader<R> {
             return Ok(None);
         }
 
+        let layer = self.layers.layer_for_gds(gds_layer, datatype);
         // Check if this is an axis-aligned rectangle (4 vertices)
         if points.len() == 4 && is_axis_aligned_rect(&points) {
             let bbox = opensilicon_core::geometry::BBox::from_points(&points).unwrap();
@@ -422,38 +629,57 @@ impl<R: Read + Seek> GdsReader<R> {
     }
 
     fn read_path(&mut self) -> Result<Option<GeomPrimitive>, GdsError> {
-        let mut layer: u32 = 0;
-        let mut width: f64 = 0.0;
+        let mut gds_layer: u16 = 0;
+        let mut datatype: u16 = 0;
+        let mut width: Coord = 0;
+        let mut path_type: i16 = 0;
+        let (mut begin_ext, mut end_ext): (Coord, Coord) = (0, 0);
         let mut points: Vec<Point> = Vec::new();
 
-        loop {
-            let rec = match self.read_record()? {
-                Some(r) => r,
-                None => break,
-            };
+        while let Some(rec) = self.read_element_record()? {
 
             match rec.record_type {
                 record_type::LAYER => {
                     let vals = rec.as_i16_vec();
                     if !vals.is_empty() {
-                        layer = vals[0] as u32;
+                        gds_layer = vals[0] as u16;
+                    }
+                }
+                record_type::DATATYPE => {
+                    let vals = rec.as_i16_vec();
+                    if !vals.is_empty() {
+                        datatype = vals[0] as u16;
+                    }
+                }
+                record_type::PATHTYPE => {
+                    let vals = rec.as_i16_vec();
+                    if !vals.is_empty() {
+                        path_type = vals[0];
+                    }
+                }
+                record_type::BGNEXTN => {
+                    let vals = rec.as_i32_vec();
+                    if !vals.is_empty() {
+                        begin_ext = vals[0] as Coord;
+                    }
+                }
+                record_type::ENDEXTN => {
+                    let vals = rec.as_i32_vec();
+                    if !vals.is_empty() {
+                        end_ext = vals[0] as Coord;
                     }
                 }
-                record_type::DATATYPE => {}
-                record_type::PATHTYPE => {}
                 record_type::WIDTH => {
                     let vals = rec.as_i32_vec();
                     if !vals.is_empty() {
-                        width = vals[0] as f64 * self.db_unit_in_um;
+                        // Negative widths are absolute (not scaled by MAG)
+                        width = (vals[0] as Coord).abs();
                     }
                 }
                 record_type::XY => {
                     let coords = rec.as_i32_vec();
                     for pair in coords.chunks_exact(2) {
-                        points.push(Point::new(
-                            pair[0] as f64 * self.db_unit_in_um,
-                            pair[1] as f64 * self.db_unit_in_um,
-                        ));
+                        points.push(Point::new(pair[0] as Coord, pair[1] as Coord));
                     }
                 }
                 record_type::ENDEL => break,
@@ -465,21 +691,32 @@ impl<R: Read + Seek> GdsReader<R> {
             return Ok(None);
         }
 
-        Ok(Some(GeomPrimitive::Path(LayoutPath::new(
-            layer, points, width,
-        ))))
+        let end_style = match path_type {
+            1 => PathEndStyle::Round,
+            2 => PathEndStyle::HalfWidth,
+            4 => PathEndStyle::Custom {
+                begin: begin_ext,
+                end: end_ext,
+            },
+            _ => PathEndStyle::Flush,
+        };
+
+        let layer = self.layers.layer_for_gds(gds_layer, datatype);
+        Ok(Some(GeomPrimitive::Path(
+            LayoutPath::new(layer, points, width).with_end_style(end_style),
+        )))
     }
 
-    fn read_sref(&mut self) -> Result<Option<CellInstance>, GdsError> {
+    /// Read an SREF, or an AREF when `array` is set, up to its ENDEL.
+    fn read_reference(&mut self, array: bool) -> Result<Option<CellInstance>, GdsError> {
         let mut cell_name = String::new();
-        let mut transform = Transform::default();
-        let mut position = Point::new(0.0, 0.0);
+        let mut mirror_x = false;
+        let mut magnification = 1.0;
+        let mut angle = 0.0;
+        let mut colrow: Option<(i16, i16)> = None;
+        let mut points: Vec<Point> = Vec::new();
 
-        loop {
-            let rec = match self.read_record()? {
-                Some(r) => r,
-                None => break,
-            };
+        while let Some(rec) = self.read_element_record()? {
 
             match rec.record_type {
                 record_type::SNAME => {
@@ -488,29 +725,33 @@ impl<R: Read + Seek> GdsReader<R> {
                 record_type::STRANS => {
                     let vals = rec.as_i16_vec();
                     if !vals.is_empty() {
-                        transform.mirror_x = (vals[0] & 0x8000u16 as i16) != 0;
+                        mirror_x = (vals[0] & 0x8000u16 as i16) != 0;
                     }
                 }
                 record_type::MAG => {
                     let vals = rec.as_f64_vec();
                     if !vals.is_empty() {
-                        transform.scale = vals[0];
+                        magnification = vals[0];
                     }
                 }
                 record_type::ANGLE => {
                     let vals = rec.as_f64_vec();
                     if !vals.is_empty() {
-                        transform.rotation = vals[0];
+                        angle = vals[0];
+                    }
+                }
+                record_type::COLROW => {
+                    let vals = rec.as_i16_vec();
+                    if vals.len() >= 2 {
+                        colrow = Some((vals[0], vals[1]));
                     }
                 }
                 record_type::XY => {
                     let coords = rec.as_i32_vec();
-                    if coords.len() >= 2 {
-                        position = Point::new(
-                            coords[0] as f64 * self.db_unit_in_um,
-                            coords[1] as f64 * self.db_unit_in_um,
-                        );
-                    }
+                    points = coords
+                        .chunks_exact(2)
+                        .map(|pair| Point::new(pair[0] as Coord, pair[1] as Coord))
+                        .collect();
                 }
                 record_type::ENDEL => break,
                 _ => {}
@@ -521,42 +762,151 @@ impl<R: Read + Seek> GdsReader<R> {
             return Ok(None);
         }
 
-        transform.offset = position;
+        let position = points.first().copied().unwrap_or(Point::new(0, 0));
+        let transform = Transform::from_parts(position, mirror_x, angle, magnification);
+
+        // The structure may not have been read yet: name the instance after
+        // it and leave a nil UUID until `link` resolves it once the full
+        // library is loaded.
+        let inst = CellInstance::new(uuid::Uuid::nil(), &cell_name, transform);
+        if !array {
+            return Ok(Some(inst));
+        }
+
+        // AREF XY holds the origin, the origin displaced by all columns and
+        // the origin displaced by all rows.
+        let (columns, rows) = match colrow {
+            Some((c, r)) if c > 0 && r > 0 => (c as i64, r as i64),
+            Some((c, r)) => {
+                let message = format!("AREF of '{}' has {}x{} elements", cell_name, c, r);
+                return Err(self.invalid_record(message));
+            }
+            None => {
+                let message = format!("AREF of '{}' has no COLROW", cell_name);
+                return Err(self.invalid_record(message));
+            }
+        };
+        let [origin, column_end, row_end] = points[..] else {
+            let message = format!(
+                "AREF of '{}' has {} XY points, expected 3",
+                cell_name,
+                points.len()
+            );
+            return Err(self.invalid_record(message));
+        };
+        let column_pitch = Point::new(
+            div_round(column_end.x - origin.x, columns),
+            div_round(column_end.y - origin.y, columns),
+        );
+        let row_pitch = Point::new(
+            div_round(row_end.x - origin.x, rows),
+            div_round(row_end.y - origin.y, rows),
+        );
+        let params = ArrayParams::new(columns as u32, rows as u32, column_pitch, row_pitch);
+        Ok(Some(inst.with_array(params)))
+    }
+
+    fn read_text(&mut self) -> Result<Option<GeomPrimitive>, GdsError> {
+        let mut gds_layer: u16 = 0;
+        let mut text_type: u16 = 0;
+        let mut presentation = TextPresentation::default();
+        let mut mirror_x = false;
+        let mut magnification = 1.0;
+        let mut angle = 0.0;
+        let mut position = None;
+        let mut string = None;
+
+        while let Some(rec) = self.read_element_record()? {
 
-        // We use a nil UUID here; it will be resolved when the full library is loaded
-        Ok(Some(CellInstance::new(
-            uuid::Uuid::nil(),
-            &cell_name,
-            transform,
+            match rec.record_type {
+                record_type::LAYER => {
+                    let vals = rec.as_i16_vec();
+                    if !vals.is_empty() {
+                        gds_layer = vals[0] as u16;
+                    }
+                }
+                record_type::TEXTTYPE => {
+                    let vals = rec.as_i16_vec();
+                    if !vals.is_empty() {
+                        text_type = vals[0] as u16;
+                    }
+                }
+                record_type::PRESENTATION => {
+                    let vals = rec.as_i16_vec();
+                    if !vals.is_empty() {
+                        presentation = TextPresentation::from_bits(vals[0] as u16);
+                    }
+                }
+                record_type::STRANS => {
+                    let vals = rec.as_i16_vec();
+                    if !vals.is_empty() {
+                        mirror_x = (vals[0] & 0x8000u16 as i16) != 0;
+                    }
+                }
+                record_type::MAG => {
+                    let vals = rec.as_f64_vec();
+                    if !vals.is_empty() {
+                        magnification = vals[0];
+                    }
+                }
+                record_type::ANGLE => {
+                    let vals = rec.as_f64_vec();
+                    if !vals.is_empty() {
+                        angle = vals[0];
+                    }
+                }
+                record_type::XY => {
+                    let coords = rec.as_i32_vec();
+                    if coords.len() >= 2 {
+                        position = Some(Point::new(coords[0] as Coord, coords[1] as Coord));
+                    }
+                }
+                record_type::STRING => {
+                    string = Some(rec.as_string());
+                }
+                record_type::ENDEL => break,
+                _ => {}
+            }
+        }
+
+        let (Some(position), Some(string)) = (position, string) else {
+            return Ok(None);
+        };
+        let layer = self.layers.layer_for_gds(gds_layer, text_type);
+        let transform = Transform::from_parts(position, mirror_x, angle, magnification);
+        Ok(Some(GeomPrimitive::Text(
+            Text::new(layer, string, position)
+                .with_transform(transform)
+                .with_presentation(presentation),
         )))
     }
 
     fn read_box(&mut self) -> Result<Option<GeomPrimitive>, GdsError> {
-        // BOX is similar to BOUNDARY but with BOXTYPE instead of DATATYPE
-        let mut layer: u32 = 0;
+        // BOX is similar to BOUNDARY but with BOXTYPE instead of DATATYPE. It
+        // becomes a rectangle on the layer of its layer/boxtype pair.
+        let mut gds_layer: u16 = 0;
+        let mut box_type: u16 = 0;
         let mut points: Vec<Point> = Vec::new();
 
-        loop {
-            let rec = match self.read_record()? {
-                Some(r) => r,
-                None => break,
-            };
+        while let Some(rec) = self.read_element_record()? {
 
             match rec.record_type {
                 record_type::LAYER => {
                     let vals = rec.as_i16_vec();
                     if !vals.is_empty() {
-                        layer = vals[0] as u32;
+                        gds_layer = vals[0] as u16;
+                    }
+                }
+                record_type::BOXTYPE => {
+                    let vals = rec.as_i16_vec();
+                    if !vals.is_empty() {
+                        box_type = vals[0] as u16;
                     }
                 }
-                record_type::BOXTYPE => {}
                 record_type::XY => {
                     let coords = rec.as_i32_vec();
                     for pair in coords.chunks_exact(2) {
-                        points.push(Point::new(
-                            pair[0] as f64 * self.db_unit_in_um,
-                            pair[1] as f64 * self.db_unit_in_um,
-                        ));
+                        points.push(Point::new(pair[0] as Coord, pair[1] as Coord));
                     }
                 }
                 record_type::ENDEL => break,
@@ -572,6 +922,7 @@ impl<R: Read + Seek> GdsReader<R> {
             return Ok(None);
         }
 
+        let layer = self.layers.layer_for_gds(gds_layer, box_type);
         let bbox = opensilicon_core::geometry::BBox::from_points(&points).unwrap();
         Ok(Some(GeomPrimitive::Rect(Rect::new(
             layer,
@@ -582,12 +933,13 @@ impl<R: Read + Seek> GdsReader<R> {
         ))))
     }
 
+    fn invalid_record(&mut self, message: String) -> GdsError {
+        let offset = self.reader.stream_position().unwrap_or(0);
+        GdsError::InvalidRecord { offset, message }
+    }
+
     fn skip_to_endel(&mut self) -> Result<(), GdsError> {
-        loop {
-            let rec = match self.read_record()? {
-                Some(r) => r,
-                None => break,
-            };
+        while let Some(rec) = self.read_record()? {
             if rec.record_type == record_type::ENDEL {
                 break;
             }
@@ -601,47 +953,151 @@ fn is_axis_aligned_rect(points: &[Point]) -> bool {
     if points.len() != 4 {
         return false;
     }
-    let xs: Vec<f64> = points.iter().map(|p| p.x).collect();
-    let ys: Vec<f64> = points.iter().map(|p| p.y).collect();
+    let unique_x: std::collections::HashSet<Coord> = points.iter().map(|p| p.x).collect();
+    let unique_y: std::collections::HashSet<Coord> = points.iter().map(|p| p.y).collect();
+
+    // Consecutive vertices must share an x or a y to rule out "bow-tie" orderings.
+    let edges_axis_aligned = (0..4).all(|i| {
+        let (a, b) = (points[i], points[(i + 1) % 4]);
+        a.x == b.x || a.y == b.y
+    });
 
-    let unique_x: std::collections::HashSet<u64> = xs.iter().map(|x| x.to_bits()).collect();
-    let unique_y: std::collections::HashSet<u64> = ys.iter().map(|y| y.to_bits()).collect();
+    unique_x.len() == 2 && unique_y.len() == 2 && edges_axis_aligned
+}
 
-    unique_x.len() == 2 && unique_y.len() == 2
+/// `n / d` rounded to the nearest integer, halves away from zero.
+fn div_round(n: Coord, d: Coord) -> Coord {
+    let q = n / d;
+    let r = n % d;
+    if 2 * r.abs() >= d.abs() {
+        q + n.signum() * d.signum()
+    } else {
+        q
+    }
 }
 
 // ── GDS-II Writer ─────────────────────────────────────────────────────
 
+/// A coordinate as a GDS-II 32-bit integer.
+fn gds_coord(value: Coord) -> Result<i32, GdsError> {
+    i32::try_from(value).map_err(|_| GdsError::CoordinateOverflow(value))
+}
+
+/// Points as XY record values.
+fn gds_xy(points: impl IntoIterator<Item = Point>) -> Result<Vec<i32>, GdsError> {
+    let mut coords = Vec::new();
+    for p in points {
+        coords.push(gds_coord(p.x)?);
+        coords.push(gds_coord(p.y)?);
+    }
+    Ok(coords)
+}
+
+/// A time as the year, month, day, hour, minute and second of a GDS-II
+/// date, in UTC.
+fn gds_date(time: SystemTime) -> [i16; 6] {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as i64;
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    [year, month, day, rem / 3600, rem / 60 % 60, rem % 60].map(|v| v as i16)
+}
+
 pub struct GdsWriter<W: io::Write> {
     writer: W,
-    db_unit_in_um: f64,
+    layers: LayerStack,
+    /// Structure names by cell ID, for SNAME.
+    cell_names: HashMap<CellId, String>,
+    /// Modification date for BGNLIB and BGNSTR; the time of writing if unset.
+    timestamp: Option<SystemTime>,
+    date: [i16; 6],
+    cell_properties: bool,
+    instance_names: bool,
 }
 
 impl<W: io::Write> GdsWriter<W> {
     pub fn new(writer: W) -> Self {
         Self {
             writer,
-            db_unit_in_um: 0.001,
+            layers: LayerStack::new(),
+            cell_names: HashMap::new(),
+            timestamp: None,
+            date: [0; 6],
+            cell_properties: false,
+            instance_names: false,
         }
     }
 
+    /// Stamp the library and its structures with `time` instead of the
+    /// current time, for reproducible output.
+    pub fn with_timestamp(mut self, time: SystemTime) -> Self {
+        self.timestamp = Some(time);
+        self
+    }
+
+    /// Also write cell properties, as PROPATTR/PROPVALUE pairs after
+    /// STRNAME. GDS-II has no structure properties, so strict readers may
+    /// reject the file; off by default.
+    pub fn with_cell_properties(mut self, enabled: bool) -> Self {
+        self.cell_properties = enabled;
+        self
+    }
+
+    /// Also write instance names, under [`INSTANCE_NAME_ATTRIBUTE`]. Names
+    /// that are empty or equal to the referenced structure's name are left
+    /// out, as the reader falls back to that name. Off by default.
+    pub fn with_instance_names(mut self, enabled: bool) -> Self {
+        self.instance_names = enabled;
+        self
+    }
+
     /// Write a LayoutDatabase as a GDS-II stream.
     pub fn write(&mut self, db: &LayoutDatabase) -> Result<(), GdsError> {
+        self.layers = db.layer_stack.clone();
+        self.cell_names = db.all_cells().map(|c| (c.id, c.name.clone())).collect();
+        self.date = gds_date(self.timestamp.unwrap_or_else(SystemTime::now));
+
         self.write_header()?;
         self.write_bgnlib()?;
         self.write_libname(&db.name)?;
-        self.write_units()?;
+        self.write_units(db)?;
 
-        for cell in db.all_cells() {
-            self.write_cell(cell)?;
+        for id in Self::cell_order(db) {
+            self.write_cell(db.get_cell(&id).expect("listed cell exists"))?;
         }
 
         self.write_endlib()?;
         Ok(())
     }
 
+    /// Cells in a stable order for writing: each before the cells placing
+    /// it, or by name if the hierarchy has a cycle.
+    fn cell_order(db: &LayoutDatabase) -> Vec<CellId> {
+        match db.hierarchy().topological_order() {
+            Ok(mut order) => {
+                order.reverse();
+                order
+            }
+            Err(_) => {
+                let mut cells: Vec<(&str, CellId)> =
+                    db.all_cells().map(|c| (c.name.as_str(), c.id)).collect();
+                cells.sort();
+                cells.into_iter().map(|(_, id)| id).collect()
+            }
+        }
+    }
+
     fn write_record(&mut self, record_type: u16, data: &[u8]) -> Result<(), GdsError> {
-        let total_len = (data.len() + 4) as u16;
+        let total_len =
+            u16::try_from(data.len() + 4).map_err(|_| GdsError::RecordTooLong(data.len() + 4))?;
         self.writer.write_all(&total_len.to_be_bytes())?;
         self.writer.write_all(&record_type.to_be_bytes())?;
         if !data.is_empty() {
@@ -663,7 +1119,7 @@ impl<W: io::Write> GdsWriter<W> {
     fn write_string_record(&mut self, record_type: u16, s: &str) -> Result<(), GdsError> {
         let mut data: Vec<u8> = s.bytes().collect();
         // GDS strings must be even length
-        if data.len() % 2 != 0 {
+        if !data.len().is_multiple_of(2) {
             data.push(0);
         }
         self.write_record(record_type, &data)
@@ -682,42 +1138,72 @@ impl<W: io::Write> GdsWriter<W> {
     }
 
     fn write_bgnlib(&mut self) -> Result<(), GdsError> {
-        // Timestamp: 12 i16 values (mod/access date)
-        let timestamp = [2026i16, 2, 6, 0, 0, 0, 2026, 2, 6, 0, 0, 0];
-        self.write_i16_record(record_type::BGNLIB, &timestamp)
+        self.write_timestamp(record_type::BGNLIB)
+    }
+
+    /// Write a BGNLIB or BGNSTR record: modification then access date.
+    fn write_timestamp(&mut self, record_type: u16) -> Result<(), GdsError> {
+        let date = self.date;
+        let mut values = [0i16; 12];
+        values[..6].copy_from_slice(&date);
+        values[6..].copy_from_slice(&date);
+        self.write_i16_record(record_type, &values)
     }
 
     fn write_libname(&mut self, name: &str) -> Result<(), GdsError> {
         self.write_string_record(record_type::LIBNAME, name)
     }
 
-    fn write_units(&mut self) -> Result<(), GdsError> {
-        // db_unit_in_user_units, db_unit_in_meters
-        let db_in_m = self.db_unit_in_um * 1e-6;
-        self.write_real8_record(record_type::UNITS, &[self.db_unit_in_um * 1e-3, db_in_m])
+    /// Write UNITS: the ones the library was read from if they still match
+    /// the database grid, otherwise the grid with a 1µm user unit.
+    fn write_units(&mut self, db: &LayoutDatabase) -> Result<(), GdsError> {
+        let units = match db.gds_units {
+            Some([user, meters])
+                if meters > 0.0 && (1e-6 / meters - db.dbu_per_um).abs() <= db.dbu_per_um * 1e-9 =>
+            {
+                [user, meters]
+            }
+            _ => [1.0 / db.dbu_per_um, db.units().dbu_in_meters()],
+        };
+        self.write_real8_record(record_type::UNITS, &units)
     }
 
     fn write_cell(&mut self, cell: &Cell) -> Result<(), GdsError> {
         // BGNSTR
-        let timestamp = [2026i16, 2, 6, 0, 0, 0, 2026, 2, 6, 0, 0, 0];
-        self.write_i16_record(record_type::BGNSTR, &timestamp)?;
+        self.write_timestamp(record_type::BGNSTR)?;
 
         // STRNAME
         self.write_string_record(record_type::STRNAME, &cell.name)?;
 
+        // Non-standard: structure properties are an extension (as in KLayout).
+        if self.cell_properties {
+            self.write_properties(&cell.properties)?;
+        }
+
         // Write all geometries
-        for geom in &cell.geometries {
-            match geom {
+        for shape in &cell.shapes {
+            match &shape.geometry {
                 GeomPrimitive::Rect(rect) => self.write_rect(rect)?,
                 GeomPrimitive::Polygon(poly) => self.write_polygon(poly)?,
                 GeomPrimitive::Path(path) => self.write_path(path)?,
                 GeomPrimitive::Via(via) => self.write_via(via)?,
+                GeomPrimitive::Text(text) => self.write_text(text)?,
             }
+            self.end_element(&shape.properties)?;
         }
 
         // Write instances
         for inst in &cell.instances {
-            self.write_sref(inst)?;
+            self.write_reference(inst)?;
+            let structure = self.cell_names.get(&inst.cell_id);
+            let name = &inst.instance_name;
+            if self.instance_names && !name.is_empty() && structure != Some(name) {
+                let mut properties = inst.properties.clone();
+                properties.insert(INSTANCE_NAME_ATTRIBUTE, name.clone());
+                self.end_element(&properties)?;
+            } else {
+                self.end_element(&inst.properties)?;
+            }
         }
 
         // ENDSTR
@@ -726,106 +1212,152 @@ impl<W: io::Write> GdsWriter<W> {
     }
 
     fn write_rect(&mut self, rect: &Rect) -> Result<(), GdsError> {
-        let scale = 1.0 / self.db_unit_in_um;
-        let x1 = (rect.lower_left.x * scale) as i32;
-        let y1 = (rect.lower_left.y * scale) as i32;
-        let x2 = (rect.upper_right.x * scale) as i32;
-        let y2 = (rect.upper_right.y * scale) as i32;
+        let [x1, y1, x2, y2] = [
+            gds_coord(rect.lower_left.x)?,
+            gds_coord(rect.lower_left.y)?,
+            gds_coord(rect.upper_right.x)?,
+            gds_coord(rect.upper_right.y)?,
+        ];
 
         self.write_record(record_type::BOUNDARY, &[])?;
-        self.write_i16_record(record_type::LAYER, &[rect.layer_id as i16])?;
-        self.write_i16_record(record_type::DATATYPE, &[0])?;
+        self.write_layer(record_type::DATATYPE, rect.layer_id)?;
         // 5 points: closed rectangle
         self.write_i32_record(
             record_type::XY,
             &[x1, y1, x2, y1, x2, y2, x1, y2, x1, y1],
         )?;
-        self.write_record(record_type::ENDEL, &[])?;
         Ok(())
     }
 
     fn write_polygon(&mut self, poly: &Polygon) -> Result<(), GdsError> {
-        let scale = 1.0 / self.db_unit_in_um;
-
+        // GDS-II has no holes: join them to the outer ring with cut lines.
+        let poly = &poly.keyholed();
         self.write_record(record_type::BOUNDARY, &[])?;
-        self.write_i16_record(record_type::LAYER, &[poly.layer_id as i16])?;
-        self.write_i16_record(record_type::DATATYPE, &[0])?;
+        self.write_layer(record_type::DATATYPE, poly.layer_id)?;
 
-        let mut coords: Vec<i32> = poly
-            .vertices
-            .iter()
-            .flat_map(|p| vec![(p.x * scale) as i32, (p.y * scale) as i32])
-            .collect();
         // Close the polygon
-        if let Some(first) = poly.vertices.first() {
-            coords.push((first.x * scale) as i32);
-            coords.push((first.y * scale) as i32);
-        }
+        let closed = poly.vertices.iter().chain(poly.vertices.first()).copied();
+        let coords = gds_xy(closed)?;
 
         self.write_i32_record(record_type::XY, &coords)?;
-        self.write_record(record_type::ENDEL, &[])?;
         Ok(())
     }
 
     fn write_path(&mut self, path: &LayoutPath) -> Result<(), GdsError> {
-        let scale = 1.0 / self.db_unit_in_um;
-
         self.write_record(record_type::PATH, &[])?;
-        self.write_i16_record(record_type::LAYER, &[path.layer_id as i16])?;
-        self.write_i16_record(record_type::DATATYPE, &[0])?;
-        self.write_i32_record(record_type::WIDTH, &[(path.width * scale) as i32])?;
+        self.write_layer(record_type::DATATYPE, path.layer_id)?;
+        match path.end_style {
+            PathEndStyle::Flush => {}
+            PathEndStyle::Round => self.write_i16_record(record_type::PATHTYPE, &[1])?,
+            PathEndStyle::HalfWidth => self.write_i16_record(record_type::PATHTYPE, &[2])?,
+            PathEndStyle::Custom { .. } => self.write_i16_record(record_type::PATHTYPE, &[4])?,
+        }
+        self.write_i32_record(record_type::WIDTH, &[gds_coord(path.width)?])?;
+        if let PathEndStyle::Custom { begin, end } = path.end_style {
+            self.write_i32_record(record_type::BGNEXTN, &[gds_coord(begin)?])?;
+            self.write_i32_record(record_type::ENDEXTN, &[gds_coord(end)?])?;
+        }
 
-        let coords: Vec<i32> = path
-            .points
-            .iter()
-            .flat_map(|p| vec![(p.x * scale) as i32, (p.y * scale) as i32])
-            .collect();
+        let coords = gds_xy(path.points.iter().copied())?;
 
         self.write_i32_record(record_type::XY, &coords)?;
-        self.write_record(record_type::ENDEL, &[])?;
         Ok(())
     }
 
     fn write_via(&mut self, via: &Via) -> Result<(), GdsError> {
         // Write via as a rectangle on the cut layer
-        let half_w = via.width / 2.0;
-        let half_h = via.height / 2.0;
-        let rect = Rect::new(
-            via.cut_layer,
-            via.position.x - half_w,
-            via.position.y - half_h,
-            via.position.x + half_w,
-            via.position.y + half_h,
-        );
+        let bbox = via.bbox();
+        let rect = Rect::new(via.cut_layer, bbox.min.x, bbox.min.y, bbox.max.x, bbox.max.y);
         self.write_rect(&rect)
     }
 
-    fn write_sref(&mut self, inst: &CellInstance) -> Result<(), GdsError> {
-        let scale = 1.0 / self.db_unit_in_um;
+    /// Write an instance as an SREF, or as an AREF if it is an array.
+    fn write_reference(&mut self, inst: &CellInstance) -> Result<(), GdsError> {
+        let kind = if inst.array.is_some() { record_type::AREF } else { record_type::SREF };
+        let name = self
+            .cell_names
+            .get(&inst.cell_id)
+            .cloned()
+            .ok_or_else(|| GdsError::UndefinedCell(inst.cell_id.to_string()))?;
+        self.write_record(kind, &[])?;
+        self.write_string_record(record_type::SNAME, &name)?;
+
+        self.write_strans(&inst.transform)?;
+
+        let origin = inst.transform.offset;
+        let mut points = vec![origin];
+        if let Some(array) = &inst.array {
+            let (columns, rows) = match (i16::try_from(array.columns), i16::try_from(array.rows)) {
+                (Ok(c), Ok(r)) if c > 0 && r > 0 => (c, r),
+                _ => {
+                    return Err(GdsError::ArrayTooLarge {
+                        columns: array.columns,
+                        rows: array.rows,
+                    })
+                }
+            };
+            self.write_i16_record(record_type::COLROW, &[columns, rows])?;
+            let column_end = array.element_offset(array.columns, 0);
+            let row_end = array.element_offset(0, array.rows);
+            points.push(origin.translate(column_end.x, column_end.y));
+            points.push(origin.translate(row_end.x, row_end.y));
+        }
+        let coords = gds_xy(points)?;
+        self.write_i32_record(record_type::XY, &coords)?;
 
-        self.write_record(record_type::SREF, &[])?;
-        self.write_string_record(record_type::SNAME, &inst.instance_name)?;
+        Ok(())
+    }
 
-        // STRANS if mirrored
-        if inst.transform.mirror_x {
-            self.write_i16_record(record_type::STRANS, &[i16::MIN])?; // 0x8000
-        } else if inst.transform.rotation != 0.0 || inst.transform.scale != 1.0 {
-            self.write_i16_record(record_type::STRANS, &[0])?;
+    fn write_text(&mut self, text: &Text) -> Result<(), GdsError> {
+        self.write_record(record_type::TEXT, &[])?;
+        self.write_layer(record_type::TEXTTYPE, text.layer_id)?;
+        if text.presentation != TextPresentation::default() {
+            let bits = text.presentation.bits() as i16;
+            self.write_i16_record(record_type::PRESENTATION, &[bits])?;
         }
+        self.write_strans(&text.transform)?;
+        self.write_i32_record(record_type::XY, &gds_xy([text.position()])?)?;
+        self.write_string_record(record_type::STRING, &text.text)?;
+        Ok(())
+    }
 
-        if inst.transform.scale != 1.0 {
-            self.write_real8_record(record_type::MAG, &[inst.transform.scale])?;
+    /// Finish an element with its properties and ENDEL.
+    fn end_element(&mut self, properties: &Properties) -> Result<(), GdsError> {
+        self.write_properties(properties)?;
+        self.write_record(record_type::ENDEL, &[])
+    }
+
+    fn write_properties(&mut self, properties: &Properties) -> Result<(), GdsError> {
+        for (&attribute, value) in properties {
+            self.write_i16_record(record_type::PROPATTR, &[attribute as i16])?;
+            self.write_string_record(record_type::PROPVALUE, value)?;
         }
+        Ok(())
+    }
+
+    /// Write LAYER and the datatype record `kind` for a layer's GDS-II pair.
+    fn write_layer(&mut self, kind: u16, layer: LayerId) -> Result<(), GdsError> {
+        let (gds_layer, datatype) = self.layers.gds_pair(layer);
+        self.write_i16_record(record_type::LAYER, &[gds_layer as i16])?;
+        self.write_i16_record(kind, &[datatype as i16])
+    }
 
-        if inst.transform.rotation != 0.0 {
-            self.write_real8_record(record_type::ANGLE, &[inst.transform.rotation])?;
+    /// Write STRANS, MAG and ANGLE for a transform, omitting the defaults.
+    fn write_strans(&mut self, t: &Transform) -> Result<(), GdsError> {
+        let rotation = t.rotation_degrees();
+        if t.mirror_x() {
+            self.write_i16_record(record_type::STRANS, &[i16::MIN])?; // 0x8000
+        } else if rotation != 0.0 || t.magnification != 1.0 {
+            self.write_i16_record(record_type::STRANS, &[0])?;
         }
 
-        let x = (inst.transform.offset.x * scale) as i32;
-        let y = (inst.transform.offset.y * scale) as i32;
-        self.write_i32_record(record_type::XY, &[x, y])?;
+        if t.magnification != 1.0 {
+            self.write_real8_record(record_type::MAG, &[t.magnification])?;
+        }
 
-        self.write_record(record_type::ENDEL, &[])?;
+        if rotation != 0.0 {
+            self.write_real8_record(record_type::ANGLE, &[rotation])?;
+        }
         Ok(())
     }
 
@@ -837,11 +1369,13 @@ impl<W: io::Write> GdsWriter<W> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use opensilicon_core::geometry::{HorizontalAlign, VerticalAlign};
+    use opensilicon_core::layer::Layer;
     use std::io::Cursor;
 
     #[test]
     fn test_gds_real8_roundtrip() {
-        let values = [0.0, 1.0, -1.0, 0.001, 1e-9, 3.14159, 1000.0];
+        let values = [0.0, 1.0, -1.0, 0.001, 1e-9, 1.234567, 1000.0];
         for &v in &values {
             let bytes = f64_to_gds_real8(v);
             let result = gds_real8_to_f64(&bytes);
@@ -858,8 +1392,8 @@ mod tests {
     fn test_write_and_read_roundtrip() {
         let mut db = LayoutDatabase::new("test_lib");
         let mut cell = Cell::new("test_cell");
-        cell.add_geometry(GeomPrimitive::Rect(Rect::new(0, 0.0, 0.0, 1.0, 0.5)));
-        cell.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0.5, 0.25, 2.0, 0.75)));
+        cell.add_geometry(GeomPrimitive::Rect(Rect::new(0, 0, 0, 1000, 500)));
+        cell.add_geometry(GeomPrimitive::Rect(Rect::new(1, 500, 250, 2000, 750)));
         db.add_cell(cell);
 
         // Write
@@ -876,4 +1410,638 @@ mod tests {
         let read_cell = read_db.find_cell_by_name("test_cell").unwrap();
         assert_eq!(read_cell.geometry_count(), 2);
     }
+
+    #[test]
+    fn test_roundtrip_preserves_dbu_coordinates() {
+        let mut db = LayoutDatabase::new("test_lib");
+        db.dbu_per_um = 4000.0; // 0.25nm grid
+        let mut cell = Cell::new("odd_grid");
+        let vertices = vec![
+            Point::new(-7, 3),
+            Point::new(1_000_001, 3),
+            Point::new(1_000_001, 999_999),
+            Point::new(13, 123_457),
+        ];
+        cell.add_geometry(GeomPrimitive::Polygon(Polygon::new(5, vertices.clone())));
+        cell.add_geometry(GeomPrimitive::Path(LayoutPath::new(
+            6,
+            vec![Point::new(1, 1), Point::new(333_333, 1)],
+            71,
+        )));
+        db.add_cell(cell);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut buffer).write(&db).unwrap();
+        let read_db = GdsReader::new(Cursor::new(buffer)).read().unwrap();
+
+        assert!((read_db.dbu_per_um - 4000.0).abs() < 1e-6);
+        let read_cell = read_db.find_cell_by_name("odd_grid").unwrap();
+        assert_eq!(
+            read_cell.shapes[0].geometry,
+            GeomPrimitive::Polygon(Polygon::new(5, vertices))
+        );
+        match &read_cell.shapes[1].geometry {
+            GeomPrimitive::Path(p) => {
+                assert_eq!(p.width, 71);
+                assert_eq!(p.points, vec![Point::new(1, 1), Point::new(333_333, 1)]);
+            }
+            other => panic!("expected path, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_path_end_styles_roundtrip() {
+        let styles = [
+            PathEndStyle::Flush,
+            PathEndStyle::Round,
+            PathEndStyle::HalfWidth,
+            PathEndStyle::Custom { begin: 15, end: -4 },
+        ];
+        let mut db = LayoutDatabase::new("test_lib");
+        let mut cell = Cell::new("paths");
+        for (i, style) in styles.iter().enumerate() {
+            let y = i as Coord * 100;
+            let path = LayoutPath::new(1, vec![Point::new(0, y), Point::new(500, y)], 20);
+            cell.add_geometry(GeomPrimitive::Path(path.with_end_style(*style)));
+        }
+        db.add_cell(cell);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut buffer).write(&db).unwrap();
+        let read_db = GdsReader::new(Cursor::new(buffer)).read().unwrap();
+
+        let read_cell = read_db.find_cell_by_name("paths").unwrap();
+        let read_styles: Vec<PathEndStyle> = read_cell
+            .geometries()
+            .map(|g| match g {
+                GeomPrimitive::Path(p) => p.end_style,
+                other => panic!("expected path, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(read_styles, styles);
+    }
+
+    fn write_single_polygon(polygon: Polygon) -> Vec<u8> {
+        let mut db = LayoutDatabase::new("test_lib");
+        let mut cell = Cell::new("top");
+        cell.add_geometry(GeomPrimitive::Polygon(polygon));
+        db.add_cell(cell);
+        let mut buffer: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut buffer).write(&db).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_polygon_holes_roundtrip_as_keyholes() {
+        let outer = vec![
+            Point::new(0, 0),
+            Point::new(100, 0),
+            Point::new(100, 100),
+            Point::new(0, 100),
+        ];
+        let hole = vec![
+            Point::new(40, 40),
+            Point::new(40, 60),
+            Point::new(60, 60),
+            Point::new(60, 40),
+        ];
+        let buffer = write_single_polygon(Polygon::with_holes(1, outer, vec![hole]));
+
+        // Keyholed as written; valid as-is.
+        let mut reader = GdsReader::new(Cursor::new(buffer.clone()))
+            .with_polygon_check(PolygonCheck::Report);
+        let read_db = reader.read().unwrap();
+        assert!(reader.warnings().is_empty(), "{:?}", reader.warnings());
+        let cell = read_db.find_cell_by_name("top").unwrap();
+        match &cell.shapes[0].geometry {
+            GeomPrimitive::Polygon(p) => assert!(p.holes.is_empty()),
+            other => panic!("expected polygon, got {:?}", other),
+        }
+
+        // Repair normalizes the keyhole back into a hole.
+        let mut reader = GdsReader::new(Cursor::new(buffer))
+            .with_polygon_check(PolygonCheck::Repair);
+        let read_db = reader.read().unwrap();
+        let cell = read_db.find_cell_by_name("top").unwrap();
+        match &cell.shapes[0].geometry {
+            GeomPrimitive::Polygon(p) => assert_eq!(p.area(), 100 * 100 - 20 * 20),
+            other => panic!("expected polygon, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_invalid_polygon_report_repair_reject() {
+        let bow_tie = vec![
+            Point::new(0, 0),
+            Point::new(10, 10),
+            Point::new(10, 0),
+            Point::new(0, 10),
+        ];
+        let buffer = write_single_polygon(Polygon::new(1, bow_tie));
+
+        let mut reader = GdsReader::new(Cursor::new(buffer.clone()))
+            .with_polygon_check(PolygonCheck::Report);
+        let read_db = reader.read().unwrap();
+        assert!(!reader.warnings().is_empty());
+        assert_eq!(read_db.find_cell_by_name("top").unwrap().geometry_count(), 1);
+
+        let mut reader = GdsReader::new(Cursor::new(buffer.clone()))
+            .with_polygon_check(PolygonCheck::Repair);
+        let read_db = reader.read().unwrap();
+        assert!(!reader.warnings().is_empty());
+        assert_eq!(read_db.find_cell_by_name("top").unwrap().geometry_count(), 2);
+
+        let result = GdsReader::new(Cursor::new(buffer))
+            .with_polygon_check(PolygonCheck::Reject)
+            .read();
+        assert!(matches!(result, Err(GdsError::InvalidPolygon { .. })));
+    }
+
+    /// A stream of `cells` written in the given order. References may name
+    /// any cell in `names`, whether or not it is written.
+    fn write_structures(names: &[&Cell], cells: &[&Cell]) -> Vec<u8> {
+        let db = LayoutDatabase::new("test_lib");
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut writer = GdsWriter::new(&mut buffer);
+        writer.cell_names = names.iter().map(|c| (c.id, c.name.clone())).collect();
+        writer.write_header().unwrap();
+        writer.write_bgnlib().unwrap();
+        writer.write_libname(&db.name).unwrap();
+        writer.write_units(&db).unwrap();
+        for cell in cells {
+            writer.write_cell(cell).unwrap();
+        }
+        writer.write_endlib().unwrap();
+        buffer
+    }
+
+    /// A stream where `top` places `mid` twice and `mid` places `leaf`, plus
+    /// an unplaced `spare` cell. With `dangling`, `top` also places a
+    /// structure `missing` that the stream does not define.
+    fn write_hierarchy(dangling: bool) -> Vec<u8> {
+        let mut leaf = Cell::new("leaf");
+        leaf.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 10)));
+        let mut mid = Cell::new("mid");
+        mid.add_instance(CellInstance::new(leaf.id, "L0", Transform::default()));
+        let mut top = Cell::new("top");
+        top.add_instance(CellInstance::new(mid.id, "M0", Transform::default()));
+        top.add_instance(CellInstance::new(mid.id, "M1", Transform::translate(1000, 0)));
+        // Named by the SREF but never written as a structure.
+        let missing = Cell::new("missing");
+        if dangling {
+            top.add_instance(CellInstance::new(missing.id, "X0", Transform::default()));
+        }
+        // `spare` is also a root and sorts first by name.
+        let spare = Cell::new("spare");
+        write_structures(
+            &[&leaf, &mid, &top, &spare, &missing],
+            &[&leaf, &mid, &spare, &top],
+        )
+    }
+
+    #[test]
+    fn test_references_resolved_after_import() {
+        let mut reader = GdsReader::new(Cursor::new(write_hierarchy(false)));
+        let db = reader.read().unwrap();
+        assert!(reader.warnings().is_empty(), "{:?}", reader.warnings());
+
+        let top = db.find_cell_by_name("top").unwrap();
+        let mid = db.find_cell_by_name("mid").unwrap();
+        let leaf = db.find_cell_by_name("leaf").unwrap();
+        assert!(top.instances.iter().all(|i| i.cell_id == mid.id));
+        assert_eq!(mid.instances[0].cell_id, leaf.id);
+
+        assert_eq!(db.top_cell, Some(top.id));
+        let spare = db.find_cell_by_name("spare").unwrap();
+        assert_eq!(db.root_cells(), vec![spare.id, top.id]);
+        let bb = db.cell_bbox(&top.id).unwrap();
+        assert_eq!((bb.min, bb.max), (Point::new(0, 0), Point::new(1010, 10)));
+    }
+
+    #[test]
+    fn test_unresolved_reference_warns_or_fails() {
+        let buffer = write_hierarchy(true);
+
+        let mut reader = GdsReader::new(Cursor::new(buffer.clone()));
+        let db = reader.read().unwrap();
+        assert_eq!(
+            reader.warnings(),
+            &[GdsWarning::UnresolvedReference {
+                cell: "top".into(),
+                name: "missing".into(),
+            }]
+        );
+        assert_eq!(db.find_cell_by_name("top").unwrap().instance_count(), 2);
+
+        let result = GdsReader::new(Cursor::new(buffer))
+            .with_strict_references(true)
+            .read();
+        assert!(matches!(result, Err(GdsError::UndefinedCell(name)) if name == "missing"));
+    }
+
+    #[test]
+    fn test_duplicate_structure_resolves_to_first() {
+        let mut first = Cell::new("leaf");
+        first.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 10)));
+        let mut second = Cell::new("leaf");
+        second.add_geometry(GeomPrimitive::Rect(Rect::new(2, 0, 0, 20, 20)));
+        let mut top = Cell::new("top");
+        top.add_instance(CellInstance::new(first.id, "L0", Transform::default()));
+        let buffer = write_structures(&[&first, &second, &top], &[&first, &second, &top]);
+
+        for _ in 0..4 {
+            let mut reader = GdsReader::new(Cursor::new(buffer.clone()));
+            let db = reader.read().unwrap();
+            assert_eq!(reader.warnings(), &[GdsWarning::DuplicateCell("leaf".into())]);
+            let top = db.find_cell_by_name("top").unwrap();
+            let target = db.get_cell(&top.instances[0].cell_id).unwrap();
+            let rect = Rect::new(1, 0, 0, 10, 10);
+            assert_eq!(target.geometries().collect::<Vec<_>>(), [&GeomPrimitive::Rect(rect)]);
+        }
+    }
+
+    /// Cells by name, with their geometry and their placements by child name.
+    fn hierarchy_summary(db: &LayoutDatabase) -> Vec<(String, Vec<GeomPrimitive>, Vec<String>)> {
+        let mut cells: Vec<_> = db
+            .all_cells()
+            .map(|cell| {
+                let instances = cell
+                    .instances
+                    .iter()
+                    .map(|inst| {
+                        let child = &db.get_cell(&inst.cell_id).unwrap().name;
+                        format!("{} {:?} {:?}", child, inst.transform, inst.array)
+                    })
+                    .collect();
+                (cell.name.clone(), cell.geometries().cloned().collect(), instances)
+            })
+            .collect();
+        cells.sort_by(|a, b| a.0.cmp(&b.0));
+        cells
+    }
+
+    #[test]
+    fn test_hierarchy_roundtrip() {
+        let mut db = LayoutDatabase::new("test_lib");
+        let mut leaf = Cell::new("leaf");
+        leaf.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 20)));
+        let leaf_id = db.add_cell(leaf);
+        let mut mid = Cell::new("mid");
+        let rotated = Transform::from_parts(Point::new(100, 0), false, 90.0, 1.0);
+        mid.add_instance(CellInstance::new(leaf_id, "L0", rotated));
+        let array = ArrayParams::new(4, 2, Point::new(30, 0), Point::new(0, 40));
+        let placement = Transform::translate(0, 200);
+        mid.add_instance(CellInstance::new(leaf_id, "L1", placement).with_array(array));
+        let mid_id = db.add_cell(mid);
+        let mut top = Cell::new("top");
+        top.add_instance(CellInstance::new(mid_id, "M0", Transform::default()));
+        let mirrored = Transform::from_parts(Point::new(0, -500), true, 0.0, 1.0);
+        top.add_instance(CellInstance::new(mid_id, "M1", mirrored));
+        db.add_cell(top);
+
+        let mut first: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut first).write(&db).unwrap();
+        let read = GdsReader::new(Cursor::new(first)).read().unwrap();
+        let mut second: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut second).write(&read).unwrap();
+        let reread = GdsReader::new(Cursor::new(second)).read().unwrap();
+
+        let summary = hierarchy_summary(&read);
+        assert_eq!(summary, hierarchy_summary(&reread));
+        let names: Vec<&str> = summary.iter().map(|c| c.0.as_str()).collect();
+        assert_eq!(names, ["leaf", "mid", "top"]);
+        assert!(summary[2].2.iter().all(|i| i.starts_with("mid ")));
+        assert!(summary[1].2.iter().all(|i| i.starts_with("leaf ")));
+
+        let top_id = db.find_cell_by_name("top").unwrap().id;
+        let flat = |db: &LayoutDatabase, id| db.flatten(id, Default::default()).unwrap().count();
+        let reread_top = reread.find_cell_by_name("top").unwrap().id;
+        assert_eq!(flat(&reread, &reread_top), 18);
+        assert_eq!(flat(&db, &top_id), 18);
+        assert_eq!(reread.cell_bbox(&reread_top), db.cell_bbox(&top_id));
+    }
+
+    #[test]
+    fn test_reference_to_unknown_cell_is_an_error() {
+        let mut db = LayoutDatabase::new("test_lib");
+        let mut top = Cell::new("top");
+        let orphan = uuid::Uuid::new_v4();
+        top.add_instance(CellInstance::new(orphan, "X0", Transform::default()));
+        db.add_cell(top);
+
+        let result = GdsWriter::new(Vec::new()).write(&db);
+        assert!(matches!(result, Err(GdsError::UndefinedCell(id)) if id == orphan.to_string()));
+    }
+
+    /// The two REAL8 values of the UNITS record.
+    fn units_record(buffer: &[u8]) -> [f64; 2] {
+        let at = buffer.windows(4).position(|w| w == [0, 20, 0x03, 0x05]).unwrap();
+        let value = |i: usize| gds_real8_to_f64(buffer[i..i + 8].try_into().unwrap());
+        [value(at + 4), value(at + 12)]
+    }
+
+    #[test]
+    fn test_units_preserved() {
+        // A 1nm grid in a micron user unit.
+        let mut db = LayoutDatabase::new("test_lib");
+        db.add_cell(Cell::new("top"));
+        let mut buffer: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut buffer).write(&db).unwrap();
+        let [user, meters] = units_record(&buffer);
+        assert!((user - 1e-3).abs() < 1e-15 && (meters - 1e-9).abs() < 1e-20);
+        let read = GdsReader::new(Cursor::new(buffer)).read().unwrap();
+        assert_eq!(read.dbu_per_um, 1000.0);
+
+        // A 0.1nm grid in a nanometer user unit survives a round trip.
+        db.dbu_per_um = 10_000.0;
+        db.gds_units = Some([0.1, 1e-10]);
+        let mut buffer: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut buffer).write(&db).unwrap();
+        let read = GdsReader::new(Cursor::new(buffer)).read().unwrap();
+        assert_eq!(read.dbu_per_um, 10_000.0);
+        let mut again: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut again).write(&read).unwrap();
+        let [user, meters] = units_record(&again);
+        assert!((user - 0.1).abs() < 1e-12 && (meters - 1e-10).abs() < 1e-21);
+
+        // Units that no longer match the grid are replaced.
+        db.dbu_per_um = 1000.0;
+        let mut buffer: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut buffer).write(&db).unwrap();
+        let [user, meters] = units_record(&buffer);
+        assert!((user - 1e-3).abs() < 1e-15 && (meters - 1e-9).abs() < 1e-20);
+    }
+
+    #[test]
+    fn test_coordinate_overflow_is_an_error() {
+        let mut db = LayoutDatabase::new("test_lib");
+        let mut top = Cell::new("top");
+        let far = 1i64 << 40;
+        top.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, far, 10)));
+        db.add_cell(top);
+
+        let result = GdsWriter::new(Vec::new()).write(&db);
+        assert!(matches!(result, Err(GdsError::CoordinateOverflow(c)) if c == far));
+    }
+
+    #[test]
+    fn test_timestamps() {
+        // 2024-02-29 13:45:30 UTC
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(1_709_214_330);
+        assert_eq!(gds_date(time), [2024, 2, 29, 13, 45, 30]);
+        assert_eq!(gds_date(UNIX_EPOCH), [1970, 1, 1, 0, 0, 0]);
+
+        let mut db = LayoutDatabase::new("test_lib");
+        db.add_cell(Cell::new("top"));
+        let mut buffer: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut buffer).with_timestamp(time).write(&db).unwrap();
+        let date: Vec<u8> =
+            [2024i16, 2, 29, 13, 45, 30].iter().flat_map(|v| v.to_be_bytes()).collect();
+        let bgnlib = [&[0, 28, 0x01, 0x02][..], &date, &date].concat();
+        let bgnstr = [&[0, 28, 0x05, 0x02][..], &date, &date].concat();
+        assert!(buffer.windows(28).any(|w| w == bgnlib));
+        assert!(buffer.windows(28).any(|w| w == bgnstr));
+    }
+
+    #[test]
+    fn test_output_is_reproducible() {
+        let build = || {
+            let mut db = LayoutDatabase::new("test_lib");
+            let mut top = Cell::new("top");
+            for i in 0..8 {
+                let mut leaf = Cell::new(&format!("leaf{}", i));
+                leaf.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 10 + i)));
+                let id = db.add_cell(leaf);
+                let placement = Transform::translate(100 * i, 0);
+                top.add_instance(CellInstance::new(id, &format!("L{}", i), placement));
+            }
+            db.add_cell(Cell::new("spare"));
+            db.add_cell(top);
+            db
+        };
+        let write = |db: &LayoutDatabase| {
+            let mut buffer: Vec<u8> = Vec::new();
+            GdsWriter::new(&mut buffer).with_timestamp(UNIX_EPOCH).write(db).unwrap();
+            buffer
+        };
+        let first = write(&build());
+        for _ in 0..4 {
+            assert!(write(&build()) == first);
+        }
+    }
+
+    fn write_array(array: ArrayParams) -> Result<Vec<u8>, GdsError> {
+        let mut db = LayoutDatabase::new("test_lib");
+        let mut leaf = Cell::new("leaf");
+        leaf.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 20)));
+        let leaf_id = db.add_cell(leaf);
+        let mut top = Cell::new("top");
+        let placement = Transform::from_parts(Point::new(1000, 0), false, 90.0, 1.0);
+        top.add_instance(CellInstance::new(leaf_id, "A0", placement).with_array(array));
+        db.add_cell(top);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut buffer).write(&db)?;
+        Ok(buffer)
+    }
+
+    #[test]
+    fn test_array_reference_roundtrip() {
+        let array = ArrayParams::new(3, 2, Point::new(0, 20), Point::new(-30, 0));
+        let buffer = write_array(array).unwrap();
+        let db = GdsReader::new(Cursor::new(buffer.clone())).read().unwrap();
+
+        let top = db.find_cell_by_name("top").unwrap();
+        let [inst] = &top.instances[..] else { panic!() };
+        assert_eq!(inst.array, Some(array));
+        assert_eq!(inst.transform.rotation_degrees(), 90.0);
+        let bb = db.cell_bbox(&top.id).unwrap();
+        assert_eq!((bb.min, bb.max), (Point::new(950, 0), Point::new(1000, 50)));
+        assert_eq!(db.flatten(&top.id, Default::default()).unwrap().count(), 6);
+
+        // An AREF without elements is rejected.
+        let colrow = buffer.windows(4).position(|w| w == [0, 8, 0x13, 0x02]).unwrap();
+        let mut broken = buffer;
+        broken[colrow + 4..colrow + 8].fill(0);
+        let err = GdsReader::new(Cursor::new(broken)).read().unwrap_err();
+        assert!(matches!(err, GdsError::InvalidRecord { .. }), "{err}");
+
+        let huge = ArrayParams::new(40_000, 1, Point::new(10, 0), Point::new(0, 10));
+        assert!(matches!(write_array(huge), Err(GdsError::ArrayTooLarge { .. })));
+    }
+
+    #[test]
+    fn test_text_roundtrip() {
+        let presentation = TextPresentation {
+            font: 1,
+            horizontal: HorizontalAlign::Right,
+            vertical: VerticalAlign::Middle,
+        };
+        let placement = Transform::from_parts(Point::new(-50, 70), true, 90.0, 2.0);
+        let label = Text::new(Layer::gds_id(5, 7), "net<3>", Point::new(-50, 70))
+            .with_transform(placement)
+            .with_presentation(presentation);
+        let mut db = LayoutDatabase::new("test_lib");
+        let mut cell = Cell::new("top");
+        cell.add_geometry(GeomPrimitive::Text(label.clone()));
+        cell.add_geometry(GeomPrimitive::Text(Text::new(6, "A", Point::new(0, 0))));
+        db.add_cell(cell);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut buffer).write(&db).unwrap();
+        let db = GdsReader::new(Cursor::new(buffer)).read().unwrap();
+        let cell = db.find_cell_by_name("top").unwrap();
+        let texts: Vec<&GeomPrimitive> = cell.geometries().collect();
+        assert_eq!(texts[0], &GeomPrimitive::Text(label));
+        assert_eq!(texts[1], &GeomPrimitive::Text(Text::new(6, "A", Point::new(0, 0))));
+    }
+
+    #[test]
+    fn test_datatypes_preserved() {
+        let mut technology = LayerStack::new();
+        technology.add_layer(Layer::new(1, "metal1", 68, 20));
+        let mut db = LayoutDatabase::new("test_lib");
+        db.layer_stack = technology.clone();
+        let mut cell = Cell::new("top");
+        cell.add_geometry(GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 10)));
+        let pin = Layer::gds_id(68, 16);
+        cell.add_geometry(GeomPrimitive::Rect(Rect::new(pin, 0, 0, 10, 10)));
+        let label = Text::new(Layer::gds_id(68, 5), "A", Point::new(5, 5));
+        cell.add_geometry(GeomPrimitive::Text(label));
+        cell.add_geometry(GeomPrimitive::Path(LayoutPath::new(
+            68,
+            vec![Point::new(0, 0), Point::new(100, 0)],
+            10,
+        )));
+        db.add_cell(cell);
+        let mut buffer: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut buffer).write(&db).unwrap();
+
+        let mut reader = GdsReader::new(Cursor::new(buffer.clone())).with_layer_stack(technology);
+        let db = reader.read().unwrap();
+        let cell = db.find_cell_by_name("top").unwrap();
+        let layers: Vec<LayerId> = cell.geometries().map(|g| g.layer_id()).collect();
+        assert_eq!(layers, [1, pin, Layer::gds_id(68, 5), 68]);
+        let pairs: Vec<(u16, u16)> = layers.iter().map(|&id| db.layer_stack.gds_pair(id)).collect();
+        assert_eq!(pairs, [(68, 20), (68, 16), (68, 5), (68, 0)]);
+        assert_eq!(db.layer_stack.get_layer(pin).unwrap().name, "68/16");
+
+        // A BOX keeps its box type as the datatype of its layer.
+        let boundary = buffer.windows(4).position(|w| w == [0, 4, 0x08, 0x00]).unwrap();
+        buffer[boundary + 2] = 0x2D;
+        let datatype = buffer.windows(4).position(|w| w == [0, 6, 0x0E, 0x02]).unwrap();
+        buffer[datatype + 2] = 0x2E;
+        let db = GdsReader::new(Cursor::new(buffer)).read().unwrap();
+        let cell = db.find_cell_by_name("top").unwrap();
+        let first = cell.geometries().next().unwrap();
+        assert!(matches!(first, GeomPrimitive::Rect(_)));
+        assert_eq!(db.layer_stack.gds_pair(first.layer_id()), (68, 20));
+    }
+
+    #[test]
+    fn test_properties_roundtrip() {
+        let props = |pairs: &[(u16, &str)]| -> Properties {
+            pairs.iter().map(|&(a, v)| (a, v.to_string())).collect()
+        };
+        let mut db = LayoutDatabase::new("test_lib");
+        let leaf_id = db.add_cell(Cell::new("leaf"));
+        let mut top = Cell::new("top");
+        top.properties = props(&[(126, "top level")]);
+        let rect = GeomPrimitive::Rect(Rect::new(1, 0, 0, 10, 10));
+        top.add_shape(Shape::new(rect).with_properties(props(&[(1, "VDD"), (7, "w=0.5")])));
+        top.add_geometry(GeomPrimitive::Rect(Rect::new(1, 20, 0, 30, 10)));
+        let inst = CellInstance::new(leaf_id, "X1", Transform::default())
+            .with_properties(props(&[(61, "X1")]));
+        top.add_instance(inst);
+        db.add_cell(top);
+
+        let mut standard: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut standard).write(&db).unwrap();
+        let read = GdsReader::new(Cursor::new(standard)).read().unwrap();
+        assert!(read.find_cell_by_name("top").unwrap().properties.is_empty());
+
+        let mut buffer: Vec<u8> = Vec::new();
+        GdsWriter::new(&mut buffer)
+            .with_cell_properties(true)
+            .with_instance_names(true)
+            .write(&db)
+            .unwrap();
+        let db = GdsReader::new(Cursor::new(buffer)).read().unwrap();
+        let top = db.find_cell_by_name("top").unwrap();
+        assert_eq!(top.properties, props(&[(126, "top level")]));
+        assert_eq!(top.shapes[0].properties, props(&[(1, "VDD"), (7, "w=0.5")]));
+        assert!(top.shapes[1].properties.is_empty());
+        assert_eq!(top.instances[0].properties, props(&[(61, "X1")]));
+        assert_eq!(top.instances[0].instance_name, "X1");
+        assert!(db.find_cell_by_name("leaf").unwrap().properties.is_empty());
+    }
+
+    #[test]
+    fn test_plain_reference_has_no_properties() {
+        let mut db = LayoutDatabase::new("test_lib");
+        let leaf_id = db.add_cell(Cell::new("leaf"));
+        let mut top = Cell::new("top");
+        top.add_instance(CellInstance::new(leaf_id, "leaf", Transform::default()));
+        top.add_instance(CellInstance::new(leaf_id, "X1", Transform::translate(100, 0)));
+        db.add_cell(top);
+
+        let write = |names: bool| {
+            let mut buffer: Vec<u8> = Vec::new();
+            GdsWriter::new(&mut buffer).with_instance_names(names).write(&db).unwrap();
+            buffer
+        };
+        let attributes = |buffer: &[u8]| {
+            let mut count = 0;
+            let mut rest = buffer;
+            while rest.len() >= 4 {
+                let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+                let kind = u16::from_be_bytes([rest[2], rest[3]]);
+                count += usize::from(kind == record_type::PROPATTR);
+                rest = &rest[len..];
+            }
+            count
+        };
+        // Only the renamed reference needs its name written.
+        assert_eq!(attributes(&write(false)), 0);
+        let named = write(true);
+        assert_eq!(attributes(&named), 1);
+
+        let names = |buffer: Vec<u8>| -> Vec<String> {
+            let db = GdsReader::new(Cursor::new(buffer)).read().unwrap();
+            let top = db.find_cell_by_name("top").unwrap();
+            assert!(top.instances.iter().all(|i| i.properties.is_empty()));
+            top.instances.iter().map(|i| i.instance_name.clone()).collect()
+        };
+        assert_eq!(names(named), ["leaf", "X1"]);
+        assert_eq!(names(write(false)), ["leaf", "leaf"]);
+    }
+
+    #[test]
+    fn test_unpaired_property_attribute_is_dropped() {
+        let db = LayoutDatabase::new("test_lib");
+        let rect = Rect::new(1, 0, 0, 10, 10);
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut writer = GdsWriter::new(&mut buffer);
+        writer.write_header().unwrap();
+        writer.write_bgnlib().unwrap();
+        writer.write_libname(&db.name).unwrap();
+        writer.write_units(&db).unwrap();
+        writer.write_timestamp(record_type::BGNSTR).unwrap();
+        writer.write_string_record(record_type::STRNAME, "top").unwrap();
+        // A PROPATTR without its PROPVALUE, then a PROPVALUE without its
+        // PROPATTR on the next element.
+        writer.write_rect(&rect).unwrap();
+        writer.write_i16_record(record_type::PROPATTR, &[5]).unwrap();
+        writer.write_record(record_type::ENDEL, &[]).unwrap();
+        writer.write_rect(&rect).unwrap();
+        writer.write_string_record(record_type::PROPVALUE, "stray").unwrap();
+        writer.write_record(record_type::ENDEL, &[]).unwrap();
+        writer.write_record(record_type::ENDSTR, &[]).unwrap();
+        writer.write_endlib().unwrap();
+
+        let db = GdsReader::new(Cursor::new(buffer)).read().unwrap();
+        let top = db.find_cell_by_name("top").unwrap();
+        assert_eq!(top.shapes.len(), 2);
+        assert!(top.shapes.iter().all(|s| s.properties.is_empty()));
+    }
 }
@@ -8,4 +8,4 @@ pub mod project;
 pub mod gds;
 
 pub use project::ProjectMeta;
-pub use gds::{GdsReader, GdsWriter, GdsError};
+pub use gds::{GdsReader, GdsWriter, GdsError, GdsWarning};
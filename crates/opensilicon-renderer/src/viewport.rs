@@ -39,7 +39,7 @@ impl Viewport {
         let layout_y = self.screen_to_layout_y(screen_y);
 
         self.zoom *= factor;
-        self.zoom = self.zoom.max(0.001).min(1_000_000.0);
+        self.zoom = self.zoom.clamp(0.001, 1_000_000.0);
 
         // Adjust center so the point under the cursor stays fixed
         let new_layout_x = self.screen_to_layout_x(screen_x);